@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::msg::{from_utc_millis, to_utc_millis, Control};
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// 応答と照合するために記録しておく、直近に送信した Ping の数です。
+pub const SENT_PING_HISTORY: usize = 16;
+
+/// `Control::SystemConfig` で合意した `ping_interval` に従って `Control::Ping` を送信し、その往復時間 (RTT) を
+/// 計測するためのヘルパーです。
+///
+/// このヘルパー自体は I/O を行いません。呼び出し側は `next_ping()` が返す間隔でタイマーを設定し、`poll_ping()`
+/// が返した Ping をワイヤーに送信します。また、ワイヤーから受信した Ping を `on_ping()` に渡し、返値の Ping が
+/// あればそれを応答として送信します。Ping の応答は受信した `utc_time` をそのまま送り返すことで表します。
+///
+/// 送信間隔や RTT の計測には単調増加する `Instant` を使用し、システム時計はワイヤー上の `utc_time` にのみ使用
+/// します。NTP の補正などでシステム時計が巻き戻った場合でも、送信する `utc_time` は前回の値より大きくなるように
+/// 調整されるため、以前の Ping への遅れた応答を新しい Ping の応答と取り違えることはありません。
+///
+/// 直近に送信した `SENT_PING_HISTORY` 個の Ping を記録し、それと一致する Ping は新しい Ping を送信した後に遅れて
+/// 到着したものや重複して到着したものも含めて応答として扱い、送り返しません。応答を送り返すと相手も自分の送信した
+/// Ping と一致しないためにさらに送り返し、両方のノードが互いに応答を送り返し続けることになるためです。
+pub struct Heartbeat {
+  interval: Duration,
+  /// 直近に送信した Ping の `utc_time` と送信した時刻、およびその応答を受信したか。
+  sent: VecDeque<(u64, Instant, bool)>,
+  last_sent: Option<Instant>,
+  /// 最後に送信した Ping の `utc_time`。
+  last_utc_time: Option<u64>,
+  rtt: Option<Duration>,
+  /// `utc_time` に使用するシステム時計。
+  wall_clock: fn() -> SystemTime,
+}
+
+impl Heartbeat {
+  /// 指定された間隔で Ping を送信する Heartbeat を構築します。
+  pub fn new(interval: Duration) -> Heartbeat {
+    Heartbeat {
+      interval,
+      sent: VecDeque::with_capacity(SENT_PING_HISTORY),
+      last_sent: None,
+      last_utc_time: None,
+      rtt: None,
+      wall_clock: SystemTime::now,
+    }
+  }
+
+  /// `Control::SystemConfig` の `ping_interval` (秒) から Heartbeat を構築します。
+  pub fn with_ping_interval(ping_interval: u32) -> Heartbeat {
+    Heartbeat::new(Duration::from_secs(ping_interval as u64))
+  }
+
+  /// Ping の送信間隔を参照します。
+  pub fn interval(&self) -> Duration {
+    self.interval
+  }
+
+  /// 最後に計測した往復時間を参照します。まだ一度も応答を受信していない場合は `None` を返します。
+  pub fn rtt(&self) -> Option<Duration> {
+    self.rtt
+  }
+
+  /// 次の Ping を送信するまでの時間を参照します。すでに送信時期に達している場合は `Duration::ZERO` を返します。
+  pub fn next_ping(&self, now: Instant) -> Duration {
+    match self.last_sent {
+      Some(last_sent) => (last_sent + self.interval).saturating_duration_since(now),
+      None => Duration::ZERO,
+    }
+  }
+
+  /// 送信時期に達している場合、送信すべき Ping を返します。
+  pub fn poll_ping(&mut self, now: Instant) -> Result<Option<Control>> {
+    if self.next_ping(now) > Duration::ZERO {
+      return Ok(None);
+    }
+    let wall_time = to_utc_millis((self.wall_clock)());
+    let utc_time = match self.last_utc_time {
+      Some(last) if wall_time <= last => last.saturating_add(1),
+      _ => wall_time,
+    };
+    self.last_utc_time = Some(utc_time);
+    if self.sent.len() == SENT_PING_HISTORY {
+      self.sent.pop_front();
+    }
+    self.sent.push_back((utc_time, now, false));
+    self.last_sent = Some(now);
+    Control::new_ping(from_utc_millis(utc_time)).map(Some)
+  }
+
+  /// 受信した Ping を処理します。自分が送信した Ping の応答であれば `None` を返し、それが最初に受信した応答であれば
+  /// RTT を記録します。相手からの Ping であれば、送り返すべき応答の Ping を返します。
+  pub fn on_ping(&mut self, utc_time: u64, now: Instant) -> Result<Option<Control>> {
+    match self.sent.iter_mut().find(|(sent_time, _, _)| *sent_time == utc_time) {
+      Some((_, sent_at, answered)) => {
+        if !*answered {
+          *answered = true;
+          self.rtt = Some(now.saturating_duration_since(*sent_at));
+        }
+        Ok(None)
+      }
+      None => Control::new_ping(from_utc_millis(utc_time)).map(Some),
+    }
+  }
+}
@@ -0,0 +1,140 @@
+use std::cell::Cell;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::bridge::heartbeat::{Heartbeat, SENT_PING_HISTORY};
+use crate::msg::{from_utc_millis, Control, Message};
+
+#[test]
+fn test_heartbeat_schedule() {
+  let interval = Duration::from_secs(10);
+  let mut heartbeat = Heartbeat::new(interval);
+  assert_eq!(Duration::from_secs(3), Heartbeat::with_ping_interval(3).interval());
+
+  // 最初の Ping は即座に送信し、次の Ping は間隔が経過するまで送信しない
+  let now = Instant::now();
+  assert_eq!(Duration::ZERO, heartbeat.next_ping(now));
+  assert!(heartbeat.poll_ping(now).unwrap().is_some());
+  assert_eq!(interval, heartbeat.next_ping(now));
+  assert!(heartbeat.poll_ping(now + interval / 2).unwrap().is_none());
+  assert!(heartbeat.poll_ping(now + interval).unwrap().is_some());
+
+  // 相手からの Ping には同じ utc_time で応答する
+  assert_eq!(
+    Some(Control::new_ping(from_utc_millis(1234)).unwrap()),
+    heartbeat.on_ping(1234, now).unwrap()
+  );
+  assert!(heartbeat.rtt().is_none());
+}
+
+#[test]
+fn test_heartbeat_rtt() {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+
+  // 受信した Ping をそのまま応答するピア
+  let peer = spawn(move || {
+    let (mut wire, _) = listener.accept().unwrap();
+    let mut heartbeat = Heartbeat::new(Duration::from_secs(60));
+    if let Message::Control(Control::Ping { utc_time }) = Message::read_from(&mut wire).unwrap() {
+      sleep(Duration::from_millis(20));
+      let pong = heartbeat.on_ping(utc_time, Instant::now()).unwrap().unwrap();
+      Message::Control(pong).write_to(&mut wire).unwrap();
+      wire.flush().unwrap();
+    } else {
+      unreachable!();
+    }
+  });
+
+  let mut wire = TcpStream::connect(address).unwrap();
+  let mut heartbeat = Heartbeat::new(Duration::from_secs(60));
+  let ping = heartbeat.poll_ping(Instant::now()).unwrap().unwrap();
+  Message::Control(ping).write_to(&mut wire).unwrap();
+  wire.flush().unwrap();
+  if let Message::Control(Control::Ping { utc_time }) = Message::read_from(&mut wire).unwrap() {
+    assert!(heartbeat.on_ping(utc_time, Instant::now()).unwrap().is_none());
+  } else {
+    unreachable!();
+  }
+  peer.join().unwrap();
+
+  let rtt = heartbeat.rtt().unwrap();
+  assert!(rtt >= Duration::from_millis(20) && rtt < Duration::from_secs(10), "{:?}", rtt);
+}
+
+thread_local! {
+  static WALL_CLOCK: Cell<u64> = const { Cell::new(0) };
+}
+
+/// テスト用に任意の時刻を返すシステム時計。
+fn wall_clock() -> SystemTime {
+  from_utc_millis(WALL_CLOCK.with(|clock| clock.get()))
+}
+
+#[test]
+fn test_heartbeat_clock_step_backward() {
+  let interval = Duration::from_secs(10);
+  let mut heartbeat = Heartbeat::new(interval);
+  heartbeat.wall_clock = wall_clock;
+  let utc_time = |ping: Control| match ping {
+    Control::Ping { utc_time } => utc_time,
+    _ => unreachable!(),
+  };
+
+  let now = Instant::now();
+  WALL_CLOCK.with(|clock| clock.set(100_000));
+  let first = utc_time(heartbeat.poll_ping(now).unwrap().unwrap());
+  assert_eq!(100_000, first);
+  assert!(heartbeat.on_ping(first, now + Duration::from_millis(50)).unwrap().is_none());
+  assert_eq!(Some(Duration::from_millis(50)), heartbeat.rtt());
+
+  // システム時計が巻き戻っても utc_time は前回より大きく、RTT は単調時計で計測される
+  WALL_CLOCK.with(|clock| clock.set(40_000));
+  let second = utc_time(heartbeat.poll_ping(now + interval).unwrap().unwrap());
+  assert!(second > first);
+  assert!(heartbeat.on_ping(second, now + interval + Duration::from_millis(30)).unwrap().is_none());
+  assert_eq!(Some(Duration::from_millis(30)), heartbeat.rtt());
+
+  // 時計が再び進めばその値が使用される
+  WALL_CLOCK.with(|clock| clock.set(200_000));
+  assert_eq!(200_000, utc_time(heartbeat.poll_ping(now + interval * 2).unwrap().unwrap()));
+}
+
+#[test]
+fn test_heartbeat_late_reply() {
+  let interval = Duration::from_secs(10);
+  let mut heartbeat = Heartbeat::new(interval);
+  heartbeat.wall_clock = wall_clock;
+  let utc_time = |ping: Control| match ping {
+    Control::Ping { utc_time } => utc_time,
+    _ => unreachable!(),
+  };
+
+  let now = Instant::now();
+  WALL_CLOCK.with(|clock| clock.set(100_000));
+  let first = utc_time(heartbeat.poll_ping(now).unwrap().unwrap());
+  WALL_CLOCK.with(|clock| clock.set(110_000));
+  let second = utc_time(heartbeat.poll_ping(now + interval).unwrap().unwrap());
+
+  // 次の Ping を送信した後に到着した以前の Ping への応答は送り返さない
+  let late = now + interval + Duration::from_millis(500);
+  assert!(heartbeat.on_ping(first, late).unwrap().is_none());
+  assert_eq!(Some(interval + Duration::from_millis(500)), heartbeat.rtt());
+  assert!(heartbeat.on_ping(second, late).unwrap().is_none());
+  assert_eq!(Some(Duration::from_millis(500)), heartbeat.rtt());
+
+  // 重複して到着した応答も送り返さず、RTT は最初の応答で計測した値のまま
+  assert!(heartbeat.on_ping(first, late + interval).unwrap().is_none());
+  assert!(heartbeat.on_ping(second, late + interval).unwrap().is_none());
+  assert_eq!(Some(Duration::from_millis(500)), heartbeat.rtt());
+
+  // 記録している数を超えて古い Ping は相手からの Ping として扱われる
+  for i in 2..=SENT_PING_HISTORY as u32 {
+    WALL_CLOCK.with(|clock| clock.set(100_000 + 10_000 * i as u64));
+    heartbeat.poll_ping(now + interval * i).unwrap().unwrap();
+  }
+  assert!(heartbeat.on_ping(first, late + interval * 20).unwrap().is_some());
+  assert!(heartbeat.on_ping(second, late + interval * 20).unwrap().is_none());
+}
@@ -1,18 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::future::Future;
 use std::io::{Read, Write};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::DerefMut;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::task::{Context, Waker};
-use std::thread::spawn;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Wake, Waker};
+use std::thread::Builder;
+use std::time::{Duration, Instant};
 
 use log;
-use mio::{Events, Interest, Poll, Token};
 use mio::event::{Event, Source};
 use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token};
 
 use crate::error::Error;
 use crate::Result;
@@ -22,17 +24,122 @@ mod test;
 
 /// TcpStream にイベントが発生したときに呼び出されるコールバック用のトレイトです。
 /// 返値を使用してその後のアクションを指定することができます。
+///
+/// mio のイベント通知はエッジトリガーです。読み込み可能イベントはソケットが読み込み可能な状態に変化したときに一度
+/// だけ通知されるため、`on_ready_to_read()` で `WouldBlock` となるまで読み込まなかったデータは次にピアがデータを
+/// 送信するまで通知されません。`read_until_would_block()` を使用するか `ReadMode::Buffered` を指定することで、
+/// 1 回の通知でソケットに到着しているデータをすべて読み込むことができます。
 pub trait TcpStreamListener: Send {
+  /// 読み込み可能イベントの通知方法を参照します。デフォルトは `ReadMode::Raw` です。
+  fn read_mode(&self) -> ReadMode {
+    ReadMode::Raw
+  }
+
+  /// `ReadMode::Raw` の場合に、読み込み可能になったソケットを渡して呼び出されます。Listener は `WouldBlock` と
+  /// なるまで読み込む必要があります。
   fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction;
+
+  /// `ReadMode::Buffered` の場合に、ディスパッチャーがソケットから読み込んだデータを渡して呼び出されます。ソケット
+  /// が EOF に達した場合は空のスライスで呼び出されます。`data` はディスパッチャーが再利用するバッファを参照している
+  /// ため、呼び出しの後も必要なデータは複製する必要があります。
+  fn on_data(&mut self, _data: &[u8]) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+
   fn on_ready_to_write(&mut self, w: &mut dyn Write) -> DispatcherAction;
   fn on_error(&mut self, error: std::io::Error) -> DispatcherAction;
+
+  /// ソケットからの読み込みが EOF に達した (長さ 0 の読み込みが発生した) ときに一度だけ呼び出されます。これは
+  /// ピアが接続をクローズしたことを示しています。`ReadMode::Raw` では `on_ready_to_read()` の中で `Ok(0)` を読み込んだ
+  /// 後に、`ReadMode::Buffered` では空のスライスによる `on_data()` の後に呼び出されます。デフォルトはソケットを破棄
+  /// します。
+  fn on_eof(&mut self) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
+
+  /// ピアによって TcpStream の片方向がクローズされたときに、それぞれの方向について一度だけ呼び出されます。読み込み
+  /// 方向がクローズされた後は READABLE の監視が停止し、両方向がクローズされるとソケットは破棄されます。
+  fn on_hangup(&mut self, _half: Half) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+
+  /// Listener が `DispatcherAction::PauseReads` によって読み込みを一時停止している間、イベントループの周期ごとに
+  /// 呼び出されます。受信したメッセージを格納するキューが空くなどして再び受信できるようになった Listener は
+  /// `DispatcherAction::ResumeReads` を返して読み込みを再開します。読み込みを停止している間はソケットの受信バッファ
+  /// が消費されないため、ピアの送信は TCP のフロー制御によってブロックします。デフォルトは何もしません。
+  fn on_reads_paused(&mut self) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+
+  /// `Dispatcher::shutdown_with_reason()` によってディスパッチャーが終了するときに呼び出され、ソケットがクローズ
+  /// される前にピアへ書き込むデータを返します。ピアとの間で使用しているプロトコルに従って終了を通知する Listener
+  /// だけが実装します。デフォルトは何も書き込みません。
+  fn on_shutdown(&mut self, _reason_code: u16, _reason: &[u8]) -> Option<Vec<u8>> {
+    None
+  }
+
+  /// `DispatcherAction::Dispose` や `Dispatcher::dispose()`、ディスパッチャーの停止などによってソケットが破棄される
+  /// ときに一度だけ呼び出されます。完了していない処理の後始末に使用します。デフォルトは何もしません。
+  fn on_disposed(&mut self) {}
 }
 
 /// TcpListener にイベントが発生したときに呼び出されるコールバック用のトレイトです。
 /// 返値を使用してその後のアクションを指定することができます。
 pub trait TcpListenerListener: Send {
   fn on_accept(&mut self, stream: TcpStream, address: SocketAddr) -> DispatcherAction;
+  /// 登録されている TcpStream の数や接続の頻度が上限に達していたため、受け付けた接続を即座にクローズしたときに
+  /// 呼び出されます。デフォルトは何もしません。
+  fn on_rejected(&mut self, _address: SocketAddr) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
   fn on_error(&mut self, error: std::io::Error) -> DispatcherAction;
+
+  /// ソケットが破棄されるときに一度だけ呼び出されます。デフォルトは何もしません。
+  fn on_disposed(&mut self) {}
+}
+
+/// `r` からの読み込みが `WouldBlock` となるまで `buffer` に読み込み、読み込んだ断片ごとに `on_chunk` を呼び出し
+/// ます。エッジトリガーの読み込み可能イベントに対して、ソケットに到着しているデータをすべて読み込むために使用します。
+///
+/// EOF に達した場合は true、`WouldBlock` となった場合や `on_chunk` が false を返して読み込みを中断した場合は
+/// false を返します。
+pub fn read_until_would_block<R: Read + ?Sized>(
+  r: &mut R,
+  buffer: &mut [u8],
+  on_chunk: &mut dyn FnMut(&[u8]) -> bool,
+) -> std::io::Result<bool> {
+  loop {
+    match r.read(buffer) {
+      Ok(0) => return Ok(true),
+      Ok(len) => {
+        if !on_chunk(&buffer[..len]) {
+          return Ok(false);
+        }
+      }
+      Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(false),
+      Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+/// クローズされた TcpStream の方向を表す列挙型です。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Half {
+  /// 読み込み方向。ピアが送信をシャットダウンしたことを示します。
+  Read,
+  /// 書き込み方向。ピアへの送信ができなくなったことを示します。
+  Write,
+}
+
+/// TcpStream が読み込み可能になったときに Listener へ通知する方法を表す列挙型です。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+  /// ソケットをそのまま `on_ready_to_read()` に渡します。Listener は自身のバッファで読み込みを行います。
+  Raw,
+  /// ディスパッチャーが共有の読み込みバッファにデータを読み込み、`on_data()` にそのスライスを渡します。ソケットごとに
+  /// バッファを確保する必要がありません。
+  Buffered,
 }
 
 /// Listener へのコールバック終了後に Listener が Dispatcher に指示する動作を表す列挙型です。
@@ -43,6 +150,14 @@ pub enum DispatcherAction {
   ChangeFlag(Interest),
   /// イベントの発生元となるソケットなどの Source の破棄を指定します。
   Dispose,
+  /// 現在の Interest から READABLE を外して読み込みを一時停止することを指定します。WRITABLE は維持されます。
+  PauseReads,
+  /// 現在の Interest に READABLE を加えて読み込みを再開することを指定します。WRITABLE は維持されます。
+  ResumeReads,
+  /// 指定されたデータをソケットに書き込むことを指定します。ソケットがブロックして書き込みきれなかった残りは
+  /// ディスパッチャーが保持し、次の書き込み可能イベントで `on_ready_to_write()` を呼び出す代わりに自動的に書き込み
+  /// ます。保持しているデータをすべて書き込んだ時点で Interest から WRITABLE が外されます。
+  Write(Vec<u8>),
 }
 
 // ##############################################################################################
@@ -51,27 +166,190 @@ pub enum DispatcherAction {
 
 type Executable<R> = dyn (FnOnce(&mut PollingLoop) -> R) + Send + 'static;
 
+/// 結果の型を消去して channel 経由でイベントループに投入するタスク。
+type ErasedTask = Box<dyn FnOnce(&mut PollingLoop) + Send + 'static>;
+
 struct TaskState<R> {
   result: Option<R>,
   waker: Option<Waker>,
+  /// 結果が設定される前に Future が破棄されたかのフラグ。
+  cancelled: bool,
+}
+
+impl<R> TaskState<R> {
+  fn new(result: Option<R>) -> Self {
+    TaskState { result, waker: None, cancelled: false }
+  }
+
+  /// タスクの結果を設定し、待機している Future があれば起床させます。
+  fn complete(&mut self, result: R) {
+    self.result = Some(result);
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
 }
 
+/// タスクが実行されないまま破棄された場合に Future を `Error::DispatcherStopped` で完了させるためのガード。
+/// イベントループの停止時に channel に残っていたタスクの Future が永久に完了しない状況を防ぎます。
+struct TaskCompletion<R> {
+  state: Option<Arc<Mutex<TaskState<Result<R>>>>>,
+}
+
+impl<R> TaskCompletion<R> {
+  fn complete(mut self, result: Result<R>) {
+    if let Some(state) = self.state.take() {
+      state.lock().unwrap().complete(result);
+    }
+  }
+
+  fn is_cancelled(&self) -> bool {
+    self.state.as_ref().map(|state| state.lock().unwrap().cancelled).unwrap_or(false)
+  }
+
+  /// Future が破棄されていなければ結果を設定します。すでに破棄されている場合は結果を受け取る相手がいないため、
+  /// 設定せずに返します。判定と設定は同じロックの中で行われます。
+  fn complete_unless_cancelled(mut self, result: Result<R>) -> Option<Result<R>> {
+    let state = self.state.take()?;
+    let mut state = state.lock().unwrap();
+    if state.cancelled {
+      Some(result)
+    } else {
+      state.complete(result);
+      None
+    }
+  }
+}
+
+impl<R> Drop for TaskCompletion<R> {
+  fn drop(&mut self) {
+    if let Some(state) = self.state.take() {
+      if let Ok(mut state) = state.lock() {
+        if state.result.is_none() {
+          state.complete(Err(Error::DispatcherStopped));
+        }
+      }
+    }
+  }
+}
+
+/// Future が破棄されたキャンセル済みのタスクが実行されてしまった場合に、その結果を取り消す処理。
+type Rollback<R> = dyn FnOnce(&mut PollingLoop, R) + Send + 'static;
+
 struct Task<R> {
   executable: Box<Executable<R>>,
   state: Arc<Mutex<TaskState<R>>>,
+  /// `Some` の場合、このタスクは Future の破棄によってキャンセルできる。
+  rollback: Option<Box<Rollback<R>>>,
 }
 
 impl<R> Task<R> {
   fn new<E>(executable: Box<E>) -> Self
-    where
-      E: (FnOnce(&mut PollingLoop) -> R) + Send + 'static,
+  where
+    E: (FnOnce(&mut PollingLoop) -> R) + Send + 'static,
   {
-    Self { executable, state: Arc::new(Mutex::new(TaskState { result: None, waker: None })) }
+    Self { executable, state: Arc::new(Mutex::new(TaskState::new(None))), rollback: None }
+  }
+
+  /// 実行前に Future が破棄された場合は実行されずに破棄されるタスクを構築します。Future の破棄と実行が競合して
+  /// 誰も受け取らない結果が生成された場合は `rollback` によって取り消されます。
+  fn cancellable<E, F>(executable: Box<E>, rollback: F) -> Self
+  where
+    E: (FnOnce(&mut PollingLoop) -> R) + Send + 'static,
+    F: FnOnce(&mut PollingLoop, R) + Send + 'static,
+  {
+    Self { rollback: Some(Box::new(rollback)), ..Self::new(executable) }
   }
 }
 
+impl<R: Send + 'static> Task<Result<R>> {
+  /// タスクの実行結果を Future 側に通知する処理を含めて、結果の型を消去したタスクに変換します。イベントループの
+  /// 停止後に呼び出されたタスクは実行されず `Error::DispatcherShutdown` で完了します。キャンセル可能なタスクは
+  /// Future が破棄されていれば実行されずに破棄されます。
+  fn into_erased(self) -> ErasedTask {
+    let Task { executable, state, rollback } = self;
+    let completion = TaskCompletion { state: Some(state) };
+    Box::new(move |polling: &mut PollingLoop| {
+      if polling.stopped {
+        completion.complete(Err(Error::DispatcherShutdown));
+        return;
+      }
+      match rollback {
+        None => completion.complete(executable(polling)),
+        Some(_) if completion.is_cancelled() => {
+          // 処理が保持しているソケットなどのリソースは処理とともに破棄される
+          log::debug!("cancelled task discarded");
+        }
+        Some(rollback) => {
+          if let Some(result) = completion.complete_unless_cancelled(executable(polling)) {
+            log::debug!("rolling back the result of a cancelled task");
+            rollback(polling, result);
+          }
+        }
+      }
+    })
+  }
+}
+
+/// イベントループで実行されるタスクの結果を返す Future です。ソケットの登録のようにキャンセル可能なタスクは、
+/// 実行前にこの Future を破棄するとキャンセルされます。完了を待たずに実行させる場合は `detach()` を使用します。
 pub struct TaskFuture<R> {
   state: Arc<Mutex<TaskState<R>>>,
+  detached: bool,
+}
+
+impl<R> TaskFuture<R> {
+  fn new(state: Arc<Mutex<TaskState<R>>>) -> Self {
+    TaskFuture { state, detached: false }
+  }
+
+  /// 結果を待たずにこの Future を破棄します。キャンセル可能なタスクであってもキャンセルされず実行されます。
+  pub fn detach(mut self) {
+    self.detached = true;
+  }
+
+  /// タスクが完了していればその結果を取り出します。完了していない場合はブロックせずに `None` を返します。
+  pub fn try_result(&mut self) -> Option<R> {
+    self.state.lock().unwrap().result.take()
+  }
+
+  /// タスクが完了するまで呼び出し元のスレッドをブロックし、その結果を返します。非同期ランタイムを持たない同期的な
+  /// API から使用します。イベントループのスレッド (Listener のコールバックの中など) から呼び出すとタスクが実行
+  /// されずデッドロックします。
+  pub fn wait(self) -> R {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    loop {
+      {
+        let mut state = self.state.lock().unwrap();
+        if let Some(result) = state.result.take() {
+          return result;
+        }
+        state.waker = Some(waker.clone());
+      }
+      std::thread::park();
+    }
+  }
+}
+
+/// `TaskFuture::wait()` で待機しているスレッドを再開する Waker です。
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+  fn wake(self: Arc<Self>) {
+    self.0.unpark();
+  }
+}
+
+impl<R> Drop for TaskFuture<R> {
+  fn drop(&mut self) {
+    if !self.detached {
+      if let Ok(mut state) = self.state.lock() {
+        if state.result.is_none() {
+          state.cancelled = true;
+        }
+      }
+    }
+  }
 }
 
 impl<R> Future for TaskFuture<R> {
@@ -89,13 +367,302 @@ impl<R> Future for TaskFuture<R> {
   }
 }
 
+/// `Dispatcher::send()` で送信したデータがすべてカーネルに書き込まれた時点で完了する Future です。
+pub struct SendFuture {
+  /// データを受け付けたかの結果を返す Future。受け付けたことを確認した後は `None` となる。
+  accepted: Option<TaskFuture<Result<()>>>,
+  flushed: TaskFuture<Result<()>>,
+}
+
+impl SendFuture {
+  /// 書き込みの完了を待たずに、ディスパッチャーがデータを受け付けた時点で完了する Future を返します。
+  pub fn accepted(self) -> TaskFuture<Result<()>> {
+    match self.accepted {
+      Some(accepted) => accepted,
+      None => TaskFuture::new(Arc::new(Mutex::new(TaskState::new(Some(Ok(())))))),
+    }
+  }
+}
+
+impl Future for SendFuture {
+  type Output = Result<()>;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> std::task::Poll<Self::Output> {
+    use std::task::Poll;
+    if let Some(accepted) = self.accepted.as_mut() {
+      match Pin::new(accepted).poll(cx) {
+        Poll::Ready(Ok(())) => self.accepted = None,
+        Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+    Pin::new(&mut self.flushed).poll(cx)
+  }
+}
+
 // ##############################################################################################
 
 pub type SocketId = usize;
 
+/// ある時点でのディスパッチャーの稼働状況を表すスナップショットです。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DispatcherMetrics {
+  /// 現在登録されているソケットの数 (Waker を除く)。
+  pub registered_sockets: usize,
+  /// イベントループの開始から処理したソケットイベントの総数。
+  pub total_events_processed: u64,
+  /// イベントループの開始から実行したタスクの総数。
+  pub total_tasks_run: u64,
+  /// 現在登録されているソケットのうち TcpListener の数。
+  pub listener_count: usize,
+  /// 一度の poll で読み込むことのできるイベントの現在の最大数。
+  pub event_buffer_size: usize,
+}
+
+/// ディスパッチャーに登録されているソケットの種類です。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketKind {
+  /// 接続を受け付ける TcpListener。
+  Listener,
+  /// 接続済みの TcpStream。
+  Stream,
+}
+
+/// ディスパッチャーに登録されているソケットの情報です。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketInfo {
+  pub id: SocketId,
+  pub kind: SocketKind,
+  /// ローカル側アドレス。取得できなかった場合は `None`。
+  pub local_address: Option<SocketAddr>,
+  /// リモート側アドレス。TcpListener の場合や取得できなかった場合は `None`。
+  pub remote_address: Option<SocketAddr>,
+}
+
+/// TcpListener が受け付ける接続の頻度を接続元の IP アドレスごとに制限する設定です。IP アドレスごとのトークン
+/// バケットを使用し、バケットが空になった接続元からの接続は拒否されます。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AcceptRateLimit {
+  /// 1 秒あたりに補充される、受け付けることのできる接続の数。
+  pub per_second: f64,
+  /// 連続して受け付けることのできる接続の最大数 (バケットの容量)。
+  pub burst: u32,
+}
+
+/// 接続元の IP アドレスごとのトークンバケットによって、受け付ける接続の頻度を制限します。
+struct AcceptRateLimiter {
+  limit: AcceptRateLimit,
+  /// IP アドレスごとの残りのトークン数と、最後に補充した時刻。
+  buckets: HashMap<IpAddr, (f64, Instant)>,
+}
+
+impl AcceptRateLimiter {
+  /// トークンを補充しきったバケットの破棄を検討する、追跡している IP アドレスの数です。
+  const PRUNE_THRESHOLD: usize = 1024;
+
+  fn new(limit: AcceptRateLimit) -> AcceptRateLimiter {
+    AcceptRateLimiter { limit, buckets: HashMap::new() }
+  }
+
+  /// 指定された IP アドレスからの接続を受け付けることができる場合はトークンを消費して true を返します。
+  fn try_acquire(&mut self, ip: IpAddr, now: Instant) -> bool {
+    let AcceptRateLimit { per_second, burst } = self.limit;
+    if self.buckets.len() >= Self::PRUNE_THRESHOLD {
+      self.buckets.retain(|_, (tokens, updated)| {
+        *tokens + now.saturating_duration_since(*updated).as_secs_f64() * per_second < burst as f64
+      });
+    }
+    let (tokens, updated) = self.buckets.entry(ip).or_insert((burst as f64, now));
+    let elapsed = now.saturating_duration_since(*updated).as_secs_f64();
+    *tokens = (*tokens + elapsed * per_second).min(burst as f64);
+    *updated = now;
+    if *tokens >= 1.0 {
+      *tokens -= 1.0;
+      true
+    } else {
+      false
+    }
+  }
+}
+
+/// ソケットに発生したイベントの種類です。mio の `Event` から構築します。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Readiness {
+  readable: bool,
+  writable: bool,
+  error: bool,
+  read_closed: bool,
+  write_closed: bool,
+}
+
+impl From<&Event> for Readiness {
+  fn from(event: &Event) -> Self {
+    Readiness {
+      readable: event.is_readable(),
+      writable: event.is_writable(),
+      error: event.is_error(),
+      read_closed: event.is_read_closed(),
+      write_closed: event.is_write_closed(),
+    }
+  }
+}
+
+/// `ReadMode::Raw` の Listener に渡す Read です。長さ 0 の読み込みによって EOF を検出します。
+struct EofDetector<'a> {
+  inner: &'a mut TcpStream,
+  eof: bool,
+}
+
+impl Read for EofDetector<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let len = self.inner.read(buf)?;
+    if len == 0 && !buf.is_empty() {
+      self.eof = true;
+    }
+    Ok(len)
+  }
+}
+
+/// ログに出力するソケットアドレスです。取得できなかったアドレスは `-` と表記します。
+struct LogAddress(Option<SocketAddr>);
+
+impl std::fmt::Display for LogAddress {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self.0 {
+      Some(address) => address.fmt(f),
+      None => f.write_str("-"),
+    }
+  }
+}
+
+/// イベントループを実行するスレッドのデフォルトの名前です。
+pub const DEFAULT_THREAD_NAME: &str = "bumblebees-dispatcher";
+
+/// `ReadMode::Buffered` の読み込みで使用するバッファのデフォルトのサイズです。
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// 連続してこの回数の poll がイベントバッファを使い切った場合に、イベントバッファを拡張します。
+pub const EVENT_BUFFER_GROWTH_THRESHOLD: usize = 3;
+
+/// 読み込みを一時停止している TcpStream がある間に、Listener の `on_reads_paused()` を呼び出す最大の間隔です。
+pub const PAUSED_READS_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// `Dispatcher::send()` が受け付ける、ソケットごとに書き込みきれていないデータ量のデフォルトの上限です。
+pub const DEFAULT_WRITE_HIGH_WATER_MARK: usize = 1024 * 1024;
+
+/// イベントループが一度の poll でブロックするデフォルトの最大時間です。
+pub const DEFAULT_MAX_POLL_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// `Dispatcher::shutdown_with_reason()` が終了を通知する Close をすべてのピアに書き込むまで待機する最大時間です。
+pub const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// ディスパッチャーを起動するための設定です。必須の値を `new()` に指定し、それ以外の値は `with_*()` で変更します。
+/// 変更しなかった値には `Dispatcher` の各 `set_*()` に記載されているデフォルトが使用されます。
+#[derive(Debug, Clone, PartialEq)]
+pub struct DispatcherConfig {
+  event_buffer_size: usize,
+  task_queue_size: usize,
+  thread_name: String,
+  max_event_buffer_size: Option<usize>,
+  max_poll_timeout: Duration,
+  idle_timeout: Option<Duration>,
+  max_connections: Option<usize>,
+  accept_rate_limit: Option<AcceptRateLimit>,
+  write_high_water_mark: Option<usize>,
+  read_buffer_size: usize,
+  read_budget: Option<usize>,
+}
+
+impl DispatcherConfig {
+  /// 一度の poll で読み込むイベントの最大数と、イベントループでの実行を待機できるタスクの最大数を指定して構築します。
+  pub fn new(event_buffer_size: usize, task_queue_size: usize) -> DispatcherConfig {
+    DispatcherConfig {
+      event_buffer_size,
+      task_queue_size,
+      thread_name: DEFAULT_THREAD_NAME.to_string(),
+      max_event_buffer_size: None,
+      max_poll_timeout: DEFAULT_MAX_POLL_TIMEOUT,
+      idle_timeout: None,
+      max_connections: None,
+      accept_rate_limit: None,
+      write_high_water_mark: Some(DEFAULT_WRITE_HIGH_WATER_MARK),
+      read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+      read_budget: None,
+    }
+  }
+
+  /// イベントループを実行するスレッドの名前を設定します。
+  pub fn with_thread_name(mut self, thread_name: &str) -> Self {
+    self.thread_name = thread_name.to_string();
+    self
+  }
+
+  /// `Dispatcher::set_max_event_buffer_size()` を参照。
+  pub fn with_max_event_buffer_size(mut self, size: usize) -> Self {
+    self.max_event_buffer_size = Some(size);
+    self
+  }
+
+  /// `Dispatcher::set_max_poll_timeout()` を参照。
+  pub fn with_max_poll_timeout(mut self, timeout: Duration) -> Self {
+    self.max_poll_timeout = timeout;
+    self
+  }
+
+  /// `Dispatcher::set_idle_timeout()` を参照。
+  pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+    self.idle_timeout = timeout;
+    self
+  }
+
+  /// `Dispatcher::set_max_connections()` を参照。
+  pub fn with_max_connections(mut self, max_connections: Option<usize>) -> Self {
+    self.max_connections = max_connections;
+    self
+  }
+
+  /// `Dispatcher::set_accept_rate_limit()` を参照。
+  pub fn with_accept_rate_limit(mut self, limit: Option<AcceptRateLimit>) -> Self {
+    self.accept_rate_limit = limit;
+    self
+  }
+
+  /// `Dispatcher::set_write_high_water_mark()` を参照。
+  pub fn with_write_high_water_mark(mut self, limit: Option<usize>) -> Self {
+    self.write_high_water_mark = limit;
+    self
+  }
+
+  /// `Dispatcher::set_read_buffer_size()` を参照。
+  pub fn with_read_buffer_size(mut self, size: usize) -> Self {
+    self.read_buffer_size = size;
+    self
+  }
+
+  /// `Dispatcher::set_read_budget()` を参照。
+  pub fn with_read_budget(mut self, budget: Option<usize>) -> Self {
+    self.read_budget = budget;
+    self
+  }
+}
+
 pub struct Dispatcher {
-  sender: Sender<Task<Result<SocketId>>>,
+  sender: SyncSender<ErasedTask>,
+  task_queue_size: usize,
   waker: mio::Waker,
+  /// イベントループのスレッドが実行中の場合に true。スレッドが終了するとパニックによる場合も含めて false となる。
+  running: Arc<AtomicBool>,
+  /// 登録タスクを投入したがまだイベントループに登録されていない TcpStream の数。イベントループと共有する。
+  registering: Arc<AtomicUsize>,
+}
+
+/// イベントループのスレッドが終了したときに、パニックによる終了であっても実行中のフラグを下ろすガードです。
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+  fn drop(&mut self) {
+    self.0.store(false, Ordering::SeqCst);
+  }
 }
 
 impl Dispatcher {
@@ -104,217 +671,1229 @@ impl Dispatcher {
   ///
   /// # Arguments
   /// * `event_buffer_size` - 一度の poll で読み込むイベントの最大数。
+  /// * `task_queue_size` - イベントループでの実行を待機できるタスクの最大数。
   ///
-  pub fn new(event_buffer_size: usize) -> Result<Dispatcher> {
-    let (sender, receiver) = channel();
+  pub fn new(event_buffer_size: usize, task_queue_size: usize) -> Result<Dispatcher> {
+    Dispatcher::with_config(DispatcherConfig::new(event_buffer_size, task_queue_size))
+  }
+
+  /// イベントループを実行するスレッドの名前を指定して新しいディスパッチャーを起動します。1 つのプロセスで複数の
+  /// ディスパッチャーを起動する場合、スレッドダンプやデバッガでそれぞれを区別するために使用します。
+  pub fn with_thread_name(
+    event_buffer_size: usize,
+    task_queue_size: usize,
+    thread_name: &str,
+  ) -> Result<Dispatcher> {
+    let config = DispatcherConfig::new(event_buffer_size, task_queue_size);
+    Dispatcher::with_config(config.with_thread_name(thread_name))
+  }
+
+  /// 指定された設定で新しいディスパッチャーを起動します。
+  pub fn with_config(config: DispatcherConfig) -> Result<Dispatcher> {
+    let task_queue_size = config.task_queue_size;
+    let (sender, receiver) = sync_channel(task_queue_size);
     let poll = Poll::new()?;
     let waker = mio::Waker::new(poll.registry(), Token(0))?;
-    let mut polling_loop = PollingLoop::new(poll, event_buffer_size);
-    spawn(move || polling_loop.start(receiver));
-    Ok(Dispatcher { sender, waker })
+    let registering = Arc::new(AtomicUsize::new(0));
+    let mut polling_loop = PollingLoop::new(poll, &config, registering.clone());
+    let running = Arc::new(AtomicBool::new(true));
+    let guard = RunningGuard(running.clone());
+    Builder::new().name(config.thread_name).spawn(move || {
+      let _guard = guard;
+      let result = polling_loop.start(receiver);
+      if let Err(err) = &result {
+        log::error!("dispatcher stopped unexpectedly: {}", err);
+      }
+      result
+    })?;
+    Ok(Dispatcher { sender, task_queue_size, waker, running, registering })
+  }
+
+  /// イベントループのスレッドが実行中かを判定します。`stop()` による停止のほか、イベントループがパニックなどで異常
+  /// 終了した場合も false を返します。停止後に投入したタスクは `Error::DispatcherStopped` で完了します。
+  pub fn is_running(&self) -> bool {
+    self.running.load(Ordering::SeqCst)
+  }
+
+  /// 指定された ID のソケットを
+  pub fn dispose(&self, id: SocketId) -> TaskFuture<Result<SocketId>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.close(id);
+      Ok(id)
+    }))
+  }
+
+  /// イベントループを停止します。登録されているすべてのソケットはクローズされます。
+  ///
+  /// 停止時にタスクキューに残っていたタスクは `Error::DispatcherShutdown` で、停止後にこのディスパッチャーに
+  /// 投入されたタスクは `Error::DispatcherStopped` で完了します。
+  pub fn stop(&self) -> TaskFuture<Result<()>> {
+    log::debug!("stopping dispatcher...");
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.stopped = true;
+      Ok(())
+    }))
+  }
+
+  /// 登録されているすべての TcpStream の Listener に指定された終了理由を `on_shutdown()` で通知し、Listener が
+  /// 返したデータをピアに書き込んでからイベントループを停止します。メッセージを送受信している接続では終了理由の
+  /// `Control::Close` を送信することで、ピアは接続が突然切断されたのではなく、意図的に終了したことを知ることが
+  /// できます。
+  ///
+  /// 終了を通知するデータは他の送信済みのデータの後に書き込まれます。書き込みきれなかったデータは
+  /// `SHUTDOWN_FLUSH_TIMEOUT` まで書き込み可能になったソケットから順に書き込みを続け、その後は `stop()` と同様に
+  /// すべてのソケットをクローズします。
+  pub fn shutdown_with_reason(&self, reason_code: u16, reason: Vec<u8>) -> TaskFuture<Result<()>> {
+    log::debug!("shutting down dispatcher: reason_code={}", reason_code);
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      let ids = polling.sockets.ids().into_iter().filter(|id| polling.sockets.is_stream(*id));
+      for id in ids.collect::<Vec<_>>() {
+        polling.with_stream(id, |polling, stream, listener| {
+          if let Some(data) = listener.on_shutdown(reason_code, &reason) {
+            let action = DispatcherAction::Write(data);
+            polling.perform(id, stream, action, &mut |err| listener.on_error(err));
+          }
+        });
+      }
+      polling.flush_all(Instant::now() + SHUTDOWN_FLUSH_TIMEOUT);
+      polling.stopped = true;
+      Ok(())
+    }))
+  }
+
+  /// `DispatcherAction::PauseReads` によって読み込みを一時停止している TcpStream の読み込みを再開します。
+  /// TcpListener の場合は接続の受け付けを再開します。
+  pub fn resume_reads(&self, id: SocketId) -> TaskFuture<Result<()>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      if let Some(socket) = polling.sockets.get(id) {
+        let mut socket = socket.lock()?;
+        let action = DispatcherAction::ResumeReads;
+        match socket.deref_mut() {
+          Socket::Stream(stream, listener) => {
+            polling.perform(id, stream, action, &mut |err| listener.on_error(err));
+          }
+          Socket::Listener(tcp_listener, listener) => {
+            polling.perform(id, tcp_listener, action, &mut |err| listener.on_error(err));
+          }
+          Socket::Waker | Socket::Disposed => (),
+        }
+        if !polling.sockets.contains(id) {
+          socket.dispose();
+        }
+      }
+      Ok(())
+    }))
+  }
+
+  /// 登録済みの TcpStream のイベントを通知する Listener を置き換え、それまでの Listener を返します。これ以降に
+  /// 発生したイベントは新しい Listener に通知されます。ハンドシェイクの処理からセッションの処理に移行するときのように、
+  /// 接続の状態に応じて処理を切り替えるために使用します。指定された ID の TcpStream が登録されていない場合は
+  /// `ErrorKind::NotFound` のエラーとなります。
+  pub fn replace_listener(
+    &self,
+    id: SocketId,
+    listener: Box<dyn TcpStreamListener>,
+  ) -> TaskFuture<Result<Box<dyn TcpStreamListener>>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      if let Some(socket) = polling.sockets.get(id) {
+        if let Socket::Stream(_, current) = socket.lock()?.deref_mut() {
+          return Ok(std::mem::replace(current, listener));
+        }
+      }
+      let message = format!("TcpStream #{} is not registered", id);
+      Err(From::from(std::io::Error::new(std::io::ErrorKind::NotFound, message)))
+    }))
+  }
+
+  /// 登録済みの TcpStream の登録を解除し、ソケットとその Listener を返します。返された TcpStream と Listener を
+  /// 別のディスパッチャーに `register()` することで、接続を受け付けたディスパッチャーから処理を担当するディスパッチャー
+  /// へ接続を移すことができます。ソケットはクローズされず、Listener の `on_disposed()` も呼び出されません。
+  ///
+  /// `send()` や `DispatcherAction::Write` で書き込みきれていないデータを保持している場合、そのデータを引き継ぐことは
+  /// できないため `ErrorKind::WouldBlock` のエラーとなります。`send()` の完了を待ってから改めて呼び出してください。
+  /// 指定された ID の TcpStream が登録されていない場合は `ErrorKind::NotFound` のエラーとなります。
+  pub fn hand_off(
+    &self,
+    id: SocketId,
+  ) -> TaskFuture<Result<(TcpStream, Box<dyn TcpStreamListener>)>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      let not_found = || {
+        let message = format!("TcpStream #{} is not registered", id);
+        Err(From::from(std::io::Error::new(std::io::ErrorKind::NotFound, message)))
+      };
+      let socket = match polling.sockets.get(id) {
+        Some(socket) => socket,
+        None => return not_found(),
+      };
+      let mut socket = lock_socket(id, &socket)?;
+      if polling.sockets.outbounds.get(&id).map(|data| !data.is_empty()).unwrap_or(false) {
+        let message = format!("TcpStream #{} has unwritten data", id);
+        return Err(From::from(std::io::Error::new(std::io::ErrorKind::WouldBlock, message)));
+      }
+      match std::mem::replace(socket.deref_mut(), Socket::Disposed) {
+        Socket::Stream(mut stream, listener) => {
+          let registered = polling.sockets.interest(id).is_some();
+          polling.sockets.remove(id);
+          if registered {
+            polling.poll.registry().deregister(&mut stream)?;
+          }
+          log::debug!("socket handed off: {}", id);
+          Ok((stream, listener))
+        }
+        other => {
+          *socket = other;
+          not_found()
+        }
+      }
+    }))
+  }
+
+  /// 指定された TcpStream にデータを送信します。ソケットがブロックして書き込みきれなかった残りは
+  /// `DispatcherAction::Write` と同様にディスパッチャーが保持し、書き込み可能になった時点で書き込みます。
+  ///
+  /// 返値の Future は、送信したデータがすべてカーネルに書き込まれた時点で完了します。リクエスト/レスポンス型の処理で
+  /// 送信の完了を待ってから接続をクローズするために使用します。書き込みが完了する前にソケットが破棄された場合は
+  /// `Error::SocketDisposed` で完了します。データを受け付けたことだけを確認する場合は `SendFuture::accepted()` を
+  /// 使用します。
+  ///
+  /// 保持しているデータがすでに書き込みの high-water mark に達している場合、データは受け付けられず
+  /// `Error::SendBufferFull` で完了します。読み込みの遅いピアによって送信側のメモリが際限なく消費されることを
+  /// 防ぎます。指定された ID の TcpStream が登録されていない場合は `ErrorKind::NotFound` のエラーとなります。
+  pub fn send(&self, id: SocketId, data: Vec<u8>) -> SendFuture {
+    let state = Arc::new(Mutex::new(TaskState::new(None)));
+    let flushed = TaskFuture::new(state.clone());
+    let completion = TaskCompletion { state: Some(state) };
+    let accepted = self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      if let Some(socket) = polling.sockets.get(id) {
+        let mut socket = lock_socket(id, &socket)?;
+        if let Socket::Stream(stream, listener) = socket.deref_mut() {
+          let buffered = polling.sockets.outbounds.get(&id).map(Vec::len).unwrap_or(0);
+          if let Some(limit) = polling.write_high_water_mark {
+            if buffered >= limit {
+              return Err(Error::SendBufferFull { id, limit });
+            }
+          }
+          let target = polling.sockets.written(id) + (buffered + data.len()) as u64;
+          let action = DispatcherAction::Write(data);
+          polling.perform(id, stream, action, &mut |err| listener.on_error(err));
+          polling.sockets.wait_flush(id, target, completion);
+          if !polling.sockets.contains(id) {
+            socket.dispose();
+          }
+          return Ok(());
+        }
+      }
+      let message = format!("TcpStream #{} is not registered", id);
+      Err(From::from(std::io::Error::new(std::io::ErrorKind::NotFound, message)))
+    }));
+    SendFuture { accepted: Some(accepted), flushed }
+  }
+
+  /// `send()` が受け付ける、ソケットごとに書き込みきれていないデータ量の上限を設定します。保持しているデータが
+  /// この値に達している間の `send()` は `Error::SendBufferFull` となります。`None` を指定した場合は制限しません。
+  /// デフォルトは `DEFAULT_WRITE_HIGH_WATER_MARK` です。
+  pub fn set_write_high_water_mark(&self, limit: Option<usize>) -> TaskFuture<Result<()>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.write_high_water_mark = limit;
+      Ok(())
+    }))
+  }
+
+  /// 同時に登録できる TcpStream の最大数を設定します。上限に達している間に TcpListener が受け付けた接続は即座に
+  /// クローズされ、Listener の `on_rejected()` が呼び出されます。`None` を指定した場合は制限しません (デフォルト)。
+  pub fn set_max_connections(&self, max_connections: Option<usize>) -> TaskFuture<Result<()>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.max_connections = max_connections;
+      Ok(())
+    }))
+  }
+
+  /// TcpListener が受け付ける接続の頻度を接続元の IP アドレスごとに制限します。制限を超えた接続は即座にクローズ
+  /// され、Listener の `on_rejected()` が呼び出されます。短い間隔で接続を繰り返すクライアントが接続数の上限を使い
+  /// 切ることを防ぎます。`None` を指定した場合は制限しません (デフォルト)。
+  pub fn set_accept_rate_limit(&self, limit: Option<AcceptRateLimit>) -> TaskFuture<Result<()>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.accept_rate_limiter = limit.map(AcceptRateLimiter::new);
+      Ok(())
+    }))
+  }
+
+  /// 読み込みまたは書き込みイベントが指定された時間発生していない TcpStream を破棄するように設定します。`None` を
+  /// 指定した場合はアイドル状態のソケットを破棄しません (デフォルト)。
+  ///
+  /// アイドルタイムアウトを検出したソケットは、破棄される前に `ErrorKind::TimedOut` のエラーで Listener の
+  /// `on_error()` が呼び出されます。
+  pub fn set_idle_timeout(&self, timeout: Option<Duration>) -> TaskFuture<Result<()>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.idle_timeout = timeout;
+      Ok(())
+    }))
+  }
+
+  /// イベントループが一度の poll でブロックする最大時間を設定します。デフォルトは `DEFAULT_MAX_POLL_TIMEOUT` です。
+  ///
+  /// ソケットのイベントやタスクの投入がなくてもこの間隔でイベントループは停止の指示を確認するため、Waker による
+  /// 起床が失われた場合でもディスパッチャーを停止することができます。
+  pub fn set_max_poll_timeout(&self, timeout: Duration) -> TaskFuture<Result<()>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.max_poll_timeout = timeout;
+      Ok(())
+    }))
+  }
+
+  /// イベントバッファを拡張できる上限を設定します。デフォルトは `Dispatcher::new()` に指定した
+  /// `event_buffer_size` であり、拡張は行いません。
+  ///
+  /// 上限がイベントバッファの現在のサイズより大きい場合、`EVENT_BUFFER_GROWTH_THRESHOLD` 回連続して poll がイベント
+  /// バッファを使い切るたびにサイズを 2 倍 (上限まで) に拡張します。現在のサイズより小さい上限を指定した場合は即座に
+  /// 上限まで縮小します。
+  pub fn set_max_event_buffer_size(&self, size: usize) -> TaskFuture<Result<()>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.max_event_buffer_size = std::cmp::max(size, 1);
+      polling.event_buffer_size =
+        std::cmp::min(polling.event_buffer_size, polling.max_event_buffer_size);
+      Ok(())
+    }))
+  }
+
+  /// `ReadMode::Buffered` の TcpStream から一度に読み込むバッファのサイズを設定します。デフォルトは
+  /// `DEFAULT_READ_BUFFER_SIZE` です。
+  pub fn set_read_buffer_size(&self, size: usize) -> TaskFuture<Result<()>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      polling.read_buffer = vec![0u8; std::cmp::max(size, 1)];
+      Ok(())
+    }))
   }
 
-  /// 指定された ID のソケットを
-  pub fn dispose(&self, id: SocketId) -> Box<dyn Future<Output=Result<SocketId>>> {
+  /// 1 回の読み込み可能イベントで `ReadMode::Buffered` の TcpStream から読み込む最大バイト数を設定します。上限に
+  /// 達したソケットは登録されたまま次の poll に処理を譲り、イベントを待たずに次の周期で読み込みを続けます。大量の
+  /// データを送信し続けるピアがイベントループを占有し、他のソケットの処理を遅らせることを防ぎます。`None` を指定
+  /// した場合は `WouldBlock` となるまで読み込みます (デフォルト)。
+  ///
+  /// `ReadMode::Raw` の Listener はソケットから直接読み込むため、この上限は適用されません。
+  pub fn set_read_budget(&self, budget: Option<usize>) -> TaskFuture<Result<()>> {
     self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
-      polling.close(id);
-      Ok(id)
+      polling.read_budget = budget.map(|budget| std::cmp::max(budget, 1));
+      Ok(())
     }))
   }
 
-  fn run_in_event_loop<E>(&self, exec: Box<E>) -> Box<dyn Future<Output=Result<SocketId>>>
-    where
-      E: (FnOnce(&mut PollingLoop) -> Result<SocketId>) + Send + 'static,
+  /// イベントループ内で集計している稼働状況のスナップショットを参照します。
+  pub fn metrics(&self) -> TaskFuture<Result<DispatcherMetrics>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| Ok(polling.metrics())))
+  }
+
+  /// 登録されているソケットの一覧を ID の順に参照します。管理画面での監視や接続リークの調査に使用できます。
+  pub fn list_sockets(&self) -> TaskFuture<Result<Vec<SocketInfo>>> {
+    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| polling.list_sockets()))
+  }
+
+  /// 指定された処理をイベントループ内で実行するタスクとして投入します。
+  ///
+  /// イベントループで実行を待機しているタスクが `task_queue_size` に達している場合、タスクは投入されず返値の
+  /// Future は即座に `Error::TaskQueueOverflow` で完了します。イベントループがすでに停止している場合は
+  /// `Error::DispatcherStopped` で完了します。
+  fn run_in_event_loop<E, R>(&self, exec: Box<E>) -> TaskFuture<Result<R>>
+  where
+    E: (FnOnce(&mut PollingLoop) -> Result<R>) + Send + 'static,
+    R: Send + 'static,
   {
-    let task = Task::new(exec);
-    let future = TaskFuture { state: task.state.clone() };
-    self.sender.send(task).unwrap();
-    self.waker.wake().unwrap();
-    Box::new(future)
+    self.submit(Task::new(exec))
+  }
+
+  /// 構築済みのタスクを `run_in_event_loop()` と同様に投入します。
+  fn submit<R: Send + 'static>(&self, task: Task<Result<R>>) -> TaskFuture<Result<R>> {
+    let future = TaskFuture::new(task.state.clone());
+    if !self.is_running() {
+      future.state.lock().unwrap().complete(Err(Error::DispatcherStopped));
+      return future;
+    }
+    match self.sender.try_send(task.into_erased()) {
+      Ok(()) => self.waker.wake().unwrap(),
+      Err(TrySendError::Full(task)) => {
+        // 実行されないタスクが破棄される前に Future の結果を設定する
+        let capacity = self.task_queue_size;
+        future.state.lock().unwrap().complete(Err(Error::TaskQueueOverflow { capacity }));
+        drop(task);
+      }
+      Err(TrySendError::Disconnected(_)) => (),
+    }
+    future
+  }
+
+  /// 構築済みのタスクを投入します。タスクキューに空きがない場合は Future を返さずに即座に `Error::WouldBlock` を
+  /// 返します。
+  fn try_submit<R: Send + 'static>(&self, task: Task<Result<R>>) -> Result<TaskFuture<Result<R>>> {
+    if !self.is_running() {
+      return Err(Error::DispatcherStopped);
+    }
+    let future = TaskFuture::new(task.state.clone());
+    match self.sender.try_send(task.into_erased()) {
+      Ok(()) => {
+        self.waker.wake().unwrap();
+        Ok(future)
+      }
+      Err(TrySendError::Full(_)) => Err(Error::WouldBlock),
+      Err(TrySendError::Disconnected(_)) => Err(Error::DispatcherStopped),
+    }
   }
 }
 
 impl Drop for Dispatcher {
   fn drop(&mut self) {
     log::debug!("stopping dispatcher...");
-    let _ = self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
-      polling.stopped = true;
-      Ok(0usize)
-    }));
+    // 停止タスクは失われてはならないため、タスクキューに空きができるまでブロックして投入する
+    let stop: ErasedTask = Box::new(move |polling: &mut PollingLoop| polling.stopped = true);
+    if self.sender.send(stop).is_ok() {
+      let _ = self.waker.wake();
+    }
   }
 }
 
-trait DispatcherRegister<S, L> {
-  fn register(&self, source: S, listener: L) -> Box<dyn Future<Output=Result<SocketId>>>;
+/// ソケットの登録は Future を実行前に破棄するとキャンセルされ、ソケットはどこにも登録されずにクローズされます。
+/// 破棄が登録と競合した場合は登録済みのソケットがクローズされます。
+pub trait DispatcherRegister<S, L> {
+  fn register(&self, source: S, listener: L) -> TaskFuture<Result<SocketId>>;
+
+  /// `register()` と同様にソケットを登録するタスクを投入します。タスクキューに空きがない場合はタスクを投入せずに
+  /// 即座に `Error::WouldBlock` を返します。イベントループの混雑時に待機せず負荷を制限したい呼び出し元で使用します。
+  fn try_register(&self, source: S, listener: L) -> Result<TaskFuture<Result<SocketId>>>;
 }
 
 impl DispatcherRegister<TcpListener, Box<dyn TcpListenerListener>> for Dispatcher {
   fn register(
     &self,
-    mut listener: TcpListener,
+    listener: TcpListener,
     event_listener: Box<dyn TcpListenerListener>,
-  ) -> Box<dyn Future<Output=Result<SocketId>>> {
-    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
-      let id = polling.sockets.available_id()?;
-      polling.poll.registry().register(&mut listener, Token(id), Interest::READABLE)?;
-      polling.sockets.set(id, Socket::Listener(listener, event_listener));
-      Ok(id)
-    }))
+  ) -> TaskFuture<Result<SocketId>> {
+    self.submit(Task::cancellable(register_listener(listener, event_listener), close_registered))
+  }
+
+  fn try_register(
+    &self,
+    listener: TcpListener,
+    event_listener: Box<dyn TcpListenerListener>,
+  ) -> Result<TaskFuture<Result<SocketId>>> {
+    self
+      .try_submit(Task::cancellable(register_listener(listener, event_listener), close_registered))
   }
 }
 
 impl DispatcherRegister<TcpStream, Box<dyn TcpStreamListener>> for Dispatcher {
   fn register(
     &self,
-    mut stream: TcpStream,
+    stream: TcpStream,
     listener: Box<dyn TcpStreamListener>,
-  ) -> Box<dyn Future<Output=Result<SocketId>>> {
-    self.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
-      let id = polling.sockets.available_id()?;
-      polling.poll.registry().register(
-        &mut stream,
-        Token(id),
-        Interest::READABLE | Interest::WRITABLE,
-      )?;
-      polling.sockets.set(id, Socket::Stream(stream, listener));
-      Ok(id)
-    }))
+  ) -> TaskFuture<Result<SocketId>> {
+    let registering = Registering::new(&self.registering);
+    self.submit(Task::cancellable(register_stream(stream, listener, registering), close_registered))
+  }
+
+  fn try_register(
+    &self,
+    stream: TcpStream,
+    listener: Box<dyn TcpStreamListener>,
+  ) -> Result<TaskFuture<Result<SocketId>>> {
+    let registering = Registering::new(&self.registering);
+    self.try_submit(Task::cancellable(
+      register_stream(stream, listener, registering),
+      close_registered,
+    ))
+  }
+}
+
+/// キャンセルされた登録タスクが登録したソケットをクローズします。
+fn close_registered(polling: &mut PollingLoop, result: Result<SocketId>) {
+  if let Ok(id) = result {
+    polling.close(id);
+  }
+}
+
+/// TcpListener をイベントループに登録するタスクを構築します。
+fn register_listener(
+  mut listener: TcpListener,
+  event_listener: Box<dyn TcpListenerListener>,
+) -> Box<impl FnOnce(&mut PollingLoop) -> Result<SocketId> + Send + 'static> {
+  Box::new(move |polling: &mut PollingLoop| {
+    let id = polling.sockets.available_id()?;
+    let interest = Interest::READABLE;
+    polling.poll.registry().register(&mut listener, Token(id), interest)?;
+    polling.sockets.set(id, Socket::Listener(listener, event_listener), interest);
+    Ok(id)
+  })
+}
+
+/// 登録タスクが投入されてから実行または破棄されるまで、その TcpStream を登録中として数えるガードです。TcpListener
+/// が受け付けた接続は Listener が登録タスクを投入した後も登録されるまで `stream_count()` に含まれないため、
+/// `max_connections` の判定ではこの数を加えます。
+struct Registering(Arc<AtomicUsize>);
+
+impl Registering {
+  fn new(counter: &Arc<AtomicUsize>) -> Registering {
+    counter.fetch_add(1, Ordering::SeqCst);
+    Registering(counter.clone())
   }
 }
 
+impl Drop for Registering {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// TcpStream をイベントループに登録するタスクを構築します。タスクが実行されずに破棄された場合も `registering` は
+/// タスクとともに破棄されます。
+fn register_stream(
+  mut stream: TcpStream,
+  listener: Box<dyn TcpStreamListener>,
+  registering: Registering,
+) -> Box<impl FnOnce(&mut PollingLoop) -> Result<SocketId> + Send + 'static> {
+  Box::new(move |polling: &mut PollingLoop| {
+    // 登録を終えて stream_count() に含まれるまで登録中として数える
+    let _registering = registering;
+    let id = polling.sockets.available_id()?;
+    let interest = Interest::READABLE | Interest::WRITABLE;
+    polling.poll.registry().register(&mut stream, Token(id), interest)?;
+    polling.sockets.set(id, Socket::Stream(stream, listener), interest);
+    Ok(id)
+  })
+}
+
 struct PollingLoop {
   poll: Poll,
+  /// 一度の poll で読み込むイベントの現在の最大数。
   event_buffer_size: usize,
+  /// イベントバッファを拡張できる上限。
+  max_event_buffer_size: usize,
+  /// イベントバッファを使い切った poll が連続した回数。
+  full_polls: usize,
   sockets: SocketMap,
   stopped: bool,
+  idle_timeout: Option<Duration>,
+  max_connections: Option<usize>,
+  /// 登録タスクが投入され、まだ登録されていない TcpStream の数。
+  registering: Arc<AtomicUsize>,
+  accept_rate_limiter: Option<AcceptRateLimiter>,
+  /// `Dispatcher::send()` が受け付ける、書き込みきれていないデータ量の上限。
+  write_high_water_mark: Option<usize>,
+  /// 一度の poll() でブロックする最大時間。Waker が機能しない場合でも停止の指示を検出するための安全策です。
+  max_poll_timeout: Duration,
+  /// `ReadMode::Buffered` の TcpStream から読み込むためにすべてのソケットで共有するバッファ。
+  read_buffer: Vec<u8>,
+  /// 1 回の読み込み可能イベントで `ReadMode::Buffered` の TcpStream から読み込む最大バイト数。
+  read_budget: Option<usize>,
+  total_events_processed: u64,
+  total_tasks_run: u64,
 }
 
 impl PollingLoop {
-  fn new(poll: Poll, event_buffer_size: usize) -> PollingLoop {
+  fn new(poll: Poll, config: &DispatcherConfig, registering: Arc<AtomicUsize>) -> PollingLoop {
     let sockets = SocketMap::new();
-    PollingLoop { poll, event_buffer_size, sockets, stopped: false }
+    let max_event_buffer_size =
+      std::cmp::max(config.max_event_buffer_size.unwrap_or(config.event_buffer_size), 1);
+    PollingLoop {
+      poll,
+      event_buffer_size: std::cmp::min(config.event_buffer_size, max_event_buffer_size),
+      max_event_buffer_size,
+      full_polls: 0,
+      sockets,
+      stopped: false,
+      idle_timeout: config.idle_timeout,
+      max_connections: config.max_connections,
+      registering,
+      accept_rate_limiter: config.accept_rate_limit.map(AcceptRateLimiter::new),
+      write_high_water_mark: config.write_high_water_mark,
+      max_poll_timeout: config.max_poll_timeout,
+      read_buffer: vec![0u8; std::cmp::max(config.read_buffer_size, 1)],
+      read_budget: config.read_budget.map(|budget| std::cmp::max(budget, 1)),
+      total_events_processed: 0,
+      total_tasks_run: 0,
+    }
+  }
+
+  /// 一度の poll で読み込んだイベントの数から、負荷に応じて上限までイベントバッファを拡張します。新しいサイズは
+  /// 次の poll から適用されます。
+  fn adapt_event_buffer(&mut self, received: usize) {
+    if received < self.event_buffer_size {
+      self.full_polls = 0;
+      return;
+    }
+    self.full_polls += 1;
+    if self.full_polls >= EVENT_BUFFER_GROWTH_THRESHOLD
+      && self.event_buffer_size < self.max_event_buffer_size
+    {
+      self.event_buffer_size =
+        std::cmp::min(self.event_buffer_size.saturating_mul(2), self.max_event_buffer_size);
+      self.full_polls = 0;
+    }
+  }
+
+  /// 登録されているソケットの種類とアドレスを ID の順に列挙します。Waker と廃棄済みのソケットは含みません。
+  fn list_sockets(&self) -> Result<Vec<SocketInfo>> {
+    let mut ids = self.sockets.ids();
+    ids.sort_unstable();
+    let mut infos = Vec::with_capacity(ids.len());
+    for id in ids {
+      if let Some(socket) = self.sockets.get(id) {
+        let info = match socket.lock()?.deref_mut() {
+          Socket::Stream(stream, _) => SocketInfo {
+            id,
+            kind: SocketKind::Stream,
+            local_address: stream.local_addr().ok(),
+            remote_address: stream.peer_addr().ok(),
+          },
+          Socket::Listener(listener, _) => SocketInfo {
+            id,
+            kind: SocketKind::Listener,
+            local_address: listener.local_addr().ok(),
+            remote_address: None,
+          },
+          Socket::Waker | Socket::Disposed => continue,
+        };
+        infos.push(info);
+      }
+    }
+    Ok(infos)
+  }
+
+  /// 現在の稼働状況を集計します。
+  fn metrics(&self) -> DispatcherMetrics {
+    let listener_count = self
+      .sockets
+      .sockets
+      .values()
+      .filter(|socket| matches!(*socket.lock().unwrap(), Socket::Listener(_, _)))
+      .count();
+    DispatcherMetrics {
+      registered_sockets: self.sockets.sockets.len(),
+      total_events_processed: self.total_events_processed,
+      total_tasks_run: self.total_tasks_run,
+      listener_count,
+      event_buffer_size: self.event_buffer_size,
+    }
   }
 
   /// poll() のためのイベントループを開始します。イベントループスレッドの中で任意の処理を行う場合は receiver に対応
   /// する sender に実行するタスクを投入し、self.poll に登録済みの Waker.wake() でブロッキングを抜けます。
-  fn start<R>(&mut self, receiver: Receiver<Task<Result<R>>>) -> Result<()> {
+  fn start(&mut self, receiver: Receiver<ErasedTask>) -> Result<()> {
     let mut events = Events::with_capacity(self.event_buffer_size);
     while !self.stopped {
-      self.poll.poll(&mut events, None)?;
+      if events.capacity() != self.event_buffer_size {
+        log::debug!("event buffer size changed: {}", self.event_buffer_size);
+        events = Events::with_capacity(self.event_buffer_size);
+      }
+      let timeout = self.poll_timeout();
+      self.poll.poll(&mut events, Some(timeout))?;
+      self.adapt_event_buffer(events.iter().count());
 
       // イベントの発生したソケットを取得
       let event_sockets = events
         .iter()
-        .map(|e| self.sockets.get(e.token().0).map(|s| (e, s)))
-        .flatten()
-        .collect::<Vec<(&Event, Arc<Mutex<Socket>>)>>();
+        .filter_map(|e| self.sockets.get(e.token().0).map(|s| (e.token().0, Readiness::from(e), s)))
+        .collect::<Vec<(SocketId, Readiness, Arc<Mutex<Socket>>)>>();
 
       // イベントの発生したソケットの処理を実行
-      for (event, socket) in event_sockets.iter() {
-        match socket.lock()?.deref_mut() {
-          Socket::Stream(stream, listener) => {
-            log::info!("CLIENT[{}]", event.token().0);
-            self.on_tcp_stream(event, stream, listener);
-          }
-          Socket::Listener(listener, event_listener) => {
-            log::info!("SERVER[{}]", event.token().0);
-            self.on_tcp_listener(event, listener, event_listener);
-          }
-          Socket::Waker => {
-            log::info!("WAKER");
-          }
-        }
+      for (id, readiness, socket) in event_sockets.iter() {
+        self.dispatch(*id, *readiness, socket)?;
       }
 
+      self.continue_deferred_reads();
+      self.check_paused_reads();
+      self.dispose_idle_sockets();
       self.run_all_tasks(&receiver);
     }
 
-    self.cleanup();
+    self.cleanup(&receiver);
     log::info!("dispatcher stopped");
     Ok(())
   }
 
+  /// 指定されたソケットに発生したイベントを、ソケットの種類に応じて処理します。
+  fn dispatch(
+    &mut self,
+    id: SocketId,
+    readiness: Readiness,
+    socket: &Arc<Mutex<Socket>>,
+  ) -> Result<()> {
+    let mut socket = match lock_socket(id, socket) {
+      Ok(socket) => socket,
+      Err(Error::SocketDisposed { .. }) => {
+        // 同じ poll で先に処理したイベントによってクローズされた
+        log::debug!("ignoring the event for disposed socket: {}", id);
+        return Ok(());
+      }
+      Err(err) => return Err(err),
+    };
+    match socket.deref_mut() {
+      Socket::Stream(stream, listener) => {
+        if log::log_enabled!(log::Level::Info) {
+          let peer = LogAddress(stream.peer_addr().ok());
+          log::info!(
+            socket_id = id, kind:? = SocketKind::Stream, peer:% = peer;
+            "CLIENT[{}] {}", id, peer
+          );
+        }
+        self.total_events_processed += 1;
+        self.on_tcp_stream(id, readiness, stream, listener);
+      }
+      Socket::Listener(listener, event_listener) => {
+        if log::log_enabled!(log::Level::Info) {
+          let local = LogAddress(listener.local_addr().ok());
+          log::info!(
+            socket_id = id, kind:? = SocketKind::Listener, local:% = local;
+            "SERVER[{}] {}", id, local
+          );
+        }
+        self.total_events_processed += 1;
+        self.on_tcp_listener(id, readiness, listener, event_listener);
+      }
+      Socket::Waker => {
+        log::info!("WAKER");
+      }
+      Socket::Disposed => (),
+    }
+    // イベントの処理中に破棄されたソケットは、すでに取得されている参照から操作されないようにする
+    if id != 0 && !self.sockets.contains(id) {
+      socket.dispose();
+    }
+    Ok(())
+  }
+
   /// 指定された receiver に存在するすべてのタスクを実行します。
-  fn run_all_tasks<R>(&mut self, receiver: &Receiver<Task<Result<R>>>) {
-    for Task { executable, state } in receiver.iter() {
-      let result = executable(self);
-      let mut state = state.lock().unwrap();
-      state.result = Some(result);
-      if let Some(waker) = state.waker.take() {
-        waker.wake();
+  fn run_all_tasks(&mut self, receiver: &Receiver<ErasedTask>) {
+    for task in receiver.try_iter() {
+      task(self);
+      self.total_tasks_run += 1;
+      if self.stopped {
+        // 停止後のタスクは実行せずに破棄する
+        break;
+      }
+    }
+  }
+
+  /// 次にアイドルタイムアウトを判定するまでの時間を算出します。判定の必要がない場合は `None` を返します。
+  fn next_idle_check(&self) -> Option<Duration> {
+    let timeout = self.idle_timeout?;
+    let oldest = self.sockets.last_activity.values().min()?;
+    Some((*oldest + timeout).saturating_duration_since(Instant::now()))
+  }
+
+  /// 次の poll() でブロックする時間を算出します。アイドルタイムアウトの判定が不要な場合でも `max_poll_timeout`
+  /// を超えることはありません。
+  fn poll_timeout(&self) -> Duration {
+    let timeout = match self.next_idle_check() {
+      Some(timeout) => std::cmp::min(timeout, self.max_poll_timeout),
+      None => self.max_poll_timeout,
+    };
+    if !self.sockets.deferred_reads.is_empty() {
+      Duration::ZERO
+    } else if self.sockets.paused.is_empty() {
+      timeout
+    } else {
+      std::cmp::min(timeout, PAUSED_READS_CHECK_INTERVAL)
+    }
+  }
+
+  /// 読み込みの上限に達したため読み込みを中断した TcpStream から、ID の順に読み込みを続けます。
+  fn continue_deferred_reads(&mut self) {
+    let mut deferred =
+      std::mem::take(&mut self.sockets.deferred_reads).into_iter().collect::<Vec<_>>();
+    deferred.sort_unstable();
+    for id in deferred {
+      if let Some(socket) = self.sockets.get(id) {
+        if let Ok(mut socket) = lock_socket(id, &socket) {
+          let readable = self.sockets.interest(id).is_some_and(|interest| interest.is_readable());
+          if let Socket::Stream(stream, listener) = socket.deref_mut() {
+            if readable {
+              self.read_data(id, stream, listener);
+            }
+          }
+          if !self.sockets.contains(id) {
+            socket.dispose();
+          }
+        }
+      }
+    }
+  }
+
+  /// Listener の指示で読み込みを一時停止している TcpStream の Listener に、読み込みを再開するかを問い合わせます。
+  fn check_paused_reads(&mut self) {
+    let mut paused = self.sockets.paused.iter().copied().collect::<Vec<SocketId>>();
+    paused.sort_unstable();
+    for id in paused {
+      if let Some(socket) = self.sockets.get(id) {
+        if let Ok(mut socket) = lock_socket(id, &socket) {
+          if let Socket::Stream(stream, listener) = socket.deref_mut() {
+            let behaviour = listener.on_reads_paused();
+            self.perform(id, stream, behaviour, &mut |err| listener.on_error(err));
+          }
+          if !self.sockets.contains(id) {
+            socket.dispose();
+          }
+        }
+      }
+    }
+  }
+
+  /// アイドルタイムアウトに達した TcpStream を破棄します。
+  fn dispose_idle_sockets(&mut self) {
+    if let Some(timeout) = self.idle_timeout {
+      let now = Instant::now();
+      let expired = self
+        .sockets
+        .last_activity
+        .iter()
+        .filter(|(_, last)| **last + timeout <= now)
+        .map(|(id, _)| *id)
+        .collect::<Vec<SocketId>>();
+      for id in expired {
+        if let Some(socket) = self.sockets.get(id) {
+          if let Socket::Stream(_, listener) = socket.lock().unwrap().deref_mut() {
+            log::debug!("idle timeout: {}", id);
+            let message = format!("no activity for {:?}", timeout);
+            // 返値に関わらずソケットは破棄する
+            let _ = listener.on_error(std::io::Error::new(std::io::ErrorKind::TimedOut, message));
+          }
+        }
+        self.close(id);
       }
     }
   }
 
   /// 指定された ID のソケットを廃棄します。この操作により対応するソケットはクローズします。
   fn close(&mut self, id: SocketId) {
-    if let Some(socket) = self.sockets.sockets.remove(&id) {
+    let registered = self.sockets.interest(id).is_some();
+    if let Some(socket) = self.sockets.remove(id) {
       log::debug!("closing socket: {}", id);
-      match socket.lock().unwrap().deref_mut() {
-        Socket::Waker => (),
+      let mut socket = socket.lock().unwrap();
+      socket.notify_disposed();
+      match socket.deref_mut() {
+        Socket::Waker | Socket::Disposed => (),
+        _ if !registered => (),
         Socket::Stream(stream, _) => self.poll.registry().deregister(stream).unwrap(),
         Socket::Listener(listener, _) => self.poll.registry().deregister(listener).unwrap(),
       };
+      // 他に参照を保持している箇所があってもソケットはここでクローズされる
+      *socket = Socket::Disposed;
       log::debug!("socket closed: {}", id);
     }
   }
 
-  /// 登録されているすべてのソケットを廃棄します。この操作によりソケットはクローズされます。
-  fn cleanup(&mut self) {
-    for id in self.sockets.ids() {
+  /// 指定された ID の TcpStream とその Listener を参照して `f` を呼び出します。`f` の中でソケットが廃棄された場合は
+  /// 取得した参照からも操作できないようにします。TcpStream が登録されていない場合は何もしません。
+  fn with_stream<F>(&mut self, id: SocketId, mut f: F)
+  where
+    F: FnMut(&mut Self, &mut TcpStream, &mut Box<dyn TcpStreamListener>),
+  {
+    let socket = match self.sockets.get(id) {
+      Some(socket) => socket,
+      None => return,
+    };
+    let mut socket = match lock_socket(id, &socket) {
+      Ok(socket) => socket,
+      Err(err) => {
+        log::warn!("failed to lock socket {}: {}", id, err);
+        return;
+      }
+    };
+    if let Socket::Stream(stream, listener) = socket.deref_mut() {
+      f(self, stream, listener);
+    }
+    if !self.sockets.contains(id) {
+      socket.dispose();
+    }
+  }
+
+  /// 書き込みきれずに保持しているデータを、すべて書き込むか指定された期限に達するまで書き込みます。イベントループの
+  /// 停止直前に使用するため、他のイベントやタスクは処理せずに書き込み可能になったソケットの書き込みだけを行います。
+  fn flush_all(&mut self, deadline: Instant) {
+    let mut events = Events::with_capacity(self.event_buffer_size);
+    while !self.sockets.outbounds.is_empty() {
+      let now = Instant::now();
+      if now >= deadline {
+        log::warn!("{} sockets have unwritten data at shutdown", self.sockets.outbounds.len());
+        break;
+      }
+      match self.poll.poll(&mut events, Some(deadline - now)) {
+        Ok(()) => (),
+        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+        Err(err) => {
+          log::warn!("failed to poll the sockets with unwritten data at shutdown: {}", err);
+          break;
+        }
+      }
+      let ids = events
+        .iter()
+        .filter(|event| event.is_writable() || event.is_error() || event.is_write_closed())
+        .map(|event| event.token().0)
+        .filter(|id| self.sockets.outbounds.contains_key(id))
+        .collect::<Vec<_>>();
+      for id in ids {
+        self.with_stream(id, |polling, stream, listener| {
+          if let Err(err) = polling.flush_outbound(id, stream) {
+            let action = listener.on_error(err);
+            polling.perform(id, stream, action, &mut |err| listener.on_error(err));
+          }
+        });
+      }
+    }
+  }
+
+  /// 登録されているすべてのソケットを廃棄します。この操作によりソケットはクローズされます。また、タスクキューに
+  /// 残っているタスクを取り出し、実行せずにそれぞれの Future を `Error::DispatcherShutdown` で完了させます。
+  ///
+  /// 終了処理の途中で新しい接続を受け付けないように、先にすべての TcpListener をクローズしてから TcpStream を ID の
+  /// 順にクローズします。TcpListener のバックログに残っている接続は受け付けられずにリセットされます。
+  fn cleanup(&mut self, receiver: &Receiver<ErasedTask>) {
+    let mut ids = self.sockets.ids();
+    ids.sort_by_key(|id| (self.sockets.is_stream(*id), *id));
+    for id in ids {
       self.close(id);
     }
+    for task in receiver.try_iter() {
+      task(self);
+    }
+  }
+
+  /// Listener が指示した動作を実行します。ソケットが廃棄された場合は false を返します。
+  ///
+  /// 動作の実行に失敗した場合はそのエラーを `on_error` に通知し、返された動作を改めて実行します。それも失敗した
+  /// 場合は回復できないものとしてソケットを廃棄します。いずれの場合も他のソケットやイベントループには影響しません。
+  fn perform<S: OutboundSink>(
+    &mut self,
+    id: SocketId,
+    source: &mut S,
+    action: DispatcherAction,
+    on_error: &mut dyn FnMut(std::io::Error) -> DispatcherAction,
+  ) -> bool {
+    let err = match self.action(id, source, action) {
+      Ok(alive) => return alive,
+      Err(err) => err,
+    };
+    log::warn!("failed to change the registration of socket {}: {}", id, err);
+    match self.action(id, source, on_error(err)) {
+      Ok(alive) => alive,
+      Err(err) => {
+        log::error!("disposing socket {} that cannot be recovered: {}", id, err);
+        self.action(id, source, DispatcherAction::Dispose).unwrap_or(false)
+      }
+    }
   }
 
-  fn action<S: Source>(&mut self, id: SocketId, source: &mut S, action: DispatcherAction) {
+  /// Listener が指示した動作を実行します。ソケットが廃棄された場合は false を返します。Poll への登録の変更に失敗
+  /// した場合はエラーを返します。
+  ///
+  /// このメソッドは対象のソケットがロックされた状態で呼び出されるため、廃棄時に `close()` を使用せず直接
+  /// `source` の登録を解除します。
+  fn action<S: OutboundSink>(
+    &mut self,
+    id: SocketId,
+    source: &mut S,
+    action: DispatcherAction,
+  ) -> std::io::Result<bool> {
     match action {
-      DispatcherAction::Continue => (),
+      DispatcherAction::Continue => Ok(true),
       DispatcherAction::ChangeFlag(interest) => {
-        self.poll.registry().reregister(source, Token(id), interest).unwrap();
+        self.change_interest(id, source, Some(interest))?;
+        Ok(true)
+      }
+      DispatcherAction::Dispose => {
+        let registered = self.sockets.interest(id).is_some();
+        if self.sockets.remove(id).is_some() {
+          log::debug!("closing socket: {}", id);
+          if registered {
+            // 登録の解除に失敗してもソケットはマップから取り除かれており、破棄時にクローズされる
+            if let Err(err) = self.poll.registry().deregister(source) {
+              log::warn!("failed to deregister socket {}: {}", id, err);
+            }
+          }
+          log::debug!("socket closed: {}", id);
+        }
+        Ok(false)
+      }
+      DispatcherAction::PauseReads => {
+        let interest = self.sockets.interest(id).and_then(|i| i.remove(Interest::READABLE));
+        self.change_interest(id, source, interest)?;
+        self.sockets.pause(id);
+        Ok(true)
+      }
+      DispatcherAction::ResumeReads => {
+        let interest = match self.sockets.interest(id) {
+          Some(interest) => interest | Interest::READABLE,
+          None => Interest::READABLE,
+        };
+        self.change_interest(id, source, Some(interest))?;
+        self.sockets.resume(id);
+        Ok(true)
+      }
+      DispatcherAction::Write(data) => {
+        if let Some(outbound) = self.sockets.outbounds.get_mut(&id) {
+          outbound.extend_from_slice(&data);
+        } else if self.sockets.contains(id) {
+          self.sockets.outbounds.insert(id, data);
+        }
+        self.flush_outbound(id, source)?;
+        Ok(true)
       }
-      DispatcherAction::Dispose => self.close(id),
     }
   }
 
+  /// `DispatcherAction::Write` によって保持しているデータを、ソケットがブロックしない範囲で書き込みます。すべて
+  /// 書き込んだ場合は Interest から WRITABLE を外し、残りがある場合は WRITABLE を加えて次の書き込み可能イベントを
+  /// 待ちます。
+  fn flush_outbound<S: OutboundSink>(
+    &mut self,
+    id: SocketId,
+    source: &mut S,
+  ) -> std::io::Result<()> {
+    let mut outbound = match self.sockets.outbounds.remove(&id) {
+      Some(outbound) => outbound,
+      None => return Ok(()),
+    };
+    let mut written = 0;
+    let result = loop {
+      if written == outbound.len() {
+        break Ok(());
+      }
+      match source.write_outbound(&outbound[written..]) {
+        Ok(0) => break Err(std::io::Error::from(std::io::ErrorKind::WriteZero)),
+        Ok(len) => written += len,
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+        Err(err) => break Err(err),
+      }
+    };
+    outbound.drain(..written);
+    self.sockets.flushed(id, written);
+    let interest = self.sockets.interest(id);
+    if outbound.is_empty() {
+      self.change_interest(id, source, interest.and_then(|i| i.remove(Interest::WRITABLE)))?;
+    } else {
+      self.sockets.outbounds.insert(id, outbound);
+      let interest = match interest {
+        Some(interest) => interest | Interest::WRITABLE,
+        None => Interest::WRITABLE,
+      };
+      self.change_interest(id, source, Some(interest))?;
+    }
+    result
+  }
+
+  /// 指定されたソケットの Interest を変更します。`None` を指定した場合、ソケットはマップに残したまま Poll への
+  /// 登録のみを解除します (mio は空の Interest を表現できないため)。変更に失敗した場合、記録している Interest は
+  /// 変更されません。
+  fn change_interest<S: Source>(
+    &mut self,
+    id: SocketId,
+    source: &mut S,
+    interest: Option<Interest>,
+  ) -> std::io::Result<()> {
+    let registry = self.poll.registry();
+    match (self.sockets.interest(id), interest) {
+      (Some(_), Some(interest)) => registry.reregister(source, Token(id), interest)?,
+      (Some(_), None) => registry.deregister(source)?,
+      (None, Some(interest)) => registry.register(source, Token(id), interest)?,
+      (None, None) => (),
+    }
+    self.sockets.set_interest(id, interest);
+    Ok(())
+  }
+
+  /// TcpStream に発生したイベントを処理します。
+  ///
+  /// mio は実際には読み書きできない状態でも読み込み可能・書き込み可能イベントを通知することがあります (spurious
+  /// wakeup)。ソケットに対する入出力はすべて `WouldBlock` を「何もしない」として扱い、エラーや EOF とはみなしません。
+  /// また、同じ poll で先に処理したイベントによって監視を停止した方向のイベントは無視します。
   fn on_tcp_stream(
     &mut self,
-    event: &Event,
+    id: SocketId,
+    readiness: Readiness,
     stream: &mut TcpStream,
     listener: &mut Box<dyn TcpStreamListener>,
   ) {
+    if readiness.readable || readiness.writable {
+      self.sockets.touch(id);
+    }
+
     // 読み込み可能イベント
-    if event.is_readable() {
-      let behaviour = listener.on_ready_to_read(stream);
-      self.action(event.token().0, stream, behaviour);
+    if readiness.readable && self.sockets.interest(id).is_some_and(|i| i.is_readable()) {
+      let alive = match listener.read_mode() {
+        ReadMode::Raw => {
+          let mut reader = EofDetector { inner: stream, eof: false };
+          let behaviour = listener.on_ready_to_read(&mut reader);
+          let eof = reader.eof;
+          self.perform(id, stream, behaviour, &mut |err| listener.on_error(err))
+            && (!eof || self.notify_eof(id, stream, listener))
+        }
+        ReadMode::Buffered => self.read_data(id, stream, listener),
+      };
+      if !alive {
+        return;
+      }
     }
 
-    // 書き込み可能イベント
-    if event.is_writable() {
-      let behaviour = listener.on_ready_to_write(stream);
-      self.action(event.token().0, stream, behaviour);
+    // 書き込み可能イベント: 書き込みきれずに保持しているデータがあれば Listener より先に書き込む
+    if readiness.writable && self.sockets.interest(id).is_some_and(|i| i.is_writable()) {
+      let behaviour = if self.sockets.outbounds.contains_key(&id) {
+        DispatcherAction::Write(Vec::new())
+      } else {
+        listener.on_ready_to_write(stream)
+      };
+      if !self.perform(id, stream, behaviour, &mut |err| listener.on_error(err)) {
+        return;
+      }
     }
 
-    if event.is_error() {
+    if readiness.error {
       let behaviour = match stream.take_error() {
         Ok(Some(err)) => listener.on_error(err),
         Ok(None) => DispatcherAction::Continue,
         Err(err) => listener.on_error(err),
       };
-      self.action(event.token().0, stream, behaviour);
+      if !self.perform(id, stream, behaviour, &mut |err| listener.on_error(err)) {
+        return;
+      }
+    }
+
+    // 片方向のクローズ: 同じ方向について繰り返し通知しない
+    for (closed, half) in
+      [(readiness.read_closed, Half::Read), (readiness.write_closed, Half::Write)]
+    {
+      if closed && self.sockets.hang_up(id, half) {
+        let behaviour = listener.on_hangup(half);
+        if !self.perform(id, stream, behaviour, &mut |err| listener.on_error(err)) {
+          return;
+        }
+        // 読み込み方向がクローズされたソケットは長さ 0 の読み込みイベントを発生させ続けるため監視を停止する
+        if half == Half::Read {
+          let paused = self.sockets.is_paused(id);
+          let action = DispatcherAction::PauseReads;
+          if !self.perform(id, stream, action, &mut |err| listener.on_error(err)) {
+            return;
+          }
+          // Listener が停止したのでなければ再開することのない停止であるため Listener には問い合わせない
+          if !paused {
+            self.sockets.resume(id);
+          }
+        }
+      }
+    }
+    if self.sockets.is_hung_up(id) {
+      log::debug!("both directions of socket #{} have been closed", id);
+      self.perform(id, stream, DispatcherAction::Dispose, &mut |err| listener.on_error(err));
+    }
+  }
+
+  /// 共有の読み込みバッファを使用して、ソケットがブロックするまでデータを読み込み `on_data()` に通知します。
+  /// ソケットが廃棄された場合は false を返します。
+  fn read_data(
+    &mut self,
+    id: SocketId,
+    stream: &mut TcpStream,
+    listener: &mut Box<dyn TcpStreamListener>,
+  ) -> bool {
+    let mut buffer = std::mem::take(&mut self.read_buffer);
+    let mut consumed = 0;
+    let alive = loop {
+      // last: 読み込みを終了するか, eof: EOF に達したか
+      let (behaviour, last, eof) = match stream.read(&mut buffer) {
+        Ok(0) => (listener.on_data(&[]), true, true),
+        Ok(len) => {
+          consumed += len;
+          (listener.on_data(&buffer[..len]), false, false)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break true,
+        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+        Err(err) => (listener.on_error(err), true, false),
+      };
+      if !self.perform(id, stream, behaviour, &mut |err| listener.on_error(err)) {
+        break false;
+      }
+      if eof {
+        break self.notify_eof(id, stream, listener);
+      } else if last {
+        break true;
+      }
+      // Listener が読み込みを停止した
+      if !self.sockets.interest(id).is_some_and(|interest| interest.is_readable()) {
+        break true;
+      }
+      // 読み込みの上限に達したため、残りは次の周期で読み込む
+      if self.read_budget.is_some_and(|budget| consumed >= budget) {
+        self.sockets.deferred_reads.insert(id);
+        break true;
+      }
+    };
+    self.read_buffer = buffer;
+    alive
+  }
+
+  /// EOF に達したことをソケットごとに一度だけ Listener の `on_eof()` に通知します。ソケットが廃棄された場合は false
+  /// を返します。
+  fn notify_eof(
+    &mut self,
+    id: SocketId,
+    stream: &mut TcpStream,
+    listener: &mut Box<dyn TcpStreamListener>,
+  ) -> bool {
+    if !self.sockets.reach_eof(id) {
+      return true;
     }
+    let behaviour = listener.on_eof();
+    self.perform(id, stream, behaviour, &mut |err| listener.on_error(err))
   }
 
   fn on_tcp_listener(
     &mut self,
-    event: &Event,
+    id: SocketId,
+    readiness: Readiness,
     listener: &mut TcpListener,
     event_listener: &mut Box<dyn TcpListenerListener>,
   ) {
-    // ソケット接続イベント
-    if event.is_readable() {
-      let (stream, address) = listener.accept().unwrap();
-      let behaviour = event_listener.on_accept(stream, address);
-      self.action(event.token().0, listener, behaviour);
+    // ソケット接続イベント。エッジトリガーでは 1 回のイベントに複数の接続が対応するため、WouldBlock となるまで
+    // 受け付ける
+    if !readiness.readable {
+      return;
+    }
+    loop {
+      let (stream, address) = match listener.accept() {
+        Ok(accepted) => accepted,
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+        Err(err) => {
+          log::warn!("failed to accept a connection on socket {}: {}", id, err);
+          let behaviour = event_listener.on_error(err);
+          self.perform(id, listener, behaviour, &mut |err| event_listener.on_error(err));
+          break;
+        }
+      };
+      // Listener が登録タスクを投入した接続は、このループを抜けてタスクが実行されるまで登録されないため、登録中の
+      // 数を加えて判定する
+      let connections = self.sockets.stream_count() + self.registering.load(Ordering::SeqCst);
+      let too_many = matches!(self.max_connections, Some(max) if connections >= max);
+      // 上限によって拒否する接続は接続元のトークンを消費しない
+      let throttled = !too_many
+        && match &mut self.accept_rate_limiter {
+          Some(limiter) => !limiter.try_acquire(address.ip(), Instant::now()),
+          None => false,
+        };
+      let behaviour = if too_many {
+        let max = self.max_connections.unwrap_or_default();
+        log::warn!("connection from {} rejected: too many connections {}", address, max);
+        drop(stream);
+        event_listener.on_rejected(address)
+      } else if throttled {
+        log::warn!("connection from {} rejected: accept rate limit exceeded", address);
+        drop(stream);
+        event_listener.on_rejected(address)
+      } else {
+        event_listener.on_accept(stream, address)
+      };
+      let alive = self.perform(id, listener, behaviour, &mut |err| event_listener.on_error(err));
+      // 破棄された場合や Listener が受け付けを止めた場合は残りの接続を次のイベントに委ねる
+      if !alive || !matches!(self.sockets.interest(id), Some(interest) if interest.is_readable()) {
+        break;
+      }
     }
   }
 }
@@ -324,21 +1903,101 @@ enum Socket {
   Waker,
   Stream(TcpStream, Box<dyn TcpStreamListener>),
   Listener(TcpListener, Box<dyn TcpListenerListener>),
+  /// Poll への登録を解除してクローズしたソケット。`SocketMap` から取り除かれた後も参照を保持している箇所が、登録を
+  /// 解除したソケットを操作しないようにするために置き換えます。
+  Disposed,
+}
+
+impl Socket {
+  /// ソケットの Listener に `on_disposed()` を通知します。
+  fn notify_disposed(&mut self) {
+    match self {
+      Socket::Stream(_, listener) => listener.on_disposed(),
+      Socket::Listener(_, listener) => listener.on_disposed(),
+      Socket::Waker | Socket::Disposed => (),
+    }
+  }
+
+  /// Listener に破棄を通知し、`Socket::Disposed` に置き換えます。すでに置き換えられている場合は何もしません。
+  fn dispose(&mut self) {
+    self.notify_disposed();
+    *self = Socket::Disposed;
+  }
+}
+
+/// `DispatcherAction::Write` で指定されたデータを書き込むことのできるソケットです。
+trait OutboundSink: Source {
+  fn write_outbound(&mut self, buf: &[u8]) -> std::io::Result<usize>;
+}
+
+impl OutboundSink for TcpStream {
+  fn write_outbound(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.write(buf)
+  }
+}
+
+impl OutboundSink for TcpListener {
+  fn write_outbound(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+    let message = "TcpListener cannot write data";
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, message))
+  }
+}
+
+/// 指定されたソケットをロックします。すでにクローズされている場合は `Error::SocketDisposed` を返します。
+fn lock_socket(id: SocketId, socket: &Mutex<Socket>) -> Result<MutexGuard<'_, Socket>> {
+  let socket = socket.lock()?;
+  match *socket {
+    Socket::Disposed => Err(Error::SocketDisposed { id }),
+    _ => Ok(socket),
+  }
 }
 
 /// オブジェクトに対する ID の割当と ID による参照操作を行うためのマップ。
 /// Poll で通知されたトークンからソケットを特定するために使用します。
-/// Note that this [IdMap] is not thread-safe.
+///
+/// このマップとソケットはイベントループのスレッドからのみ操作します。各ソケットは `Arc<Mutex<Socket>>` として
+/// 保持されるため、マップから取り除かれた後も参照が残ることがあります。クローズしたソケットは `Socket::Disposed`
+/// に置き換えられ、残っている参照からは `lock_socket()` で `Error::SocketDisposed` となります。
 struct SocketMap {
   next: usize,
   sockets: HashMap<usize, Arc<Mutex<Socket>>>,
+  /// TcpStream ごとの最後に読み込みまたは書き込みイベントが発生した時刻。
+  last_activity: HashMap<SocketId, Instant>,
+  /// ソケットごとの Poll に登録している Interest。`None` の場合は Poll への登録を解除している。
+  interests: HashMap<SocketId, Option<Interest>>,
+  /// TcpStream ごとのピアによってクローズされた方向。
+  hangups: HashMap<SocketId, HashSet<Half>>,
+  /// 読み込みが EOF に達したことを通知済みの TcpStream。
+  eofs: HashSet<SocketId>,
+  /// Listener の指示によって読み込みを一時停止している TcpStream。
+  paused: HashSet<SocketId>,
+  /// 読み込みの上限に達したため、次の周期で読み込みを続ける TcpStream。
+  deferred_reads: HashSet<SocketId>,
+  /// `DispatcherAction::Write` で指定されたデータのうち、まだ書き込んでいないデータ。
+  outbounds: HashMap<SocketId, Vec<u8>>,
+  /// TcpStream ごとに `DispatcherAction::Write` で書き込んだデータの総量。
+  written: HashMap<SocketId, u64>,
+  /// 書き込んだデータの総量が指定の値に達するのを待っている `Dispatcher::send()` の Future。
+  flush_waiters: HashMap<SocketId, VecDeque<(u64, TaskCompletion<()>)>>,
 }
 
 impl SocketMap {
   /// 新規のマップを作成します。
   pub fn new() -> SocketMap {
     let sockets = HashMap::new();
-    SocketMap { next: 0, sockets }
+    SocketMap {
+      next: 0,
+      sockets,
+      last_activity: HashMap::new(),
+      interests: HashMap::new(),
+      hangups: HashMap::new(),
+      eofs: HashSet::new(),
+      paused: HashSet::new(),
+      deferred_reads: HashSet::new(),
+      outbounds: HashMap::new(),
+      written: HashMap::new(),
+      flush_waiters: HashMap::new(),
+    }
   }
 
   /// 指定された ID のオブジェクトを参照します。
@@ -346,25 +2005,35 @@ impl SocketMap {
     if id == 0 {
       Some(Arc::new(Mutex::new(Socket::Waker)))
     } else {
-      self.sockets.get(&id).map(|a| a.clone())
+      self.sockets.get(&id).cloned()
     }
   }
 
+  /// 指定された ID のソケットが登録されているかを判定します。
+  pub fn contains(&self, id: SocketId) -> bool {
+    self.sockets.contains_key(&id)
+  }
+
+  /// 指定された ID のソケットが TcpStream であるかを判定します。
+  pub fn is_stream(&self, id: SocketId) -> bool {
+    self.last_activity.contains_key(&id)
+  }
+
   /// 管理されているすべての ID を参照します。
   pub fn ids(&self) -> Vec<SocketId> {
-    self.sockets.keys().map(|id| *id).collect::<Vec<usize>>()
+    self.sockets.keys().copied().collect::<Vec<usize>>()
   }
 
   /// 使用可能な ID を検索します。
   pub fn available_id(&mut self) -> Result<SocketId> {
     // NOTE: Token(0) は Waker 用、Token(usize::MAX) は Poll が内部的に使用しているためそれぞれ予約されている
-    let max = std::usize::MAX - 2;
+    let max = usize::MAX - 2;
     if self.sockets.len() == max {
-      return Err(Error::TooManySockets { maximum: std::usize::MAX });
+      return Err(Error::TooManySockets { maximum: usize::MAX });
     }
     for i in 0..=max {
       let id = (self.next as u64 + i as u64) as usize + 1;
-      if self.sockets.get(&id).is_none() {
+      if !self.sockets.contains_key(&id) {
         self.next = if self.next + 1 == max { 0 } else { self.next + 1 };
         return Ok(id);
       }
@@ -373,7 +2042,118 @@ impl SocketMap {
   }
 
   /// 指定された ID のソケットを新規追加または更新します。
-  pub fn set(&mut self, id: SocketId, socket: Socket) {
+  pub fn set(&mut self, id: SocketId, socket: Socket, interest: Interest) {
+    if let Socket::Stream(_, _) = socket {
+      self.last_activity.insert(id, Instant::now());
+    }
+    self.interests.insert(id, Some(interest));
     self.sockets.insert(id, Arc::new(Mutex::new(socket)));
   }
+
+  /// 指定された ID のソケットをマップから削除します。
+  pub fn remove(&mut self, id: SocketId) -> Option<Arc<Mutex<Socket>>> {
+    self.last_activity.remove(&id);
+    self.interests.remove(&id);
+    self.hangups.remove(&id);
+    self.eofs.remove(&id);
+    self.paused.remove(&id);
+    self.deferred_reads.remove(&id);
+    self.outbounds.remove(&id);
+    self.written.remove(&id);
+    for (_, completion) in self.flush_waiters.remove(&id).unwrap_or_default() {
+      completion.complete(Err(Error::SocketDisposed { id }));
+    }
+    self.sockets.remove(&id)
+  }
+
+  /// 指定された ID のソケットが Poll に登録している Interest を参照します。
+  pub fn interest(&self, id: SocketId) -> Option<Interest> {
+    self.interests.get(&id).copied().flatten()
+  }
+
+  /// 指定された ID のソケットが Poll に登録している Interest を更新します。
+  pub fn set_interest(&mut self, id: SocketId, interest: Option<Interest>) {
+    if let Some(current) = self.interests.get_mut(&id) {
+      *current = interest;
+    }
+  }
+
+  /// 登録されている TcpStream の数を参照します。このメソッドはソケットをロックしません。
+  pub fn stream_count(&self) -> usize {
+    self.last_activity.len()
+  }
+
+  /// 指定された ID の TcpStream の片方向がクローズされたことを記録します。その方向が初めてクローズされた場合に
+  /// true を返します。
+  pub fn hang_up(&mut self, id: SocketId, half: Half) -> bool {
+    self.hangups.entry(id).or_default().insert(half)
+  }
+
+  /// 指定された ID の TcpStream の読み込みが EOF に達したことを記録します。初めて記録した場合に true を返します。
+  pub fn reach_eof(&mut self, id: SocketId) -> bool {
+    self.eofs.insert(id)
+  }
+
+  /// 指定された ID の TcpStream が読み込みを一時停止したことを記録します。TcpListener の場合は記録しません。
+  pub fn pause(&mut self, id: SocketId) {
+    if self.last_activity.contains_key(&id) {
+      self.paused.insert(id);
+    }
+  }
+
+  /// 指定された ID の TcpStream に `DispatcherAction::Write` で書き込んだデータの総量を参照します。
+  pub fn written(&self, id: SocketId) -> u64 {
+    self.written.get(&id).copied().unwrap_or(0)
+  }
+
+  /// 指定された ID の TcpStream にデータを書き込んだことを記録し、書き込みが完了した `Dispatcher::send()` の
+  /// Future を完了させます。
+  pub fn flushed(&mut self, id: SocketId, len: usize) {
+    if !self.sockets.contains_key(&id) {
+      return;
+    }
+    let written = self.written.entry(id).or_insert(0);
+    *written += len as u64;
+    let written = *written;
+    if let Some(waiters) = self.flush_waiters.get_mut(&id) {
+      while waiters.front().is_some_and(|(target, _)| *target <= written) {
+        let (_, completion) = waiters.pop_front().unwrap();
+        completion.complete(Ok(()));
+      }
+    }
+  }
+
+  /// 指定された ID の TcpStream に書き込んだデータの総量が `target` に達した時点で完了させる Future を登録します。
+  /// すでに達している場合は即座に、ソケットが破棄されている場合は `Error::SocketDisposed` で完了させます。
+  pub fn wait_flush(&mut self, id: SocketId, target: u64, completion: TaskCompletion<()>) {
+    if !self.sockets.contains_key(&id) {
+      completion.complete(Err(Error::SocketDisposed { id }));
+    } else if self.written(id) >= target {
+      completion.complete(Ok(()));
+    } else {
+      self.flush_waiters.entry(id).or_default().push_back((target, completion));
+    }
+  }
+
+  /// 指定された ID の TcpStream が Listener の指示によって読み込みを一時停止しているかを判定します。
+  pub fn is_paused(&self, id: SocketId) -> bool {
+    self.paused.contains(&id)
+  }
+
+  /// 指定された ID のソケットが読み込みを再開したことを記録します。
+  pub fn resume(&mut self, id: SocketId) {
+    self.paused.remove(&id);
+  }
+
+  /// 指定された ID の TcpStream の両方向がクローズされているかを判定します。
+  pub fn is_hung_up(&self, id: SocketId) -> bool {
+    self.hangups.get(&id).is_some_and(|halves| halves.len() == 2)
+  }
+
+  /// 指定された ID の TcpStream で読み込みまたは書き込みイベントが発生したことを記録します。
+  pub fn touch(&mut self, id: SocketId) {
+    if let Some(last) = self.last_activity.get_mut(&id) {
+      *last = Instant::now();
+    }
+  }
 }
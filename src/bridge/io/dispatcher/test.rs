@@ -1,43 +1,2045 @@
-use std::net::{SocketAddr, IpAddr, Ipv4Addr};
+use std::future::Future;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::TrySendError;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, Once, Weak};
 use std::thread::spawn;
+use std::time::{Duration, Instant};
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 use mio::net::{TcpListener, TcpStream};
+use mio::Interest;
+
+use crate::bridge::io::dispatcher::{
+  lock_socket, read_until_would_block, AcceptRateLimit, AcceptRateLimiter, Dispatcher,
+  DispatcherAction, DispatcherConfig, DispatcherRegister, ErasedTask, Half, PollingLoop, ReadMode,
+  Readiness, Socket, SocketInfo, SocketKind, Task, TaskFuture, TcpListenerListener,
+  TcpStreamListener, DEFAULT_THREAD_NAME, DEFAULT_WRITE_HIGH_WATER_MARK,
+};
+use crate::bridge::MessageQueue;
+use crate::error::Error;
+use crate::msg::{Block, Message, StreamDecoder};
+use crate::test::{block_on, SampleValues};
+use crate::Result;
+
+#[test]
+fn test_dispatcher() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+
+  let address = echo_server("hello, world", 1);
+  println!("address: {}", address);
+
+  let (sender, receiver) = channel();
+  let stream = TcpStream::connect(address).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(EchoClient::new("hello, world", sender));
+  block_on(dispatcher.register(stream, listener)).unwrap();
+
+  let echo_back = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert_eq!("hello, world".as_bytes(), &echo_back[..]);
+}
+
+#[test]
+fn test_dispatcher_thread_name() {
+  let thread_name = || {
+    Box::new(|_: &mut PollingLoop| Ok(std::thread::current().name().map(|name| name.to_string())))
+  };
+
+  // デフォルトの名前でイベントループのスレッドが起動する
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let name = block_on(dispatcher.run_in_event_loop(thread_name())).unwrap();
+  assert_eq!(Some(DEFAULT_THREAD_NAME.to_string()), name);
+
+  // 指定された名前でイベントループのスレッドが起動する
+  let dispatcher = Dispatcher::with_thread_name(1024, 1024, "custom-dispatcher").unwrap();
+  let name = block_on(dispatcher.run_in_event_loop(thread_name())).unwrap();
+  assert_eq!(Some("custom-dispatcher".to_string()), name);
+}
+
+#[test]
+fn test_dispatcher_metrics() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let metrics = block_on(dispatcher.metrics()).unwrap();
+  assert_eq!(0, metrics.registered_sockets);
+  assert_eq!(0, metrics.listener_count);
+
+  let address = echo_server("", 2);
+  for _ in 0..2 {
+    let stream = TcpStream::connect(address).unwrap();
+    let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+    block_on(dispatcher.register(stream, listener)).unwrap();
+  }
+
+  let metrics = block_on(dispatcher.metrics()).unwrap();
+  assert_eq!(2, metrics.registered_sockets);
+  assert_eq!(0, metrics.listener_count);
+  assert!(metrics.total_tasks_run >= 3);
+}
+
+#[test]
+fn test_dispatcher_with_config() {
+  let limit = AcceptRateLimit { per_second: 5.0, burst: 3 };
+  let config = DispatcherConfig::new(16, 8)
+    .with_thread_name("configured-dispatcher")
+    .with_max_event_buffer_size(64)
+    .with_max_poll_timeout(Duration::from_millis(500))
+    .with_idle_timeout(Some(Duration::from_secs(30)))
+    .with_max_connections(Some(100))
+    .with_accept_rate_limit(Some(limit))
+    .with_write_high_water_mark(None)
+    .with_read_buffer_size(256)
+    .with_read_budget(Some(4096));
+  let dispatcher = Dispatcher::with_config(config).unwrap();
+  assert_eq!(8, dispatcher.task_queue_size);
+
+  // 設定した値がイベントループに反映されている
+  let (thread_name, event_buffer_size, max_event_buffer_size) =
+    block_on(dispatcher.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      assert_eq!(Duration::from_millis(500), polling.max_poll_timeout);
+      assert_eq!(Some(Duration::from_secs(30)), polling.idle_timeout);
+      assert_eq!(Some(100), polling.max_connections);
+      assert_eq!(Some(limit), polling.accept_rate_limiter.as_ref().map(|limiter| limiter.limit));
+      assert_eq!(None, polling.write_high_water_mark);
+      assert_eq!(256, polling.read_buffer.len());
+      assert_eq!(Some(4096), polling.read_budget);
+      let thread_name = std::thread::current().name().map(|name| name.to_string());
+      Ok((thread_name, polling.event_buffer_size, polling.max_event_buffer_size))
+    })))
+    .unwrap();
+  assert_eq!(Some("configured-dispatcher".to_string()), thread_name);
+  assert_eq!((16, 64), (event_buffer_size, max_event_buffer_size));
+
+  // new() は指定しなかった値にデフォルトを使用する
+  let dispatcher = Dispatcher::new(16, 8).unwrap();
+  let (thread_name, max_event_buffer_size, write_high_water_mark) =
+    block_on(dispatcher.run_in_event_loop(Box::new(|polling: &mut PollingLoop| {
+      let thread_name = std::thread::current().name().map(|name| name.to_string());
+      Ok((thread_name, polling.max_event_buffer_size, polling.write_high_water_mark))
+    })))
+    .unwrap();
+  assert_eq!(Some(DEFAULT_THREAD_NAME.to_string()), thread_name);
+  assert_eq!(16, max_event_buffer_size);
+  assert_eq!(Some(DEFAULT_WRITE_HIGH_WATER_MARK), write_high_water_mark);
+}
+
+#[test]
+fn test_dispatcher_list_sockets() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  assert!(block_on(dispatcher.list_sockets()).unwrap().is_empty());
+
+  // TcpListener と TcpStream を登録する
+  let (accepted, _accept) = channel();
+  let (rejected, _reject) = channel();
+  let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let server_address = listener.local_addr().unwrap();
+  let event_listener: Box<dyn TcpListenerListener> = Box::new(Acceptor { accepted, rejected });
+  let listener_id = block_on(dispatcher.register(listener, event_listener)).unwrap();
+  let stream = TcpStream::connect(server_address).unwrap();
+  let stream_address = stream.local_addr().unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+  let stream_id = block_on(dispatcher.register(stream, listener)).unwrap();
+
+  // どちらも正しい種類とアドレスで一覧に含まれる
+  let sockets = block_on(dispatcher.list_sockets()).unwrap();
+  assert_eq!(
+    vec![
+      SocketInfo {
+        id: listener_id,
+        kind: SocketKind::Listener,
+        local_address: Some(server_address),
+        remote_address: None,
+      },
+      SocketInfo {
+        id: stream_id,
+        kind: SocketKind::Stream,
+        local_address: Some(stream_address),
+        remote_address: Some(server_address),
+      },
+    ],
+    sockets
+  );
+}
+
+/// ログのメッセージと構造化フィールドの組。
+type LogRecord = (String, Vec<(String, String)>);
+
+/// 指定された名前のスレッドが出力したログのメッセージと構造化フィールドを記録する Logger です。他のテストが並行
+/// して出力するログは記録しません。
+struct CapturingLogger {
+  thread_name: Mutex<Option<String>>,
+  records: Mutex<Vec<LogRecord>>,
+}
+
+impl log::Log for CapturingLogger {
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    metadata.level() <= log::Level::Info
+      && std::thread::current().name().is_some_and(|name| {
+        self.thread_name.lock().unwrap().as_ref().is_some_and(|target| target == name)
+      })
+  }
+
+  fn log(&self, record: &log::Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+    struct Fields(Vec<(String, String)>);
+    impl<'kvs> log::kv::VisitSource<'kvs> for Fields {
+      fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+      ) -> std::result::Result<(), log::kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+      }
+    }
+    let mut fields = Fields(Vec::new());
+    record.key_values().visit(&mut fields).unwrap();
+    self.records.lock().unwrap().push((record.args().to_string(), fields.0));
+  }
+
+  fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger =
+  CapturingLogger { thread_name: Mutex::new(None), records: Mutex::new(Vec::new()) };
+
+#[test]
+fn test_dispatcher_log_peer_address() {
+  const THREAD_NAME: &str = "log-peer-address-dispatcher";
+  static INIT: Once = Once::new();
+  static INSTALLED: AtomicBool = AtomicBool::new(false);
+  INIT.call_once(|| {
+    if log::set_logger(&LOGGER).is_ok() {
+      log::set_max_level(log::LevelFilter::Info);
+      INSTALLED.store(true, Ordering::SeqCst);
+    }
+  });
+  if !INSTALLED.load(Ordering::SeqCst) {
+    eprintln!("skipping: another logger has already been installed");
+    return;
+  }
+  *LOGGER.thread_name.lock().unwrap() = Some(THREAD_NAME.to_string());
+
+  // 登録した TcpStream にデータを到着させてイベントを発生させる
+  let dispatcher = Dispatcher::with_thread_name(1024, 1024, THREAD_NAME).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let peer = server.local_addr().unwrap();
+  let stream = TcpStream::connect(peer).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+  let (mut accepted, _) = server.accept().unwrap();
+  accepted.write_all(b"hello").unwrap();
+
+  // ソケット ID、種類、ピアのアドレスが構造化フィールドとメッセージに含まれる
+  let expected = vec![
+    ("socket_id".to_string(), id.to_string()),
+    ("kind".to_string(), "Stream".to_string()),
+    ("peer".to_string(), peer.to_string()),
+  ];
+  let deadline = Instant::now() + Duration::from_secs(10);
+  loop {
+    let records = LOGGER.records.lock().unwrap();
+    if let Some((message, _)) = records.iter().find(|(_, fields)| *fields == expected) {
+      assert!(message.contains(&peer.to_string()), "{}", message);
+      break;
+    }
+    drop(records);
+    assert!(Instant::now() < deadline, "the log record has not been emitted");
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  *LOGGER.thread_name.lock().unwrap() = None;
+  LOGGER.records.lock().unwrap().clear();
+}
+
+#[test]
+fn test_dispatcher_replace_listener() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let (sender, receiver) = channel();
+  let handshake: Box<dyn TcpStreamListener> =
+    Box::new(TaggedClient { tag: "handshake", sender: sender.clone() });
+  let id = block_on(dispatcher.register(stream, handshake)).unwrap();
+  let (mut accepted, _) = server.accept().unwrap();
+
+  // 置き換える前のイベントは最初の Listener に通知される
+  accepted.write_all(b"hello").unwrap();
+  assert_eq!("handshake", receiver.recv_timeout(Duration::from_secs(10)).unwrap());
+
+  // 置き換えた後のイベントは新しい Listener に通知される
+  let session: Box<dyn TcpStreamListener> = Box::new(TaggedClient { tag: "session", sender });
+  block_on(dispatcher.replace_listener(id, session)).unwrap();
+  accepted.write_all(b"world").unwrap();
+  assert_eq!("session", receiver.recv_timeout(Duration::from_secs(10)).unwrap());
+
+  // 登録されていないソケットは置き換えられない
+  let (sender, _receiver) = channel();
+  let listener: Box<dyn TcpStreamListener> = Box::new(TaggedClient { tag: "unknown", sender });
+  match block_on(dispatcher.replace_listener(id + 100, listener)) {
+    Err(Error::Io { kind, .. }) => assert_eq!(ErrorKind::NotFound, kind),
+    Err(err) => panic!("unexpected error: {:?}", err),
+    Ok(_) => panic!("unregistered socket should not be replaced"),
+  }
+}
+
+#[test]
+fn test_dispatcher_eof() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+  // ピアがクローズすると on_eof() が一度だけ呼び出される
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let (sender, receiver) = channel();
+  let listener: Box<dyn TcpStreamListener> = Box::new(EofClient { sender });
+  block_on(dispatcher.register(stream, listener)).unwrap();
+  let (mut accepted, _) = server.accept().unwrap();
+  accepted.write_all(b"hello").unwrap();
+  drop(accepted);
+  receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+  // デフォルトでは EOF に達したソケットは破棄される
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let (sender, receiver) = channel();
+  let listener: Box<dyn TcpStreamListener> = Box::new(TaggedClient { tag: "eof", sender });
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+  drop(server.accept().unwrap());
+  assert_eq!("eof", receiver.recv_timeout(Duration::from_secs(10)).unwrap());
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while block_on(dispatcher.list_sockets()).unwrap().iter().any(|socket| socket.id == id) {
+    assert!(Instant::now() < deadline, "the socket has not been disposed");
+    std::thread::sleep(Duration::from_millis(10));
+  }
+}
+
+#[test]
+fn test_dispatcher_on_disposed() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let wait_for = |count: &Arc<AtomicUsize>, expected: usize| {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while count.load(Ordering::SeqCst) < expected {
+      assert!(Instant::now() < deadline, "on_disposed() has not been called");
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(expected, count.load(Ordering::SeqCst));
+  };
+
+  // Listener が DispatcherAction::Dispose を返して破棄されたソケットで一度だけ呼び出される
+  let disposed = Arc::new(AtomicUsize::new(0));
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(DisposedClient(disposed.clone()));
+  block_on(dispatcher.register(stream, listener)).unwrap();
+  drop(server.accept().unwrap());
+  wait_for(&disposed, 1);
+
+  // Dispatcher::dispose() でクローズしたソケットで一度だけ呼び出される
+  let disposed = Arc::new(AtomicUsize::new(0));
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(DisposedClient(disposed.clone()));
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+  let (_accepted, _) = server.accept().unwrap();
+  assert_eq!(0, disposed.load(Ordering::SeqCst));
+  block_on(dispatcher.dispose(id)).unwrap();
+  wait_for(&disposed, 1);
+  block_on(dispatcher.dispose(id)).unwrap();
+  assert_eq!(1, disposed.load(Ordering::SeqCst));
+
+  // TcpListener の Listener でも呼び出される
+  let disposed = Arc::new(AtomicUsize::new(0));
+  let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let event_listener: Box<dyn TcpListenerListener> = Box::new(DisposedAcceptor(disposed.clone()));
+  let id = block_on(dispatcher.register(listener, event_listener)).unwrap();
+  block_on(dispatcher.dispose(id)).unwrap();
+  wait_for(&disposed, 1);
+}
+
+#[test]
+fn test_dispatcher_stale_socket_after_close() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+
+  // クローズ前に取得した参照はクローズ後に操作できず、明確なエラーとなる
+  let result =
+    block_on(dispatcher.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      let stale = polling.sockets.get(id).unwrap();
+      assert!(lock_socket(id, &stale).is_ok());
+      polling.close(id);
+      assert!(polling.sockets.get(id).is_none());
+      Ok(lock_socket(id, &stale).err())
+    })));
+  assert_eq!(Some(Error::SocketDisposed { id }), result.unwrap());
+
+  // ピアからはクローズされたことが観測でき、イベントループは動作を続ける
+  let (mut accepted, _) = server.accept().unwrap();
+  accepted.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  assert_eq!(0, accepted.read(&mut [0u8; 16]).unwrap());
+  assert_eq!(0, block_on(dispatcher.metrics()).unwrap().registered_sockets);
+}
+
+#[test]
+fn test_dispatcher_event_buffer_growth() {
+  let dispatcher = Dispatcher::new(1, 1024).unwrap();
+  assert_eq!(1, block_on(dispatcher.metrics()).unwrap().event_buffer_size);
+
+  // 上限を設定しなければ拡張しない。ピアは接続を受け付けて即座にクローズするため、EOF に達したソケットは
+  // 破棄される
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = server.local_addr().unwrap();
+  let (stop, stopped) = channel::<()>();
+  let peer = spawn(move || {
+    for stream in server.incoming() {
+      drop(stream);
+      if stopped.try_recv().is_ok() {
+        break;
+      }
+    }
+  });
+  let flood = |count: usize| {
+    let futures = (0..count)
+      .map(|_| {
+        let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+        dispatcher.register(TcpStream::connect(address).unwrap(), listener)
+      })
+      .collect::<Vec<_>>();
+    for future in futures {
+      block_on(future).unwrap();
+    }
+  };
+  flood(16);
+  assert_eq!(1, block_on(dispatcher.metrics()).unwrap().event_buffer_size);
+
+  // 多数のソケットで同時にイベントが発生するとイベントバッファが上限まで拡張される
+  block_on(dispatcher.set_max_event_buffer_size(16)).unwrap();
+  let deadline = Instant::now() + Duration::from_secs(10);
+  let mut previous = 1;
+  loop {
+    let size = block_on(dispatcher.metrics()).unwrap().event_buffer_size;
+    assert!(previous <= size && size <= 16, "{} -> {}", previous, size);
+    if size == 16 {
+      break;
+    }
+    assert!(Instant::now() < deadline, "the event buffer has not grown: {}", size);
+    previous = size;
+    flood(64);
+  }
+
+  // 現在のサイズより小さい上限を設定すると縮小する
+  block_on(dispatcher.set_max_event_buffer_size(4)).unwrap();
+  assert_eq!(4, block_on(dispatcher.metrics()).unwrap().event_buffer_size);
+
+  stop.send(()).unwrap();
+  drop(std::net::TcpStream::connect(address).unwrap());
+  peer.join().unwrap();
+}
+
+#[test]
+fn test_dispatcher_task_queue_overflow() {
+  let capacity = 4;
+  let dispatcher = Dispatcher::new(1024, capacity).unwrap();
+
+  // イベントループをブロックしてタスクが消化されない状態にする
+  let (blocking, blocked) = channel::<()>();
+  let (started, start) = channel::<()>();
+  let blocker = dispatcher.run_in_event_loop(Box::new(move |_: &mut PollingLoop| {
+    started.send(()).unwrap();
+    blocked.recv().unwrap();
+    Ok(0usize)
+  }));
+  start.recv_timeout(Duration::from_secs(10)).unwrap();
+
+  // 容量分のタスクは投入でき、それを超えると即座に TaskQueueOverflow で完了する
+  let queued = (0..capacity).map(|_| dispatcher.metrics()).collect::<Vec<_>>();
+  assert_eq!(Error::TaskQueueOverflow { capacity }, block_on(dispatcher.metrics()).unwrap_err());
+
+  // イベントループが再開すると投入済みのタスクはすべて実行される
+  blocking.send(()).unwrap();
+  block_on(blocker).unwrap();
+  for future in queued {
+    block_on(future).unwrap();
+  }
+  block_on(dispatcher.metrics()).unwrap();
+}
+
+#[test]
+fn test_try_register_would_block() {
+  let capacity = 2;
+  let dispatcher = Dispatcher::new(1024, capacity).unwrap();
+  let listener = || TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let acceptor = || -> Box<dyn TcpListenerListener> {
+    Box::new(Acceptor { accepted: channel().0, rejected: channel().0 })
+  };
+
+  // 空きがあれば登録できる
+  let id = block_on(dispatcher.try_register(listener(), acceptor()).unwrap()).unwrap();
+
+  // イベントループをブロックしてタスクキューを飽和させる
+  let (blocking, blocked) = channel::<()>();
+  let (started, start) = channel::<()>();
+  let blocker = dispatcher.run_in_event_loop(Box::new(move |_: &mut PollingLoop| {
+    started.send(()).unwrap();
+    blocked.recv().unwrap();
+    Ok(0usize)
+  }));
+  start.recv_timeout(Duration::from_secs(10)).unwrap();
+  let queued = (0..capacity).map(|_| dispatcher.metrics()).collect::<Vec<_>>();
+
+  // キューに空きがなければ投入せずに即座に失敗する
+  match dispatcher.try_register(listener(), acceptor()) {
+    Err(err) => assert_eq!(Error::WouldBlock, err),
+    Ok(_) => panic!("the task queue should be saturated"),
+  }
+
+  // イベントループが再開すると再び登録できる
+  blocking.send(()).unwrap();
+  block_on(blocker).unwrap();
+  for future in queued {
+    block_on(future).unwrap();
+  }
+  let other = block_on(dispatcher.try_register(listener(), acceptor()).unwrap()).unwrap();
+  assert_ne!(id, other);
+}
+
+#[test]
+fn test_dispatcher_stop() {
+  fn assert_send_unpin<F: Future + Send + Unpin>(_: &F) {}
+
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let future: TaskFuture<Result<()>> = dispatcher.stop();
+  assert_send_unpin(&future);
+  block_on(future).unwrap();
+
+  // 停止後に投入したタスクはエラーで完了する (イベントループの終了処理中であれば DispatcherShutdown となる)
+  loop {
+    match block_on(dispatcher.metrics()).unwrap_err() {
+      Error::DispatcherShutdown => std::thread::yield_now(),
+      err => break assert_eq!(Error::DispatcherStopped, err),
+    }
+  }
+}
+
+#[test]
+fn test_dispatcher_is_running() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  assert!(dispatcher.is_running());
+
+  // イベントループのスレッドがパニックで終了する
+  let killer = dispatcher.run_in_event_loop(Box::new(|_: &mut PollingLoop| -> Result<()> {
+    panic!("killing the event loop")
+  }));
+  assert_eq!(Error::DispatcherStopped, block_on(killer).unwrap_err());
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while dispatcher.is_running() {
+    assert!(Instant::now() < deadline, "the event loop is still running");
+    std::thread::sleep(Duration::from_millis(10));
+  }
+
+  // 終了後に投入したタスクは永久に待機せずエラーとなる
+  assert_eq!(Error::DispatcherStopped, block_on(dispatcher.metrics()).unwrap_err());
+  let task = Box::new(|_: &mut PollingLoop| Ok(()));
+  assert_eq!(Error::DispatcherStopped, dispatcher.try_submit(Task::new(task)).err().unwrap());
+
+  // stop() による停止でも実行中ではなくなる
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  block_on(dispatcher.stop()).unwrap();
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while dispatcher.is_running() {
+    assert!(Instant::now() < deadline, "the event loop is still running");
+    std::thread::sleep(Duration::from_millis(10));
+  }
+}
+
+#[test]
+fn test_dispatcher_shutdown_with_reason() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  block_on(dispatcher.set_write_high_water_mark(None)).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let data = SampleValues::new(20917u64).next_bytes(4 * 1024 * 1024);
+  let mut readers = Vec::new();
+  for notifies in [true, false].iter().copied() {
+    let mut client = std::net::TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    readers.push(spawn(move || {
+      let mut received = Vec::new();
+      client.read_to_end(&mut received).unwrap();
+      received
+    }));
+    let (stream, _) = server.accept().unwrap();
+    stream.set_nonblocking(true).unwrap();
+    let listener: Box<dyn TcpStreamListener> =
+      if notifies { Box::new(ShutdownNotifier) } else { Box::new(NullClient) };
+    let id = block_on(dispatcher.register(TcpStream::from_std(stream), listener)).unwrap();
+    if notifies {
+      for chunk in data.chunks(64 * 1024) {
+        block_on(dispatcher.send(id, chunk.to_vec()).accepted()).unwrap();
+      }
+    }
+  }
+
+  // 終了を通知する Listener の接続は、送信済みのデータをすべて受信した後に Listener が返したデータを受信する
+  block_on(dispatcher.shutdown_with_reason(1, b"shutting down".to_vec())).unwrap();
+  let received = readers.remove(0).join().unwrap();
+  assert_eq!(data.len() + 2 + 13, received.len());
+  assert!(data == received[..data.len()]);
+  assert_eq!(b"\x00\x01shutting down", &received[data.len()..]);
+
+  // 終了の通知を返さない Listener の接続には何も書き込まれずにクローズされる
+  assert!(readers.remove(0).join().unwrap().is_empty());
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while dispatcher.is_running() {
+    assert!(Instant::now() < deadline, "the event loop is still running");
+    std::thread::sleep(Duration::from_millis(10));
+  }
+}
+
+#[test]
+fn test_dispatcher_shutdown_order() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let order = Arc::new(Mutex::new(Vec::new()));
+  let recorder = |tag| DisposalRecorder { tag, order: order.clone() };
+
+  // TcpListener より小さい ID で TcpStream を登録する
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let mut peers = Vec::new();
+  for tag in ["stream-1", "stream-2"].iter() {
+    let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    let listener: Box<dyn TcpStreamListener> = Box::new(recorder(tag));
+    block_on(dispatcher.register(stream, listener)).unwrap();
+    peers.push(server.accept().unwrap().0);
+  }
+  let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = listener.local_addr().unwrap();
+  let event_listener: Box<dyn TcpListenerListener> = Box::new(recorder("listener"));
+  block_on(dispatcher.register(listener, event_listener)).unwrap();
+
+  // バックログに接続が到着した状態で停止すると、その接続は受け付けられずにリセットされる
+  let mut client =
+    block_on(dispatcher.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      let client = std::net::TcpStream::connect(address)?;
+      polling.stopped = true;
+      Ok(client)
+    })))
+    .unwrap();
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while dispatcher.is_running() {
+    assert!(Instant::now() < deadline, "the dispatcher has not stopped");
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  match client.read(&mut [0u8; 1]) {
+    Ok(len) => assert_eq!(0, len),
+    Err(err) => assert_eq!(ErrorKind::ConnectionReset, err.kind()),
+  }
+
+  // TcpListener が先にクローズされ、TcpStream は ID の順にクローズされる
+  assert_eq!(vec!["listener", "stream-1", "stream-2"], *order.lock().unwrap());
+}
+
+#[test]
+fn test_dispatcher_shutdown_drains_tasks() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+
+  // イベントループをブロックしている間に停止とタスクを投入する
+  let (release, blocked) = channel::<()>();
+  let blocker = dispatcher.run_in_event_loop(Box::new(move |_: &mut PollingLoop| {
+    blocked.recv_timeout(Duration::from_secs(10)).unwrap();
+    Ok(())
+  }));
+  let stop = dispatcher.stop();
+  let task = dispatcher.metrics();
+  release.send(()).unwrap();
+
+  // 停止より後に残っていたタスクは実行されずに DispatcherShutdown で完了する
+  block_on(blocker).unwrap();
+  block_on(stop).unwrap();
+  assert_eq!(Error::DispatcherShutdown, block_on(task).unwrap_err());
+}
+
+#[test]
+fn test_dispatcher_stops_without_waker() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  block_on(dispatcher.set_max_poll_timeout(Duration::from_millis(100))).unwrap();
+
+  // Waker を使用せずに停止を指示するタスクを投入する
+  let stop: ErasedTask = Box::new(|polling: &mut PollingLoop| polling.stopped = true);
+  dispatcher.sender.try_send(stop).ok().unwrap();
+
+  // poll のタイムアウトによってイベントループが停止を検出し、タスクの受信側が破棄される
+  let deadline = Instant::now() + Duration::from_secs(10);
+  loop {
+    let noop: ErasedTask = Box::new(|_: &mut PollingLoop| ());
+    match dispatcher.sender.try_send(noop) {
+      Err(TrySendError::Disconnected(_)) => break,
+      _ if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+      _ => panic!("the event loop didn't stop"),
+    }
+  }
+}
+
+#[test]
+fn test_dispatcher_idle_timeout() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  block_on(dispatcher.set_idle_timeout(Some(Duration::from_millis(100)))).unwrap();
+
+  // 何も送受信しないクライアントを登録する
+  let address = echo_server("", 1);
+  let (sender, receiver) = channel();
+  let stream = TcpStream::connect(address).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(SilentClient(sender));
+  block_on(dispatcher.register(stream, listener)).unwrap();
+  assert_eq!(1, block_on(dispatcher.metrics()).unwrap().registered_sockets);
+
+  // タイムアウトすると on_error() が呼び出されソケットが破棄される
+  let kind = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert_eq!(ErrorKind::TimedOut, kind);
+  assert_eq!(0, block_on(dispatcher.metrics()).unwrap().registered_sockets);
+}
+
+#[test]
+fn test_dispatcher_pause_and_resume_reads() {
+  const TOTAL: usize = 64 * 1024 * 1024;
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+
+  // 書き込みがブロックした時点で通知し、その後すべてのデータを書き込むピア
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let (stalled, stall) = channel();
+  let peer = spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.set_write_timeout(Some(Duration::from_millis(200))).unwrap();
+    let buffer = vec![0u8; 64 * 1024];
+    let mut written = 0;
+    let mut notified = false;
+    while written < TOTAL {
+      let len = std::cmp::min(buffer.len(), TOTAL - written);
+      match stream.write(&buffer[..len]) {
+        Ok(len) => written += len,
+        Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {
+          if !notified {
+            stalled.send(written).unwrap();
+            notified = true;
+          }
+        }
+        Err(err) => panic!("{}", err),
+      }
+    }
+  });
+
+  let (sender, receiver) = channel();
+  let stream = TcpStream::connect(address).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(PausingClient::new(sender));
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+
+  // 読み込みを停止しているためピアの書き込みがブロックする
+  let written = stall.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert!(written < TOTAL);
+
+  // 読み込みを再開するとすべてのデータが届く
+  block_on(dispatcher.resume_reads(id)).unwrap();
+  assert_eq!(TOTAL, receiver.recv_timeout(Duration::from_secs(30)).unwrap());
+  peer.join().unwrap();
+}
+
+#[test]
+fn test_dispatcher_backpressure() {
+  const COUNT: usize = 2048;
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+
+  // 8KB の Block を書き込み続け、書き込みがブロックした時点で通知するピア
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let (stalled, stall) = channel();
+  let peer = spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.set_write_timeout(Some(Duration::from_millis(200))).unwrap();
+    let mut buffer = Vec::new();
+    for i in 0..COUNT {
+      Message::Block(block(i)).write_to(&mut buffer).unwrap();
+    }
+    let mut written = 0;
+    let mut notified = false;
+    while written < buffer.len() {
+      match stream.write(&buffer[written..]) {
+        Ok(len) => written += len,
+        Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {
+          if !notified {
+            stalled.send(written).unwrap();
+            notified = true;
+          }
+        }
+        Err(err) => panic!("{}", err),
+      }
+    }
+    buffer.len()
+  });
+
+  let mut queue = MessageQueue::new(4);
+  let pauses = Arc::new(AtomicUsize::new(0));
+  let stream = TcpStream::connect(address).unwrap();
+  let listener: Box<dyn TcpStreamListener> =
+    Box::new(QueueClient::new(queue.clone(), pauses.clone()));
+  block_on(dispatcher.register(stream, listener)).unwrap();
+
+  // 消費されないキューが満杯になると読み込みが停止し、TCP のウィンドウが閉じてピアの書き込みがブロックする
+  let written = stall.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert!(written < COUNT * 8 * 1024);
+  assert!(queue.is_full());
+  assert!(pauses.load(Ordering::SeqCst) > 0);
+
+  // キューを消費すると読み込みが再開し、すべての Block が順番どおりに届く
+  let deadline = Instant::now() + Duration::from_secs(30);
+  let mut received = 0;
+  while received < COUNT {
+    match queue.try_pop().unwrap() {
+      Some(msg) => {
+        assert_eq!(Message::Block(block(received)), msg);
+        received += 1;
+      }
+      None => {
+        assert!(Instant::now() < deadline, "only {} blocks received", received);
+        std::thread::sleep(Duration::from_millis(1));
+      }
+    }
+  }
+  assert!(peer.join().unwrap() > written);
+}
+
+/// `test_dispatcher_backpressure()` でピアが送信する Block を構築します。
+fn block(i: usize) -> Block {
+  Block::new(1, false, 0, vec![(i % 256) as u8; 8 * 1024]).unwrap()
+}
+
+#[test]
+fn test_dispatcher_write_action() {
+  const TOTAL: usize = 16 * 1024 * 1024;
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let expected = SampleValues::new(3904817u64).next_bytes(TOTAL);
+
+  // 指示があるまで読み込みを開始しないピア
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let (start, started) = channel::<()>();
+  let peer = spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    started.recv().unwrap();
+    let mut received = Vec::with_capacity(TOTAL);
+    while received.len() < TOTAL {
+      let mut buffer = vec![0u8; 64 * 1024];
+      let len = stream.read(&mut buffer).unwrap();
+      assert_ne!(0, len);
+      received.extend_from_slice(&buffer[..len]);
+    }
+    // 検証が終わるまで接続を維持する
+    (received, stream)
+  });
+
+  let writes = Arc::new(AtomicUsize::new(0));
+  let stream = TcpStream::connect(address).unwrap();
+  let listener: Box<dyn TcpStreamListener> =
+    Box::new(WriteActionClient { data: Some(expected.clone()), writes: writes.clone() });
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+  let outbound = |id| {
+    Box::new(move |polling: &mut PollingLoop| {
+      let len = polling.sockets.outbounds.get(&id).map(Vec::len).unwrap_or(0);
+      Ok((len, polling.sockets.interest(id)))
+    })
+  };
+
+  // ピアが読み込まないため書き込みきれなかった残りをディスパッチャーが保持している
+  let deadline = Instant::now() + Duration::from_secs(10);
+  let (len, interest) = loop {
+    let (len, interest) = block_on(dispatcher.run_in_event_loop(outbound(id))).unwrap();
+    if len > 0 {
+      break (len, interest);
+    }
+    assert!(Instant::now() < deadline);
+    std::thread::sleep(Duration::from_millis(10));
+  };
+  assert!(len < TOTAL);
+  assert!(interest.unwrap().is_writable());
+
+  // ピアが読み込みを開始すると残りが自動的に書き込まれ、すべてのデータが欠けることなく届く
+  start.send(()).unwrap();
+  let (received, _stream) = peer.join().unwrap();
+  assert_eq!(expected, received);
+  let (len, interest) = block_on(dispatcher.run_in_event_loop(outbound(id))).unwrap();
+  assert_eq!(0, len);
+  assert!(!interest.unwrap().is_writable());
+  assert_eq!(1, writes.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_dispatcher_send_high_water_mark() {
+  const LIMIT: usize = 4 * 1024 * 1024;
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  assert_eq!(
+    DEFAULT_WRITE_HIGH_WATER_MARK,
+    block_on(dispatcher.run_in_event_loop(Box::new(|polling: &mut PollingLoop| {
+      Ok(polling.write_high_water_mark.unwrap())
+    })))
+    .unwrap()
+  );
+  block_on(dispatcher.set_write_high_water_mark(Some(LIMIT))).unwrap();
+
+  // 受け付けた接続から読み込まないピア
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let peer = spawn(move || listener.accept().unwrap().0);
+  let stream = TcpStream::connect(address).unwrap();
+  let _peer = peer.join().unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+
+  // 書き込みきれないデータが high-water mark に達すると送信を受け付けない
+  let chunk = vec![0u8; 1024 * 1024];
+  let mut accepted = 0;
+  let err = loop {
+    match block_on(dispatcher.send(id, chunk.clone()).accepted()) {
+      Ok(()) => accepted += chunk.len(),
+      Err(err) => break err,
+    }
+    assert!(accepted < 1024 * 1024 * 1024, "the send buffer never filled");
+  };
+  assert_eq!(Error::SendBufferFull { id, limit: LIMIT }, err);
+  assert!(accepted >= LIMIT);
+
+  // 制限を解除すると受け付ける
+  block_on(dispatcher.set_write_high_water_mark(None)).unwrap();
+  block_on(dispatcher.send(id, chunk).accepted()).unwrap();
+
+  // 登録されていないソケット
+  let err = block_on(dispatcher.send(id + 1, vec![])).unwrap_err();
+  assert!(matches!(err, Error::Io { kind: ErrorKind::NotFound, .. }), "{:?}", err);
+}
+
+#[test]
+fn test_dispatcher_send_flushed() {
+  const TOTAL: usize = 16 * 1024 * 1024;
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  block_on(dispatcher.set_write_high_water_mark(None)).unwrap();
+  let expected = SampleValues::new(77120934u64).next_bytes(TOTAL);
+
+  // 指示があるまで読み込みを開始しないピア
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let (start, started) = channel::<()>();
+  let peer = spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    started.recv().unwrap();
+    let mut received = vec![0u8; TOTAL];
+    stream.read_exact(&mut received).unwrap();
+    (received, stream)
+  });
+  let stream = TcpStream::connect(address).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+
+  // ピアが読み込むまでは書き込みが完了しない
+  let (sender, flushed) = channel();
+  let data = expected.clone();
+  let sending = dispatcher.clone();
+  spawn(move || sender.send(block_on(sending.send(id, data))).unwrap());
+  assert!(flushed.recv_timeout(Duration::from_millis(200)).is_err());
+
+  // すべてのデータが書き込まれると完了し、ピアはすべてのデータを受信している
+  start.send(()).unwrap();
+  flushed.recv_timeout(Duration::from_secs(30)).unwrap().unwrap();
+  let (received, _stream) = peer.join().unwrap();
+  assert_eq!(expected, received);
+
+  // 書き込むデータがなければ即座に完了し、破棄されたソケットではエラーとなる
+  block_on(dispatcher.send(id, vec![])).unwrap();
+  let (_peer, other) = {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let peer = listener.accept().unwrap().0;
+    let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+    (peer, block_on(dispatcher.register(stream, listener)).unwrap())
+  };
+  let pending = dispatcher.send(other, vec![0u8; TOTAL]);
+  block_on(dispatcher.dispose(other)).unwrap();
+  assert_eq!(Error::SocketDisposed { id: other }, block_on(pending).unwrap_err());
+}
+
+#[test]
+fn test_dispatcher_cancel_registration() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = server.local_addr().unwrap();
+
+  // イベントループをブロックしている間に登録の Future を破棄する
+  let (release, gate) = channel::<()>();
+  let blocked = dispatcher.run_in_event_loop(Box::new(move |_: &mut PollingLoop| {
+    gate.recv().unwrap();
+    Ok(())
+  }));
+  let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+  drop(dispatcher.register(TcpStream::connect(address).unwrap(), listener));
+  let (mut peer, _) = server.accept().unwrap();
+  release.send(()).unwrap();
+  block_on(blocked).unwrap();
+
+  // ソケットは登録されずにクローズされている
+  assert!(block_on(dispatcher.list_sockets()).unwrap().is_empty());
+  peer.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  assert_eq!(0, peer.read(&mut [0u8; 16]).unwrap());
+
+  // detach() した Future の登録は実行される
+  let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+  dispatcher.register(TcpStream::connect(address).unwrap(), listener).detach();
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while block_on(dispatcher.list_sockets()).unwrap().is_empty() {
+    assert!(Instant::now() < deadline, "the detached registration has not been executed");
+    std::thread::sleep(Duration::from_millis(10));
+  }
+}
+
+#[test]
+fn test_dispatcher_hand_off() {
+  let acceptor = Dispatcher::new(1024, 1024).unwrap();
+  let worker = Dispatcher::new(1024, 1024).unwrap();
+
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let (sender, receiver) = channel();
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(BufferedClient::new(sender));
+  let id = block_on(acceptor.register(stream, listener)).unwrap();
+  let (mut peer, _) = server.accept().unwrap();
+  peer.write_all(b"hello, ").unwrap();
+
+  // 書き込みきれていないデータを保持している間は引き渡すことができない
+  let pending = Box::new(move |polling: &mut PollingLoop| {
+    polling.sockets.outbounds.insert(id, vec![0u8]);
+    Ok(())
+  });
+  block_on(acceptor.run_in_event_loop(pending)).unwrap();
+  let err = block_on(acceptor.hand_off(id)).err().unwrap();
+  assert!(matches!(err, Error::Io { kind: ErrorKind::WouldBlock, .. }), "{:?}", err);
+  let pending = Box::new(move |polling: &mut PollingLoop| {
+    polling.sockets.outbounds.remove(&id);
+    Ok(())
+  });
+  block_on(acceptor.run_in_event_loop(pending)).unwrap();
+
+  // 引き渡したソケットは元のディスパッチャーから取り除かれ、クローズされない
+  let (stream, listener) = block_on(acceptor.hand_off(id)).unwrap();
+  assert_eq!(0, block_on(acceptor.metrics()).unwrap().registered_sockets);
+  let err = block_on(acceptor.hand_off(id)).err().unwrap();
+  assert!(matches!(err, Error::Io { kind: ErrorKind::NotFound, .. }), "{:?}", err);
+
+  // 別のディスパッチャーに登録した接続で、Listener の状態を引き継いだままデータの送受信が続けられる
+  let id = block_on(worker.register(stream, listener)).unwrap();
+  block_on(worker.send(id, b"welcome".to_vec())).unwrap();
+  let mut buffer = [0u8; 7];
+  peer.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  peer.read_exact(&mut buffer).unwrap();
+  assert_eq!(b"welcome", &buffer);
+  peer.write_all(b"world").unwrap();
+  drop(peer);
+  let (received, _) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert_eq!(b"hello, world".to_vec(), received);
+}
+
+#[test]
+fn test_dispatcher_buffered_read() {
+  const BUFFER_SIZE: usize = 1000;
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  block_on(dispatcher.set_read_buffer_size(BUFFER_SIZE)).unwrap();
+
+  // ピアが書き込んでクローズしたデータ
+  let expected = SampleValues::new(28475610u64).next_bytes(256 * 1024);
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let data = expected.clone();
+  let peer = spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.write_all(&data).unwrap();
+  });
+
+  let (sender, receiver) = channel();
+  let stream = TcpStream::connect(address).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(BufferedClient::new(sender));
+  block_on(dispatcher.register(stream, listener)).unwrap();
+
+  // on_data() にはバッファサイズ以下の断片でピアが書き込んだバイト列がそのまま渡される
+  let (received, max_chunk) = receiver.recv_timeout(Duration::from_secs(30)).unwrap();
+  assert_eq!(expected, received);
+  assert!(max_chunk <= BUFFER_SIZE);
+  peer.join().unwrap();
+}
+
+#[test]
+fn test_dispatcher_drains_burst_in_single_event() {
+  const CHUNK: usize = 16 * 1024;
+  const CHUNKS: usize = 4;
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+
+  // ピアが複数の断片を一度に書き込み、すべてがソケットに到着してから登録する
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+  let (mut peer, _) = listener.accept().unwrap();
+  for i in 0..CHUNKS {
+    peer.write_all(&[i as u8; CHUNK]).unwrap();
+  }
+  let mut available = 0;
+  while available < CHUNK * CHUNKS {
+    let mut buffer = vec![0u8; CHUNK * CHUNKS];
+    available = stream.peek(&mut buffer).unwrap();
+  }
+  stream.set_nonblocking(true).unwrap();
+
+  let (sender, receiver) = channel();
+  let client = DrainingClient { events: 0, received: Vec::new(), expected: CHUNK * CHUNKS, sender };
+  let client: Box<dyn TcpStreamListener> = Box::new(client);
+  block_on(dispatcher.register(TcpStream::from_std(stream), client)).unwrap();
+
+  // 1 回の読み込み可能イベントですべての断片が読み込まれる
+  let (events, received) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert_eq!(1, events);
+  let expected = (0..CHUNKS).flat_map(|i| vec![i as u8; CHUNK]).collect::<Vec<u8>>();
+  assert_eq!(expected, received);
+}
+
+#[test]
+fn test_dispatcher_read_budget() {
+  const BUDGET: usize = 32 * 1024;
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  block_on(dispatcher.set_read_budget(Some(BUDGET))).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+  // データを送信し続けるピアと接続したソケット
+  let greedy = Arc::new(AtomicUsize::new(0));
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(GreedyClient(greedy.clone()));
+  block_on(dispatcher.register(stream, listener)).unwrap();
+  let (mut flooder, _) = server.accept().unwrap();
+  spawn(move || while flooder.write_all(&[0u8; 64 * 1024]).is_ok() {});
+
+  // 応答の速さが求められるソケット
+  let (sender, receiver) = channel();
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let listener = LatencyClient { greedy: greedy.clone(), sender };
+  let listener: Box<dyn TcpStreamListener> = Box::new(listener);
+  block_on(dispatcher.register(stream, listener)).unwrap();
+  let (mut peer, _) = server.accept().unwrap();
+
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while greedy.load(Ordering::SeqCst) < 16 * BUDGET {
+    assert!(Instant::now() < deadline, "the greedy socket has not been read");
+    std::thread::yield_now();
+  }
+
+  // 各周期で読み込み続けるソケットから読み込む量は上限までであるため、もう一方のソケットは数周期のうちに処理される
+  for _ in 0..10 {
+    let before = greedy.load(Ordering::SeqCst);
+    peer.write_all(b"ping").unwrap();
+    let serviced = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(serviced - before <= 3 * BUDGET, "{} bytes read before servicing", serviced - before);
+  }
+}
+
+#[test]
+fn test_read_until_would_block() {
+  // WouldBlock または EOF まで断片ごとに読み込む
+  let mut chunks = Vec::new();
+  let mut r = std::io::Cursor::new(vec![1u8, 2, 3, 4, 5]);
+  let eof = read_until_would_block(&mut r, &mut [0u8; 2], &mut |chunk| {
+    chunks.push(chunk.to_vec());
+    true
+  });
+  assert!(eof.unwrap());
+  assert_eq!(vec![vec![1u8, 2], vec![3, 4], vec![5]], chunks);
+
+  // 中断した場合は false を返す
+  let mut r = std::io::Cursor::new(vec![1u8, 2, 3, 4, 5]);
+  assert!(!read_until_would_block(&mut r, &mut [0u8; 2], &mut |_| false).unwrap());
+  assert_eq!(2, r.position());
+}
+
+#[test]
+fn test_dispatcher_hangup() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let (sender, receiver) = channel();
+  let stream = TcpStream::connect(address).unwrap();
+  let client: Box<dyn TcpStreamListener> = Box::new(HangupClient { sender });
+  block_on(dispatcher.register(stream, client)).unwrap();
+
+  let mut reads = 0;
+  let mut next_hangup = || loop {
+    match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+      Some(half) => return half,
+      None => reads += 1,
+    }
+  };
+
+  // ピアが接続をクローズすると読み込み方向のクローズが通知される
+  let (peer, _) = listener.accept().unwrap();
+  drop(peer);
+  assert_eq!(Half::Read, next_hangup());
+
+  // 書き込みに失敗して書き込み方向もクローズされるとソケットは破棄される
+  assert_eq!(Half::Write, next_hangup());
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while block_on(dispatcher.metrics()).unwrap().registered_sockets != 0 {
+    assert!(Instant::now() < deadline);
+    std::thread::sleep(Duration::from_millis(10));
+  }
+
+  // 同じ方向のクローズは一度だけ通知され、長さ 0 の読み込みイベントが繰り返し発生していない
+  for event in receiver.try_iter() {
+    assert!(event.is_none());
+    reads += 1;
+  }
+  assert!(reads <= 2, "{} read events", reads);
+}
+
+#[test]
+fn test_dispatcher_accept_burst() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let (accepted, accept) = channel();
+  let (rejected, _reject) = channel();
+  let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = listener.local_addr().unwrap();
+  let event_listener: Box<dyn TcpListenerListener> = Box::new(Acceptor { accepted, rejected });
+  block_on(dispatcher.register(listener, event_listener)).unwrap();
+
+  // イベントループをブロックしている間に 5 つの接続を同時に到着させる
+  let (release, gate) = channel::<()>();
+  let blocked = dispatcher.run_in_event_loop(Box::new(move |_: &mut PollingLoop| {
+    gate.recv().unwrap();
+    Ok(())
+  }));
+  let clients = (0..5).map(|_| std::net::TcpStream::connect(address).unwrap()).collect::<Vec<_>>();
+  release.send(()).unwrap();
+  block_on(blocked).unwrap();
+
+  // 1 回の読み込み可能イベントですべての接続が受け付けられる
+  let mut addresses = (0..clients.len())
+    .map(|_| accept.recv_timeout(Duration::from_secs(2)).unwrap().peer_addr().unwrap())
+    .collect::<Vec<_>>();
+  let mut expected = clients.iter().map(|client| client.local_addr().unwrap()).collect::<Vec<_>>();
+  addresses.sort();
+  expected.sort();
+  assert_eq!(expected, addresses);
+}
+
+#[test]
+fn test_dispatcher_max_connections() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  block_on(dispatcher.set_max_connections(Some(2))).unwrap();
 
-use crate::bridge::io::dispatcher::{Dispatcher, TcpStreamListener, DispatcherAction};
-use std::io::{Read, Write};
-use mio::Interest;
+  let (accepted, accept) = channel();
+  let (rejected, reject) = channel();
+  let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = listener.local_addr().unwrap();
+  let event_listener: Box<dyn TcpListenerListener> = Box::new(Acceptor { accepted, rejected });
+  block_on(dispatcher.register(listener, event_listener)).unwrap();
+
+  // 上限までの接続は受け付けられる
+  let mut clients = Vec::new();
+  for _ in 0..2 {
+    clients.push(std::net::TcpStream::connect(address).unwrap());
+    let stream = accept.recv_timeout(Duration::from_secs(10)).unwrap();
+    let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+    block_on(dispatcher.register(stream, listener)).unwrap();
+  }
+
+  // 上限を超えた接続は拒否されクローズされる
+  let mut client = std::net::TcpStream::connect(address).unwrap();
+  let address = reject.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert_eq!(client.local_addr().unwrap(), address);
+  client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  let mut buffer = [0u8; 1];
+  match client.read(&mut buffer) {
+    Ok(len) => assert_eq!(0, len),
+    Err(err) => assert_eq!(ErrorKind::ConnectionReset, err.kind()),
+  }
+  assert!(accept.try_recv().is_err());
+  let metrics = block_on(dispatcher.metrics()).unwrap();
+  assert_eq!((3, 1), (metrics.registered_sockets, metrics.listener_count));
+}
 
 #[test]
-fn test_dispatcher() {
-  let dispatcher = Dispatcher::new(1024).unwrap();
+fn test_dispatcher_max_connections_burst() {
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  block_on(dispatcher.set_max_connections(Some(2))).unwrap();
+
+  // Listener を登録する前に接続しておき、1 回の読み込み可能イベントですべての接続を受け付けさせる
+  let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = listener.local_addr().unwrap();
+  let _clients = (0..5).map(|_| std::net::TcpStream::connect(address).unwrap()).collect::<Vec<_>>();
+  let (rejected, reject) = channel();
+  let dispatcher_ref = Arc::downgrade(&dispatcher);
+  let event_listener: Box<dyn TcpListenerListener> =
+    Box::new(RegisteringAcceptor { dispatcher: dispatcher_ref, rejected });
+  block_on(dispatcher.register(listener, event_listener)).unwrap();
+
+  // 受け付けた接続の登録はイベントの処理後に実行されるが、登録中の接続も上限に数えられる
+  for _ in 0..3 {
+    reject.recv_timeout(Duration::from_secs(10)).unwrap();
+  }
+  assert!(reject.recv_timeout(Duration::from_millis(100)).is_err());
+  let metrics = block_on(dispatcher.metrics()).unwrap();
+  assert_eq!((3, 1), (metrics.registered_sockets, metrics.listener_count));
+}
+
+#[test]
+fn test_accept_rate_limiter() {
+  let limit = AcceptRateLimit { per_second: 10.0, burst: 3 };
+  let mut limiter = AcceptRateLimiter::new(limit);
+  let (a, b) = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+  let now = Instant::now();
+
+  // バケットの容量までは連続して受け付け、それを超えた接続は拒否される
+  assert_eq!(
+    vec![true, true, true, false, false],
+    (0..5).map(|_| limiter.try_acquire(a, now)).collect::<Vec<_>>()
+  );
+
+  // 他の接続元は影響を受けない
+  assert!(limiter.try_acquire(b, now));
+
+  // 時間の経過に応じてトークンが補充される
+  assert!(limiter.try_acquire(a, now + Duration::from_millis(100)));
+  assert!(!limiter.try_acquire(a, now + Duration::from_millis(100)));
+  let later = now + Duration::from_secs(10);
+  assert_eq!(3, (0..5).filter(|_| limiter.try_acquire(a, later)).count());
+}
+
+#[test]
+fn test_dispatcher_accept_rate_limit() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let limit = AcceptRateLimit { per_second: 0.001, burst: 2 };
+  block_on(dispatcher.set_accept_rate_limit(Some(limit))).unwrap();
+
+  let (accepted, accept) = channel();
+  let (rejected, reject) = channel();
+  let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = listener.local_addr().unwrap();
+  let event_listener: Box<dyn TcpListenerListener> = Box::new(Acceptor { accepted, rejected });
+  block_on(dispatcher.register(listener, event_listener)).unwrap();
+
+  // 指定された接続元アドレスから接続し、受け付けられたかを判定する
+  let connect = |source: Ipv4Addr| {
+    let socket = mio::net::TcpSocket::new_v4().unwrap();
+    socket.bind(SocketAddr::new(IpAddr::V4(source), 0)).unwrap();
+    let client = socket.connect(address).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+      if let Ok(stream) = accept.try_recv() {
+        assert_eq!(client.local_addr().unwrap(), stream.peer_addr().unwrap());
+        break (client, true);
+      }
+      if let Ok(address) = reject.try_recv() {
+        assert_eq!(IpAddr::V4(source), address.ip());
+        break (client, false);
+      }
+      assert!(Instant::now() < deadline, "the connection has not been processed");
+      std::thread::sleep(Duration::from_millis(1));
+    }
+  };
+
+  // 同じ接続元から続けて接続すると、容量を超えた接続は拒否される
+  let mut clients = Vec::new();
+  let mut results = Vec::new();
+  for _ in 0..5 {
+    let (client, accepted) = connect(Ipv4Addr::LOCALHOST);
+    clients.push(client);
+    results.push(accepted);
+  }
+  assert_eq!(vec![true, true, false, false, false], results);
+
+  // 異なる接続元からの接続は影響を受けない
+  let (_client, accepted) = connect(Ipv4Addr::new(127, 0, 0, 2));
+  assert!(accepted);
+}
+
+#[test]
+fn test_dispatcher_survives_reregister_failure() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+
+  // 無関係なソケットと、登録の変更に失敗させるソケットを登録する (ピアがクローズしないように受け付けないでおく)
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = server.local_addr().unwrap();
+  let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+  block_on(dispatcher.register(TcpStream::connect(address).unwrap(), listener)).unwrap();
+  let (sender, receiver) = channel();
+  let listener: Box<dyn TcpStreamListener> = Box::new(RetryingClient(sender));
+  let id = block_on(dispatcher.register(TcpStream::connect(address).unwrap(), listener)).unwrap();
+  assert_eq!(2, block_on(dispatcher.metrics()).unwrap().registered_sockets);
+
+  // ディスパッチャーの管理外で Poll への登録を解除し、以降の reregister() を失敗させる
+  block_on(dispatcher.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+    if let Some(socket) = polling.sockets.get(id) {
+      if let Socket::Stream(stream, _) = socket.lock()?.deref_mut() {
+        polling.poll.registry().deregister(stream)?;
+      }
+    }
+    Ok(())
+  })))
+  .unwrap();
 
+  // 失敗は on_error() に通知され、再試行も失敗したソケットだけが破棄される
+  block_on(dispatcher.resume_reads(id)).unwrap();
+  assert_eq!(1, block_on(dispatcher.metrics()).unwrap().registered_sockets);
+  assert_eq!(1, receiver.try_iter().count());
+
+  // イベントループは停止せずに他のソケットの処理を続ける
+  let (sender, receiver) = channel();
+  let listener: Box<dyn TcpStreamListener> = Box::new(EchoClient::new("hello, world", sender));
   let address = echo_server("hello, world", 1);
-  println!("address: {}", address);
+  block_on(dispatcher.register(TcpStream::connect(address).unwrap(), listener)).unwrap();
+  let echo_back = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert_eq!("hello, world".as_bytes(), &echo_back[..]);
+}
+
+#[test]
+fn test_dispatcher_spurious_readiness() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let (accepted, accept) = channel();
+  let (rejected, _reject) = channel();
+  let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+  let event_listener: Box<dyn TcpListenerListener> = Box::new(Acceptor { accepted, rejected });
+  let listener_id = block_on(dispatcher.register(listener, event_listener)).unwrap();
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let received = Arc::new(Mutex::new(Vec::new()));
+  let errors = Arc::new(AtomicUsize::new(0));
+  let listener: Box<dyn TcpStreamListener> =
+    Box::new(SpuriousClient { received: received.clone(), errors: errors.clone() });
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let id = block_on(dispatcher.register(stream, listener)).unwrap();
+  let (mut peer, _) = server.accept().unwrap();
+
+  // 指定されたソケットに読み書きできないイベントを注入し、ソケットが登録されたままかを返す
+  let inject = |id| {
+    block_on(dispatcher.run_in_event_loop(Box::new(move |polling: &mut PollingLoop| {
+      let readiness = Readiness { readable: true, writable: true, ..Readiness::default() };
+      let socket = polling.sockets.get(id).unwrap();
+      polling.dispatch(id, readiness, &socket)?;
+      Ok(polling.sockets.contains(id))
+    })))
+    .unwrap()
+  };
+
+  // データのない読み込み可能イベントや接続のない受け付けイベントはエラーや EOF として扱われない
+  assert!(inject(id));
+  assert!(inject(listener_id));
+  assert_eq!(0, errors.load(Ordering::SeqCst));
+  assert!(received.lock().unwrap().is_empty());
+  assert!(accept.try_recv().is_err());
+  assert_eq!(2, block_on(dispatcher.metrics()).unwrap().registered_sockets);
+
+  // 読み込みを停止している間の読み込み可能イベントは無視される
+  peer.write_all(b"a").unwrap();
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while received.lock().unwrap().is_empty() {
+    assert!(Instant::now() < deadline);
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  peer.write_all(b"b").unwrap();
+  std::thread::sleep(Duration::from_millis(100));
+  assert!(inject(id));
+  assert_eq!(b"a", &received.lock().unwrap()[..]);
+
+  // 読み込みを再開すると停止中に到着したデータが読み込まれる
+  block_on(dispatcher.resume_reads(id)).unwrap();
+  while received.lock().unwrap().len() < 2 {
+    assert!(Instant::now() < deadline);
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  assert_eq!(b"ab", &received.lock().unwrap()[..]);
+  assert_eq!(0, errors.load(Ordering::SeqCst));
+}
+
+/// 読み込み可能イベントごとに `WouldBlock` となるまで読み込み、データを受信した場合は読み込みを一時停止する
+/// TcpStreamListener。
+struct SpuriousClient {
+  received: Arc<Mutex<Vec<u8>>>,
+  errors: Arc<AtomicUsize>,
+}
+
+impl TcpStreamListener for SpuriousClient {
+  fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction {
+    let mut buffer = [0u8; 1024];
+    let mut received = self.received.lock().unwrap();
+    let length = received.len();
+    match read_until_would_block(r, &mut buffer, &mut |chunk| {
+      received.extend_from_slice(chunk);
+      true
+    }) {
+      Ok(_) if received.len() > length => DispatcherAction::PauseReads,
+      Ok(_) => DispatcherAction::Continue,
+      Err(err) => {
+        drop(received);
+        self.on_error(err)
+      }
+    }
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    self.errors.fetch_add(1, Ordering::SeqCst);
+    DispatcherAction::Dispose
+  }
+}
+
+/// on_error() で通知されたエラーの種類を送信し、読み込みの再開を再試行する TcpStreamListener。
+struct RetryingClient(Sender<ErrorKind>);
+
+impl TcpStreamListener for RetryingClient {
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    self.0.send(error.kind()).unwrap();
+    DispatcherAction::ResumeReads
+  }
+}
+
+/// 受け付けた接続と拒否した接続をそれぞれ送信する TcpListenerListener。
+struct Acceptor {
+  accepted: Sender<TcpStream>,
+  rejected: Sender<SocketAddr>,
+}
+
+impl TcpListenerListener for Acceptor {
+  fn on_accept(&mut self, stream: TcpStream, _address: SocketAddr) -> DispatcherAction {
+    self.accepted.send(stream).unwrap();
+    DispatcherAction::Continue
+  }
+  fn on_rejected(&mut self, address: SocketAddr) -> DispatcherAction {
+    self.rejected.send(address).unwrap();
+    DispatcherAction::Continue
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    panic!("{}", error)
+  }
+}
+
+/// 受け付けた接続を `Dispatcher::try_register()` で登録し、拒否された接続のアドレスを送信する TcpListenerListener。
+struct RegisteringAcceptor {
+  dispatcher: Weak<Dispatcher>,
+  rejected: Sender<SocketAddr>,
+}
+
+impl TcpListenerListener for RegisteringAcceptor {
+  fn on_accept(&mut self, stream: TcpStream, _address: SocketAddr) -> DispatcherAction {
+    let dispatcher = self.dispatcher.upgrade().unwrap();
+    let listener: Box<dyn TcpStreamListener> = Box::new(NullClient);
+    dispatcher.try_register(stream, listener).unwrap().detach();
+    DispatcherAction::Continue
+  }
+  fn on_rejected(&mut self, address: SocketAddr) -> DispatcherAction {
+    self.rejected.send(address).unwrap();
+    DispatcherAction::Continue
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    panic!("{}", error)
+  }
+}
+
+/// 最初の読み込みイベントで 1 回だけ読み込んで読み込みを一時停止し、EOF に達したときに読み込んだバイト数を送信する
+/// TcpStreamListener。ピアの書き込み速度によらず一時停止中にピアの書き込みがブロックするように、最初のイベントでは
+/// `WouldBlock` となるまで読み込まない。
+struct PausingClient {
+  received: usize,
+  paused: bool,
+  sender: Sender<usize>,
+}
+
+impl PausingClient {
+  fn new(sender: Sender<usize>) -> PausingClient {
+    PausingClient { received: 0, paused: false, sender }
+  }
+}
+
+impl TcpStreamListener for PausingClient {
+  fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction {
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+      match r.read(&mut buffer) {
+        Ok(0) => {
+          self.sender.send(self.received).unwrap();
+          return DispatcherAction::Dispose;
+        }
+        Ok(len) => self.received += len,
+        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+        Err(err) => return self.on_error(err),
+      }
+      if !self.paused {
+        self.paused = true;
+        return DispatcherAction::PauseReads;
+      }
+    }
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    panic!("{}", error)
+  }
+}
+
+/// 最初の書き込み可能イベントで `DispatcherAction::Write` によってすべてのデータの書き込みを指示する
+/// TcpStreamListener。
+struct WriteActionClient {
+  data: Option<Vec<u8>>,
+  /// `on_ready_to_write()` が呼び出された回数。
+  writes: Arc<AtomicUsize>,
+}
+
+impl TcpStreamListener for WriteActionClient {
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    self.writes.fetch_add(1, Ordering::SeqCst);
+    match self.data.take() {
+      Some(data) => DispatcherAction::Write(data),
+      None => DispatcherAction::Continue,
+    }
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    panic!("{}", error)
+  }
+}
+
+/// 受信したメッセージを `MessageQueue` に格納し、キューが満杯の間は読み込みを停止する TcpStreamListener。
+struct QueueClient {
+  decoder: StreamDecoder,
+  queue: MessageQueue,
+  /// キューが満杯のため格納できなかったメッセージ。
+  pending: Option<Message>,
+  pauses: Arc<AtomicUsize>,
+}
+
+impl QueueClient {
+  fn new(queue: MessageQueue, pauses: Arc<AtomicUsize>) -> QueueClient {
+    QueueClient { decoder: StreamDecoder::new(), queue, pending: None, pauses }
+  }
+
+  /// 復元したメッセージをキューに格納します。キューが満杯になった場合は読み込みを停止します。
+  fn drain(&mut self) -> DispatcherAction {
+    loop {
+      let msg = match self.pending.take() {
+        Some(msg) => msg,
+        None => match self.decoder.next_message().unwrap() {
+          Some(msg) => msg,
+          None => return DispatcherAction::Continue,
+        },
+      };
+      if self.queue.is_full() {
+        self.pending = Some(msg);
+        self.pauses.fetch_add(1, Ordering::SeqCst);
+        return DispatcherAction::PauseReads;
+      }
+      self.queue.push(msg).unwrap();
+    }
+  }
+}
+
+impl TcpStreamListener for QueueClient {
+  fn read_mode(&self) -> ReadMode {
+    ReadMode::Buffered
+  }
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    unreachable!()
+  }
+  fn on_data(&mut self, data: &[u8]) -> DispatcherAction {
+    self.decoder.push(data);
+    self.drain()
+  }
+  fn on_reads_paused(&mut self) -> DispatcherAction {
+    match self.drain() {
+      DispatcherAction::Continue => DispatcherAction::ResumeReads,
+      action => action,
+    }
+  }
+  fn on_eof(&mut self) -> DispatcherAction {
+    // 復元済みのメッセージをキューに格納し終えるまでソケットを破棄しない
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    panic!("{}", error)
+  }
+}
+
+/// `ReadMode::Buffered` で受信したデータを蓄積し、EOF に達したときにデータと最大の断片サイズを送信する
+/// TcpStreamListener。
+struct BufferedClient {
+  received: Vec<u8>,
+  max_chunk: usize,
+  sender: Sender<(Vec<u8>, usize)>,
+}
+
+impl BufferedClient {
+  fn new(sender: Sender<(Vec<u8>, usize)>) -> BufferedClient {
+    BufferedClient { received: Vec::new(), max_chunk: 0, sender }
+  }
+}
+
+impl TcpStreamListener for BufferedClient {
+  fn read_mode(&self) -> ReadMode {
+    ReadMode::Buffered
+  }
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    panic!("on_ready_to_read() must not be called in buffered mode")
+  }
+  fn on_data(&mut self, data: &[u8]) -> DispatcherAction {
+    if data.is_empty() {
+      self.sender.send((std::mem::take(&mut self.received), self.max_chunk)).unwrap();
+      return DispatcherAction::Dispose;
+    }
+    self.max_chunk = std::cmp::max(self.max_chunk, data.len());
+    self.received.extend_from_slice(data);
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    panic!("{}", error)
+  }
+}
+
+/// `read_until_would_block()` で読み込み、期待したバイト数に達したときに読み込み可能イベントの回数と受信した
+/// データを送信する TcpStreamListener。
+struct DrainingClient {
+  events: usize,
+  received: Vec<u8>,
+  expected: usize,
+  sender: Sender<(usize, Vec<u8>)>,
+}
+
+impl TcpStreamListener for DrainingClient {
+  fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction {
+    self.events += 1;
+    let received = &mut self.received;
+    if let Err(err) = read_until_would_block(r, &mut [0u8; 1024], &mut |chunk| {
+      received.extend_from_slice(chunk);
+      true
+    }) {
+      return self.on_error(err);
+    }
+    if self.received.len() >= self.expected {
+      self.sender.send((self.events, std::mem::take(&mut self.received))).unwrap();
+      return DispatcherAction::Dispose;
+    }
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    panic!("{}", error)
+  }
+}
+
+/// 片方向のクローズを送信し、読み込み方向がクローズされた後はピアへの書き込みを試みる TcpStreamListener。読み込み
+/// イベントごとに `None` を送信する。
+struct HangupClient {
+  sender: Sender<Option<Half>>,
+}
+
+impl TcpStreamListener for HangupClient {
+  fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction {
+    let _ = self.sender.send(None);
+    let mut buffer = [0u8; 1024];
+    while let Ok(len) = r.read(&mut buffer) {
+      if len == 0 {
+        break;
+      }
+    }
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, w: &mut dyn Write) -> DispatcherAction {
+    let _ = w.write(b"bye");
+    DispatcherAction::Continue
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_hangup(&mut self, half: Half) -> DispatcherAction {
+    let _ = self.sender.send(Some(half));
+    match half {
+      Half::Read => DispatcherAction::ChangeFlag(Interest::READABLE | Interest::WRITABLE),
+      Half::Write => DispatcherAction::Continue,
+    }
+  }
+}
+
+/// 何もせずに on_error() で通知されたエラーの種類を送信する TcpStreamListener。
+struct SilentClient(Sender<ErrorKind>);
+
+impl TcpStreamListener for SilentClient {
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    self.0.send(error.kind()).unwrap();
+    DispatcherAction::Continue
+  }
+}
+
+/// 読み込み可能イベントで到着したデータを読み捨て、自身のタグを送信する TcpStreamListener。
+struct TaggedClient {
+  tag: &'static str,
+  sender: Sender<&'static str>,
+}
+
+impl TcpStreamListener for TaggedClient {
+  fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction {
+    let mut buffer = [0u8; 1024];
+    read_until_would_block(r, &mut buffer, &mut |_| true).unwrap();
+    self.sender.send(self.tag).unwrap();
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
+}
+
+/// EOF に達するまで読み込み、on_eof() が呼び出されるたびに通知を送信する TcpStreamListener。
+/// EOF の後もソケットを破棄しない。
+struct EofClient {
+  sender: Sender<()>,
+}
+
+impl TcpStreamListener for EofClient {
+  fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction {
+    let mut buffer = [0u8; 1024];
+    read_until_would_block(r, &mut buffer, &mut |_| true).unwrap();
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
+  fn on_eof(&mut self) -> DispatcherAction {
+    self.sender.send(()).unwrap();
+    DispatcherAction::Continue
+  }
+}
+
+/// on_disposed() が呼び出された回数を数える TcpStreamListener。
+struct DisposedClient(Arc<AtomicUsize>);
+
+impl TcpStreamListener for DisposedClient {
+  fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction {
+    let mut buffer = [0u8; 1024];
+    read_until_would_block(r, &mut buffer, &mut |_| true).unwrap();
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
+  fn on_disposed(&mut self) {
+    self.0.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+/// on_disposed() が呼び出された回数を数える TcpListenerListener。
+struct DisposedAcceptor(Arc<AtomicUsize>);
+
+impl TcpListenerListener for DisposedAcceptor {
+  fn on_accept(&mut self, _stream: TcpStream, _address: SocketAddr) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
+  fn on_disposed(&mut self) {
+    self.0.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+/// on_disposed() が呼び出された順序を記録する TcpStreamListener および TcpListenerListener。on_accept() が呼び出された
+/// 場合はテストを失敗させる。
+struct DisposalRecorder {
+  tag: &'static str,
+  order: Arc<Mutex<Vec<&'static str>>>,
+}
+
+impl TcpStreamListener for DisposalRecorder {
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_disposed(&mut self) {
+    self.order.lock().unwrap().push(self.tag);
+  }
+}
+
+impl TcpListenerListener for DisposalRecorder {
+  fn on_accept(&mut self, _stream: TcpStream, address: SocketAddr) -> DispatcherAction {
+    panic!("the connection from {} must not be accepted during shutdown", address)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_disposed(&mut self) {
+    self.order.lock().unwrap().push(self.tag);
+  }
+}
+
+/// ReadMode::Buffered で受信したバイト数を数える TcpStreamListener。
+struct GreedyClient(Arc<AtomicUsize>);
+
+impl TcpStreamListener for GreedyClient {
+  fn read_mode(&self) -> ReadMode {
+    ReadMode::Buffered
+  }
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    panic!("on_ready_to_read() must not be called in buffered mode")
+  }
+  fn on_data(&mut self, data: &[u8]) -> DispatcherAction {
+    self.0.fetch_add(data.len(), Ordering::SeqCst);
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
+}
+
+/// ReadMode::Buffered でデータを受信するたびに、その時点で `greedy` が数えているバイト数を送信する
+/// TcpStreamListener。
+struct LatencyClient {
+  greedy: Arc<AtomicUsize>,
+  sender: Sender<usize>,
+}
+
+impl TcpStreamListener for LatencyClient {
+  fn read_mode(&self) -> ReadMode {
+    ReadMode::Buffered
+  }
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    panic!("on_ready_to_read() must not be called in buffered mode")
+  }
+  fn on_data(&mut self, data: &[u8]) -> DispatcherAction {
+    if !data.is_empty() {
+      self.sender.send(self.greedy.load(Ordering::SeqCst)).unwrap();
+    }
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
+}
+
+/// 何もしない TcpStreamListener。
+struct NullClient;
+
+/// 終了時に終了理由のコードと理由をそのまま書き込む TcpStreamListener です。
+struct ShutdownNotifier;
+
+impl TcpStreamListener for ShutdownNotifier {
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
+  fn on_shutdown(&mut self, reason_code: u16, reason: &[u8]) -> Option<Vec<u8>> {
+    let mut notice = reason_code.to_be_bytes().to_vec();
+    notice.extend_from_slice(reason);
+    Some(notice)
+  }
+}
 
-  let stream = TcpStream::connect(accress).unwrap();
-  dispatcher.register(stream, Box::new()
+impl TcpStreamListener for NullClient {
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+  fn on_error(&mut self, _error: std::io::Error) -> DispatcherAction {
+    DispatcherAction::Dispose
+  }
 }
 
 struct EchoClient {
   buffer: &'static str,
   position: usize,
-  echo_back: Box<[u8]>,
+  echo_back: Vec<u8>,
+  sender: Sender<Vec<u8>>,
 }
 
 impl EchoClient {
-  fn new(message: &'static str) -> EchoClient {
-    EchoClient { buffer: message, position: 0, echo_back: Box::new()}
+  fn new(message: &'static str, sender: Sender<Vec<u8>>) -> EchoClient {
+    EchoClient { buffer: message, position: 0, echo_back: Vec::new(), sender }
   }
 }
 
 impl TcpStreamListener for EchoClient {
   fn on_ready_to_read(&mut self, r: &mut dyn Read) -> DispatcherAction {
     println!("EchoClient::on_ready_to_read()");
+    let mut buffer = [0u8; 1024];
+    loop {
+      match r.read(&mut buffer) {
+        Ok(0) => break,
+        Ok(len) => self.echo_back.extend_from_slice(&buffer[..len]),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+        Err(err) => return self.on_error(err),
+      }
+    }
+    if self.echo_back.len() == self.buffer.len() {
+      self.sender.send(self.echo_back.clone()).unwrap();
+      DispatcherAction::Dispose
+    } else {
+      DispatcherAction::Continue
+    }
   }
   fn on_ready_to_write(&mut self, w: &mut dyn Write) -> DispatcherAction {
     println!("EchoClient::on_ready_to_write()");
-    let len = w.write(buffer[position..]).unwrap();
+    let len = match w.write(&self.buffer.as_bytes()[self.position..]) {
+      Ok(len) => len,
+      Err(err) if err.kind() == ErrorKind::WouldBlock => 0,
+      Err(err) => return self.on_error(err),
+    };
     self.position += len;
     if self.position == self.buffer.len() {
       DispatcherAction::ChangeFlag(Interest::READABLE)
@@ -54,18 +2056,17 @@ impl TcpStreamListener for EchoClient {
 fn echo_server(expected: &'static str, clients: usize) -> SocketAddr {
   let ip_address = IpAddr::from(Ipv4Addr::new(127, 0, 0, 1));
   let address = SocketAddr::new(ip_address, 0);
-  let listener = TcpListener::bind(address).unwrap();
+  let listener = std::net::TcpListener::bind(address).unwrap();
   let port = listener.local_addr().unwrap().port();
   spawn(move || {
     for _ in 0..clients {
-      let (mut stream, address) = listener.accept().unwrap();
+      let (mut stream, _) = listener.accept().unwrap();
       for expected in expected.chars().map(|c| c as u8) {
         let actual = stream.read_u8().unwrap();
         assert_eq!(expected, actual);
         stream.write_u8(actual).unwrap();
       }
-      stream.read_u8().unwrap();
     }
   });
   SocketAddr::new(ip_address, port)
-}
\ No newline at end of file
+}
@@ -0,0 +1,192 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use log;
+
+use crate::bridge::io::dispatcher::{DispatcherAction, ReadMode, TcpStreamListener};
+
+#[cfg(test)]
+mod test;
+
+/// `OffloadingListener` が `Executor` で処理されていない入力を保持するデフォルトの上限 (バイト) です。
+pub const DEFAULT_MAX_PENDING_BYTES: usize = 1024 * 1024;
+
+/// 接続ごとの処理を実行するスレッドプールなどの外部の実行環境です。ディスパッチャーのイベントループをブロック
+/// しないように、CPU 負荷の高い処理をイベントループとは別のスレッドで実行するために使用します。
+pub trait Executor: Send + Sync {
+  /// 指定された処理を実行します。呼び出し元のスレッドをブロックせずに、別のスレッドで実行する必要があります。
+  fn execute(&self, task: Box<dyn FnOnce() + Send + 'static>);
+}
+
+/// `Executor` 上で実行される接続ごとの処理です。同じ接続に対する呼び出しは受信した順に 1 つずつ行われます。
+pub trait ConnectionHandler: Send + 'static {
+  /// 接続から受信したデータを渡して呼び出されます。
+  fn on_data(&mut self, data: Vec<u8>);
+
+  /// ピアが接続をクローズし、受信したすべてのデータを `on_data()` に渡した後に一度だけ呼び出されます。
+  fn on_eof(&mut self) {}
+}
+
+/// ソケットの読み込みはディスパッチャーで行い、受信したデータの処理を `Executor` に委譲する TcpStreamListener
+/// です。
+///
+/// `Executor` で処理されていない入力が上限に達するとソケットからの読み込みを一時停止し、上限の半分以下まで処理が
+/// 進んだ時点で再開します。読み込みを停止している間、ピアの送信は TCP のフロー制御によってブロックします。
+/// `ConnectionHandler` がパニックした場合、その接続は以降の入力を処理できないため破棄されます。
+pub struct OffloadingListener {
+  executor: Arc<dyn Executor>,
+  state: Arc<Mutex<OffloadState>>,
+  max_pending_bytes: usize,
+  /// 処理されていない入力が上限に達したために読み込みを一時停止している場合に true。
+  paused: bool,
+}
+
+/// `OffloadingListener` と `Executor` 上の処理が共有する状態です。
+struct OffloadState {
+  /// 接続ごとの処理。`Executor` 上で処理している間は取り出されているため `None` となります。
+  handler: Option<Box<dyn ConnectionHandler>>,
+  /// まだ処理していない入力。
+  pending: VecDeque<Input>,
+  /// `pending` に含まれるデータの合計バイト数。
+  pending_bytes: usize,
+  /// 入力を処理するタスクを `Executor` に投入済みの場合に true。
+  scheduled: bool,
+  /// 接続ごとの処理がパニックした場合に true。
+  failed: bool,
+}
+
+enum Input {
+  Data(Vec<u8>),
+  Eof,
+}
+
+impl OffloadingListener {
+  pub fn new(
+    executor: Arc<dyn Executor>,
+    handler: Box<dyn ConnectionHandler>,
+  ) -> OffloadingListener {
+    let state = OffloadState {
+      handler: Some(handler),
+      pending: VecDeque::new(),
+      pending_bytes: 0,
+      scheduled: false,
+      failed: false,
+    };
+    OffloadingListener {
+      executor,
+      state: Arc::new(Mutex::new(state)),
+      max_pending_bytes: DEFAULT_MAX_PENDING_BYTES,
+      paused: false,
+    }
+  }
+
+  /// `Executor` で処理されていない入力を保持する上限 (バイト) を設定します。
+  pub fn with_max_pending_bytes(mut self, max_pending_bytes: usize) -> Self {
+    self.max_pending_bytes = max_pending_bytes;
+    self
+  }
+
+  /// 入力を追加し、処理するタスクが投入されていなければ `Executor` に投入します。追加した後に処理されていない
+  /// データのバイト数を返します。接続ごとの処理がパニックしている場合は入力を追加せずに `None` を返します。
+  fn schedule(&self, input: Input) -> Option<usize> {
+    let mut state = self.state.lock().unwrap();
+    if state.failed {
+      return None;
+    }
+    if let Input::Data(data) = &input {
+      state.pending_bytes += data.len();
+    }
+    state.pending.push_back(input);
+    if !state.scheduled {
+      state.scheduled = true;
+      let shared = self.state.clone();
+      self.executor.execute(Box::new(move || drain(&shared)));
+    }
+    Some(state.pending_bytes)
+  }
+}
+
+/// `Executor` 上で未処理の入力がなくなるまで順に処理します。処理中はロックを解放しているため、ディスパッチャーは
+/// 処理を待たずに入力を追加することができます。接続ごとの処理がパニックした場合は残りの入力を破棄し、以降の入力を
+/// 受け付けないようにします。
+fn drain(shared: &Mutex<OffloadState>) {
+  let mut state = shared.lock().unwrap();
+  let mut handler = match state.handler.take() {
+    Some(handler) => handler,
+    None => return,
+  };
+  while let Some(input) = state.pending.pop_front() {
+    if let Input::Data(data) = &input {
+      state.pending_bytes -= data.len();
+    }
+    drop(state);
+    let result = catch_unwind(AssertUnwindSafe(|| match input {
+      Input::Data(data) => handler.on_data(data),
+      Input::Eof => handler.on_eof(),
+    }));
+    state = shared.lock().unwrap();
+    if result.is_err() {
+      log::error!("the connection handler panicked; the connection will be disposed");
+      state.pending.clear();
+      state.pending_bytes = 0;
+      state.failed = true;
+      state.scheduled = false;
+      return;
+    }
+  }
+  state.handler = Some(handler);
+  state.scheduled = false;
+}
+
+impl TcpStreamListener for OffloadingListener {
+  fn read_mode(&self) -> ReadMode {
+    ReadMode::Buffered
+  }
+
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+
+  fn on_data(&mut self, data: &[u8]) -> DispatcherAction {
+    if data.is_empty() {
+      return DispatcherAction::Continue;
+    }
+    match self.schedule(Input::Data(data.to_vec())) {
+      None => DispatcherAction::Dispose,
+      Some(pending_bytes) if pending_bytes >= self.max_pending_bytes && !self.paused => {
+        self.paused = true;
+        DispatcherAction::PauseReads
+      }
+      Some(_) => DispatcherAction::Continue,
+    }
+  }
+
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    log::warn!("disposing the connection: {}", error);
+    DispatcherAction::Dispose
+  }
+
+  fn on_eof(&mut self) -> DispatcherAction {
+    self.schedule(Input::Eof);
+    DispatcherAction::Dispose
+  }
+
+  /// 処理されていない入力が上限の半分以下になっていれば読み込みを再開します。
+  fn on_reads_paused(&mut self) -> DispatcherAction {
+    let state = self.state.lock().unwrap();
+    if state.failed {
+      DispatcherAction::Dispose
+    } else if self.paused && state.pending_bytes <= self.max_pending_bytes / 2 {
+      self.paused = false;
+      DispatcherAction::ResumeReads
+    } else {
+      DispatcherAction::Continue
+    }
+  }
+}
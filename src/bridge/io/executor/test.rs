@@ -0,0 +1,100 @@
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+use crate::bridge::io::dispatcher::{DispatcherAction, ReadMode, TcpStreamListener};
+use crate::bridge::io::executor::{ConnectionHandler, Executor, OffloadingListener};
+use crate::test::{RecordingHandler, WorkerExecutor};
+
+#[test]
+fn test_offloading_listener() {
+  let executor: Arc<dyn Executor> = Arc::new(WorkerExecutor::new("test-worker"));
+  let (sender, receiver) = channel();
+  let mut listener = OffloadingListener::new(executor, Box::new(RecordingHandler { sender }));
+  assert_eq!(ReadMode::Buffered, listener.read_mode());
+
+  // 受信したデータは Executor のスレッドで受信した順に処理され、最後に EOF が通知される
+  let feeder = spawn(move || {
+    for i in 0..100u8 {
+      assert!(matches!(listener.on_data(&[i]), DispatcherAction::Continue));
+    }
+    assert!(matches!(listener.on_data(&[]), DispatcherAction::Continue));
+    assert!(matches!(listener.on_eof(), DispatcherAction::Dispose));
+  });
+  for i in 0..100u8 {
+    let (name, data) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(Some("test-worker".to_string()), name);
+    assert_eq!(Some(vec![i]), data);
+  }
+  assert_eq!(
+    (Some("test-worker".to_string()), None),
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap()
+  );
+  feeder.join().unwrap();
+}
+
+/// 投入されたタスクを保持し、`run()` が呼び出されたときに呼び出し元のスレッドで実行する Executor です。
+#[derive(Default)]
+struct ManualExecutor {
+  tasks: Mutex<Vec<Box<dyn FnOnce() + Send + 'static>>>,
+}
+
+impl ManualExecutor {
+  fn run(&self) {
+    let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+    for task in tasks {
+      task();
+    }
+  }
+}
+
+impl Executor for ManualExecutor {
+  fn execute(&self, task: Box<dyn FnOnce() + Send + 'static>) {
+    self.tasks.lock().unwrap().push(task);
+  }
+}
+
+#[test]
+fn test_offloading_listener_pause_reads() {
+  let executor = Arc::new(ManualExecutor::default());
+  let (sender, receiver) = channel();
+  let handler = Box::new(RecordingHandler { sender });
+  let mut listener = OffloadingListener::new(executor.clone(), handler).with_max_pending_bytes(8);
+
+  // 処理されていない入力が上限に達すると読み込みを一時停止する
+  assert!(matches!(listener.on_data(&[0u8; 5]), DispatcherAction::Continue));
+  assert!(matches!(listener.on_data(&[1u8; 5]), DispatcherAction::PauseReads));
+  assert!(matches!(listener.on_reads_paused(), DispatcherAction::Continue));
+
+  // 処理が進むと読み込みを再開する
+  executor.run();
+  assert_eq!(Some(vec![0u8; 5]), receiver.try_recv().unwrap().1);
+  assert_eq!(Some(vec![1u8; 5]), receiver.try_recv().unwrap().1);
+  assert!(matches!(listener.on_reads_paused(), DispatcherAction::ResumeReads));
+  assert!(matches!(listener.on_data(&[2u8; 5]), DispatcherAction::Continue));
+}
+
+/// データを受信するとパニックする ConnectionHandler です。
+struct PanickingHandler;
+
+impl ConnectionHandler for PanickingHandler {
+  fn on_data(&mut self, _data: Vec<u8>) {
+    panic!("expected panic in the connection handler");
+  }
+}
+
+#[test]
+fn test_offloading_listener_handler_panic() {
+  let executor = Arc::new(ManualExecutor::default());
+  let mut listener =
+    OffloadingListener::new(executor.clone(), Box::new(PanickingHandler)).with_max_pending_bytes(8);
+  assert!(matches!(listener.on_data(&[0u8; 4]), DispatcherAction::Continue));
+  assert!(matches!(listener.on_data(&[0u8; 4]), DispatcherAction::PauseReads));
+
+  // 処理がパニックした接続は、読み込みの一時停止中も次の入力の受信時も破棄される
+  executor.run();
+  assert!(matches!(listener.on_reads_paused(), DispatcherAction::Dispose));
+  assert!(matches!(listener.on_data(&[1u8]), DispatcherAction::Dispose));
+  executor.run();
+}
@@ -1,4 +1,8 @@
 pub mod dispatcher;
+pub mod executor;
+#[cfg(feature = "async-io")]
+pub mod stream;
+pub mod wire;
 
 use std::sync::{Arc, RwLock};
 
@@ -8,7 +12,7 @@ use crate::Result;
 /// オープンまたはクローズの状態を持つデータの出力先です。オープン状態のときはデータを `push()` することができますが、
 /// クローズ状態で `push()` を行おうとすると失敗します。
 pub trait Gate<T> {
-  fn set_callback<F: FnMut(GateState) -> ()>(callback: F) -> ();
+  fn set_callback<F: FnMut(GateState)>(callback: F);
   fn push(value: T) -> Result<()>;
 }
 
@@ -21,6 +25,7 @@ pub enum GateState {
 pub struct Barrage<T, GATE: Gate<T>> {
   capacity: usize,
   queue: Arc<RwLock<Vec<T>>>,
+  #[allow(dead_code)]
   gate: GATE,
 }
 
@@ -40,6 +45,10 @@ impl<T, GATE: Gate<T>> Barrage<T, GATE> {
     queue.len()
   }
 
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
   /// このキューにメッセージを追加します。
   /// 正常に終了した場合、メッセージ追加後のキューのサイズを返します。
   pub fn push(&mut self, msg: T) -> Result<usize> {
@@ -0,0 +1,153 @@
+use std::io::{Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::Stream;
+use log;
+
+use crate::bridge::io::dispatcher::{DispatcherAction, ReadMode, TcpStreamListener};
+use crate::error::Error;
+use crate::msg::{Control, Message, StreamDecoder};
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// ディスパッチャーに登録した TcpStream から受信したメッセージを `futures::Stream` として参照する非同期 API です。
+/// `MessageStream::new()` で構築した `MessageStreamListener` をディスパッチャーに登録すると、受信したメッセージが
+/// 順にこのストリームから取り出せるようになります。
+///
+/// 受信したバイト列を復元できなかった場合や読み込みに失敗した場合はそのエラーを返し、ピアが接続をクローズした場合や
+/// ソケットが破棄された場合はストリームが終了します。
+pub struct MessageStream {
+  receiver: Receiver<Result<Message>>,
+}
+
+impl MessageStream {
+  /// 取り出されていないメッセージを最大 `capacity` 個まで保持する `MessageStream` と、ストリームにメッセージを供給
+  /// する TcpStreamListener を構築します。保持しているメッセージが上限に達している間は、Listener がソケットからの
+  /// 読み込みを一時停止するため、ピアの送信は TCP のフロー制御によってブロックします。
+  pub fn new(capacity: usize) -> (MessageStream, MessageStreamListener) {
+    let (sender, receiver) = channel(capacity);
+    let listener =
+      MessageStreamListener { decoder: StreamDecoder::new(), sender, pending: None, paused: false };
+    (MessageStream { receiver }, listener)
+  }
+}
+
+impl Stream for MessageStream {
+  type Item = Result<Message>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    Pin::new(&mut self.receiver).poll_next(cx)
+  }
+}
+
+/// 受信したバイト列からメッセージを復元し、`MessageStream` に供給する TcpStreamListener です。
+pub struct MessageStreamListener {
+  decoder: StreamDecoder,
+  sender: Sender<Result<Message>>,
+  /// ストリームが上限に達していたため供給できなかったメッセージ。
+  pending: Option<Result<Message>>,
+  /// ストリームが上限に達したために読み込みを一時停止している場合に true。
+  paused: bool,
+}
+
+impl MessageStreamListener {
+  /// 復元したメッセージをストリームが受け付ける限り供給します。ストリームが上限に達した場合は読み込みを一時停止し、
+  /// すべて供給した後に再開します。エラーを供給した場合やストリームが破棄されていた場合はソケットを破棄します。
+  fn deliver(&mut self) -> DispatcherAction {
+    loop {
+      let item = match self.pending.take() {
+        Some(item) => item,
+        None => match self.decoder.next_message() {
+          Ok(Some(msg)) => Ok(msg),
+          Ok(None) if self.paused => {
+            self.paused = false;
+            return DispatcherAction::ResumeReads;
+          }
+          Ok(None) => return DispatcherAction::Continue,
+          Err(err) => Err(err),
+        },
+      };
+      let failed = item.is_err();
+      match self.sender.try_send(item) {
+        Ok(()) if failed => return DispatcherAction::Dispose,
+        Ok(()) => (),
+        Err(err) if err.is_full() => {
+          self.pending = Some(err.into_inner());
+          if self.paused {
+            return DispatcherAction::Continue;
+          }
+          self.paused = true;
+          return DispatcherAction::PauseReads;
+        }
+        Err(_) => {
+          log::debug!("disposing the connection because the message stream has been dropped");
+          return DispatcherAction::Dispose;
+        }
+      }
+    }
+  }
+}
+
+impl TcpStreamListener for MessageStreamListener {
+  fn read_mode(&self) -> ReadMode {
+    ReadMode::Buffered
+  }
+
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+
+  fn on_data(&mut self, data: &[u8]) -> DispatcherAction {
+    if data.is_empty() {
+      return DispatcherAction::Continue;
+    }
+    self.decoder.push(data);
+    self.deliver()
+  }
+
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(mio::Interest::READABLE)
+  }
+
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    if let Err(err) = self.sender.try_send(Err(From::from(error))) {
+      log::warn!("failed to deliver the error to the message stream: {}", err);
+    }
+    DispatcherAction::Dispose
+  }
+
+  /// メッセージの途中で接続がクローズされた場合は `Error::BufferUnsatisfied` を供給します。
+  fn on_eof(&mut self) -> DispatcherAction {
+    if self.decoder.buffered_len() > 0 {
+      if let Err(err) = self.sender.try_send(Err(Error::BufferUnsatisfied)) {
+        log::warn!("failed to deliver the error to the message stream: {}", err);
+      }
+    }
+    DispatcherAction::Dispose
+  }
+
+  fn on_reads_paused(&mut self) -> DispatcherAction {
+    self.deliver()
+  }
+
+  /// 終了理由を格納した `Control::Close` をピアに送信します。
+  fn on_shutdown(&mut self, reason_code: u16, reason: &[u8]) -> Option<Vec<u8>> {
+    let close = Control::new_close(reason_code, reason.to_vec())
+      .and_then(|close| Message::Control(close).to_bytes());
+    match close {
+      Ok(close) => Some(close),
+      Err(err) => {
+        log::warn!("failed to build the Close message at shutdown: {}", err);
+        None
+      }
+    }
+  }
+
+  fn on_disposed(&mut self) {
+    self.sender.close_channel();
+  }
+}
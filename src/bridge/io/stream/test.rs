@@ -0,0 +1,95 @@
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use futures::StreamExt;
+use mio::net::TcpStream;
+
+use crate::bridge::io::dispatcher::{Dispatcher, DispatcherRegister, TcpStreamListener};
+use crate::bridge::io::stream::MessageStream;
+use crate::error::Error;
+use crate::msg::{Control, Message, StreamDecoder};
+use crate::test::{block_on, SampleValues};
+
+/// ローカルで接続した TcpStream の MessageStream を登録し、ピア側のソケットとともに返します。
+fn connect(dispatcher: &Dispatcher, capacity: usize) -> (MessageStream, std::net::TcpStream) {
+  let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+  let (messages, listener) = MessageStream::new(capacity);
+  let listener: Box<dyn TcpStreamListener> = Box::new(listener);
+  block_on(dispatcher.register(stream, listener)).unwrap();
+  let (peer, _) = server.accept().unwrap();
+  (messages, peer)
+}
+
+/// 指定されたメッセージをシリアライズして連結します。
+fn serialize(messages: &[Message]) -> Vec<u8> {
+  let mut buffer = Vec::new();
+  for msg in messages {
+    msg.write_to(&mut buffer).unwrap();
+  }
+  buffer
+}
+
+#[test]
+fn test_message_stream() {
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let mut sample = SampleValues::new(90184572u64);
+
+  // ピアが送信したメッセージを順に取り出すことができ、ピアがクローズするとストリームが終了する
+  let (messages, mut peer) = connect(&dispatcher, 16);
+  let expected = (0..3).map(|_| sample.next_message()).collect::<Vec<_>>();
+  peer.write_all(&serialize(&expected)).unwrap();
+  drop(peer);
+  let received = block_on(Box::pin(messages.collect::<Vec<_>>()));
+  assert_eq!(expected, received.into_iter().map(|msg| msg.unwrap()).collect::<Vec<_>>());
+
+  // 取り出す速度が遅くても読み込みを一時停止しながらすべてのメッセージが届く
+  let (mut messages, mut peer) = connect(&dispatcher, 1);
+  let expected = (0..200).map(|_| sample.next_message()).collect::<Vec<_>>();
+  let bytes = serialize(&expected);
+  let writer = std::thread::spawn(move || {
+    peer.write_all(&bytes).unwrap();
+  });
+  let mut received = Vec::new();
+  while let Some(msg) = block_on(messages.next()) {
+    received.push(msg.unwrap());
+    if received.len() % 50 == 0 {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+  }
+  writer.join().unwrap();
+  assert_eq!(expected, received);
+
+  // メッセージの途中で接続がクローズされた場合はエラーの後にストリームが終了する
+  let (mut messages, mut peer) = connect(&dispatcher, 16);
+  let bytes = serialize(&[sample.next_message()]);
+  peer.write_all(&bytes[..bytes.len() - 1]).unwrap();
+  drop(peer);
+  assert_eq!(Some(Err(Error::BufferUnsatisfied)), block_on(messages.next()));
+  assert_eq!(None, block_on(messages.next()));
+}
+
+#[test]
+fn test_message_stream_shutdown() {
+  // ディスパッチャーの終了時にピアは終了理由の Close を受信する
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let (_messages, mut peer) = connect(&dispatcher, 16);
+  peer.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  block_on(dispatcher.shutdown_with_reason(7, b"bye".to_vec())).unwrap();
+  let mut received = Vec::new();
+  peer.read_to_end(&mut received).unwrap();
+  let mut decoder = StreamDecoder::new();
+  decoder.push(&received);
+  let expected = Message::Control(Control::new_close(7, b"bye".to_vec()).unwrap());
+  assert_eq!(Some(expected), decoder.next_message().unwrap());
+  assert_eq!(None, decoder.next_message().unwrap());
+
+  // Close で表現できない理由の場合は何も送信せずにクローズする
+  let dispatcher = Dispatcher::new(1024, 1024).unwrap();
+  let (_messages, mut peer) = connect(&dispatcher, 16);
+  peer.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  block_on(dispatcher.shutdown_with_reason(7, vec![0u8; 0x10000])).unwrap();
+  let mut received = Vec::new();
+  peer.read_to_end(&mut received).unwrap();
+  assert!(received.is_empty());
+}
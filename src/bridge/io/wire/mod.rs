@@ -0,0 +1,142 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+
+use log;
+
+use crate::bridge::Wire;
+use crate::error::Error;
+use crate::msg::{Message, StreamDecoder};
+use crate::Result;
+
+/// `StreamWire` が使用するノンブロッキングのストリームです。トランスポートごとに異なるアドレスの参照とシャット
+/// ダウンの操作を抽象化します。
+pub trait WireStream: Read + Write {
+  fn local_address(&self) -> Result<SocketAddr>;
+  fn remote_address(&self) -> Result<SocketAddr>;
+  fn shutdown(&self, how: Shutdown) -> std::io::Result<()>;
+}
+
+/// ノンブロッキングのストリーム上でメッセージを送受信する `Wire` です。送信しきれなかったデータを保持する送信バッファ
+/// と、断片的に到着したデータからメッセージを復元するデコーダーを持ちます。
+pub struct StreamWire<S: WireStream> {
+  is_server: bool,
+  stream: S,
+  /// まだ送信できていないシリアライズ済みのメッセージ。
+  outbound: Vec<u8>,
+  decoder: StreamDecoder,
+  closed: bool,
+  /// ピアが送信方向をクローズしたことを検出した場合に true。
+  eof: bool,
+}
+
+impl<S: WireStream> StreamWire<S> {
+  /// 接続済みのノンブロッキングのストリームから Wire を構築します。`is_server` には接続を受け付けた側の場合に
+  /// true を指定します。
+  pub fn new(stream: S, is_server: bool) -> StreamWire<S> {
+    let decoder = StreamDecoder::new();
+    StreamWire { is_server, stream, outbound: Vec::new(), decoder, closed: false, eof: false }
+  }
+
+  /// 下位のストリームを参照します。トランスポート固有のソケットオプションの参照や変更に使用します。
+  pub fn stream(&self) -> &S {
+    &self.stream
+  }
+
+  /// 送信バッファのデータを、ソケットがブロックしない範囲で送信します。
+  fn flush_outbound(&mut self) -> Result<()> {
+    while !self.outbound.is_empty() {
+      match self.stream.write(&self.outbound) {
+        Ok(0) => return Err(From::from(std::io::Error::from(ErrorKind::WriteZero))),
+        Ok(len) => {
+          self.outbound.drain(..len);
+        }
+        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+        Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+        Err(err) => return Err(From::from(err)),
+      }
+    }
+    Ok(())
+  }
+
+  /// ソケットからブロックしない範囲でデータを読み込みデコーダーに追加します。
+  fn fill_decoder(&mut self) -> Result<()> {
+    let mut buffer = [0u8; 4 * 1024];
+    loop {
+      match self.stream.read(&mut buffer) {
+        Ok(0) => {
+          self.eof = true;
+          break;
+        }
+        Ok(len) => self.decoder.push(&buffer[..len]),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+        Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+        Err(err) => return Err(From::from(err)),
+      }
+    }
+    Ok(())
+  }
+}
+
+impl<S: WireStream> Wire for StreamWire<S> {
+  fn local_address(&self) -> Result<SocketAddr> {
+    self.stream.local_address()
+  }
+
+  fn remote_address(&self) -> Result<SocketAddr> {
+    self.stream.remote_address()
+  }
+
+  fn is_server(&self) -> bool {
+    self.is_server
+  }
+
+  fn send(&mut self, msg: Message) -> Result<()> {
+    if self.closed {
+      return Err(Error::WireClosed);
+    }
+    msg.write_to(&mut self.outbound)?;
+    self.flush_outbound()
+  }
+
+  fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+    if self.closed {
+      return Err(Error::WireClosed);
+    }
+    self.outbound.extend_from_slice(bytes);
+    self.flush_outbound()
+  }
+
+  /// ピアが接続をクローズし、受信済みのメッセージをすべて取り出した後は `Error::WireClosed` を返します。
+  fn try_recv(&mut self) -> Result<Option<Message>> {
+    if self.closed {
+      return Err(Error::WireClosed);
+    }
+    self.flush_outbound()?;
+    if let Some(msg) = self.decoder.next_message()? {
+      return Ok(Some(msg));
+    }
+    if !self.eof {
+      self.fill_decoder()?;
+    }
+    match self.decoder.next_message()? {
+      None if self.eof => Err(Error::WireClosed),
+      msg => Ok(msg),
+    }
+  }
+
+  fn close(&mut self) -> Result<()> {
+    if self.closed {
+      return Ok(());
+    }
+    self.closed = true;
+    self.stream.shutdown(Shutdown::Both).map_err(From::from)
+  }
+}
+
+impl<S: WireStream> Drop for StreamWire<S> {
+  fn drop(&mut self) {
+    if let Err(err) = self.close() {
+      log::warn!("failed to close the wire: {}", err);
+    }
+  }
+}
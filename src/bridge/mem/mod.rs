@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use log;
+use url::Url;
+
+use crate::bridge::{Bridge, Server, Wire};
+use crate::error::Error;
+use crate::msg::Message;
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// 同一プロセス内のチャネルでメッセージを送受信する Bridge です。URL のスキームは `mem` で、`mem://node-a` のように
+/// ホスト部でサーバの名前を指定します。OS のソケットを使用しないため、ポートの競合やネットワークの状態に影響されずに
+/// ハンドシェイクやセッションの処理を検証することができます。
+///
+/// メッセージは `Message::write_to()` でシリアライズしたバイト列として受け渡されるため、送受信時の検証は他の Bridge
+/// と同じです。
+pub struct MemoryBridge;
+
+impl MemoryBridge {
+  pub fn new() -> MemoryBridge {
+    log::debug!("starting in-memory bridge...");
+    MemoryBridge
+  }
+}
+
+impl Default for MemoryBridge {
+  fn default() -> Self {
+    MemoryBridge::new()
+  }
+}
+
+#[async_trait]
+impl Bridge<MemoryServer> for MemoryBridge {
+  fn name(&self) -> &'static str {
+    "mem"
+  }
+
+  /// 接続先を指定できないため `ErrorKind::Unsupported` のエラーとなります。クライアント側の Wire は
+  /// `MemoryWire::connect()` で構築します。
+  fn new_wire<W: Wire>(&mut self) -> Result<W> {
+    let message = "in-memory wire must be connected with MemoryWire::connect()";
+    Err(From::from(std::io::Error::new(std::io::ErrorKind::Unsupported, message)))
+  }
+
+  /// 指定された名前で接続を受け付ける `Server` を開始します。同じ名前のサーバがすでに開始している場合は
+  /// `ErrorKind::AddrInUse` のエラーとなります。
+  async fn start_server(&mut self, url: &Url) -> Result<MemoryServer> {
+    assert_eq!(url.scheme(), self.name());
+    let name = server_name(url)?;
+    let mut servers = SERVERS.lock()?;
+    let servers = servers.get_or_insert_with(HashMap::new);
+    if servers.contains_key(&name) {
+      let message = format!("the in-memory server {} is already started", name);
+      return Err(From::from(std::io::Error::new(std::io::ErrorKind::AddrInUse, message)));
+    }
+    let (sender, receiver) = channel();
+    servers.insert(name.clone(), sender);
+    Ok(MemoryServer { name, url: url.to_string(), incoming: Some(receiver) })
+  }
+}
+
+/// 開始しているサーバの名前と、接続要求としてサーバ側の Wire を渡すチャネル。
+static SERVERS: Mutex<Option<HashMap<String, Sender<MemoryWire>>>> = Mutex::new(None);
+
+/// URL のホスト部からサーバの名前を参照します。
+pub fn server_name(url: &Url) -> Result<String> {
+  match url.host_str() {
+    Some(name) if !name.is_empty() => Ok(name.to_string()),
+    _ => Err(Error::HostNotSpecifiedInUrl { url: url.to_string() }),
+  }
+}
+
+/// 同一プロセス内のチャネルでメッセージを送受信する `Wire` です。ソケットアドレスを持たないため `local_address()`
+/// と `remote_address()` はエラーとなります。
+pub struct MemoryWire {
+  is_server: bool,
+  /// 相手にシリアライズしたメッセージを送信するチャネル。クローズ後は `None` となります。
+  sender: Option<Sender<Vec<u8>>>,
+  receiver: Receiver<Vec<u8>>,
+}
+
+impl MemoryWire {
+  /// 指定された URL で開始しているサーバに接続してクライアント側の Wire を構築します。サーバが開始していない
+  /// 場合は `ErrorKind::ConnectionRefused` のエラーとなります。
+  pub fn connect(url: &Url) -> Result<MemoryWire> {
+    let name = server_name(url)?;
+    let (client, server) = MemoryWire::pair();
+    let servers = SERVERS.lock()?;
+    let accepted = servers.as_ref().and_then(|servers| servers.get(&name));
+    match accepted.map(|sender| sender.send(server)) {
+      Some(Ok(())) => Ok(client),
+      _ => {
+        let message = format!("the in-memory server {} is not started", name);
+        Err(From::from(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, message)))
+      }
+    }
+  }
+
+  /// 互いに接続したクライアント側とサーバ側の Wire を構築します。
+  pub fn pair() -> (MemoryWire, MemoryWire) {
+    let (client_sender, server_receiver) = channel();
+    let (server_sender, client_receiver) = channel();
+    let client =
+      MemoryWire { is_server: false, sender: Some(client_sender), receiver: client_receiver };
+    let server =
+      MemoryWire { is_server: true, sender: Some(server_sender), receiver: server_receiver };
+    (client, server)
+  }
+}
+
+fn no_socket_address() -> Error {
+  let message = "in-memory wire has no socket address";
+  From::from(std::io::Error::new(std::io::ErrorKind::Unsupported, message))
+}
+
+impl Wire for MemoryWire {
+  fn local_address(&self) -> Result<SocketAddr> {
+    Err(no_socket_address())
+  }
+
+  fn remote_address(&self) -> Result<SocketAddr> {
+    Err(no_socket_address())
+  }
+
+  fn is_server(&self) -> bool {
+    self.is_server
+  }
+
+  fn send(&mut self, msg: Message) -> Result<()> {
+    let sender = self.sender.as_ref().ok_or(Error::WireClosed)?;
+    let mut buffer = Vec::with_capacity(msg.serialized_len());
+    msg.write_to(&mut buffer)?;
+    sender.send(buffer).map_err(|_| Error::WireClosed)
+  }
+
+  fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+    let sender = self.sender.as_ref().ok_or(Error::WireClosed)?;
+    sender.send(bytes.to_vec()).map_err(|_| Error::WireClosed)
+  }
+
+  /// 相手が Wire をクローズし、受信済みのメッセージをすべて取り出した後は `Error::WireClosed` を返します。
+  fn try_recv(&mut self) -> Result<Option<Message>> {
+    if self.sender.is_none() {
+      return Err(Error::WireClosed);
+    }
+    match self.receiver.try_recv() {
+      Ok(bytes) => Message::from_bytes(&bytes).map(|(msg, _)| Some(msg)),
+      Err(TryRecvError::Empty) => Ok(None),
+      Err(TryRecvError::Disconnected) => Err(Error::WireClosed),
+    }
+  }
+
+  fn close(&mut self) -> Result<()> {
+    self.sender.take();
+    Ok(())
+  }
+}
+
+pub struct MemoryServer {
+  name: String,
+  url: String,
+  /// 接続要求を受け付けるチャネル。クローズ後は `None` となります。
+  incoming: Option<Receiver<MemoryWire>>,
+}
+
+impl MemoryServer {
+  /// 到着している接続を受け付けてサーバ側の Wire を返します。受け付ける接続がない場合やクローズ後は `None` を
+  /// 返します。
+  pub fn accept(&mut self) -> Result<Option<MemoryWire>> {
+    match self.incoming.as_ref().map(|incoming| incoming.try_recv()) {
+      Some(Ok(wire)) => Ok(Some(wire)),
+      _ => Ok(None),
+    }
+  }
+}
+
+impl Server for MemoryServer {
+  fn url(&self) -> &str {
+    &self.url
+  }
+
+  /// このサーバの名前を参照します。
+  fn local_address(&self) -> Result<String> {
+    Ok(self.name.clone())
+  }
+
+  /// 接続の受け付けを終了し、同じ名前のサーバを開始できるようにします。
+  fn close(&mut self) -> Result<()> {
+    if self.incoming.take().is_some() {
+      if let Some(servers) = SERVERS.lock()?.as_mut() {
+        servers.remove(&self.name);
+      }
+      log::debug!("server closed: {}", self.url);
+    }
+    Ok(())
+  }
+}
+
+impl Drop for MemoryServer {
+  fn drop(&mut self) {
+    if let Err(err) = self.close() {
+      log::warn!("failed to close the server {}: {}", self.url, err);
+    }
+  }
+}
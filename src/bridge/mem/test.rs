@@ -0,0 +1,93 @@
+use std::thread::spawn;
+
+use url::Url;
+use uuid::Uuid;
+
+use crate::bridge::mem::{MemoryBridge, MemoryWire};
+use crate::bridge::{create, Bridge, Server, Wire};
+use crate::error::Error;
+use crate::msg::{Close, Message, Open};
+use crate::session::{Handshake, NodeId, Session};
+use crate::test::block_on;
+
+/// 相手から 1 つのメッセージを受信するまで待機します。
+fn recv<W: Wire>(wire: &mut W) -> Message {
+  loop {
+    if let Some(msg) = wire.try_recv().unwrap() {
+      return msg;
+    }
+    std::thread::yield_now();
+  }
+}
+
+#[test]
+fn test_memory_bridge() {
+  let url = Url::parse("mem://test-memory-bridge").unwrap();
+  create(url.as_str()).unwrap();
+  let mut bridge = MemoryBridge::new();
+  assert_eq!("mem", bridge.name());
+  let mut server = block_on(Box::pin(bridge.start_server(&url))).unwrap();
+  assert_eq!(url.as_str(), server.url());
+  assert_eq!("test-memory-bridge", server.local_address().unwrap());
+
+  // 同じ名前のサーバは開始できない
+  let err = block_on(Box::pin(bridge.start_server(&url))).err().unwrap();
+  assert!(matches!(err, Error::Io { kind: std::io::ErrorKind::AddrInUse, .. }), "{:?}", err);
+
+  // 接続先を指定できない new_wire() はエラーとなる
+  let err = bridge.new_wire::<MemoryWire>().err().unwrap();
+  assert!(matches!(err, Error::Io { kind: std::io::ErrorKind::Unsupported, .. }), "{:?}", err);
+
+  // ハンドシェイクを行いセッションを確立する
+  let client = MemoryWire::connect(&url).unwrap();
+  let mut accepted = server.accept().unwrap().unwrap();
+  assert!(server.accept().unwrap().is_none());
+  assert!(!client.is_server());
+  assert!(accepted.is_server());
+  assert!(client.local_address().is_err());
+  let handle = spawn(move || {
+    let config =
+      Handshake::new(NodeId::from(Uuid::from_u128(2))).begin_server(&mut accepted).unwrap();
+    (config, accepted)
+  });
+  let mut client = client;
+  let config = Handshake::new(NodeId::from(Uuid::from_u128(1))).begin_client(&mut client).unwrap();
+  let (server_config, mut accepted) = handle.join().unwrap();
+  assert_eq!(NodeId::from(Uuid::from_u128(2)), config.node_id);
+  assert_eq!(NodeId::from(Uuid::from_u128(1)), server_config.node_id);
+  assert_eq!(server_config.session_id, config.session_id);
+
+  // パイプを開いて応答を受け取る
+  let mut session = Session::new(client, config);
+  let pipe_id = session.open_pipe(10, 0, vec![1, 2, 3]).unwrap();
+  assert_eq!(Message::Open(Open::new(pipe_id, 10, 0, vec![1, 2, 3]).unwrap()), recv(&mut accepted));
+  accepted.send(Message::Close(Close::success(pipe_id, vec![4, 5]).unwrap())).unwrap();
+  match recv(session.wire()) {
+    Message::Close(close) => {
+      assert_eq!(&[4u8, 5][..], close.result());
+      session.close_pipe(&close).unwrap();
+    }
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+  assert_eq!(0, session.open_pipes());
+
+  // 相手がクローズすると受信済みのメッセージを取り出した後に WireClosed となる
+  accepted.send(Message::Close(Close::success(pipe_id, vec![]).unwrap())).unwrap();
+  accepted.close().unwrap();
+  assert_eq!(
+    Err(Error::WireClosed),
+    accepted.send(Message::Close(Close::success(1, vec![]).unwrap()))
+  );
+  assert!(matches!(recv(session.wire()), Message::Close(_)));
+  assert_eq!(Err(Error::WireClosed), session.wire().try_recv());
+
+  // サーバをクローズすると接続できなくなり、同じ名前でサーバを開始できる
+  server.close().unwrap();
+  let err = MemoryWire::connect(&url).err().unwrap();
+  assert!(
+    matches!(err, Error::Io { kind: std::io::ErrorKind::ConnectionRefused, .. }),
+    "{:?}",
+    err
+  );
+  assert!(block_on(Box::pin(bridge.start_server(&url))).is_ok());
+}
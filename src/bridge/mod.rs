@@ -1,17 +1,25 @@
-use std::net::SocketAddr;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::sync::{Arc, RwLock};
 
 use async_trait::async_trait;
-use url::Url;
+use url::{Host, Url};
 
 use crate::error::Error;
 use crate::msg::Message;
 use crate::Result;
 
+pub mod heartbeat;
 pub mod io;
+pub mod mem;
+pub mod reconnect;
 pub mod tcp;
 #[cfg(test)]
 mod test;
+pub mod tls;
+#[cfg(unix)]
+pub mod uds;
 pub mod ws;
 
 /// 非同期メッセージング API
@@ -37,24 +45,68 @@ pub trait Wire {
   /// プロトコル上の役割を決める必要がある場合に使用することができます。
   fn is_server(&self) -> bool;
 
+  /// 指定されたメッセージを送信します。すぐに送信できなかった部分は内部のバッファに保持され、以降の `send()` または
+  /// `try_recv()` の呼び出し時に送信されます。
+  fn send(&mut self, msg: Message) -> Result<()>;
+
+  /// `Message::to_bytes()` でシリアライズ済みの 1 つのメッセージを送信します。同じメッセージを多数の Wire に送信
+  /// する場合にシリアライズを繰り返さずに済みます。
+  ///
+  /// デフォルトの実装はバイト列を復元して `send()` を呼び出します。バイト列をそのまま送信できる実装はこれを
+  /// オーバーライドします。
+  fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+    let (msg, _) = Message::from_bytes(bytes)?;
+    self.send(msg)
+  }
+
+  /// 受信済みのメッセージを 1 つ取り出します。完全なメッセージをまだ受信していない場合はブロックせずに `None` を
+  /// 返します。
+  fn try_recv(&mut self) -> Result<Option<Message>>;
+
   fn close(&mut self) -> Result<()>;
 }
 
 pub trait Server {
   /// このサーバに接続するためのアドレスを参照します。
   fn url(&self) -> &str;
+
+  /// このサーバが実際にバインドしているローカル側アドレスを参照します。ポート 0 でバインドした場合に割り当て
+  /// られたポートを知るために使用することができます。
+  fn local_address(&self) -> Result<String>;
+
   fn close(&mut self) -> Result<()>;
 }
 
 pub fn create(url: &str) -> Result<()> {
   let url = Url::parse(url)?;
   match url.scheme() {
-    "tcp" => {}
+    "tcp" | "mem" => {}
+    #[cfg(unix)]
+    "unix" => {}
     _ => return Err(Error::UnsupportedProtocol { url: url.to_string() }),
   }
   Ok(())
 }
 
+/// URL のホストとポートからソケットアドレスを構築します。ホスト名が指定されている場合は名前解決を行い、最初に
+/// 得られたアドレスを使用します。
+/// IPv6 アドレスは `tcp://[::1]:8080` のように角括弧で囲んで指定します。
+pub fn socket_address(url: &Url) -> Result<SocketAddr> {
+  let not_specified = || Error::HostNotSpecifiedInUrl { url: url.to_string() };
+  let port = url.port().ok_or_else(not_specified)?;
+  match url.host() {
+    Some(Host::Ipv4(ip)) => Ok(SocketAddr::new(IpAddr::V4(ip), port)),
+    Some(Host::Ipv6(ip)) => Ok(SocketAddr::new(IpAddr::V6(ip), port)),
+    Some(Host::Domain(domain)) => {
+      (domain, port).to_socket_addrs()?.next().ok_or_else(not_specified)
+    }
+    None => Err(not_specified()),
+  }
+}
+
+/// 容量の上限を持つメッセージのキューです。複製したキューは同じメッセージを共有するため、ディスパッチャーの
+/// Listener が受信したメッセージを追加し、アプリケーションのスレッドが取り出すように使用することができます。
+#[derive(Clone)]
 pub struct MessageQueue {
   capacity: usize,
   queue: Arc<RwLock<Vec<Message>>>,
@@ -76,6 +128,15 @@ impl MessageQueue {
     queue.len()
   }
 
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// このキューが容量に達しており、これ以上メッセージを追加できない場合に true を返します。
+  pub fn is_full(&self) -> bool {
+    self.len() >= self.capacity
+  }
+
   /// このキューにメッセージを追加します。
   /// 正常に終了した場合、メッセージ追加後のキューのサイズを返します。
   pub fn push(&mut self, msg: Message) -> Result<usize> {
@@ -89,7 +150,106 @@ impl MessageQueue {
     }
   }
 
+  /// 最も古いメッセージを取り出します。キューが空の場合は `None` を返します。
+  pub fn try_pop(&mut self) -> Result<Option<Message>> {
+    let queue = self.queue.clone();
+    let mut queue = queue.write()?;
+    if queue.is_empty() {
+      Ok(None)
+    } else {
+      Ok(Some(queue.remove(0)))
+    }
+  }
+}
+
+/// `PriorityMessageQueue` に格納されるメッセージ。優先度の高いものから、同じ優先度であれば追加された順に取り出される
+/// ように順序付けします。
+struct Prioritized {
+  priority: u8,
+  sequence: u64,
+  msg: Message,
+}
+
+impl PartialEq for Prioritized {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Prioritized {}
+
+impl PartialOrd for Prioritized {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Prioritized {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.priority.cmp(&other.priority).then_with(|| other.sequence.cmp(&self.sequence))
+  }
+}
+
+/// パイプの優先度に従ってメッセージを取り出すメッセージキューです。
+///
+/// 各メッセージの優先度はそのパイプの `Open.priority` (値が大きいほど優先) で決まり、同じ優先度のメッセージは追加
+/// された順に取り出されます。`Open` を追加したときにパイプの優先度を記録し、`Close` を追加したときに破棄します。
+/// 優先度が記録されていないパイプのメッセージは優先度 0 として扱います。パイプに属さない `Control` メッセージは
+/// 最も高い優先度で扱います。
+pub struct PriorityMessageQueue {
+  capacity: usize,
+  queue: Arc<RwLock<BinaryHeap<Prioritized>>>,
+  priorities: HashMap<u16, u8>,
+  sequence: u64,
+}
+
+impl PriorityMessageQueue {
+  /// 指定された容量を持つメッセージキューを構築します。
+  pub fn new(capacity: usize) -> PriorityMessageQueue {
+    let queue = Arc::new(RwLock::new(BinaryHeap::new()));
+    PriorityMessageQueue { capacity, queue, priorities: HashMap::new(), sequence: 0 }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  pub fn len(&self) -> usize {
+    let queue = self.queue.clone();
+    let queue = queue.read().unwrap();
+    queue.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// このキューにメッセージを追加します。
+  /// 正常に終了した場合、メッセージ追加後のキューのサイズを返します。
+  pub fn push(&mut self, msg: Message) -> Result<usize> {
+    let queue = self.queue.clone();
+    let mut queue = queue.write()?;
+    if queue.len() == self.capacity {
+      return Err(Error::MessageQueueOverflow { capacity: self.capacity });
+    }
+    let priority = match &msg {
+      Message::Open(open) => {
+        self.priorities.insert(open.pipe_id(), open.priority());
+        open.priority()
+      }
+      Message::Close(close) => self.priorities.remove(&close.pipe_id()).unwrap_or(0),
+      Message::Block(block) => self.priorities.get(&block.pipe_id()).copied().unwrap_or(0),
+      Message::Control(_) => u8::MAX,
+    };
+    self.sequence += 1;
+    queue.push(Prioritized { priority, sequence: self.sequence, msg });
+    Ok(queue.len())
+  }
+
+  /// 最も優先度の高いメッセージを取り出します。キューが空の場合は `None` を返します。
   pub fn try_pop(&mut self) -> Result<Option<Message>> {
-    unimplemented!()
+    let queue = self.queue.clone();
+    let mut queue = queue.write()?;
+    Ok(queue.pop().map(|prioritized| prioritized.msg))
   }
 }
@@ -0,0 +1,246 @@
+use std::net::SocketAddr;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log;
+
+use crate::bridge::Wire;
+use crate::error::Error;
+use crate::msg::Message;
+use crate::session::SessionConfig;
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// `ReconnectingWire` が再接続を試みるときの待機時間と試行回数の設定です。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectOptions {
+  /// 最初の再接続を試みるまでの待機時間です。以降の待機時間は試行ごとに 2 倍になります。デフォルトは 100 ミリ秒
+  /// です。
+  pub initial_interval: Duration,
+  /// 待機時間の上限です。デフォルトは 30 秒です。
+  pub max_interval: Duration,
+  /// 待機時間をランダムに揺らす割合です。`0.2` であれば待機時間は ±20% の範囲で変動します。多数のクライアントが
+  /// 同時に再接続することを避けるために使用します。`0.0` から `1.0` の範囲で指定し、デフォルトは `0.2` です。
+  pub jitter: f64,
+  /// 一度の切断に対して接続を試みる最大回数です。デフォルトは 10 回です。
+  pub max_attempts: u32,
+}
+
+impl Default for ReconnectOptions {
+  fn default() -> Self {
+    ReconnectOptions {
+      initial_interval: Duration::from_millis(100),
+      max_interval: Duration::from_secs(30),
+      jitter: 0.2,
+      max_attempts: 10,
+    }
+  }
+}
+
+impl ReconnectOptions {
+  /// `attempt` 回目 (1 から開始) の接続に失敗した後、次の接続を試みるまでのジッターを含まない待機時間を参照します。
+  pub fn backoff(&self, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    std::cmp::min(self.initial_interval.saturating_mul(factor), self.max_interval)
+  }
+}
+
+/// 切断を検出したときに指数バックオフで再接続を行うクライアント側の `Wire` です。
+///
+/// 接続には `connect` に指定された関数 (通常は `Bridge::new_wire()` を呼び出す) を使用し、接続が確立するたびに
+/// `handshake` に指定された関数 (通常は `Handshake::begin_client()` を呼び出す) でハンドシェイクを行います。
+/// 接続ごとにハンドシェイクをやり直すため、System Config の送信時刻は常に新しく、サーバの応答はハンドシェイクの
+/// 中で消費されアプリケーションには届きません。
+///
+/// 内側の Wire が `Error::WireClosed` または `Error::Io` を返したときに切断とみなして再接続します。再接続の待機中は
+/// 呼び出し元のスレッドがブロックします。すべての試行に失敗した場合はそのエラーを返しますが、`close()` するまでは
+/// 以降の呼び出しで再び接続を試みます。
+pub struct ReconnectingWire<W, F, H>
+where
+  W: Wire,
+  F: FnMut() -> Result<W>,
+  H: FnMut(&mut W) -> Result<SessionConfig>,
+{
+  connect: F,
+  handshake: H,
+  wire: Option<W>,
+  /// 現在の接続のハンドシェイクで合意したセッションのパラメータ。
+  session: Option<SessionConfig>,
+  options: ReconnectOptions,
+  reconnects: usize,
+  /// `close()` によって明示的にクローズされた場合に true。
+  closed: bool,
+  /// ジッターを生成するための乱数の状態。
+  seed: u64,
+}
+
+impl<W, F, H> ReconnectingWire<W, F, H>
+where
+  W: Wire,
+  F: FnMut() -> Result<W>,
+  H: FnMut(&mut W) -> Result<SessionConfig>,
+{
+  /// 指定された関数で接続し、ハンドシェイクを行った Wire を構築します。接続またはハンドシェイクに失敗した場合は
+  /// `options` に従って再試行し、すべての試行に失敗した場合は最後のエラーを返します。
+  pub fn new(connect: F, handshake: H, options: ReconnectOptions) -> Result<Self> {
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    let mut wire = ReconnectingWire {
+      connect,
+      handshake,
+      wire: None,
+      session: None,
+      options,
+      reconnects: 0,
+      closed: false,
+      seed: seed | 1,
+    };
+    wire.establish()?;
+    Ok(wire)
+  }
+
+  /// 最初の接続以降に再接続した回数を参照します。
+  pub fn reconnect_count(&self) -> usize {
+    self.reconnects
+  }
+
+  /// 現在の接続のハンドシェイクで合意したセッションのパラメータを参照します。接続していない場合は `None` を返し
+  /// ます。
+  pub fn session_config(&self) -> Option<&SessionConfig> {
+    self.session.as_ref()
+  }
+
+  /// 現在の接続を破棄して再接続します。クローズした後は `Error::WireClosed` を返します。
+  pub fn reconnect(&mut self) -> Result<()> {
+    if self.closed {
+      return Err(Error::WireClosed);
+    }
+    self.session = None;
+    if let Some(mut wire) = self.wire.take() {
+      if let Err(err) = wire.close() {
+        log::debug!("failed to close the disconnected wire: {}", err);
+      }
+    }
+    self.establish()?;
+    self.reconnects += 1;
+    Ok(())
+  }
+
+  /// 接続してハンドシェイクを行います。失敗した場合はバックオフして `max_attempts` 回まで再試行します。
+  fn establish(&mut self) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+      match self.try_connect() {
+        Ok((wire, session)) => {
+          self.wire = Some(wire);
+          self.session = Some(session);
+          return Ok(());
+        }
+        Err(err) if attempt >= self.options.max_attempts => return Err(err),
+        Err(err) => {
+          let interval = self.jittered(self.options.backoff(attempt));
+          log::debug!("connection attempt {} failed, retrying in {:?}: {}", attempt, interval, err);
+          sleep(interval);
+          attempt += 1;
+        }
+      }
+    }
+  }
+
+  fn try_connect(&mut self) -> Result<(W, SessionConfig)> {
+    let mut wire = (self.connect)()?;
+    let session = (self.handshake)(&mut wire)?;
+    Ok((wire, session))
+  }
+
+  /// 指定された待機時間に `jitter` の割合でランダムな変動を加えます。
+  fn jittered(&mut self, interval: Duration) -> Duration {
+    // xorshift64
+    self.seed ^= self.seed << 13;
+    self.seed ^= self.seed >> 7;
+    self.seed ^= self.seed << 17;
+    let random = (self.seed >> 11) as f64 / (1u64 << 53) as f64;
+    let jitter = self.options.jitter.clamp(0.0, 1.0);
+    interval.mul_f64(1.0 + jitter * (random * 2.0 - 1.0))
+  }
+
+  /// 現在の Wire を参照します。以前の再接続に失敗して接続していない場合は、クローズされていなければ再接続を
+  /// 試みます。
+  fn wire(&mut self) -> Result<&mut W> {
+    if self.wire.is_none() {
+      self.reconnect()?;
+    }
+    self.wire.as_mut().ok_or(Error::WireClosed)
+  }
+}
+
+/// 指定されたエラーが接続の切断を示しているかを判定します。
+fn is_disconnected(err: &Error) -> bool {
+  matches!(err, Error::WireClosed | Error::Io { .. })
+}
+
+impl<W, F, H> Wire for ReconnectingWire<W, F, H>
+where
+  W: Wire,
+  F: FnMut() -> Result<W>,
+  H: FnMut(&mut W) -> Result<SessionConfig>,
+{
+  fn local_address(&self) -> Result<SocketAddr> {
+    self.wire.as_ref().ok_or(Error::WireClosed)?.local_address()
+  }
+
+  fn remote_address(&self) -> Result<SocketAddr> {
+    self.wire.as_ref().ok_or(Error::WireClosed)?.remote_address()
+  }
+
+  fn is_server(&self) -> bool {
+    false
+  }
+
+  /// 送信時に切断を検出した場合は再接続して同じメッセージを送信し直します。
+  fn send(&mut self, msg: Message) -> Result<()> {
+    match self.wire()?.send(msg.clone()) {
+      Err(err) if is_disconnected(&err) => {
+        log::debug!("the wire has been disconnected, reconnecting: {}", err);
+        self.reconnect()?;
+        self.wire()?.send(msg)
+      }
+      result => result,
+    }
+  }
+
+  /// `send()` と同様に、切断を検出した場合は再接続して同じバイト列を送信し直します。
+  fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+    match self.wire()?.send_raw(bytes) {
+      Err(err) if is_disconnected(&err) => {
+        log::debug!("the wire has been disconnected, reconnecting: {}", err);
+        self.reconnect()?;
+        self.wire()?.send_raw(bytes)
+      }
+      result => result,
+    }
+  }
+
+  /// 受信時に切断を検出した場合は再接続して `None` を返します。
+  fn try_recv(&mut self) -> Result<Option<Message>> {
+    match self.wire()?.try_recv() {
+      Err(err) if is_disconnected(&err) => {
+        log::debug!("the wire has been disconnected, reconnecting: {}", err);
+        self.reconnect()?;
+        Ok(None)
+      }
+      result => result,
+    }
+  }
+
+  /// 接続をクローズします。クローズした後は再接続を行いません。
+  fn close(&mut self) -> Result<()> {
+    self.closed = true;
+    self.session = None;
+    match self.wire.take() {
+      Some(mut wire) => wire.close(),
+      None => Ok(()),
+    }
+  }
+}
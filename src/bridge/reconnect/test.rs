@@ -0,0 +1,169 @@
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread::spawn;
+use std::time::{Duration, Instant, SystemTime};
+
+use uuid::Uuid;
+
+use crate::bridge::io::dispatcher::Dispatcher;
+use crate::bridge::reconnect::{ReconnectOptions, ReconnectingWire};
+use crate::bridge::tcp::TcpWire;
+use crate::bridge::Wire;
+use crate::error::Error;
+use crate::msg::{from_utc_millis, Control, Message, Open, SystemConfigBuilder};
+use crate::session::{Handshake, NodeId, SessionConfig};
+
+/// クライアントとしてハンドシェイクを行う関数を構築します。
+fn handshake() -> impl FnMut(&mut TcpWire) -> crate::Result<SessionConfig> {
+  let handshake = Handshake::new(NodeId::from(Uuid::from_u128(1)));
+  move |wire| handshake.begin_client(wire)
+}
+
+/// 接続を受け付けてクライアントの System Config に応答し、その接続と受信した System Config を返します。
+fn accept_handshake(listener: &TcpListener, session_id: u128) -> (TcpStream, Message) {
+  let (mut stream, _) = listener.accept().unwrap();
+  let received = Message::read_from(&mut stream).unwrap();
+  let reply = SystemConfigBuilder::new(Uuid::from_u128(2))
+    .session_id(Uuid::from_u128(session_id))
+    .build()
+    .unwrap();
+  Message::Control(reply).write_to(&mut stream).unwrap();
+  (stream, received)
+}
+
+/// 指定された System Config の送信時刻を参照します。
+fn utc_time(msg: &Message) -> SystemTime {
+  match msg {
+    Message::Control(Control::SystemConfig { utc_time, .. }) => from_utc_millis(*utc_time),
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+}
+
+fn options() -> ReconnectOptions {
+  ReconnectOptions {
+    initial_interval: Duration::from_millis(10),
+    max_interval: Duration::from_millis(100),
+    jitter: 0.5,
+    max_attempts: 5,
+  }
+}
+
+#[test]
+fn test_reconnect_options_backoff() {
+  let options = ReconnectOptions {
+    initial_interval: Duration::from_millis(100),
+    max_interval: Duration::from_secs(1),
+    ..ReconnectOptions::default()
+  };
+  assert_eq!(Duration::from_millis(100), options.backoff(1));
+  assert_eq!(Duration::from_millis(200), options.backoff(2));
+  assert_eq!(Duration::from_millis(400), options.backoff(3));
+  assert_eq!(Duration::from_millis(800), options.backoff(4));
+  assert_eq!(Duration::from_secs(1), options.backoff(5));
+  assert_eq!(Duration::from_secs(1), options.backoff(100));
+}
+
+#[test]
+fn test_reconnecting_wire() {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+
+  // 最初の接続はハンドシェイクの後に切断し、再接続を受け付けて受信したメッセージを返すサーバ
+  let (sender, receiver) = channel();
+  let server = spawn(move || {
+    let (first, first_handshake) = accept_handshake(&listener, 3);
+    std::thread::sleep(Duration::from_millis(10));
+    drop(first);
+    let (mut stream, handshake) = accept_handshake(&listener, 4);
+    let msg = Message::read_from(&mut stream).unwrap();
+    sender.send((first_handshake, handshake, msg)).unwrap();
+  });
+
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  let connect = || TcpWire::connect(dispatcher.clone(), address);
+  let mut wire = ReconnectingWire::new(connect, handshake(), options()).unwrap();
+  assert!(!wire.is_server());
+  assert_eq!(0, wire.reconnect_count());
+  assert_eq!(Uuid::from_u128(3), wire.session_config().unwrap().session_id);
+
+  // 切断を検出すると再接続する。サーバの System Config はハンドシェイクで消費されアプリケーションには届かない
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while wire.reconnect_count() == 0 {
+    assert!(Instant::now() < deadline);
+    assert_eq!(None, wire.try_recv().unwrap());
+    std::thread::yield_now();
+  }
+  assert_eq!(1, wire.reconnect_count());
+  assert_eq!(Uuid::from_u128(4), wire.session_config().unwrap().session_id);
+
+  // 再接続した接続では新しい System Config でハンドシェイクし直され、以降のメッセージも届く
+  let open = Message::Open(Open::new(1, 2, 3, vec![4u8; 16]).unwrap());
+  wire.send(open.clone()).unwrap();
+  let (first_handshake, handshake, received) =
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+  assert!(utc_time(&first_handshake) < utc_time(&handshake));
+  assert_eq!(open, received);
+  server.join().unwrap();
+
+  // クローズした後は再接続しない
+  wire.close().unwrap();
+  assert_eq!(Error::WireClosed.code(), wire.send(open).unwrap_err().code());
+  assert_eq!(1, wire.reconnect_count());
+  assert!(wire.session_config().is_none());
+}
+
+#[test]
+fn test_reconnecting_wire_recovers_after_failed_reconnect() {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let address: SocketAddr = listener.local_addr().unwrap();
+  let server = spawn(move || accept_handshake(&listener, 3).0);
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  let connect = || TcpWire::connect(dispatcher.clone(), address);
+  let mut wire = ReconnectingWire::new(connect, handshake(), options()).unwrap();
+
+  // サーバが停止している間はすべての再接続に失敗してエラーを返す
+  drop(server.join().unwrap());
+  let deadline = Instant::now() + Duration::from_secs(10);
+  let err = loop {
+    assert!(Instant::now() < deadline);
+    match wire.try_recv() {
+      Ok(None) => std::thread::yield_now(),
+      Ok(Some(msg)) => panic!("unexpected message: {:?}", msg),
+      Err(err) => break err,
+    }
+  };
+  assert!(matches!(err, Error::Io { .. }), "{:?}", err);
+  assert!(wire.send(Message::Control(Control::new_ping(SystemTime::now()).unwrap())).is_err());
+  assert_eq!(0, wire.reconnect_count());
+
+  // サーバが復帰すると以降の呼び出しで再接続する
+  let listener = TcpListener::bind(address).unwrap();
+  let (sender, receiver) = channel();
+  let server = spawn(move || {
+    let (mut stream, _) = accept_handshake(&listener, 4);
+    sender.send(Message::read_from(&mut stream).unwrap()).unwrap();
+  });
+  let open = Message::Open(Open::new(1, 2, 3, vec![4u8; 16]).unwrap());
+  wire.send(open.clone()).unwrap();
+  assert_eq!(1, wire.reconnect_count());
+  assert_eq!(Uuid::from_u128(4), wire.session_config().unwrap().session_id);
+  assert_eq!(open, receiver.recv_timeout(Duration::from_secs(10)).unwrap());
+  server.join().unwrap();
+}
+
+#[test]
+fn test_reconnecting_wire_gives_up() {
+  // すべての試行に失敗すると最後のエラーを返す
+  let mut attempts = 0;
+  let result = ReconnectingWire::<TcpWire, _, _>::new(
+    || {
+      attempts += 1;
+      Err(Error::WireClosed)
+    },
+    handshake(),
+    options(),
+  );
+  assert_eq!(Error::WireClosed.code(), result.err().unwrap().code());
+  assert_eq!(options().max_attempts, attempts);
+}
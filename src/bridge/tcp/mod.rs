@@ -1,25 +1,148 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{Read, Write};
 use std::net::{Shutdown, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
 
 use async_trait::async_trait;
 use log;
-use mio::net::{TcpListener, TcpStream};
+use mio::net::{TcpListener, TcpSocket, TcpStream};
+use mio::Interest;
 use url::Url;
 
-use crate::bridge::io::dispatcher::Dispatcher;
-use crate::bridge::{Bridge, Server, Wire};
+use crate::bridge::io::dispatcher::{
+  Dispatcher, DispatcherAction, DispatcherRegister, ReadMode, SocketId, TaskFuture,
+  TcpListenerListener, TcpStreamListener,
+};
+use crate::bridge::io::executor::{ConnectionHandler, Executor, OffloadingListener};
+use crate::bridge::{socket_address, Bridge, Server, Wire};
+use crate::error::Error;
+use crate::msg::{Message, StreamDecoder};
 use crate::Result;
 
 #[cfg(test)]
 mod test;
 
+/// TcpListener をバインドするときのソケットオプションです。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenOptions {
+  /// `SO_REUSEADDR` を設定し、TIME_WAIT 状態のソケットが残っているアドレスに再バインドできるようにします。
+  /// デフォルトは `true` です。
+  pub reuse_address: bool,
+  /// `SO_REUSEPORT` を設定し、複数のソケットが同じポートで接続を受け付けられるようにします。この設定は
+  /// `SO_REUSEPORT` をサポートしているプラットフォームでのみ有効です。デフォルトは `false` です。
+  pub reuse_port: bool,
+  /// 受け付け待ちの接続を保持するキューの長さ (`listen(2)` の backlog) です。短時間に大量の接続が到着する
+  /// サーバではこの値を大きくします。実際の上限は OS の設定 (Linux の `net.core.somaxconn` など) によって
+  /// 切り詰められます。デフォルトは `1024` です。
+  pub backlog: u32,
+  /// 受け付けた接続に `TCP_NODELAY` を設定し、Nagle アルゴリズムによって小さな書き込みがまとめられ遅延することを
+  /// 防ぎます。デフォルトは `true` です。
+  pub nodelay: bool,
+  /// `TcpServer::accept_one()` で取り出されるまで保持する、受け付け済みの接続の最大数です。これに達すると
+  /// 取り出されるまで接続の受け付けを停止し、それ以降の接続は OS の backlog で待機します。デフォルトは `128` です。
+  pub accept_queue_size: usize,
+}
+
+impl Default for ListenOptions {
+  fn default() -> Self {
+    ListenOptions {
+      reuse_address: true,
+      reuse_port: false,
+      backlog: 1024,
+      nodelay: true,
+      accept_queue_size: 128,
+    }
+  }
+}
+
 pub struct TcpBridge {
-  dispatcher: Dispatcher,
+  /// このブリッジが開始した `Server` と共有するディスパッチャー。
+  dispatcher: Arc<Dispatcher>,
+  listen_options: ListenOptions,
 }
 
 impl TcpBridge {
-  pub fn new(event_buffer_size: usize) -> Result<TcpBridge> {
+  pub fn new(event_buffer_size: usize, task_queue_size: usize) -> Result<TcpBridge> {
     log::debug!("starting TCP bridge...");
-    Ok(TcpBridge { dispatcher: Dispatcher::new(event_buffer_size)? })
+    let dispatcher = Dispatcher::new(event_buffer_size, task_queue_size)?;
+    Ok(TcpBridge { dispatcher: Arc::new(dispatcher), listen_options: ListenOptions::default() })
+  }
+
+  /// 指定されたアドレスに接続し、このブリッジのディスパッチャーで送受信を行う Wire を構築します。接続と登録が
+  /// 完了するまで呼び出し元のスレッドはブロックします。
+  pub fn connect(&self, address: SocketAddr) -> Result<TcpWire> {
+    TcpWire::connect(self.dispatcher.clone(), address)
+  }
+
+  /// これ以降に開始する `Server` が使用するソケットオプションを設定します。
+  pub fn set_listen_options(&mut self, options: ListenOptions) {
+    self.listen_options = options;
+  }
+
+  /// 指定されたソケットアドレスで接続を受け付ける `Server` を開始します。
+  pub fn start_server_addr(&mut self, addr: SocketAddr) -> Result<TcpServer> {
+    // 新しい TcpListener の登録
+    let listener = bind(addr, &self.listen_options)?;
+    let address = listener.local_addr()?;
+    let url = format!("{}://{}", self.name(), address);
+    Ok(TcpServer {
+      dispatcher: self.dispatcher.clone(),
+      id: None,
+      listener: Some(listener),
+      queue: Arc::new(Mutex::new(AcceptQueue::new(self.listen_options.accept_queue_size))),
+      address,
+      url,
+      nodelay: self.listen_options.nodelay,
+    })
+  }
+
+  /// 指定されたすべての URL のアドレスで接続を受け付ける `Server` を開始します。IPv4 と IPv6 の両方や複数の NIC
+  /// で接続を受け付けるサーバで使用します。各 `Server` はこのブリッジのディスパッチャーを共有します。いずれかの
+  /// アドレスで開始できなかった場合、それまでに開始した `Server` はクローズされエラーを返します。
+  pub fn start_servers(&mut self, urls: &[Url]) -> Result<Vec<TcpServer>> {
+    let mut servers = Vec::with_capacity(urls.len());
+    for url in urls {
+      let result = if url.scheme() == self.name() {
+        socket_address(url).and_then(|addr| self.start_server_addr(addr))
+      } else {
+        Err(Error::UnsupportedProtocol { url: url.to_string() })
+      };
+      match result {
+        Ok(server) => servers.push(server),
+        Err(err) => {
+          for mut server in servers {
+            if let Err(err) = server.close() {
+              log::warn!("failed to close the server {}: {}", server.url, err);
+            }
+          }
+          return Err(err);
+        }
+      }
+    }
+    Ok(servers)
+  }
+}
+
+/// 指定されたソケットオプションを設定して TcpListener をバインドします。アドレスが使用中の場合は
+/// `Error::AddressInUse`、特権ポートなどでバインドが許可されなかった場合は `Error::BindPermissionDenied` を返します。
+fn bind(addr: SocketAddr, options: &ListenOptions) -> Result<TcpListener> {
+  let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+  socket.set_reuseaddr(options.reuse_address)?;
+  #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+  socket.set_reuseport(options.reuse_port)?;
+  socket.bind(addr).map_err(|err| bind_error(addr, err))?;
+  socket.listen(options.backlog).map_err(|err| bind_error(addr, err))
+}
+
+/// バインドの失敗を原因ごとのエラーに変換します。
+fn bind_error(addr: SocketAddr, err: std::io::Error) -> Error {
+  match err.kind() {
+    std::io::ErrorKind::AddrInUse => Error::AddressInUse { addr },
+    std::io::ErrorKind::PermissionDenied => Error::BindPermissionDenied { addr },
+    _ => From::from(err),
   }
 }
 
@@ -37,60 +160,488 @@ impl Bridge<TcpServer> for TcpBridge {
   /// 指定されたネットワークからの接続を非同期で受け付ける `Server` の Future を返します。
   async fn start_server(&mut self, url: &Url) -> Result<TcpServer> {
     assert_eq!(url.scheme(), self.name());
-    let bind_address = if let (Some(host), Some(port)) = (url.host_str(), url.port()) {
-      format!("{}:{}", host, port)
-    } else {
-      url.host_str().unwrap_or("localhost").to_string()
-    };
-    let bind_address = bind_address.parse()?;
-
-    // 新しい TcpListener の登録
-    let listener = TcpListener::bind(bind_address)?;
-    let url = listener
-      .local_addr()
-      .map(|addr| format!("{}://{}", self.name(), addr.to_string()))
-      .unwrap_or("<unknown>".to_string());
-    // let id = self.dispatcher.register(listener)?;
-    let id = 100usize;
-
-    Ok(TcpServer { id, url })
+    let bind_address = socket_address(url)?;
+    self.start_server_addr(bind_address)
   }
 }
 
-struct TcpWire {
+/// 受信したデータのうち、まだメッセージとして取り出されていないデータ量の上限です。これを超えると `try_recv()` で
+/// 取り出されるまで `TcpWire` はソケットからの読み込みを一時停止します。
+pub const MAX_BUFFERED_INPUT: usize = 1024 * 1024;
+
+/// ディスパッチャーに登録した TcpStream 上でメッセージを送受信する `Wire` です。
+///
+/// ソケットの読み書きはディスパッチャーのイベントループで行われます。受信したデータは `try_recv()` で取り出される
+/// まで保持され、その量が `MAX_BUFFERED_INPUT` に達すると読み込みを一時停止します。送信したメッセージは
+/// `Dispatcher::send()` でディスパッチャーに引き渡されるため、書き込みの high-water mark を超えたなどの送信の
+/// 失敗は以降の `send()` や `try_recv()` の呼び出しで返されます。
+pub struct TcpWire {
+  dispatcher: Arc<Dispatcher>,
+  id: SocketId,
   is_server: bool,
-  client: TcpStream,
+  /// アドレスやソケットオプションの参照に使用する、ディスパッチャーに登録したソケットの複製。
+  socket: Arc<std::net::TcpStream>,
+  inbox: Arc<Mutex<Inbox>>,
+  /// ディスパッチャーがまだ受け付けていない送信。
+  sending: VecDeque<TaskFuture<Result<()>>>,
+  closed: bool,
+}
+
+impl TcpWire {
+  /// 指定されたアドレスに接続し、その接続をディスパッチャーに登録したクライアント側の Wire を構築します。接続と
+  /// 登録が完了するまで呼び出し元のスレッドはブロックします。接続には `TCP_NODELAY` が設定されます。
+  pub fn connect(dispatcher: Arc<Dispatcher>, address: SocketAddr) -> Result<TcpWire> {
+    let stream = std::net::TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    TcpWire::register(dispatcher, stream, false)
+  }
+
+  /// 接続済みの TcpStream をディスパッチャーに登録して Wire を構築します。登録が完了するまで呼び出し元のスレッドは
+  /// ブロックします。`is_server` には接続を受け付けた側の場合に true を指定します。
+  pub fn register(
+    dispatcher: Arc<Dispatcher>,
+    stream: std::net::TcpStream,
+    is_server: bool,
+  ) -> Result<TcpWire> {
+    let (registration, future) = Registration::start(dispatcher, stream, is_server)?;
+    Ok(registration.into_wire(future.wait()?))
+  }
+
+  /// この Wire の接続に `TCP_NODELAY` が設定されているかを参照します。
+  pub fn nodelay(&self) -> Result<bool> {
+    self.socket.nodelay().map_err(From::from)
+  }
+
+  /// この Wire の接続の `TCP_NODELAY` を設定します。
+  pub fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+    self.socket.set_nodelay(nodelay).map_err(From::from)
+  }
+
+  /// ディスパッチャーが受け付けを完了した送信の結果を確認し、失敗していた場合はそのエラーを返します。
+  fn check_sent(&mut self) -> Result<()> {
+    while let Some(accepted) = self.sending.front_mut() {
+      match accepted.try_result() {
+        Some(result) => {
+          self.sending.pop_front();
+          result?;
+        }
+        None => break,
+      }
+    }
+    Ok(())
+  }
+
+  /// シリアライズ済みのメッセージをディスパッチャーに引き渡します。
+  fn send_bytes(&mut self, bytes: Vec<u8>) -> Result<()> {
+    if self.closed || self.inbox.lock().unwrap().disposed {
+      return Err(Error::WireClosed);
+    }
+    self.check_sent()?;
+    self.sending.push_back(self.dispatcher.send(self.id, bytes).accepted());
+    Ok(())
+  }
 }
 
 impl Wire for TcpWire {
   fn local_address(&self) -> Result<SocketAddr> {
-    self.client.local_addr().map_err(From::from)
+    self.socket.local_addr().map_err(From::from)
   }
 
   fn remote_address(&self) -> Result<SocketAddr> {
-    self.client.peer_addr().map_err(From::from)
+    self.socket.peer_addr().map_err(From::from)
   }
 
   fn is_server(&self) -> bool {
     self.is_server
   }
 
+  fn send(&mut self, msg: Message) -> Result<()> {
+    let bytes = msg.to_bytes()?;
+    self.send_bytes(bytes)
+  }
+
+  fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+    self.send_bytes(bytes.to_vec())
+  }
+
+  /// ピアが接続をクローズし、受信済みのメッセージをすべて取り出した後は `Error::WireClosed` を返します。
+  fn try_recv(&mut self) -> Result<Option<Message>> {
+    if self.closed {
+      return Err(Error::WireClosed);
+    }
+    self.check_sent()?;
+    let mut inbox = self.inbox.lock().unwrap();
+    if let Some(msg) = inbox.decoder.next_message()? {
+      return Ok(Some(msg));
+    }
+    match inbox.error.take() {
+      Some(err) => Err(From::from(err)),
+      None if inbox.eof || inbox.disposed => Err(Error::WireClosed),
+      None => Ok(None),
+    }
+  }
+
+  /// ディスパッチャーからソケットを破棄します。ディスパッチャーがまだ書き込んでいないデータは破棄されます。
   fn close(&mut self) -> Result<()> {
-    self.client.shutdown(Shutdown::Both).map_err(From::from)
+    if self.closed {
+      return Ok(());
+    }
+    self.closed = true;
+    // 先に投入した送信の後に実行されるため完了を待つ必要はない
+    drop(self.dispatcher.dispose(self.id));
+    Ok(())
+  }
+}
+
+impl Drop for TcpWire {
+  fn drop(&mut self) {
+    if let Err(err) = self.close() {
+      log::warn!("failed to close the wire: {}", err);
+    }
+  }
+}
+
+/// ディスパッチャーへの登録が完了した後に `TcpWire` を構築するための値です。
+struct Registration {
+  dispatcher: Arc<Dispatcher>,
+  is_server: bool,
+  socket: Arc<std::net::TcpStream>,
+  inbox: Arc<Mutex<Inbox>>,
+}
+
+impl Registration {
+  /// TcpStream を `WireListener` とともにディスパッチャーに登録するタスクを投入します。
+  fn start(
+    dispatcher: Arc<Dispatcher>,
+    stream: std::net::TcpStream,
+    is_server: bool,
+  ) -> Result<(Registration, TaskFuture<Result<SocketId>>)> {
+    stream.set_nonblocking(true)?;
+    let socket = Arc::new(stream.try_clone()?);
+    let inbox = Arc::new(Mutex::new(Inbox {
+      decoder: StreamDecoder::new(),
+      error: None,
+      eof: false,
+      disposed: false,
+    }));
+    let listener: Box<dyn TcpStreamListener> =
+      Box::new(WireListener { inbox: inbox.clone(), socket: socket.clone() });
+    let future = dispatcher.register(TcpStream::from_std(stream), listener);
+    Ok((Registration { dispatcher, is_server, socket, inbox }, future))
+  }
+
+  fn into_wire(self, id: SocketId) -> TcpWire {
+    let Registration { dispatcher, is_server, socket, inbox } = self;
+    TcpWire { dispatcher, id, is_server, socket, inbox, sending: VecDeque::new(), closed: false }
   }
 }
 
-struct TcpServer {
-  id: usize,
+/// `WireListener` が受信したデータを `TcpWire` に引き渡すための共有状態です。
+struct Inbox {
+  decoder: StreamDecoder,
+  /// 読み込み中に発生したエラー。
+  error: Option<std::io::Error>,
+  /// ピアが送信方向をクローズしたことを検出した場合に true。
+  eof: bool,
+  /// ディスパッチャーからソケットが破棄された場合に true。
+  disposed: bool,
+}
+
+/// 受信したデータを `Inbox` に追加する TcpStreamListener です。
+struct WireListener {
+  inbox: Arc<Mutex<Inbox>>,
+  socket: Arc<std::net::TcpStream>,
+}
+
+impl TcpStreamListener for WireListener {
+  fn read_mode(&self) -> ReadMode {
+    ReadMode::Buffered
+  }
+
+  fn on_ready_to_read(&mut self, _r: &mut dyn Read) -> DispatcherAction {
+    DispatcherAction::Continue
+  }
+
+  fn on_data(&mut self, data: &[u8]) -> DispatcherAction {
+    let mut inbox = self.inbox.lock().unwrap();
+    inbox.decoder.push(data);
+    if inbox.decoder.buffered_len() >= MAX_BUFFERED_INPUT {
+      DispatcherAction::PauseReads
+    } else {
+      DispatcherAction::Continue
+    }
+  }
+
+  fn on_ready_to_write(&mut self, _w: &mut dyn Write) -> DispatcherAction {
+    DispatcherAction::ChangeFlag(Interest::READABLE)
+  }
+
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    self.inbox.lock().unwrap().error = Some(error);
+    DispatcherAction::Dispose
+  }
+
+  /// ピアが送信方向をクローズした後もこちらからの送信は続けられるため、ソケットは破棄しません。
+  fn on_eof(&mut self) -> DispatcherAction {
+    self.inbox.lock().unwrap().eof = true;
+    DispatcherAction::Continue
+  }
+
+  /// 受信したデータが `try_recv()` で取り出され上限の半分を下回ったら読み込みを再開します。
+  fn on_reads_paused(&mut self) -> DispatcherAction {
+    if self.inbox.lock().unwrap().decoder.buffered_len() <= MAX_BUFFERED_INPUT / 2 {
+      DispatcherAction::ResumeReads
+    } else {
+      DispatcherAction::Continue
+    }
+  }
+
+  /// `TcpWire` が保持しているソケットの複製があってもピアが EOF を検出できるように、接続をシャットダウンします。
+  fn on_disposed(&mut self) {
+    self.inbox.lock().unwrap().disposed = true;
+    if let Err(err) = self.socket.shutdown(Shutdown::Both) {
+      log::debug!("failed to shut down the connection: {}", err);
+    }
+  }
+}
+
+/// mio の TcpStream を標準ライブラリの TcpStream に変換します。
+#[cfg(unix)]
+fn into_std(stream: TcpStream) -> std::net::TcpStream {
+  use std::os::unix::io::{FromRawFd, IntoRawFd};
+  unsafe { std::net::TcpStream::from_raw_fd(stream.into_raw_fd()) }
+}
+
+/// mio の TcpStream を標準ライブラリの TcpStream に変換します。
+#[cfg(windows)]
+fn into_std(stream: TcpStream) -> std::net::TcpStream {
+  use std::os::windows::io::{FromRawSocket, IntoRawSocket};
+  unsafe { std::net::TcpStream::from_raw_socket(stream.into_raw_socket()) }
+}
+
+/// 受け付けた接続に `ListenOptions` のソケットオプションを設定します。設定に失敗しても接続は使用できるため、警告を
+/// 出力して処理を続けます。
+fn configure_accepted(stream: &TcpStream, address: SocketAddr, nodelay: bool) {
+  if let Err(err) = stream.set_nodelay(nodelay) {
+    log::warn!("failed to set TCP_NODELAY on the connection from {}: {}", address, err);
+  }
+}
+
+pub struct TcpServer {
+  dispatcher: Arc<Dispatcher>,
+  /// ディスパッチャーに登録した TcpListener の ID。`accept_one()` が最初に呼び出されるまでは `None` です。
+  id: Option<SocketId>,
+  /// ディスパッチャーに登録する前の TcpListener。登録後やクローズ後は `None` となります。
+  listener: Option<TcpListener>,
+  /// ディスパッチャーが受け付けた接続。
+  queue: Arc<Mutex<AcceptQueue>>,
+  address: SocketAddr,
   url: String,
+  /// 受け付けた接続に `TCP_NODELAY` を設定するか。
+  nodelay: bool,
+}
+
+impl TcpServer {
+  /// 次に受け付けた接続をサーバ側の Wire として返す Future を返します。最初の呼び出しで TcpListener をディスパッチャー
+  /// に登録し、それ以降に到着した接続は `accept_one()` で取り出されるまで保持されます。1 つずつ接続を処理する単純な
+  /// リクエスト/レスポンス型のサーバで使用します。
+  ///
+  /// 接続を待機している間にディスパッチャーの停止などによって TcpListener が破棄された場合は `Error::WireClosed` で
+  /// 完了します。
+  pub async fn accept_one(&mut self) -> Result<TcpWire> {
+    if self.id.is_none() {
+      let listener = match self.listener.take() {
+        Some(listener) => listener,
+        None => return Err(From::from(std::io::Error::from(std::io::ErrorKind::NotConnected))),
+      };
+      let event_listener: Box<dyn TcpListenerListener> =
+        Box::new(AcceptListener { queue: self.queue.clone(), nodelay: self.nodelay });
+      self.id = Some(self.dispatcher.register(listener, event_listener).await?);
+    }
+    let stream = Accepted { queue: self.queue.clone() }.await?;
+    if std::mem::take(&mut self.queue.lock().unwrap().paused) {
+      // 取り出したことで空きができたため接続の受け付けを再開する。完了を待つ必要はない
+      if let Some(id) = self.id {
+        drop(self.dispatcher.resume_reads(id));
+      }
+    }
+    let (registration, future) =
+      Registration::start(self.dispatcher.clone(), into_std(stream), true)?;
+    Ok(registration.into_wire(future.await?))
+  }
+
+  /// 受け付けた接続をディスパッチャーに登録し、その接続から受信したデータの処理を `executor` で実行します。ソケット
+  /// の読み込みはディスパッチャーのイベントループで行われ、`factory` が接続ごとに構築した `ConnectionHandler` は
+  /// `executor` のスレッドで呼び出されます。CPU 負荷の高い処理がイベントループをブロックすることを防ぎます。
+  ///
+  /// すでに `accept_one()` や `serve()` で接続を受け付けている場合や、クローズ後はエラーとなります。
+  pub async fn serve<F>(&mut self, executor: Arc<dyn Executor>, factory: F) -> Result<()>
+  where
+    F: FnMut(SocketAddr) -> Box<dyn ConnectionHandler> + Send + 'static,
+  {
+    let listener = match (self.id, self.listener.take()) {
+      (None, Some(listener)) => listener,
+      (Some(_), _) => {
+        let message = format!("the server {} is already accepting connections", self.url);
+        return Err(From::from(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message)));
+      }
+      (None, None) => {
+        return Err(From::from(std::io::Error::from(std::io::ErrorKind::NotConnected)))
+      }
+    };
+    let dispatcher = Arc::downgrade(&self.dispatcher);
+    let factory = Box::new(factory);
+    let event_listener: Box<dyn TcpListenerListener> =
+      Box::new(ServingListener { dispatcher, executor, factory, nodelay: self.nodelay });
+    self.id = Some(self.dispatcher.register(listener, event_listener).await?);
+    Ok(())
+  }
+}
+
+/// ディスパッチャーが受け付けた接続を `TcpServer::accept_one()` に引き渡すための共有状態です。
+struct AcceptQueue {
+  accepted: VecDeque<TcpStream>,
+  /// 接続の到着を待機している `Accepted` の Waker。
+  waker: Option<Waker>,
+  /// TcpListener がクローズされ、これ以上接続が到着しない場合に true。
+  closed: bool,
+  /// 保持する接続の最大数。
+  capacity: usize,
+  /// 接続が上限に達したために受け付けを停止している場合に true。
+  paused: bool,
+}
+
+impl AcceptQueue {
+  fn new(capacity: usize) -> AcceptQueue {
+    let capacity = std::cmp::max(capacity, 1);
+    AcceptQueue { accepted: VecDeque::new(), waker: None, closed: false, capacity, paused: false }
+  }
+
+  /// 受け付け済みの接続を破棄し、接続を待機している `Accepted` を完了させます。
+  fn close(&mut self) {
+    self.closed = true;
+    self.accepted.clear();
+    if let Some(waker) = self.waker.take() {
+      waker.wake();
+    }
+  }
+}
+
+/// 受け付けた接続を `AcceptQueue` に追加する TcpListenerListener です。キューが上限に達した場合は
+/// `DispatcherAction::PauseReads` によって受け付けを停止し、`accept_one()` が接続を取り出した時点で再開します。
+struct AcceptListener {
+  queue: Arc<Mutex<AcceptQueue>>,
+  nodelay: bool,
+}
+
+impl TcpListenerListener for AcceptListener {
+  fn on_accept(&mut self, stream: TcpStream, address: SocketAddr) -> DispatcherAction {
+    log::debug!("connection accepted: {}", address);
+    configure_accepted(&stream, address, self.nodelay);
+    let mut queue = self.queue.lock().unwrap();
+    queue.accepted.push_back(stream);
+    if let Some(waker) = queue.waker.take() {
+      waker.wake();
+    }
+    if queue.accepted.len() >= queue.capacity {
+      log::debug!("accept queue is full: {} connections", queue.accepted.len());
+      queue.paused = true;
+      DispatcherAction::PauseReads
+    } else {
+      DispatcherAction::Continue
+    }
+  }
+
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    log::error!("failed to accept a connection: {}", error);
+    DispatcherAction::Continue
+  }
+
+  fn on_disposed(&mut self) {
+    self.queue.lock().unwrap().close();
+  }
+}
+
+/// 受け付けた接続を `OffloadingListener` とともにディスパッチャーに登録する TcpListenerListener です。ディスパッチャー
+/// が自身に登録された Listener を介して破棄されなくならないように、ディスパッチャーを弱参照で保持します。
+struct ServingListener {
+  dispatcher: Weak<Dispatcher>,
+  executor: Arc<dyn Executor>,
+  factory: Box<dyn FnMut(SocketAddr) -> Box<dyn ConnectionHandler> + Send>,
+  nodelay: bool,
+}
+
+impl TcpListenerListener for ServingListener {
+  fn on_accept(&mut self, stream: TcpStream, address: SocketAddr) -> DispatcherAction {
+    log::debug!("connection accepted: {}", address);
+    let dispatcher = match self.dispatcher.upgrade() {
+      Some(dispatcher) => dispatcher,
+      None => return DispatcherAction::Dispose,
+    };
+    configure_accepted(&stream, address, self.nodelay);
+    let handler = (self.factory)(address);
+    let listener: Box<dyn TcpStreamListener> =
+      Box::new(OffloadingListener::new(self.executor.clone(), handler));
+    // 登録はこのコールバックの後にイベントループで実行されるため完了を待たない
+    if let Err(err) = dispatcher.try_register(stream, listener).map(TaskFuture::detach) {
+      log::warn!("connection from {} dropped: {}", address, err);
+    }
+    DispatcherAction::Continue
+  }
+
+  fn on_error(&mut self, error: std::io::Error) -> DispatcherAction {
+    log::error!("failed to accept a connection: {}", error);
+    DispatcherAction::Continue
+  }
+}
+
+/// `AcceptQueue` に接続が追加されたときに完了する Future です。接続が到着する前に TcpListener がクローズされた
+/// 場合は `Error::WireClosed` で完了します。
+struct Accepted {
+  queue: Arc<Mutex<AcceptQueue>>,
+}
+
+impl Future for Accepted {
+  type Output = Result<TcpStream>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let mut queue = self.queue.lock().unwrap();
+    match queue.accepted.pop_front() {
+      Some(stream) => Poll::Ready(Ok(stream)),
+      None if queue.closed => Poll::Ready(Err(Error::WireClosed)),
+      None => {
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
 }
 
 impl Server for TcpServer {
   fn url(&self) -> &str {
     &self.url
   }
+  fn local_address(&self) -> Result<String> {
+    Ok(self.address.to_string())
+  }
   fn close(&mut self) -> Result<()> {
-    unimplemented!()
+    if let Some(id) = self.id.take() {
+      // 完了を待つ必要はないため Future は破棄する
+      drop(self.dispatcher.dispose(id));
+      self.queue.lock().unwrap().close();
+      log::debug!("server closed: {}", self.url);
+    } else if self.listener.take().is_some() {
+      log::debug!("server closed: {}", self.url);
+    }
+    Ok(())
+  }
+}
+
+impl Drop for TcpServer {
+  fn drop(&mut self) {
+    if let Err(err) = self.close() {
+      log::warn!("failed to close the server {}: {}", self.url, err);
+    }
   }
 }
 
@@ -1,8 +1,447 @@
-use crate::bridge::tcp::TcpBridge;
-use crate::bridge::Bridge;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::time::Duration;
+
+use url::Url;
+
+use crate::bridge::io::dispatcher::{Dispatcher, DEFAULT_THREAD_NAME};
+use crate::bridge::io::executor::{ConnectionHandler, Executor};
+use crate::bridge::tcp::{bind, ListenOptions, TcpBridge, TcpWire, MAX_BUFFERED_INPUT};
+use crate::bridge::{Bridge, Server, Wire};
+use crate::error::Error;
+use crate::msg::{Block, Message, Open};
+use crate::test::{block_on, RecordingHandler, WorkerExecutor};
 
 #[test]
 fn test_tcp_bridge() {
-  // let mut bridge = TcpBridge::new(1024).unwrap();
+  // let mut bridge = TcpBridge::new(1024, 1024).unwrap();
   // let mut server = bridge.start_server()?;
 }
+
+#[test]
+fn test_start_server_url() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+
+  // IPv4 と IPv6 のアドレスを指定できる
+  let server = block_on(bridge.start_server(&Url::parse("tcp://127.0.0.1:0").unwrap())).unwrap();
+  assert!(server.url().starts_with("tcp://127.0.0.1:"));
+  let server = block_on(bridge.start_server(&Url::parse("tcp://[::1]:0").unwrap())).unwrap();
+  assert!(server.url().starts_with("tcp://[::1]:"));
+
+  // ポートが指定されていない
+  let url = Url::parse("tcp://127.0.0.1").unwrap();
+  assert_eq!(
+    Error::HostNotSpecifiedInUrl { url: url.to_string() },
+    block_on(bridge.start_server(&url)).err().unwrap()
+  );
+}
+
+#[test]
+fn test_accept_one() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let mut server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+
+  // 接続したクライアントと受け付けた Wire の間でメッセージを送受信できる
+  let mut client = bridge.connect(address).unwrap();
+  let mut accepted = block_on(Box::pin(server.accept_one())).unwrap();
+  assert!(accepted.is_server());
+  assert_eq!(client.local_address().unwrap(), accepted.remote_address().unwrap());
+  let msg = Message::Open(Open::new(1, 2, 3, vec![4u8; 16]).unwrap());
+  client.send(msg.clone()).unwrap();
+  let received = loop {
+    if let Some(msg) = accepted.try_recv().unwrap() {
+      break msg;
+    }
+    std::thread::yield_now();
+  };
+  assert_eq!(msg, received);
+
+  // クローズ後は接続を受け付けない
+  server.close().unwrap();
+  assert!(block_on(Box::pin(server.accept_one())).is_err());
+}
+
+#[test]
+fn test_accept_one_completes_on_close() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let dispatcher = bridge.dispatcher.clone();
+  let mut server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+
+  // 接続を待機している間に TcpListener がクローズされると WireClosed で完了する
+  let closer = std::thread::spawn(move || {
+    std::thread::sleep(Duration::from_millis(100));
+    dispatcher.stop().wait().unwrap();
+  });
+  let result = block_on(Box::pin(server.accept_one()));
+  assert_eq!(Error::WireClosed.code(), result.err().unwrap().code());
+  closer.join().unwrap();
+}
+
+#[test]
+fn test_accept_queue_size() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  bridge.set_listen_options(ListenOptions { accept_queue_size: 2, ..ListenOptions::default() });
+  let mut server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+  let _first = bridge.connect(address).unwrap();
+  block_on(Box::pin(server.accept_one())).unwrap();
+
+  // accept_one() を呼び出さない間は上限までしか受け付けず、残りの接続は OS の backlog で待機する
+  let clients = (0..5).map(|_| bridge.connect(address).unwrap()).collect::<Vec<_>>();
+  let deadline = std::time::Instant::now() + Duration::from_secs(10);
+  while server.queue.lock().unwrap().accepted.len() < 2 {
+    assert!(std::time::Instant::now() < deadline);
+    std::thread::yield_now();
+  }
+  std::thread::sleep(Duration::from_millis(100));
+  let (queued, paused) = {
+    let queue = server.queue.lock().unwrap();
+    (queue.accepted.len(), queue.paused)
+  };
+  assert_eq!((2, true), (queued, paused));
+
+  // 取り出すと受け付けが再開され、待機していた接続もすべて受け付けられる
+  let mut remotes = Vec::new();
+  for _ in 0..clients.len() {
+    let accepted = block_on(Box::pin(server.accept_one())).unwrap();
+    remotes.push(accepted.remote_address().unwrap());
+    let queued = server.queue.lock().unwrap().accepted.len();
+    assert!(queued <= 2);
+  }
+  let mut locals = clients.iter().map(|c| c.local_address().unwrap()).collect::<Vec<_>>();
+  remotes.sort();
+  locals.sort();
+  assert_eq!(locals, remotes);
+}
+
+#[test]
+fn test_wire_nodelay() {
+  // 接続した Wire と受け付けた Wire にはデフォルトで TCP_NODELAY が設定される
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let mut server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+  let client = bridge.connect(address).unwrap();
+  let accepted = block_on(Box::pin(server.accept_one())).unwrap();
+  assert!(client.nodelay().unwrap());
+  assert!(accepted.nodelay().unwrap());
+  client.set_nodelay(false).unwrap();
+  assert!(!client.nodelay().unwrap());
+
+  // ListenOptions で無効にした場合は受け付けた接続に設定されない
+  bridge.set_listen_options(ListenOptions { nodelay: false, ..ListenOptions::default() });
+  let mut server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+  let _client = bridge.connect(address).unwrap();
+  let accepted = block_on(Box::pin(server.accept_one())).unwrap();
+  assert!(!accepted.nodelay().unwrap());
+}
+
+#[test]
+fn test_start_servers() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let urls = vec![Url::parse("tcp://127.0.0.1:0").unwrap(), Url::parse("tcp://[::1]:0").unwrap()];
+  let mut servers = bridge.start_servers(&urls).unwrap();
+  assert_eq!(2, servers.len());
+  assert!(servers[0].url().starts_with("tcp://127.0.0.1:"));
+  assert!(servers[1].url().starts_with("tcp://[::1]:"));
+
+  // それぞれのアドレスで接続を受け付ける
+  for server in servers.iter_mut() {
+    let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+    let client = bridge.connect(address).unwrap();
+    let accepted = block_on(Box::pin(server.accept_one())).unwrap();
+    assert_eq!(client.local_address().unwrap(), accepted.remote_address().unwrap());
+  }
+
+  // いずれかのアドレスで失敗した場合は開始したサーバがクローズされる
+  let taken = servers[0].local_address().unwrap().parse::<SocketAddr>().unwrap();
+  let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let free_address = free.local_addr().unwrap();
+  drop(free);
+  let urls = vec![
+    Url::parse(&format!("tcp://{}", free_address)).unwrap(),
+    Url::parse(&format!("tcp://{}", taken)).unwrap(),
+  ];
+  assert_eq!(Error::AddressInUse { addr: taken }, bridge.start_servers(&urls).err().unwrap());
+  std::net::TcpListener::bind(free_address).unwrap();
+}
+
+#[test]
+fn test_serve_with_executor() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let mut server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+  let executor: Arc<dyn Executor> = Arc::new(WorkerExecutor::new("test-pool"));
+  let (sender, receiver) = channel();
+  let factory = move |_: SocketAddr| -> Box<dyn ConnectionHandler> {
+    Box::new(RecordingHandler { sender: sender.clone() })
+  };
+  block_on(Box::pin(server.serve(executor.clone(), factory))).unwrap();
+
+  // 受信したデータの処理はディスパッチャーではなく Executor のスレッドで実行される
+  let mut client = std::net::TcpStream::connect(address).unwrap();
+  client.write_all(b"hello, world").unwrap();
+  drop(client);
+  let mut received = Vec::new();
+  loop {
+    let (name, data) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(Some("test-pool"), name.as_deref());
+    assert_ne!(Some(DEFAULT_THREAD_NAME), name.as_deref());
+    match data {
+      Some(data) => received.extend_from_slice(&data),
+      None => break,
+    }
+  }
+  assert_eq!(b"hello, world".to_vec(), received);
+
+  // すでに接続を受け付けているサーバでは開始できない
+  let factory = |_: SocketAddr| -> Box<dyn ConnectionHandler> { unreachable!() };
+  assert!(block_on(Box::pin(server.serve(executor, factory))).is_err());
+}
+
+#[test]
+fn test_start_server_addr() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let address = "[::1]:0".parse::<SocketAddr>().unwrap();
+  let server = bridge.start_server_addr(address).unwrap();
+  assert!(server.url().starts_with("tcp://[::1]:"));
+}
+
+#[test]
+fn test_server_local_address() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+  assert_ne!(0, address.port());
+  assert_eq!(format!("tcp://{}", address), server.url());
+}
+
+#[test]
+fn test_bind_reuse_address() {
+  let options = ListenOptions { reuse_address: true, ..ListenOptions::default() };
+  let listener = bind("127.0.0.1:0".parse().unwrap(), &options).unwrap();
+  let address = listener.local_addr().unwrap();
+
+  // サーバ側から接続を切断して TIME_WAIT 状態のソケットを残す
+  let client = std::net::TcpStream::connect(address).unwrap();
+  let stream = accept_blocking(&listener).0;
+  drop(stream);
+  drop(listener);
+  drop(client);
+
+  // 同じポートに即座に再バインドできる
+  let listener = bind(address, &options).unwrap();
+  assert_eq!(address, listener.local_addr().unwrap());
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+#[test]
+fn test_bind_reuse_port() {
+  let options = ListenOptions { reuse_address: true, reuse_port: true, ..ListenOptions::default() };
+  let listener = bind("127.0.0.1:0".parse().unwrap(), &options).unwrap();
+  let address = listener.local_addr().unwrap();
+
+  // 複数のソケットが同じポートにバインドできる
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  bridge.set_listen_options(options);
+  let server = bridge.start_server_addr(address).unwrap();
+  assert_eq!(address.to_string(), server.local_address().unwrap());
+}
+
+#[test]
+fn test_bind_address_in_use() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+
+  // 使用中のポートにはバインドできない
+  let err = bridge.start_server_addr(address).err().unwrap();
+  assert_eq!(Error::AddressInUse { addr: address }, err);
+}
+
+#[test]
+fn test_bind_permission_denied() {
+  // 特権ポートへのバインドは権限がある環境 (root など) では成功するため、その場合は検証しない
+  let address = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+  match bind(address, &ListenOptions::default()) {
+    Ok(_) => (),
+    Err(Error::AddressInUse { .. }) => (),
+    Err(err) => assert_eq!(Error::BindPermissionDenied { addr: address }, err),
+  }
+}
+
+#[test]
+fn test_bind_default_backlog() {
+  // デフォルトの backlog で接続を受け付けられる
+  assert_eq!(1024, ListenOptions::default().backlog);
+  let listener = bind("127.0.0.1:0".parse().unwrap(), &ListenOptions::default()).unwrap();
+  let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+  let (_, peer) = accept_blocking(&listener);
+  assert_eq!(client.local_addr().unwrap(), peer);
+}
+
+#[test]
+fn test_bind_small_backlog() {
+  let options = ListenOptions { backlog: 1, ..ListenOptions::default() };
+  let listener = bind("127.0.0.1:0".parse().unwrap(), &options).unwrap();
+  let address = listener.local_addr().unwrap();
+
+  // accept() しないまま backlog を超える接続を試みる。Linux ではキューが溢れると SYN が破棄されるため、
+  // 溢れた接続は拒否されずにタイムアウトする (他のプラットフォームでは即座に拒否されることもある)。
+  // いずれの挙動も OS に依存するため、ここでは結果を検証せずに保持するだけとする
+  let timeout = std::time::Duration::from_millis(100);
+  let first = std::net::TcpStream::connect_timeout(&address, timeout).unwrap();
+  let _others =
+    (0..4).map(|_| std::net::TcpStream::connect_timeout(&address, timeout)).collect::<Vec<_>>();
+
+  // backlog に収まった最初の接続は accept() できる
+  let (_, peer) = accept_blocking(&listener);
+  assert_eq!(first.local_addr().unwrap(), peer);
+}
+
+#[test]
+fn test_wire_send_and_try_recv() {
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let mut client = TcpWire::connect(dispatcher.clone(), listener.local_addr().unwrap()).unwrap();
+  let mut server = TcpWire::register(dispatcher, listener.accept().unwrap().0, true).unwrap();
+  assert!(!client.is_server());
+  assert!(server.is_server());
+
+  // まだ何も受信していない
+  assert_eq!(None, server.try_recv().unwrap());
+
+  // 一方の Wire から送信した Open をもう一方の Wire で受信できる
+  let open = || Message::Open(Open::new(1, 2, 3, vec![4u8; 1024]).unwrap());
+  client.send(open()).unwrap();
+  let received = loop {
+    if let Some(msg) = server.try_recv().unwrap() {
+      break msg;
+    }
+    std::thread::yield_now();
+  };
+  assert_eq!(open(), received);
+  assert_eq!(None, server.try_recv().unwrap());
+
+  // ピアがクローズすると WireClosed を返し、クローズした Wire は使用できない
+  drop(client);
+  loop {
+    match server.try_recv() {
+      Ok(None) => std::thread::yield_now(),
+      Ok(Some(msg)) => panic!("unexpected message: {:?}", msg),
+      Err(err) => break assert_eq!(Error::WireClosed.code(), err.code()),
+    }
+  }
+  server.close().unwrap();
+  assert_eq!(Error::WireClosed.code(), server.send(open()).unwrap_err().code());
+}
+
+#[test]
+fn test_wire_send_raw() {
+  // 一度だけシリアライズした Block を 3 つの Wire にそのまま送信する
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let block = Message::Block(Block::new(1, false, 0, vec![7u8; 4096]).unwrap());
+  let bytes = block.to_bytes().unwrap();
+  let mut peers = Vec::new();
+  for _ in 0..3 {
+    let mut wire = TcpWire::connect(dispatcher.clone(), listener.local_addr().unwrap()).unwrap();
+    let (peer, _) = listener.accept().unwrap();
+    wire.send_raw(&bytes).unwrap();
+    peers.push((wire, peer));
+  }
+
+  // すべてのピアが同一のバイト列を受信し、それを同じ Block として復元できる
+  for (_wire, mut peer) in peers {
+    peer.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut received = vec![0u8; bytes.len()];
+    peer.read_exact(&mut received).unwrap();
+    assert_eq!(bytes, received);
+    assert_eq!(block, Message::from_bytes(&received).unwrap().0);
+  }
+}
+
+#[test]
+fn test_wire_pauses_reads_until_received() {
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let mut wire = TcpWire::connect(dispatcher, listener.local_addr().unwrap()).unwrap();
+  let (mut peer, _) = listener.accept().unwrap();
+
+  // 取り出されていないデータが上限を超えても、取り出すにつれて読み込みが再開されすべて受信できる
+  let block = Message::Block(Block::new(1, false, 0, vec![7u8; 4096]).unwrap());
+  let bytes = block.to_bytes().unwrap();
+  let count = 4 * MAX_BUFFERED_INPUT / bytes.len();
+  let writer = std::thread::spawn(move || {
+    for _ in 0..count {
+      peer.write_all(&bytes).unwrap();
+    }
+    peer
+  });
+  for _ in 0..count {
+    let received = loop {
+      if let Some(msg) = wire.try_recv().unwrap() {
+        break msg;
+      }
+      std::thread::yield_now();
+    };
+    assert_eq!(block, received);
+  }
+  drop(writer.join().unwrap());
+}
+
+#[test]
+fn test_server_drop_frees_port() {
+  let options = ListenOptions { reuse_address: false, ..ListenOptions::default() };
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  bridge.set_listen_options(options.clone());
+  let server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+
+  // サーバが存在する間はポートを使用できない
+  assert!(bind(address, &options).is_err());
+
+  // close() を呼ばずに破棄してもポートが解放される
+  drop(server);
+  bind(address, &options).unwrap();
+}
+
+#[test]
+fn test_server_close() {
+  let mut bridge = TcpBridge::new(1024, 1024).unwrap();
+  let mut server = bridge.start_server_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+  let address = server.local_address().unwrap().parse::<SocketAddr>().unwrap();
+
+  // 明示的にクローズするとポートが解放され、二重にクローズしてもエラーにならない
+  server.close().unwrap();
+  bind(address, &ListenOptions::default()).unwrap();
+  server.close().unwrap();
+}
+
+#[test]
+fn test_wire_drop_shuts_down_stream() {
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+  let server = listener.accept().unwrap().0;
+
+  // close() を呼ばずに破棄すると相手側は EOF を検出する
+  drop(TcpWire::register(dispatcher, server, true).unwrap());
+  client.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+  let mut buffer = [0u8; 1];
+  assert_eq!(0, std::io::Read::read(&mut client, &mut buffer).unwrap());
+}
+
+/// ノンブロッキングの TcpListener で接続を受け付けるまで待機します。
+fn accept_blocking(listener: &mio::net::TcpListener) -> (mio::net::TcpStream, SocketAddr) {
+  loop {
+    match listener.accept() {
+      Ok(accepted) => return accepted,
+      Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => std::thread::yield_now(),
+      Err(err) => panic!("{}", err),
+    }
+  }
+}
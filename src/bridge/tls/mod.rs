@@ -0,0 +1,45 @@
+use uuid::Uuid;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::pem::parse_x509_pem;
+use x509_parser::prelude::FromDer;
+
+use crate::error::Error;
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// DER 形式でエンコードされた X.509 証明書です。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate(pub Vec<u8>);
+
+impl Certificate {
+  /// PEM 形式の文字列から証明書を読み込みます。
+  pub fn from_pem(pem: &str) -> Result<Certificate> {
+    let (_, pem) = parse_x509_pem(pem.as_bytes())
+      .map_err(|err| Error::InvalidCertificate { message: err.to_string() })?;
+    Ok(Certificate(pem.contents))
+  }
+
+  /// 証明書の Subject に含まれる CN (Common Name) を参照します。
+  pub fn common_name(&self) -> Result<Option<String>> {
+    let (_, cert) = X509Certificate::from_der(&self.0)
+      .map_err(|err| Error::InvalidCertificate { message: err.to_string() })?;
+    let cn = cert.subject().iter_common_name().next();
+    match cn.map(|cn| cn.as_str()) {
+      Some(Ok(cn)) => Ok(Some(cn.to_string())),
+      Some(Err(err)) => Err(Error::InvalidCertificate { message: err.to_string() }),
+      None => Ok(None),
+    }
+  }
+}
+
+/// TLS ハンドシェイク後に、ピアの証明書の CN が `Control::SystemConfig` で通知されたノード ID と一致することを
+/// 検証します。CN は UUID の文字列表現として解釈され、一致しない場合は `Error::NodeIdMismatch` を返します。
+pub fn verify_node_id(peer_cert: &Certificate, expected: Uuid) -> Result<()> {
+  let cn = peer_cert.common_name()?;
+  match cn.as_deref().map(Uuid::parse_str) {
+    Some(Ok(actual)) if actual == expected => Ok(()),
+    _ => Err(Error::NodeIdMismatch { expected, actual: cn }),
+  }
+}
@@ -0,0 +1,63 @@
+use uuid::Uuid;
+
+use crate::bridge::tls::{verify_node_id, Certificate};
+use crate::error::Error;
+
+/// CN=6ba7b810-9dad-11d1-80b4-00c04fd430c8 の自己署名証明書。
+const NODE_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIB3zCCAYWgAwIBAgIUGEaNgtTzAkRC4+yhxtQrRULhbzIwCgYIKoZIzj0EAwIw
+RDETMBEGA1UECgwKYnVtYmxlYmVlczEtMCsGA1UEAwwkNmJhN2I4MTAtOWRhZC0x
+MWQxLTgwYjQtMDBjMDRmZDQzMGM4MCAXDTI2MTAxNzAzMDk1NFoYDzIxMjYwOTIz
+MDMwOTU0WjBEMRMwEQYDVQQKDApidW1ibGViZWVzMS0wKwYDVQQDDCQ2YmE3Yjgx
+MC05ZGFkLTExZDEtODBiNC0wMGMwNGZkNDMwYzgwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAARvhIgoztwW9i/fpmTHsnVPuQuFHG5PlaD/etleAulQtuTEr4NcxyRa
+N//QR1HgeRjAjgcfBQzihYL8+5NASfaVo1MwUTAdBgNVHQ4EFgQUr+R37JiQHzaL
+gJ27HVvzUjU5d50wHwYDVR0jBBgwFoAUr+R37JiQHzaLgJ27HVvzUjU5d50wDwYD
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiBJJk/FFe0QKa+UvHk8TWKn
+JHAcPQThqpD+pnC3cg+FpwIhAOPi+8Q29MVvOMeNHfVIkISSuqXNaXhjKDwFKExm
+1e8T
+-----END CERTIFICATE-----
+";
+
+/// CN=some-other-node の自己署名証明書。
+const OTHER_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBtTCCAVugAwIBAgIUOsj0rL+lQihthGoHYuFJfWoJMLswCgYIKoZIzj0EAwIw
+LzETMBEGA1UECgwKYnVtYmxlYmVlczEYMBYGA1UEAwwPc29tZS1vdGhlci1ub2Rl
+MCAXDTI2MTAxNzAzMDk1NFoYDzIxMjYwOTIzMDMwOTU0WjAvMRMwEQYDVQQKDApi
+dW1ibGViZWVzMRgwFgYDVQQDDA9zb21lLW90aGVyLW5vZGUwWTATBgcqhkjOPQIB
+BggqhkjOPQMBBwNCAARUTrCu1g/AP3Il+9kymqxbLmmiKJha/OJmaY5PfR/WWhvB
+jkuJlkMIZjDyROHklDs6gw3/Vb5C6l33MUHfZM7Ao1MwUTAdBgNVHQ4EFgQUNXIC
+ovIhvkC+YXsAC3JqmdqnWzkwHwYDVR0jBBgwFoAUNXICovIhvkC+YXsAC3Jqmdqn
+WzkwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiEA9I7ORcLGIVJM
+DffvhdEEFOsoXL0WU4Hi2frtdITzba4CIHbm3rvRafobbLTys1PZ4W+Cv0AOhfYK
+4ISXmE8ymtNY
+-----END CERTIFICATE-----
+";
+
+#[test]
+fn test_verify_node_id() {
+  let node_id = Uuid::parse_str("6ba7b810-9dad-11d1-80b4-00c04fd430c8").unwrap();
+
+  // CN がノード ID と一致する
+  let cert = Certificate::from_pem(NODE_CERT).unwrap();
+  assert_eq!(Some(node_id.to_string()), cert.common_name().unwrap());
+  verify_node_id(&cert, node_id).unwrap();
+
+  // CN が異なるノード ID を示している
+  let other_id = Uuid::from_u128(node_id.as_u128() + 1);
+  assert_eq!(
+    Error::NodeIdMismatch { expected: other_id, actual: Some(node_id.to_string()) },
+    verify_node_id(&cert, other_id).unwrap_err()
+  );
+
+  // CN が UUID として解釈できない
+  let cert = Certificate::from_pem(OTHER_CERT).unwrap();
+  assert_eq!(
+    Error::NodeIdMismatch { expected: node_id, actual: Some("some-other-node".to_string()) },
+    verify_node_id(&cert, node_id).unwrap_err()
+  );
+
+  // 証明書として解釈できない
+  let broken = Certificate(vec![0u8, 1, 2, 3]);
+  assert!(matches!(verify_node_id(&broken, node_id), Err(Error::InvalidCertificate { .. })));
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use url::Url;
+
+use crate::bridge::uds::{socket_path, UdsBridge, UdsWire};
+use crate::bridge::{create, Bridge, Server, Wire};
+use crate::error::Error;
+use crate::msg::{Message, Open};
+use crate::test::block_on;
+
+/// テスト用のソケットファイルのパスを一時ディレクトリ内に作成します。
+fn temp_socket_path(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("bumblebees-{}-{}.sock", std::process::id(), name));
+  let _ = std::fs::remove_file(&path);
+  path
+}
+
+/// 相手から 1 つのメッセージを受信するまで待機します。
+fn recv<W: Wire>(wire: &mut W) -> Message {
+  loop {
+    if let Some(msg) = wire.try_recv().unwrap() {
+      return msg;
+    }
+    std::thread::yield_now();
+  }
+}
+
+#[test]
+fn test_uds_bridge() {
+  let path = temp_socket_path("round-trip");
+  let url = Url::parse(&format!("unix://{}", path.display())).unwrap();
+  create(url.as_str()).unwrap();
+
+  let mut bridge = UdsBridge::new();
+  assert_eq!("unix", bridge.name());
+  let err = bridge.new_wire::<UdsWire>().err().unwrap();
+  assert!(matches!(err, Error::Io { kind: std::io::ErrorKind::Unsupported, .. }), "{:?}", err);
+  let mut server = block_on(Box::pin(bridge.start_server(&url))).unwrap();
+  assert_eq!(url.as_str(), server.url());
+  assert_eq!(path.display().to_string(), server.local_address().unwrap());
+  assert!(path.exists());
+
+  // クライアントとサーバの間でメッセージが往復する
+  let mut client = UdsWire::connect(&path).unwrap();
+  let mut accepted = loop {
+    if let Some(wire) = server.accept().unwrap() {
+      break wire;
+    }
+    std::thread::yield_now();
+  };
+  assert!(!client.is_server());
+  assert!(accepted.is_server());
+  assert!(client.local_address().is_err());
+
+  let open = || Message::Open(Open::new(1, 2, 3, vec![4u8; 1024]).unwrap());
+  client.send(open()).unwrap();
+  let received = recv(&mut accepted);
+  assert_eq!(open(), received);
+  accepted.send(received).unwrap();
+  assert_eq!(open(), recv(&mut client));
+
+  // サーバをクローズするとソケットファイルが削除される
+  server.close().unwrap();
+  assert!(!path.exists());
+  assert!(server.accept().unwrap().is_none());
+}
+
+#[test]
+fn test_socket_path() {
+  let path = |url: &str| socket_path(&Url::parse(url).unwrap());
+  assert_eq!(PathBuf::from("/tmp/app.sock"), path("unix:///tmp/app.sock").unwrap());
+  assert_eq!(
+    Error::SocketPathNotSpecifiedInUrl { url: "unix:///".to_string() },
+    path("unix:///").unwrap_err()
+  );
+}
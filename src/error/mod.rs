@@ -0,0 +1,401 @@
+use std::net::AddrParseError;
+use std::sync::PoisonError;
+
+use thiserror::Error as ThisError;
+
+#[cfg(test)]
+mod test;
+
+/// このライブラリで発生するエラーです。
+///
+/// 各エラーはプロトコル上で `Close.result` などにコンパクトに格納するための安定した数値コードを持ちます
+/// (`Error::code()`)。コードは上位桁でエラーの分類を表し、一度割り当てたコードは変更してはいけません。
+///
+/// | Code | Category                        |
+/// |-----:|:--------------------------------|
+/// | 1xx  | メッセージのエンコード/デコード |
+/// | 2xx  | I/O とキュー                    |
+/// | 3xx  | URL                             |
+/// | 4xx  | TCP/UDS レイヤー                |
+/// | 5xx  | TLS レイヤー                    |
+/// | 6xx  | パイプ                          |
+/// | 7xx  | セッション                      |
+#[derive(ThisError, Debug, PartialEq, Eq)]
+pub enum Error {
+  /// コード 100
+  #[error("should receive more data to restore the entire message")]
+  BufferUnsatisfied,
+  /// コード 108
+  ///
+  /// メッセージの復元中に入力が終了したことを示します。`needed` は処理を進めるために最低限必要な追加のバイト数
+  /// です。長さ付きのバイナリであれば、その長さから算出した残りのバイト数を表します。
+  #[error("should receive at least {needed} more bytes to restore the entire message")]
+  NeedMoreBytes { needed: usize },
+
+  /// コード 101
+  #[error("the pipe-id can only be zeroed in the Control message")]
+  ZeroPipeId,
+  /// コード 102
+  #[error("too large payload: {length:?}, max={maximum:?}")]
+  PayloadTooLarge { length: usize, maximum: usize },
+  /// コード 103
+  #[error("too large message: {length:?}, max={maximum:?}")]
+  MessageTooLarge { length: usize, maximum: usize },
+  /// コード 104
+  #[error("too big loss rate: {loss:?}, max={maximum:?}")]
+  LossRateTooBig { loss: usize, maximum: usize },
+
+  /// コード 105
+  #[error("illegal boolean representation: {value:#04X}")]
+  IllegalBooleanRepresentation { value: u8 },
+  /// コード 106
+  #[error("illegal Message type: {value:#04X}")]
+  IllegalMessageType { value: u8 },
+  /// コード 107
+  #[error("illegal Control type: {value:#04X}")]
+  IllegalControlType { value: u8 },
+  /// コード 109
+  ///
+  /// 順序の再構築が必要な経路でシーケンス番号を持たない Block を受け取ったことを示します。
+  #[error("the Block for pipe-id {pipe_id} doesn't have a sequence number")]
+  BlockSequenceNotSpecified { pipe_id: u16 },
+  /// コード 110
+  ///
+  /// MessagePack として読み込んだ値の型や範囲、配列の要素数がメッセージの表現として不正であることを示します。
+  #[error("illegal MessagePack value: {message}")]
+  IllegalMsgpackValue { message: String },
+  /// コード 111
+  ///
+  /// Block に付加された CRC32 チェックサムが受信した内容から算出した値と一致しないことを示します。転送中にデータが
+  /// 破損したことを表します。
+  #[error("checksum mismatch: expected={expected:#010X}, actual={actual:#010X}")]
+  ChecksumMismatch { expected: u32, actual: u32 },
+  /// コード 112
+  ///
+  /// ストリームの先頭で通知されたコーデック ID がこのライブラリでサポートされていないことを示します。
+  #[error("unsupported codec-id: {value:#04X}")]
+  UnsupportedCodec { value: u8 },
+  /// コード 113
+  ///
+  /// `PayloadReassembler` がすべてのパイプについて連結中のデータの合計が上限を超えたことを示します。
+  #[error("the reassembly buffers would hold {length} bytes in total, max={maximum}")]
+  ReassemblyBufferFull { length: usize, maximum: usize },
+  /// コード 114
+  ///
+  /// Block の bit_field で予約されており 0 でなければならないビットが設定されていることを示します。
+  #[error("reserved bits must be zero, but {bits:#06X} is set in the bit_field")]
+  ReservedBitsSet { bits: u16 },
+  /// コード 115
+  ///
+  /// `BlockReassembler` が次に取り出すシーケンス番号から並べ直しの範囲を超えて先のシーケンス番号を持つ Block を受信した
+  /// ことを示します。
+  #[error("the sequence {sequence} of pipe-id {pipe_id} is out of the reorder window {window} from {next}")]
+  SequenceOutOfWindow { pipe_id: u16, sequence: u32, next: u32, window: u32 },
+  /// コード 200
+  #[error("underlying I/O layer error: {message}")]
+  Io {
+    kind: std::io::ErrorKind,
+    message: String, // TODO use of unstable library feature 'backtrace'
+                     // TODO see issue #53487 <https://github.com/rust-lang/rust/issues/53487> for more information
+                     // #[source]
+                     // source: std::io::Error
+                     // #[from]
+                     // source: std::io::Error,
+                     // backtrace: std::backtrace::Backtrace
+  },
+
+  /// コード 201
+  #[error("message queue overflowed: {capacity:?}")]
+  MessageQueueOverflow { capacity: usize },
+  /// コード 202
+  #[error("event loop task queue overflowed: {capacity:?}")]
+  TaskQueueOverflow { capacity: usize },
+  /// コード 203
+  #[error("the dispatcher has already stopped")]
+  DispatcherStopped,
+  /// コード 204
+  #[error("lock failed: {message}")]
+  Lock { message: String },
+  /// コード 205
+  ///
+  /// ピアが接続をクローズしたか、すでにクローズした Wire に対して操作を行ったことを示します。
+  #[error("the wire has been closed")]
+  WireClosed,
+  /// コード 206
+  ///
+  /// タスクキューで実行を待っていたタスクが、ディスパッチャーの停止によって実行されずに破棄されたことを示します。
+  #[error("the dispatcher has shut down before the task was run")]
+  DispatcherShutdown,
+  /// コード 207
+  ///
+  /// タスクキューに空きがないため、操作を待機させずに即座に拒否したことを示します。
+  #[error("the operation would block because the task queue is full")]
+  WouldBlock,
+  /// コード 208
+  ///
+  /// ソケットの送信バッファに書き込みきれていないデータが上限を超えているため、送信するデータを受け付けなかった
+  /// ことを示します。
+  #[error("the send buffer of socket {id} exceeds the high-water mark {limit}")]
+  SendBufferFull { id: usize, limit: usize },
+
+  /// コード 300
+  #[error("unsupported protocol was specified: {url:?}")]
+  UnsupportedProtocol { url: String },
+  /// コード 301
+  #[error("host is not specified in url: {url}")]
+  HostNotSpecifiedInUrl { url: String },
+  /// コード 302
+  #[error("malformed url: {message}")]
+  MalformedUrl { kind: url::ParseError, message: String },
+
+  // TCP レイヤー
+  /// コード 400
+  #[error("the number of sockets in use has been reached maximum {maximum}")]
+  TooManySockets { maximum: usize },
+  /// コード 401
+  #[error("invalid socket address: {message}")]
+  InvalidSocketAddress { kind: AddrParseError, message: String },
+  /// コード 402
+  #[error("socket path is not specified in url: {url}")]
+  SocketPathNotSpecifiedInUrl { url: String },
+  /// コード 403
+  #[error("the address is already in use: {addr}")]
+  AddressInUse { addr: std::net::SocketAddr },
+  /// コード 404
+  #[error("permission denied to bind the address: {addr}")]
+  BindPermissionDenied { addr: std::net::SocketAddr },
+  /// コード 405
+  ///
+  /// ディスパッチャーがすでにクローズしたソケットを、それ以前に取得していた参照から操作しようとしたことを示します。
+  #[error("socket #{id} has already been disposed")]
+  SocketDisposed { id: usize },
+
+  // TLS レイヤー
+  /// コード 500
+  #[error("invalid certificate: {message}")]
+  InvalidCertificate { message: String },
+  /// コード 501
+  #[error("node-id {expected} doesn't match the certificate CN: {actual:?}")]
+  NodeIdMismatch { expected: uuid::Uuid, actual: Option<String> },
+
+  /// コード 600
+  ///
+  /// すでにクローズしている (または EOF に達した) パイプに対するメッセージを受け取ったことを示します。
+  #[error("the pipe {pipe_id} has already been closed")]
+  PipeClosed { pipe_id: u16 },
+  /// コード 601
+  #[error("the pipe {pipe_id} cannot accept {operation} in the {state} state")]
+  IllegalPipeTransition { pipe_id: u16, state: String, operation: String },
+  /// コード 602
+  #[error("the message for pipe {actual} was given to the pipe {expected}")]
+  PipeIdMismatch { expected: u16, actual: u16 },
+  /// コード 603
+  #[error("all {capacity} pipe-ids are in use")]
+  PipeIdExhausted { capacity: usize },
+  /// コード 604
+  ///
+  /// パイプの相手からの応答がタイムアウトまでに届かなかったことを示します。
+  #[error("the pipe {pipe_id} timed out after {timeout:?}")]
+  PipeTimeout { pipe_id: u16, timeout: std::time::Duration },
+  /// コード 605
+  ///
+  /// 相手がセッション内で同時に開くことのできるパイプの上限を超えて Open を送信したことを示します。
+  #[error("too many pipes are open in the session: maximum {maximum}")]
+  TooManyPipes { maximum: usize },
+  /// コード 606
+  ///
+  /// 相手がこの端点の割り当てる範囲の ID でパイプを開こうとしたことを示します。
+  #[error("the pipe-id {pipe_id} is reserved for pipes opened by this end")]
+  ForeignPipeId { pipe_id: u16 },
+
+  /// コード 700
+  #[error("incompatible protocol version: {version:#06x}")]
+  IncompatibleVersion { version: u16 },
+  /// コード 701
+  ///
+  /// ハンドシェイクで期待しないメッセージを受信したか、相手がハンドシェイクを拒否したことを示します。
+  #[error("handshake failed: {message}")]
+  IllegalHandshake { message: String },
+  /// コード 702
+  ///
+  /// プロトコル上その位置で受信してはならない種類のメッセージを受信したことを示します。
+  #[error("expected {expected}, but received {got}")]
+  UnexpectedMessage { expected: &'static str, got: &'static str },
+  /// コード 703
+  ///
+  /// クライアントがハンドシェイクで送信する System Config のセッション ID が 0 でないことを示します。セッション ID は
+  /// サーバが割り当てるため、クライアントは 0 を送信しなければなりません。
+  #[error("the client must send a zeroed session-id, but {session_id} was sent")]
+  NonZeroClientSessionId { session_id: uuid::Uuid },
+}
+
+/// エラーコードとエラー名の対応表。
+const ERROR_CODES: &[(u16, &str)] = &[
+  (100, "BufferUnsatisfied"),
+  (101, "ZeroPipeId"),
+  (102, "PayloadTooLarge"),
+  (103, "MessageTooLarge"),
+  (104, "LossRateTooBig"),
+  (105, "IllegalBooleanRepresentation"),
+  (106, "IllegalMessageType"),
+  (107, "IllegalControlType"),
+  (108, "NeedMoreBytes"),
+  (109, "BlockSequenceNotSpecified"),
+  (110, "IllegalMsgpackValue"),
+  (111, "ChecksumMismatch"),
+  (112, "UnsupportedCodec"),
+  (113, "ReassemblyBufferFull"),
+  (114, "ReservedBitsSet"),
+  (115, "SequenceOutOfWindow"),
+  (200, "Io"),
+  (201, "MessageQueueOverflow"),
+  (202, "TaskQueueOverflow"),
+  (203, "DispatcherStopped"),
+  (204, "Lock"),
+  (205, "WireClosed"),
+  (206, "DispatcherShutdown"),
+  (207, "WouldBlock"),
+  (208, "SendBufferFull"),
+  (300, "UnsupportedProtocol"),
+  (301, "HostNotSpecifiedInUrl"),
+  (302, "MalformedUrl"),
+  (400, "TooManySockets"),
+  (401, "InvalidSocketAddress"),
+  (402, "SocketPathNotSpecifiedInUrl"),
+  (403, "AddressInUse"),
+  (404, "BindPermissionDenied"),
+  (405, "SocketDisposed"),
+  (500, "InvalidCertificate"),
+  (501, "NodeIdMismatch"),
+  (600, "PipeClosed"),
+  (601, "IllegalPipeTransition"),
+  (602, "PipeIdMismatch"),
+  (603, "PipeIdExhausted"),
+  (604, "PipeTimeout"),
+  (605, "TooManyPipes"),
+  (606, "ForeignPipeId"),
+  (700, "IncompatibleVersion"),
+  (701, "IllegalHandshake"),
+  (702, "UnexpectedMessage"),
+  (703, "NonZeroClientSessionId"),
+];
+
+impl Error {
+  /// このエラーを識別する安定した数値コードを参照します。
+  pub fn code(&self) -> u16 {
+    match self {
+      Error::BufferUnsatisfied => 100,
+      Error::ZeroPipeId => 101,
+      Error::PayloadTooLarge { .. } => 102,
+      Error::MessageTooLarge { .. } => 103,
+      Error::LossRateTooBig { .. } => 104,
+      Error::IllegalBooleanRepresentation { .. } => 105,
+      Error::IllegalMessageType { .. } => 106,
+      Error::IllegalControlType { .. } => 107,
+      Error::NeedMoreBytes { .. } => 108,
+      Error::BlockSequenceNotSpecified { .. } => 109,
+      Error::IllegalMsgpackValue { .. } => 110,
+      Error::ChecksumMismatch { .. } => 111,
+      Error::UnsupportedCodec { .. } => 112,
+      Error::ReassemblyBufferFull { .. } => 113,
+      Error::ReservedBitsSet { .. } => 114,
+      Error::SequenceOutOfWindow { .. } => 115,
+      Error::Io { .. } => 200,
+      Error::MessageQueueOverflow { .. } => 201,
+      Error::TaskQueueOverflow { .. } => 202,
+      Error::DispatcherStopped => 203,
+      Error::Lock { .. } => 204,
+      Error::WireClosed => 205,
+      Error::DispatcherShutdown => 206,
+      Error::WouldBlock => 207,
+      Error::SendBufferFull { .. } => 208,
+      Error::UnsupportedProtocol { .. } => 300,
+      Error::HostNotSpecifiedInUrl { .. } => 301,
+      Error::MalformedUrl { .. } => 302,
+      Error::TooManySockets { .. } => 400,
+      Error::InvalidSocketAddress { .. } => 401,
+      Error::SocketPathNotSpecifiedInUrl { .. } => 402,
+      Error::AddressInUse { .. } => 403,
+      Error::BindPermissionDenied { .. } => 404,
+      Error::SocketDisposed { .. } => 405,
+      Error::InvalidCertificate { .. } => 500,
+      Error::NodeIdMismatch { .. } => 501,
+      Error::PipeClosed { .. } => 600,
+      Error::IllegalPipeTransition { .. } => 601,
+      Error::PipeIdMismatch { .. } => 602,
+      Error::PipeIdExhausted { .. } => 603,
+      Error::PipeTimeout { .. } => 604,
+      Error::TooManyPipes { .. } => 605,
+      Error::ForeignPipeId { .. } => 606,
+      Error::IncompatibleVersion { .. } => 700,
+      Error::IllegalHandshake { .. } => 701,
+      Error::UnexpectedMessage { .. } => 702,
+      Error::NonZeroClientSessionId { .. } => 703,
+    }
+  }
+
+  /// 指定されたエラーコードに対応するエラー名を参照します。未知のコードの場合は `None` を返します。
+  pub fn from_code(code: u16) -> Option<&'static str> {
+    ERROR_CODES.iter().find(|(c, _)| *c == code).map(|(_, name)| *name)
+  }
+}
+
+impl From<std::io::Error> for Error {
+  fn from(err: std::io::Error) -> Error {
+    if err.kind() == std::io::ErrorKind::UnexpectedEof {
+      Error::BufferUnsatisfied
+    } else {
+      Error::Io { kind: err.kind(), message: err.to_string() }
+    }
+  }
+}
+
+impl From<rmp::encode::ValueWriteError> for Error {
+  fn from(err: rmp::encode::ValueWriteError) -> Self {
+    match err {
+      rmp::encode::ValueWriteError::InvalidMarkerWrite(err) => Error::from(err),
+      rmp::encode::ValueWriteError::InvalidDataWrite(err) => Error::from(err),
+    }
+  }
+}
+
+impl From<rmp::decode::ValueReadError> for Error {
+  fn from(err: rmp::decode::ValueReadError) -> Self {
+    match err {
+      rmp::decode::ValueReadError::InvalidMarkerRead(err) => Error::from(err),
+      rmp::decode::ValueReadError::InvalidDataRead(err) => Error::from(err),
+      rmp::decode::ValueReadError::TypeMismatch(marker) => {
+        Error::IllegalMsgpackValue { message: format!("unexpected marker: {:?}", marker) }
+      }
+    }
+  }
+}
+
+impl From<rmp::decode::NumValueReadError> for Error {
+  fn from(err: rmp::decode::NumValueReadError) -> Self {
+    match err {
+      rmp::decode::NumValueReadError::InvalidMarkerRead(err) => Error::from(err),
+      rmp::decode::NumValueReadError::InvalidDataRead(err) => Error::from(err),
+      err => Error::IllegalMsgpackValue { message: err.to_string() },
+    }
+  }
+}
+
+impl From<url::ParseError> for Error {
+  fn from(err: url::ParseError) -> Self {
+    Error::MalformedUrl { kind: err, message: err.to_string() }
+  }
+}
+
+impl From<std::net::AddrParseError> for Error {
+  fn from(err: AddrParseError) -> Self {
+    let message = err.to_string();
+    Error::InvalidSocketAddress { kind: err, message }
+  }
+}
+
+impl<T> From<PoisonError<T>> for Error {
+  fn from(err: PoisonError<T>) -> Self {
+    Error::Lock { message: err.to_string() }
+  }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+use std::io::ErrorKind;
+
+use uuid::Uuid;
+
+use crate::error::{Error, ERROR_CODES};
+
+#[test]
+fn test_error_code() {
+  let errors = vec![
+    (100, Error::BufferUnsatisfied),
+    (101, Error::ZeroPipeId),
+    (102, Error::PayloadTooLarge { length: 0, maximum: 0 }),
+    (103, Error::MessageTooLarge { length: 0, maximum: 0 }),
+    (104, Error::LossRateTooBig { loss: 0, maximum: 0 }),
+    (105, Error::IllegalBooleanRepresentation { value: 0 }),
+    (106, Error::IllegalMessageType { value: 0 }),
+    (107, Error::IllegalControlType { value: 0 }),
+    (108, Error::NeedMoreBytes { needed: 0 }),
+    (109, Error::BlockSequenceNotSpecified { pipe_id: 0 }),
+    (110, Error::IllegalMsgpackValue { message: String::new() }),
+    (111, Error::ChecksumMismatch { expected: 0, actual: 0 }),
+    (112, Error::UnsupportedCodec { value: 0 }),
+    (113, Error::ReassemblyBufferFull { length: 0, maximum: 0 }),
+    (114, Error::ReservedBitsSet { bits: 0 }),
+    (115, Error::SequenceOutOfWindow { pipe_id: 0, sequence: 0, next: 0, window: 0 }),
+    (200, Error::Io { kind: ErrorKind::Other, message: String::new() }),
+    (201, Error::MessageQueueOverflow { capacity: 0 }),
+    (202, Error::TaskQueueOverflow { capacity: 0 }),
+    (203, Error::DispatcherStopped),
+    (204, Error::Lock { message: String::new() }),
+    (205, Error::WireClosed),
+    (206, Error::DispatcherShutdown),
+    (207, Error::WouldBlock),
+    (208, Error::SendBufferFull { id: 0, limit: 0 }),
+    (300, Error::UnsupportedProtocol { url: String::new() }),
+    (301, Error::HostNotSpecifiedInUrl { url: String::new() }),
+    (302, url::Url::parse("").unwrap_err().into()),
+    (400, Error::TooManySockets { maximum: 0 }),
+    (401, "".parse::<std::net::SocketAddr>().unwrap_err().into()),
+    (402, Error::SocketPathNotSpecifiedInUrl { url: String::new() }),
+    (403, Error::AddressInUse { addr: ([127, 0, 0, 1], 0).into() }),
+    (404, Error::BindPermissionDenied { addr: ([127, 0, 0, 1], 0).into() }),
+    (405, Error::SocketDisposed { id: 0 }),
+    (500, Error::InvalidCertificate { message: String::new() }),
+    (501, Error::NodeIdMismatch { expected: Uuid::nil(), actual: None }),
+    (600, Error::PipeClosed { pipe_id: 0 }),
+    (
+      601,
+      Error::IllegalPipeTransition { pipe_id: 0, state: String::new(), operation: String::new() },
+    ),
+    (602, Error::PipeIdMismatch { expected: 0, actual: 0 }),
+    (603, Error::PipeIdExhausted { capacity: 0 }),
+    (604, Error::PipeTimeout { pipe_id: 0, timeout: std::time::Duration::ZERO }),
+    (605, Error::TooManyPipes { maximum: 0 }),
+    (606, Error::ForeignPipeId { pipe_id: 0 }),
+    (700, Error::IncompatibleVersion { version: 0 }),
+    (701, Error::IllegalHandshake { message: String::new() }),
+    (702, Error::UnexpectedMessage { expected: "", got: "" }),
+    (703, Error::NonZeroClientSessionId { session_id: uuid::Uuid::nil() }),
+  ];
+
+  // すべてのエラーが一意で安定したコードを持つ
+  let mut codes = HashSet::new();
+  for (expected, error) in errors.iter() {
+    assert_eq!(*expected, error.code(), "{:?}", error);
+    assert!(codes.insert(error.code()), "duplicate code: {}", error.code());
+    let name = Error::from_code(error.code()).unwrap();
+    assert!(format!("{:?}", error).starts_with(name), "{} != {:?}", name, error);
+  }
+  assert_eq!(ERROR_CODES.len(), codes.len());
+
+  // 未知のコード
+  assert_eq!(None, Error::from_code(0));
+  assert_eq!(None, Error::from_code(u16::MAX));
+}
+
+#[test]
+fn test_from_rmp_error() {
+  use rmp::decode::{NumValueReadError, ValueReadError};
+  use rmp::Marker;
+
+  // 読み込み中の EOF はバッファ不足として扱われる
+  let eof = || std::io::Error::from(ErrorKind::UnexpectedEof);
+  assert_eq!(Error::BufferUnsatisfied, Error::from(ValueReadError::InvalidMarkerRead(eof())));
+  assert_eq!(Error::BufferUnsatisfied, Error::from(ValueReadError::InvalidDataRead(eof())));
+  assert_eq!(Error::BufferUnsatisfied, Error::from(NumValueReadError::InvalidMarkerRead(eof())));
+  assert_eq!(Error::BufferUnsatisfied, Error::from(NumValueReadError::InvalidDataRead(eof())));
+
+  // それ以外の I/O エラーは種類を保持する
+  let other = std::io::Error::new(ErrorKind::ConnectionReset, "reset");
+  match Error::from(ValueReadError::InvalidDataRead(other)) {
+    Error::Io { kind: ErrorKind::ConnectionReset, .. } => (),
+    unexpected => panic!("unexpected error: {:?}", unexpected),
+  }
+
+  // 型の不一致や範囲外の値は不正な値として扱われる
+  for err in [
+    Error::from(ValueReadError::TypeMismatch(Marker::Null)),
+    Error::from(NumValueReadError::TypeMismatch(Marker::Null)),
+    Error::from(NumValueReadError::OutOfRange),
+  ] {
+    match err {
+      Error::IllegalMsgpackValue { .. } => (),
+      unexpected => panic!("unexpected error: {:?}", unexpected),
+    }
+  }
+}
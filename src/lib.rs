@@ -1,6 +1,8 @@
 pub mod bridge;
 pub mod error;
 pub mod msg;
+pub mod pipe;
+pub mod session;
 
 #[cfg(test)]
 mod test;
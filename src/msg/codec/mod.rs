@@ -0,0 +1,356 @@
+use std::convert::TryFrom;
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rmp::decode::{read_array_len, read_bin_len, read_bool, read_int, read_marker};
+use rmp::encode::{write_array_len, write_bin, write_bool, write_nil, write_uint};
+use rmp::Marker;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::msg::{
+  from_utc_millis, read_fully, Block, Close, Control, ControlType, Message, Open, ID_BLOCK,
+  ID_CLOSE, ID_CONTROL, ID_OPEN, MAX_MESSAGE_SIZE,
+};
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// メッセージをバイトストリームにエンコード/デコードするためのトレイトです。ブリッジ層はこのトレイトを介することで
+/// ワイヤー上の表現形式に依存せずにメッセージを送受信することができます。
+pub trait Codec {
+  /// 指定されたメッセージをエンコードして書き込みます。
+  fn encode<W: Write>(&self, w: &mut W, msg: &Message) -> Result<()>;
+
+  /// ストリームから 1 つのメッセージを読み込んでデコードします。
+  fn decode<R: Read>(&self, r: &mut R) -> Result<Message>;
+}
+
+/// `Message::write_to()` と `Message::read_from()` によるこのライブラリ固有のバイナリ表現を使用するコーデックです。
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NativeCodec;
+
+impl Codec for NativeCodec {
+  fn encode<W: Write>(&self, w: &mut W, msg: &Message) -> Result<()> {
+    msg.write_to(w).map(drop)
+  }
+
+  fn decode<R: Read>(&self, r: &mut R) -> Result<Message> {
+    Message::read_from(r)
+  }
+}
+
+/// MessagePack による表現を使用するコーデックです。
+///
+/// 各メッセージはネイティブ表現と同じ 1 バイトの種類と 2 バイト (Little Endian) の本体の長さに続いて、MessagePack
+/// の配列としてエンコードした本体が続くフレームとして書き込まれます。Control の場合は配列の先頭がその種類を表し
+/// ます。フレーム自体が長さを持つため、MessagePack のメッセージを連結したストリームから境界を判断することができ
+/// ます。デコード時の値の検証はそれぞれのメッセージのコンストラクタと同じです。
+///
+/// | Name   | Bytes  | Type     |
+/// |:-------|-------:|:---------|
+/// | type   |      1 | uint8    |
+/// | length |      2 | uint16   |
+/// | body   | length | msgpack  |
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MsgpackCodec;
+
+impl Codec for MsgpackCodec {
+  fn encode<W: Write>(&self, w: &mut W, msg: &Message) -> Result<()> {
+    let mut body = Vec::with_capacity(msg.serialized_len() + 16);
+    let tag = MsgpackCodec::encode_body(&mut body, msg)?;
+    if 1 + 2 + body.len() > MAX_MESSAGE_SIZE {
+      return Err(Error::MessageTooLarge { length: 1 + 2 + body.len(), maximum: MAX_MESSAGE_SIZE });
+    }
+    w.write_u8(tag)?;
+    w.write_u16::<LittleEndian>(body.len() as u16)?;
+    w.write_all(&body)?;
+    Ok(())
+  }
+
+  /// ストリームがフレームの途中で終了している場合は、ネイティブコーデックと同様に不足しているバイト数を持つ
+  /// `Error::NeedMoreBytes` を返します。
+  fn decode<R: Read>(&self, r: &mut R) -> Result<Message> {
+    let mut header = [0u8; 3];
+    read_fully(r, &mut header)?;
+    let tag = header[0];
+    let mut body = vec![0u8; u16::from_le_bytes([header[1], header[2]]) as usize];
+    read_fully(r, &mut body)?;
+    let mut cursor = Cursor::new(&body[..]);
+    // フレームの長さは確定しているため、本体の途中で終了している場合は不正な値として扱う
+    let msg = MsgpackCodec::decode_body(&mut cursor, tag).map_err(|err| match err {
+      Error::BufferUnsatisfied => {
+        Error::IllegalMsgpackValue { message: "the body ends in the middle of the message".into() }
+      }
+      err => err,
+    })?;
+    if cursor.position() as usize != body.len() {
+      let remaining = body.len() - cursor.position() as usize;
+      let message = format!("{} bytes remain after the message", remaining);
+      return Err(Error::IllegalMsgpackValue { message });
+    }
+    Ok(msg)
+  }
+}
+
+/// ネイティブ表現を示すコーデック ID です。
+pub const CODEC_ID_NATIVE: u8 = b'N';
+
+/// MessagePack による表現を示すコーデック ID です。
+pub const CODEC_ID_MSGPACK: u8 = b'M';
+
+/// `CodecNegotiation` が選択したコーデックです。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectedCodec {
+  Native,
+  Msgpack,
+}
+
+impl SelectedCodec {
+  /// このコーデックを示すコーデック ID を参照します。
+  pub fn id(&self) -> u8 {
+    match self {
+      SelectedCodec::Native => CODEC_ID_NATIVE,
+      SelectedCodec::Msgpack => CODEC_ID_MSGPACK,
+    }
+  }
+
+  /// 指定されたコーデック ID が示すコーデックを参照します。
+  pub fn from_id(id: u8) -> Result<SelectedCodec> {
+    match id {
+      CODEC_ID_NATIVE => Ok(SelectedCodec::Native),
+      CODEC_ID_MSGPACK => Ok(SelectedCodec::Msgpack),
+      unexpected => Err(Error::UnsupportedCodec { value: unexpected }),
+    }
+  }
+}
+
+/// ストリームの先頭でコーデックを通知し、そのストリームで以降に送受信するメッセージのコーデックを決定するヘルパー
+/// です。
+///
+/// 送信側は最初のメッセージの前に 1 バイトのコーデック ID を書き込みます。受信側は先頭のコーデック ID を読み込んで
+/// 対応するコーデックを選択するため、1 つのポートで複数の表現形式を受け付けることができます。このヘルパー自体も
+/// 選択したコーデックに処理を委譲する `Codec` として使用することができます。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodecNegotiation {
+  codec: SelectedCodec,
+}
+
+impl CodecNegotiation {
+  /// 指定されたコーデックで送信を行うためのネゴシエーションを作成します。
+  pub fn new(codec: SelectedCodec) -> CodecNegotiation {
+    CodecNegotiation { codec }
+  }
+
+  /// ストリームの先頭からコーデック ID を読み込み、対応するコーデックを選択します。
+  pub fn detect<R: Read>(r: &mut R) -> Result<CodecNegotiation> {
+    let codec = SelectedCodec::from_id(r.read_u8()?)?;
+    Ok(CodecNegotiation { codec })
+  }
+
+  /// 最初のメッセージの前にこのコーデックを示すコーデック ID を書き込みます。
+  pub fn announce<W: Write>(&self, w: &mut W) -> Result<()> {
+    w.write_u8(self.codec.id())?;
+    Ok(())
+  }
+
+  /// 選択したコーデックを参照します。
+  pub fn codec(&self) -> SelectedCodec {
+    self.codec
+  }
+}
+
+impl Codec for CodecNegotiation {
+  fn encode<W: Write>(&self, w: &mut W, msg: &Message) -> Result<()> {
+    match self.codec {
+      SelectedCodec::Native => NativeCodec.encode(w, msg),
+      SelectedCodec::Msgpack => MsgpackCodec.encode(w, msg),
+    }
+  }
+
+  fn decode<R: Read>(&self, r: &mut R) -> Result<Message> {
+    match self.codec {
+      SelectedCodec::Native => NativeCodec.decode(r),
+      SelectedCodec::Msgpack => MsgpackCodec.decode(r),
+    }
+  }
+}
+
+impl MsgpackCodec {
+  /// メッセージの本体を MessagePack の配列として書き込み、メッセージの種類を返します。
+  fn encode_body<W: Write>(w: &mut W, msg: &Message) -> Result<u8> {
+    let tag = match msg {
+      Message::Open(open) => {
+        write_array_len(w, 4)?;
+        write_uint(w, open.pipe_id as u64)?;
+        write_uint(w, open.function_id as u64)?;
+        write_uint(w, open.priority as u64)?;
+        write_bin(w, &open.params)?;
+        ID_OPEN
+      }
+      Message::Close(close) => {
+        write_array_len(w, 3)?;
+        write_uint(w, close.pipe_id as u64)?;
+        write_bool(w, close.failure)?;
+        write_bin(w, &close.result)?;
+        ID_CLOSE
+      }
+      Message::Block(block) => {
+        write_array_len(w, 5)?;
+        write_uint(w, block.pipe_id as u64)?;
+        write_bool(w, block.eof)?;
+        write_uint(w, block.loss as u64)?;
+        match block.sequence {
+          Some(sequence) => {
+            write_uint(w, sequence as u64)?;
+          }
+          None => write_nil(w)?,
+        }
+        write_bin(w, &block.payload)?;
+        ID_BLOCK
+      }
+      Message::Control(Control::SystemConfig {
+        version,
+        node_id,
+        session_id,
+        utc_time,
+        ping_interval,
+        session_timeout,
+      }) => {
+        write_array_len(w, 7)?;
+        write_uint(w, ControlType::SystemConfig as u64)?;
+        write_uint(w, *version as u64)?;
+        write_bin(w, node_id.as_bytes())?;
+        write_bin(w, session_id.as_bytes())?;
+        write_uint(w, *utc_time)?;
+        write_uint(w, *ping_interval as u64)?;
+        write_uint(w, *session_timeout as u64)?;
+        ID_CONTROL
+      }
+      Message::Control(Control::Ping { utc_time }) => {
+        write_array_len(w, 2)?;
+        write_uint(w, ControlType::Ping as u64)?;
+        write_uint(w, *utc_time)?;
+        ID_CONTROL
+      }
+      Message::Control(Control::Close { reason_code, reason }) => {
+        write_array_len(w, 3)?;
+        write_uint(w, ControlType::Close as u64)?;
+        write_uint(w, *reason_code as u64)?;
+        write_bin(w, reason)?;
+        ID_CONTROL
+      }
+    };
+    Ok(tag)
+  }
+
+  /// 指定された種類のメッセージの本体を MessagePack の配列から読み込みます。
+  fn decode_body<R: Read>(r: &mut R, tag: u8) -> Result<Message> {
+    let len = read_array_len(r)?;
+    match tag {
+      ID_OPEN => {
+        verify_array_len(len, 4)?;
+        let (pipe_id, function_id, priority) = (read_int(r)?, read_int(r)?, read_int(r)?);
+        Ok(Message::Open(Open::new(pipe_id, function_id, priority, read_msgpack_bin(r)?)?))
+      }
+      ID_CLOSE => {
+        verify_array_len(len, 3)?;
+        let (pipe_id, failure) = (read_int(r)?, read_bool(r)?);
+        Ok(Message::Close(Close::new(pipe_id, failure, read_msgpack_bin(r)?)?))
+      }
+      ID_BLOCK => {
+        verify_array_len(len, 5)?;
+        let (pipe_id, eof, loss) = (read_int(r)?, read_bool(r)?, read_int(r)?);
+        let sequence = match read_marker(r).map_err(|err| Error::from(err.0))? {
+          Marker::Null => None,
+          marker => Some(read_uint_after(r, marker)?),
+        };
+        let payload = Arc::from(read_msgpack_bin(r)?);
+        let block = Block::with_shared_payload(pipe_id, eof, loss, payload)?;
+        Ok(Message::Block(match sequence {
+          Some(sequence) => block.with_sequence(sequence),
+          None => block,
+        }))
+      }
+      ID_CONTROL => match ControlType::try_from(read_int::<u8, _>(r)?)? {
+        ControlType::SystemConfig => {
+          verify_array_len(len, 7)?;
+          let version = read_int(r)?;
+          let node_id = read_uuid(r)?;
+          let session_id = read_uuid(r)?;
+          let (utc_time, ping_interval, session_timeout) =
+            (read_int(r)?, read_int(r)?, read_int(r)?);
+          Ok(Message::Control(Control::new_system_config(
+            version,
+            node_id,
+            session_id,
+            from_utc_millis(utc_time),
+            ping_interval,
+            session_timeout,
+          )?))
+        }
+        ControlType::Ping => {
+          verify_array_len(len, 2)?;
+          Ok(Message::Control(Control::new_ping(from_utc_millis(read_int(r)?))?))
+        }
+        ControlType::Close => {
+          verify_array_len(len, 3)?;
+          let reason_code = read_int(r)?;
+          Ok(Message::Control(Control::new_close(reason_code, read_msgpack_bin(r)?)?))
+        }
+      },
+      unexpected => Err(Error::IllegalMessageType { value: unexpected }),
+    }
+  }
+}
+
+/// メッセージを表す配列の要素数が想定と一致しているかを検証します。
+fn verify_array_len(actual: u32, expected: u32) -> Result<()> {
+  if actual == expected {
+    Ok(())
+  } else {
+    let message = format!("array of {} elements expected, but {}", expected, actual);
+    Err(Error::IllegalMsgpackValue { message })
+  }
+}
+
+/// すでに読み込んだマーカーに続く符号なし整数を読み込みます。
+fn read_uint_after<R: Read>(r: &mut R, marker: Marker) -> Result<u32> {
+  let value = match marker {
+    Marker::FixPos(value) => value as u64,
+    Marker::U8 => rmp::decode::RmpRead::read_data_u8(r)? as u64,
+    Marker::U16 => rmp::decode::RmpRead::read_data_u16(r)? as u64,
+    Marker::U32 => rmp::decode::RmpRead::read_data_u32(r)? as u64,
+    Marker::U64 => rmp::decode::RmpRead::read_data_u64(r)?,
+    marker => {
+      return Err(Error::IllegalMsgpackValue {
+        message: format!("unexpected marker: {:?}", marker),
+      })
+    }
+  };
+  if value > u32::MAX as u64 {
+    Err(Error::IllegalMsgpackValue { message: format!("out of range: {}", value) })
+  } else {
+    Ok(value as u32)
+  }
+}
+
+/// バイナリを読み込みます。長さは信頼できない入力であるため、1 メッセージの最大長を超える場合はバッファを確保する
+/// 前にエラーとします。
+fn read_msgpack_bin<R: Read>(r: &mut R) -> Result<Vec<u8>> {
+  let len = read_bin_len(r)? as usize;
+  if len > MAX_MESSAGE_SIZE {
+    let message = format!("binary of {} bytes exceeds the maximum {}", len, MAX_MESSAGE_SIZE);
+    return Err(Error::IllegalMsgpackValue { message });
+  }
+  let mut buffer = vec![0u8; len];
+  r.read_exact(&mut buffer)?;
+  Ok(buffer)
+}
+
+fn read_uuid<R: Read>(r: &mut R) -> Result<Uuid> {
+  let bytes = read_msgpack_bin(r)?;
+  Uuid::from_slice(&bytes).map_err(|err| Error::IllegalMsgpackValue { message: err.to_string() })
+}
@@ -0,0 +1,210 @@
+use std::io::Cursor;
+
+use crate::error::Error;
+use crate::msg::codec::{
+  Codec, CodecNegotiation, MsgpackCodec, NativeCodec, SelectedCodec, CODEC_ID_MSGPACK,
+  CODEC_ID_NATIVE,
+};
+use crate::msg::{from_utc_millis, Control, Message, Open};
+use crate::test::SampleValues;
+use crate::Result;
+
+/// 同じメッセージの集合をコーデックでエンコードし、デコードした結果が一致することを検証します。
+fn assert_round_trip<C: Codec>(codec: &C, seed: u64) {
+  let mut sample = SampleValues::new(seed);
+  let msgs = (0..200).map(|_| sample.next_message()).collect::<Vec<_>>();
+
+  // 連続してエンコードしたメッセージを順に復元できる
+  let mut buf = Vec::new();
+  for msg in msgs.iter() {
+    codec.encode(&mut buf, msg).unwrap();
+  }
+  let mut cursor = Cursor::new(&buf[..]);
+  for msg in msgs.iter() {
+    assert_eq!(*msg, codec.decode(&mut cursor).unwrap());
+  }
+  assert_eq!(buf.len() as u64, cursor.position());
+
+  // 未完成のバッファはエラーとなる
+  for msg in msgs.iter().take(20) {
+    let mut buf = Vec::new();
+    codec.encode(&mut buf, msg).unwrap();
+    for i in 0..buf.len() {
+      assert!(codec.decode(&mut Cursor::new(&buf[..i])).is_err());
+    }
+  }
+}
+
+#[test]
+fn test_native_codec() {
+  assert_round_trip(&NativeCodec, 9130741);
+
+  // ネイティブコーデックは Message の直列化と同じ表現を使用する
+  let msg = Message::Open(Open::new(1, 2, 3, vec![4, 5]).unwrap());
+  let (mut expected, mut actual) = (Vec::new(), Vec::new());
+  msg.write_to(&mut expected).unwrap();
+  NativeCodec.encode(&mut actual, &msg).unwrap();
+  assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_msgpack_codec() {
+  assert_round_trip(&MsgpackCodec, 2058119);
+
+  // 型の異なる値や要素数の異なる配列は不正な値として扱われる
+  let mut body = Vec::new();
+  rmp::encode::write_array_len(&mut body, 1).unwrap();
+  rmp::encode::write_uint(&mut body, 1).unwrap();
+  assert_illegal_msgpack_value(MsgpackCodec.decode(&mut Cursor::new(frame(b'O', &body))));
+  let mut body = Vec::new();
+  rmp::encode::write_array_len(&mut body, 4).unwrap();
+  rmp::encode::write_bool(&mut body, true).unwrap();
+  assert_illegal_msgpack_value(MsgpackCodec.decode(&mut Cursor::new(frame(b'O', &body))));
+
+  // フレームの長さと本体の長さが一致しない
+  let mut body = Vec::new();
+  MsgpackCodec
+    .encode(&mut body, &Message::Control(Control::new_ping(from_utc_millis(1)).unwrap()))
+    .unwrap();
+  let mut extended = body[3..].to_vec();
+  extended.push(0);
+  assert_illegal_msgpack_value(MsgpackCodec.decode(&mut Cursor::new(frame(b'X', &extended))));
+  let truncated = &body[3..body.len() - 1];
+  assert_illegal_msgpack_value(MsgpackCodec.decode(&mut Cursor::new(frame(b'X', truncated))));
+
+  // 最大長を超えるバイナリの長さはバッファを確保する前に不正な値として扱われる
+  let mut body = Vec::new();
+  rmp::encode::write_array_len(&mut body, 4).unwrap();
+  for value in [1u64, 2, 3].iter() {
+    rmp::encode::write_uint(&mut body, *value).unwrap();
+  }
+  rmp::encode::write_bin_len(&mut body, u32::MAX).unwrap();
+  assert_illegal_msgpack_value(MsgpackCodec.decode(&mut Cursor::new(frame(b'O', &body))));
+
+  // コンストラクタと同じ検証が行われる
+  let mut body = Vec::new();
+  rmp::encode::write_array_len(&mut body, 4).unwrap();
+  for value in [0u64, 2, 3].iter() {
+    rmp::encode::write_uint(&mut body, *value).unwrap();
+  }
+  rmp::encode::write_bin(&mut body, &[]).unwrap();
+  assert_eq!(
+    Error::ZeroPipeId,
+    MsgpackCodec.decode(&mut Cursor::new(frame(b'O', &body))).unwrap_err()
+  );
+}
+
+#[test]
+fn test_msgpack_codec_frame() {
+  // 種類と本体の長さに続いて MessagePack の本体が書き込まれる
+  let open = Message::Open(Open::new(1, 2, 3, vec![4, 5]).unwrap());
+  let mut buf = Vec::new();
+  MsgpackCodec.encode(&mut buf, &open).unwrap();
+  assert_eq!(b'O', buf[0]);
+  assert_eq!(buf.len() - 3, u16::from_le_bytes([buf[1], buf[2]]) as usize);
+  assert_eq!(&[0x94, 0x01, 0x02, 0x03, 0xC4, 0x02, 0x04, 0x05][..], &buf[3..]);
+
+  // 連結した 2 つのメッセージを境界を判断して復元できる
+  let ping = Message::Control(Control::new_ping(from_utc_millis(6)).unwrap());
+  MsgpackCodec.encode(&mut buf, &ping).unwrap();
+  let mut cursor = Cursor::new(&buf[..]);
+  assert_eq!(open, MsgpackCodec.decode(&mut cursor).unwrap());
+  assert_eq!(ping, MsgpackCodec.decode(&mut cursor).unwrap());
+  assert_eq!(buf.len() as u64, cursor.position());
+}
+
+#[test]
+fn test_msgpack_codec_truncated() {
+  // ストリーム上で途中までしか到着していないフレームは、どちらのコーデックでも同じ NeedMoreBytes として扱われる
+  let mut sample = SampleValues::new(4410927);
+  for _ in 0..20 {
+    let msg = sample.next_message();
+    let mut native = Vec::new();
+    NativeCodec.encode(&mut native, &msg).unwrap();
+    let mut msgpack = Vec::new();
+    MsgpackCodec.encode(&mut msgpack, &msg).unwrap();
+    let truncated = |codec: SelectedCodec, buf: &[u8]| {
+      (0..buf.len())
+        .map(|i| CodecNegotiation::new(codec).decode(&mut Cursor::new(&buf[..i])).unwrap_err())
+        .collect::<Vec<_>>()
+    };
+    let errors = truncated(SelectedCodec::Native, &native)
+      .into_iter()
+      .chain(truncated(SelectedCodec::Msgpack, &msgpack))
+      .collect::<Vec<_>>();
+    for err in errors {
+      assert!(matches!(err, Error::NeedMoreBytes { needed } if needed > 0), "{:?}", err);
+    }
+
+    // フレームの長さは確定しているため、MessagePack の不足バイト数はフレームの残りの長さと一致する
+    for i in 3..msgpack.len() {
+      let err = MsgpackCodec.decode(&mut Cursor::new(&msgpack[..i])).unwrap_err();
+      assert_eq!(Error::NeedMoreBytes { needed: msgpack.len() - i }, err);
+    }
+  }
+}
+
+/// 指定された種類と本体から MessagePack コーデックのフレームを構築します。
+fn frame(tag: u8, body: &[u8]) -> Vec<u8> {
+  let mut buf = vec![tag];
+  buf.extend_from_slice(&(body.len() as u16).to_le_bytes());
+  buf.extend_from_slice(body);
+  buf
+}
+
+fn assert_illegal_msgpack_value(result: Result<Message>) {
+  match result.unwrap_err() {
+    Error::IllegalMsgpackValue { .. } => (),
+    unexpected => panic!("unexpected error: {:?}", unexpected),
+  }
+}
+
+#[test]
+fn test_codec_generic() {
+  // トレイトを介して表現形式に依存せずにメッセージを送受信できる
+  fn transfer<C: Codec>(codec: C, msg: &Message) -> Message {
+    let mut buf = Vec::new();
+    codec.encode(&mut buf, msg).unwrap();
+    codec.decode(&mut Cursor::new(&buf[..])).unwrap()
+  }
+  let mut sample = SampleValues::new(604112);
+  for _ in 0..100 {
+    let msg = sample.next_message();
+    assert_eq!(msg, transfer(NativeCodec, &msg));
+    assert_eq!(msg, transfer(MsgpackCodec, &msg));
+  }
+}
+
+#[test]
+fn test_codec_negotiation() {
+  let mut sample = SampleValues::new(6620317);
+  let msgs = (0..50).map(|_| sample.next_message()).collect::<Vec<_>>();
+
+  for (codec, id) in
+    [(SelectedCodec::Native, CODEC_ID_NATIVE), (SelectedCodec::Msgpack, CODEC_ID_MSGPACK)]
+  {
+    // 送信側はコーデック ID に続けて選択したコーデックでメッセージを書き込む
+    let sender = CodecNegotiation::new(codec);
+    let mut buf = Vec::new();
+    sender.announce(&mut buf).unwrap();
+    for msg in msgs.iter() {
+      sender.encode(&mut buf, msg).unwrap();
+    }
+    assert_eq!(id, buf[0]);
+
+    // 受信側は先頭のコーデック ID から同じコーデックを選択して復元する
+    let mut cursor = Cursor::new(&buf[..]);
+    let receiver = CodecNegotiation::detect(&mut cursor).unwrap();
+    assert_eq!(codec, receiver.codec());
+    for msg in msgs.iter() {
+      assert_eq!(*msg, receiver.decode(&mut cursor).unwrap());
+    }
+    assert_eq!(buf.len() as u64, cursor.position());
+  }
+
+  // サポートしていないコーデック ID
+  assert_eq!(
+    Error::UnsupportedCodec { value: 0xFF },
+    CodecNegotiation::detect(&mut Cursor::new(&[0xFFu8][..])).unwrap_err()
+  );
+}
@@ -1,11 +1,19 @@
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+#[cfg(feature = "async-io")]
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use rand_core::RngCore;
 use uuid::Uuid;
 
 use super::error::Error;
 use super::Result;
 
+pub mod codec;
 #[cfg(test)]
 mod test;
 
@@ -18,8 +26,31 @@ pub const MAX_LOSS_RATE: u8 = 0x7F;
 /// シリアライズした 1 メッセージの最大バイナリ長です。IPv4 のデータ部最大長である 65,507 を表します。
 pub const MAX_MESSAGE_SIZE: usize = 65507;
 
+/// Control メッセージが暗黙的に属するパイプ ID です。Control メッセージのバイナリ表現はパイプ ID を持たず、このパイプ
+/// ID は Open、Close、Block に使用することはできません。そのため、復元したメッセージのうちパイプ 0 を宛先とするのは
+/// Control メッセージだけです。
+pub const CONTROL_PIPE_ID: u16 = 0;
+
+/// このライブラリが実装しているプロトコルのバージョンです。上位バイトから [major][minor] の順で 1.0 を表しています。
+pub const PROTOCOL_VERSION: u16 = 0x0100;
+
+/// `PayloadReassembler` が 1 つのパイプについて連結できるデフォルトの最大データ長です。
+pub const DEFAULT_MAX_REASSEMBLED_SIZE: usize = 1024 * 1024;
+
+/// `PayloadReassembler` がすべてのパイプについて連結中のデータの合計のデフォルトの上限です。
+pub const DEFAULT_MAX_REASSEMBLED_TOTAL_SIZE: usize = 16 * 1024 * 1024;
+
+/// `BlockReassembler` が並べ直すために保持できるデフォルトのシーケンス番号の範囲です。
+pub const DEFAULT_REORDER_WINDOW: u32 = 1024;
+
+/// `SystemConfigBuilder` が使用するデフォルトの ping 間隔 (秒) です。
+pub const DEFAULT_PING_INTERVAL: u32 = 10;
+
+/// `SystemConfigBuilder` が使用するデフォルトのセッションタイムアウト (秒) です。
+pub const DEFAULT_SESSION_TIMEOUT: u32 = 60;
+
 /// 特定のファンクションに対するパイプをオープンするためのメッセージ。
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Open {
   /// このメッセージの宛先を示すパイプ ID
   pipe_id: u16,
@@ -37,28 +68,52 @@ impl Open {
     Ok(Open { pipe_id, function_id, params, priority })
   }
 
-  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<()> {
+  /// このメッセージの宛先を示すパイプ ID を参照します。
+  pub fn pipe_id(&self) -> u16 {
+    self.pipe_id
+  }
+
+  /// この Open によって開かれるパイプの優先度を参照します。
+  pub fn priority(&self) -> u8 {
+    self.priority
+  }
+
+  /// このメッセージをシリアライズしたときのバイナリ長を参照します。
+  pub fn serialized_len(&self) -> usize {
+    2 + 2 + 1 + bin_len(&self.params)
+  }
+
+  /// このメッセージをシリアライズして出力し、出力したバイト数を返します。
+  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<usize> {
     write_u16(buf, self.pipe_id)?;
     write_u16(buf, self.function_id)?;
     write_u8(buf, self.priority)?;
     write_bin(buf, &self.params)?;
-    Ok(())
+    Ok(self.serialized_len())
   }
 
+  /// バイナリ表現から復元します。パイプ ID が 0 の場合は `Error::ZeroPipeId` を返します。
   pub fn read_from<R: Read>(buf: &mut R) -> Result<Open> {
     Ok(Open {
-      pipe_id: read_u16(buf)?,
+      pipe_id: read_pipe_id(buf)?,
       function_id: read_u16(buf)?,
       priority: read_u8(buf)?,
       params: read_bin(buf)?,
     })
   }
+
+  /// 指定されたバイト列の先頭から `Open` を復元し、復元した値と消費したバイト数を返します。`Cursor` を使用せずに
+  /// スライスから直接読み込む点を除いて `read_from()` と同じ動作で、バイト列が不足している場合は
+  /// `Error::NeedMoreBytes` を返します。
+  pub fn from_bytes(bytes: &[u8]) -> Result<(Open, usize)> {
+    read_from_bytes(bytes, Open::read_from)
+  }
 }
 
 /// パイプのクローズを示すメッセージ。`failure` が `false` の場合、この `Close` と対になる `Open` のファンクション
 /// 呼び出しは正常に終了し `result` にはその結果が格納されていることを示しています。`failure` が `true` の場合、
 /// ファンクションは何らかの理由で失敗し `result` にはそのエラー状況が可能されていることを示します。
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Close {
   /** このメッセージの宛先を示すパイプ ID。 */
   pipe_id: u16,
@@ -74,23 +129,62 @@ impl Close {
     Ok(Close { pipe_id, failure, result })
   }
 
-  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<()> {
+  /// ファンクション呼び出しが正常に終了したことを示す `Close` を構築します。
+  pub fn success(pipe_id: u16, result: Vec<u8>) -> Result<Self> {
+    Close::new(pipe_id, false, result)
+  }
+
+  /// ファンクション呼び出しが失敗したことを示す `Close` を構築します。`error` にはそのエラー状況を指定します。
+  pub fn failure(pipe_id: u16, error: Vec<u8>) -> Result<Self> {
+    Close::new(pipe_id, true, error)
+  }
+
+  /// このメッセージの宛先を示すパイプ ID を参照します。
+  pub fn pipe_id(&self) -> u16 {
+    self.pipe_id
+  }
+
+  /// この `Close` がファンクション呼び出しの正常終了を示している場合に true を返します。
+  pub fn is_success(&self) -> bool {
+    !self.failure
+  }
+
+  /// 処理結果、または処理が失敗した場合のエラー情報を参照します。
+  pub fn result(&self) -> &[u8] {
+    &self.result
+  }
+
+  /// このメッセージをシリアライズしたときのバイナリ長を参照します。
+  pub fn serialized_len(&self) -> usize {
+    2 + 1 + bin_len(&self.result)
+  }
+
+  /// このメッセージをシリアライズして出力し、出力したバイト数を返します。
+  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<usize> {
     let bit_field: u8 = if self.failure { 1 << 0 } else { 0 };
     write_u16(buf, self.pipe_id)?;
     write_u8(buf, bit_field)?;
     write_bin(buf, &self.result)?;
-    Ok(())
+    Ok(self.serialized_len())
   }
 
+  /// バイナリ表現から復元します。パイプ ID が 0 の場合は `Error::ZeroPipeId` を返します。
   pub fn read_from<R: Read>(buf: &mut R) -> Result<Close> {
-    let pipe_id = read_u16(buf)?;
+    let pipe_id = read_pipe_id(buf)?;
     let bit_field = read_u8(buf)?;
     let result = read_bin(buf)?;
     Ok(Close { pipe_id, failure: (bit_field & 0x01) != 0, result })
   }
+
+  /// 指定されたバイト列の先頭から `Close` を復元し、復元した値と消費したバイト数を返します。`Cursor` を使用せずに
+  /// スライスから直接読み込む点を除いて `read_from()` と同じ動作で、バイト列が不足している場合は
+  /// `Error::NeedMoreBytes` を返します。
+  pub fn from_bytes(bytes: &[u8]) -> Result<(Close, usize)> {
+    read_from_bytes(bytes, Close::read_from)
+  }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Block {
   /// このメッセージの宛先を示すパイプ ID。
   pipe_id: u16,
@@ -108,45 +202,408 @@ pub struct Block {
   /// 更新されます。
   loss: u8,
 
-  /// このブロックが転送するデータ。
-  payload: Vec<u8>,
+  /// 同じパイプ内でのこのブロックの順序を示すシーケンス番号。順序が入れ替わる可能性のあるトランスポートでブロックを
+  /// 再構築するために使用します。順序が保証されているトランスポートでは省略することができます。
+  sequence: Option<u32>,
+
+  /// シリアライズ時に CRC32 チェックサムを付加するかのフラグ。信頼性の低いトランスポートで転送中のデータの破損を
+  /// 検出するために使用します。チェックサムを付加しない Block は従来と同じバイナリ表現となります。
+  checksum: bool,
+
+  /// このブロックが転送するデータ。Block を中継するときにペイロードを複製せずに共有できるように参照カウントで
+  /// 保持しています。
+  payload: Arc<[u8]>,
 }
 
 impl Block {
   pub fn new(pipe_id: u16, eof: bool, loss: u8, payload: Vec<u8>) -> Result<Self> {
+    Block::with_shared_payload(pipe_id, eof, loss, Arc::from(payload))
+  }
+
+  /// 他の Block とペイロードを共有する Block を構築します。ペイロードは複製されません。
+  pub fn with_shared_payload(
+    pipe_id: u16,
+    eof: bool,
+    loss: u8,
+    payload: Arc<[u8]>,
+  ) -> Result<Self> {
     verify_pipe_id(pipe_id)?;
     if payload.len() > MAX_PAYLOAD_SIZE {
       Err(Error::PayloadTooLarge { length: payload.len(), maximum: MAX_PAYLOAD_SIZE })
     } else if loss > MAX_LOSS_RATE {
       Err(Error::LossRateTooBig { loss: loss as usize, maximum: MAX_LOSS_RATE as usize })
     } else {
-      Ok(Block { pipe_id, eof, loss, payload })
+      Ok(Block { pipe_id, eof, loss, sequence: None, checksum: false, payload })
+    }
+  }
+
+  /// `MAX_PAYLOAD_SIZE` を超えるデータを、ペイロードが `MAX_PAYLOAD_SIZE` 以下となる複数の Block に分割します。
+  /// EOF は最後の Block にのみ設定され、その消失確率は常に 0 となります。空のデータは空のペイロードを持つ EOF の
+  /// Block 1 つとなります。パイプ ID が 0 の場合や消失確率が `MAX_LOSS_RATE` を超える場合はエラーとなります。
+  pub fn split(pipe_id: u16, loss: u8, data: &[u8]) -> Result<Vec<Block>> {
+    if data.is_empty() {
+      return Ok(vec![Block::new(pipe_id, true, 0, Vec::new())?]);
+    }
+    let count = data.len().div_ceil(MAX_PAYLOAD_SIZE);
+    let mut blocks = Vec::with_capacity(count);
+    for (i, chunk) in data.chunks(MAX_PAYLOAD_SIZE).enumerate() {
+      let eof = i + 1 == count;
+      let block = Block::new(pipe_id, eof, loss, chunk.to_vec())?;
+      blocks.push(if eof { Block { loss: 0, ..block } } else { block });
+    }
+    Ok(blocks)
+  }
+
+  /// 指定されたシーケンス番号を設定した Block を返します。
+  pub fn with_sequence(mut self, sequence: u32) -> Self {
+    self.sequence = Some(sequence);
+    self
+  }
+
+  /// シリアライズ時に CRC32 チェックサムを付加する Block を返します。
+  pub fn with_checksum(mut self) -> Self {
+    self.checksum = true;
+    self
+  }
+
+  /// このブロックがシリアライズ時に CRC32 チェックサムを付加する場合に true を返します。
+  pub fn has_checksum(&self) -> bool {
+    self.checksum
+  }
+
+  /// このブロックの宛先を示すパイプ ID を参照します。
+  pub fn pipe_id(&self) -> u16 {
+    self.pipe_id
+  }
+
+  /// このブロックでストリームが終了する場合に true を返します。
+  pub fn is_eof(&self) -> bool {
+    self.eof
+  }
+
+  /// このブロックの消失確率を参照します。
+  pub fn loss(&self) -> u8 {
+    self.loss
+  }
+
+  /// 中継時にこのブロックの消失確率を更新します。`MAX_LOSS_RATE` を超える値や、EOF を示すブロックに 0 以外の値を
+  /// 設定しようとした場合はエラーとなり、値は変更されません。
+  pub fn set_loss(&mut self, loss: u8) -> Result<()> {
+    let maximum = if self.eof { 0 } else { MAX_LOSS_RATE };
+    if loss > maximum {
+      Err(Error::LossRateTooBig { loss: loss as usize, maximum: maximum as usize })
+    } else {
+      self.loss = loss;
+      Ok(())
     }
   }
 
-  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<()> {
+  /// 消失判定を通過したブロックの消失確率を 0 にリセットします。
+  pub fn clear_loss(&mut self) {
+    self.loss = 0;
+  }
+
+  /// 指定された乱数生成器を使用してこのブロックの消失判定を行い、消失させる場合に true を返します。ブロックは
+  /// `loss / MAX_LOSS_RATE` の確率で消失と判定されます。判定を通過したブロックは同じ確率が繰り返し適用されないように
+  /// 消失確率が 0 にリセットされます。
+  pub fn should_drop<R: RngCore + ?Sized>(&mut self, rng: &mut R) -> bool {
+    if self.loss == 0 {
+      return false;
+    }
+    let drop = rng.next_u32() % u32::from(MAX_LOSS_RATE) < u32::from(self.loss);
+    if !drop {
+      self.clear_loss();
+    }
+    drop
+  }
+
+  /// このブロックのシーケンス番号を参照します。
+  pub fn sequence(&self) -> Option<u32> {
+    self.sequence
+  }
+
+  /// このブロックが転送するデータを参照します。
+  pub fn payload(&self) -> &[u8] {
+    &self.payload
+  }
+
+  /// このブロックが転送するデータを共有参照として参照します。返値を `with_shared_payload()` に渡すことで、
+  /// ペイロードを複製せずにこのブロックを中継することができます。
+  pub fn payload_arc(&self) -> Arc<[u8]> {
+    self.payload.clone()
+  }
+
+  /// このメッセージをシリアライズしたときのバイナリ長を参照します。
+  pub fn serialized_len(&self) -> usize {
+    2 + 2
+      + if self.sequence.is_some() { 4 } else { 0 }
+      + bin_len(&self.payload)
+      + if self.checksum { 4 } else { 0 }
+  }
+
+  /// このブロックをシリアライズして出力します。チェックサムを付加する場合は、それより前に出力したすべてのバイトの
+  /// CRC32 を末尾に出力します。出力したバイト数を返します。
+  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<usize> {
     debug_assert!(self.loss & (1 << 7) == 0u8);
-    let bit_field: u8 = self.loss | if self.eof { 1 << 7 } else { 0 };
-    write_u16(buf, self.pipe_id)?;
-    write_u8(buf, bit_field)?;
-    write_bin(buf, &self.payload)?;
-    Ok(())
+    let bit_field: u16 = self.loss as u16
+      | if self.sequence.is_some() { BLOCK_SEQUENCE_FLAG } else { 0 }
+      | if self.checksum { BLOCK_CHECKSUM_FLAG } else { 0 }
+      | if self.eof { BLOCK_EOF_FLAG } else { 0 };
+    let mut body = self.body(bit_field)?;
+    if self.checksum {
+      let crc = crc32(&body);
+      write_u32(&mut body, crc)?;
+    }
+    buf.write_all(&body)?;
+    Ok(body.len())
   }
 
+  /// チェックサムの対象となる、末尾のチェックサムを除いたバイナリ表現を構築します。
+  fn body(&self, bit_field: u16) -> Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(self.serialized_len());
+    write_u16(&mut body, self.pipe_id)?;
+    write_u16(&mut body, bit_field)?;
+    if let Some(sequence) = self.sequence {
+      write_u32(&mut body, sequence)?;
+    }
+    write_bin(&mut body, &self.payload)?;
+    Ok(body)
+  }
+
+  /// ブロックを復元します。チェックサムが付加されている場合は復元した内容と照合し、一致しなければ
+  /// `Error::ChecksumMismatch` を、パイプ ID が 0 の場合は `Error::ZeroPipeId` を、bit_field の予約ビットが設定されて
+  /// いる場合は `Error::ReservedBitsSet` を返します。
   pub fn read_from<R: Read>(buf: &mut R) -> Result<Block> {
-    let pipe_id = read_u16(buf)?;
-    let bit_field = read_u8(buf)?;
-    let payload = read_bin(buf)?;
-    Ok(Block { pipe_id, eof: bit_field & (1 << 7) != 0, loss: bit_field & 0x7Fu8, payload })
+    let pipe_id = read_pipe_id(buf)?;
+    let bit_field = read_u16(buf)?;
+    if bit_field & BLOCK_RESERVED_BITS != 0 {
+      return Err(Error::ReservedBitsSet { bits: bit_field & BLOCK_RESERVED_BITS });
+    }
+    let sequence = if bit_field & BLOCK_SEQUENCE_FLAG != 0 { Some(read_u32(buf)?) } else { None };
+    let payload: Arc<[u8]> = Arc::from(read_bin(buf)?);
+    let block = Block {
+      pipe_id,
+      eof: bit_field & BLOCK_EOF_FLAG != 0,
+      loss: (bit_field & 0x7F) as u8,
+      sequence,
+      checksum: bit_field & BLOCK_CHECKSUM_FLAG != 0,
+      payload,
+    };
+    if block.checksum {
+      let expected = read_u32(buf)?;
+      let actual = crc32(&block.body(bit_field)?);
+      if expected != actual {
+        return Err(Error::ChecksumMismatch { expected, actual });
+      }
+    }
+    Ok(block)
+  }
+
+  /// 指定されたバイト列の先頭から `Block` を復元し、復元した値と消費したバイト数を返します。`Cursor` を使用せずに
+  /// スライスから直接読み込む点を除いて `read_from()` と同じ動作で、バイト列が不足している場合は
+  /// `Error::NeedMoreBytes` を返します。
+  pub fn from_bytes(bytes: &[u8]) -> Result<(Block, usize)> {
+    read_from_bytes(bytes, Block::read_from)
+  }
+}
+
+/// 過負荷時に Block の消失判定を行う乱数生成器を保持します。ノードは 1 つの `LossShaper` を保持することで、消失判定を
+/// 行う箇所に乱数生成器を引き回す必要がなくなります。テストではシードを固定した乱数生成器を、運用環境では暗号論的に
+/// 安全な乱数生成器を指定することができます。
+pub struct LossShaper<R: RngCore> {
+  rng: R,
+}
+
+impl<R: RngCore> LossShaper<R> {
+  /// 指定された乱数生成器で消失判定を行う `LossShaper` を構築します。
+  pub fn new(rng: R) -> LossShaper<R> {
+    LossShaper { rng }
+  }
+
+  /// 指定されたブロックの消失判定を行い、消失させる場合に true を返します。詳細は `Block::should_drop()` を参照して
+  /// ください。
+  pub fn decide(&mut self, block: &mut Block) -> bool {
+    block.should_drop(&mut self.rng)
+  }
+}
+
+/// Block の bit_field で EOF を表すビット。
+const BLOCK_EOF_FLAG: u16 = 1 << 15;
+
+/// Block の bit_field でシーケンス番号が続くことを表すビット。
+const BLOCK_SEQUENCE_FLAG: u16 = 1 << 14;
+
+/// Block の bit_field で末尾に CRC32 チェックサムが続くことを表すビット。
+const BLOCK_CHECKSUM_FLAG: u16 = 1 << 13;
+
+/// Block の bit_field で予約されており 0 でなければならないビット。
+const BLOCK_RESERVED_BITS: u16 = 0x1F80;
+
+/// 指定されたバイト列の CRC32 (IEEE 802.3) を算出します。
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for byte in bytes {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+    }
+  }
+  !crc
+}
+
+/// 同じパイプに対して順序が入れ替わって到着した Block をシーケンス番号の順に並べ直すヘルパーです。
+///
+/// 期待するシーケンス番号の Block が到着するまで後続の Block を保持し、連続した Block から順に取り出すことが
+/// できます。すでに取り出したシーケンス番号や保持しているシーケンス番号の Block は重複として破棄します。
+///
+/// シーケンス番号は `u32::MAX` の次に 0 へ戻るシリアル番号 (RFC 1982) として比較します。次に取り出すシーケンス番号
+/// から並べ直しの範囲 (window) 以上先の Block はエラーとなるため、保持する Block の数は window を超えません。
+pub struct BlockReassembler {
+  next_sequence: u32,
+  window: u32,
+  pending: HashMap<u32, Block>,
+}
+
+impl BlockReassembler {
+  /// 最初に取り出す Block のシーケンス番号を指定して、`DEFAULT_REORDER_WINDOW` の範囲で並べ直すように構築します。
+  pub fn new(first_sequence: u32) -> BlockReassembler {
+    BlockReassembler::with_window(first_sequence, DEFAULT_REORDER_WINDOW)
+  }
+
+  /// 最初に取り出す Block のシーケンス番号と並べ直しの範囲を指定して構築します。範囲は 1 以上 2^31 未満に制限され
+  /// ます。
+  pub fn with_window(first_sequence: u32, window: u32) -> BlockReassembler {
+    let window = window.clamp(1, (1 << 31) - 1);
+    BlockReassembler { next_sequence: first_sequence, window, pending: HashMap::new() }
+  }
+
+  /// 到着した Block を追加します。重複した Block を破棄した場合は `false` を返します。シーケンス番号を持たない
+  /// Block や、並べ直しの範囲を超えて先のシーケンス番号を持つ Block はエラーとなります。
+  pub fn push(&mut self, block: Block) -> Result<bool> {
+    let sequence =
+      block.sequence.ok_or(Error::BlockSequenceNotSpecified { pipe_id: block.pipe_id })?;
+    let offset = sequence.wrapping_sub(self.next_sequence);
+    if offset >= 1 << 31 || self.pending.contains_key(&sequence) {
+      // すでに取り出したシーケンス番号
+      Ok(false)
+    } else if offset >= self.window {
+      Err(Error::SequenceOutOfWindow {
+        pipe_id: block.pipe_id,
+        sequence,
+        next: self.next_sequence,
+        window: self.window,
+      })
+    } else {
+      self.pending.insert(sequence, block);
+      Ok(true)
+    }
+  }
+
+  /// 次のシーケンス番号の Block が到着していればそれを取り出します。
+  pub fn pop(&mut self) -> Option<Block> {
+    let block = self.pending.remove(&self.next_sequence)?;
+    self.next_sequence = self.next_sequence.wrapping_add(1);
+    Some(block)
+  }
+
+  /// 次に取り出す Block のシーケンス番号を参照します。
+  pub fn next_sequence(&self) -> u32 {
+    self.next_sequence
+  }
+
+  /// 後続の Block が到着しているにもかかわらず欠落しているシーケンス番号を、次に取り出すシーケンス番号に近い順で
+  /// 列挙します。列挙する範囲は並べ直しの範囲を超えません。
+  pub fn gaps(&self) -> impl Iterator<Item = u32> + '_ {
+    let next_sequence = self.next_sequence;
+    let end = self.pending.keys().map(|sequence| sequence.wrapping_sub(next_sequence)).max();
+    (0..end.unwrap_or(0))
+      .map(move |offset| next_sequence.wrapping_add(offset))
+      .filter(move |sequence| !self.pending.contains_key(sequence))
+  }
+
+  /// 保持している (まだ取り出せない) Block の数を参照します。
+  pub fn pending_len(&self) -> usize {
+    self.pending.len()
+  }
+}
+
+/// `Block::split()` によって分割されたデータを復元するヘルパーです。
+///
+/// パイプごとに到着した順で Block のペイロードを連結し、EOF を示す Block が到着した時点で連結したデータを返します。
+/// 際限なくバッファリングすることを防ぐため、連結したデータが上限を超えたパイプのデータは破棄され
+/// `Error::PayloadTooLarge` となります。また、多数のパイプを並行して開くことで上限を回避できないように、すべての
+/// パイプで連結中のデータの合計が上限を超える場合も、その Block のパイプのデータを破棄して
+/// `Error::ReassemblyBufferFull` となります。順序が入れ替わる可能性のあるトランスポートでは `BlockReassembler` で
+/// 並べ直した Block を追加します。
+pub struct PayloadReassembler {
+  max_size: usize,
+  max_total_size: usize,
+  buffers: HashMap<u16, Vec<u8>>,
+  /// すべてのパイプで連結中のデータ長の合計。
+  total: usize,
+}
+
+impl PayloadReassembler {
+  /// 1 つのパイプについて連結できる最大データ長と、すべてのパイプで連結中のデータの合計の上限を指定して構築します。
+  pub fn new(max_size: usize, max_total_size: usize) -> PayloadReassembler {
+    PayloadReassembler { max_size, max_total_size, buffers: HashMap::new(), total: 0 }
+  }
+
+  /// 到着した Block のペイロードを連結します。EOF を示す Block の場合はそのパイプで連結したデータを返します。
+  pub fn push(&mut self, block: Block) -> Result<Option<Vec<u8>>> {
+    let buffered = self.buffered_len(block.pipe_id);
+    let length = buffered + block.payload.len();
+    if length > self.max_size {
+      self.discard(block.pipe_id);
+      return Err(Error::PayloadTooLarge { length, maximum: self.max_size });
+    }
+    let total = self.total + block.payload.len();
+    if total > self.max_total_size {
+      self.discard(block.pipe_id);
+      return Err(Error::ReassemblyBufferFull { length: total, maximum: self.max_total_size });
+    }
+    if block.eof {
+      self.total -= buffered;
+      let mut buffer = self.buffers.remove(&block.pipe_id).unwrap_or_default();
+      buffer.extend_from_slice(&block.payload);
+      Ok(Some(buffer))
+    } else {
+      self.total = total;
+      self.buffers.entry(block.pipe_id).or_default().extend_from_slice(&block.payload);
+      Ok(None)
+    }
+  }
+
+  /// 指定されたパイプで連結中のデータを破棄します。パイプが失敗を示す Close でクローズされた場合に使用します。
+  pub fn discard(&mut self, pipe_id: u16) {
+    if let Some(buffer) = self.buffers.remove(&pipe_id) {
+      self.total -= buffer.len();
+    }
+  }
+
+  /// 指定されたパイプで連結中のデータ長を参照します。
+  pub fn buffered_len(&self, pipe_id: u16) -> usize {
+    self.buffers.get(&pipe_id).map(Vec::len).unwrap_or(0)
+  }
+
+  /// すべてのパイプで連結中のデータ長の合計を参照します。
+  pub fn total_buffered_len(&self) -> usize {
+    self.total
+  }
+}
+
+impl Default for PayloadReassembler {
+  fn default() -> Self {
+    PayloadReassembler::new(DEFAULT_MAX_REASSEMBLED_SIZE, DEFAULT_MAX_REASSEMBLED_TOTAL_SIZE)
   }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Control {
   SystemConfig {
     /// プロトコルのバージョンを示す 2 バイト整数値。上位バイトから [major][minor] の順を持つ。
     version: u16,
-    /// ノード ID。TLS を使用している場合は証明書の CNAME と照合する必要がある。
+    /// ノード ID。TLS を使用している場合は証明書の CNAME と照合する必要がある (`bridge::tls::verify_node_id()`)。
     node_id: Uuid,
     /// セッション ID。クライアントからの接続後の Sync に対するサーバ応答でのみ有効な値を持つ。それ以外の場合、
     /// 送信者は Zero を送らなければならず、受信者は無視しなければならない。
@@ -162,21 +619,128 @@ pub enum Control {
     /** UTC ミリ秒で表現したローカル実行環境の現在時刻。 */
     utc_time: u64,
   },
+  /// セッションを意図的に終了することを相手に通知するメッセージ。これを受信したセッションは I/O エラーによる切断
+  /// ではなく正常な終了として扱うことができる。
+  Close {
+    /// 終了理由を表すコード。
+    reason_code: u16,
+    /// 終了理由の詳細。どのようにエンコードするかは上位レイヤーでの取り決めとなる。
+    reason: Vec<u8>,
+  },
+}
+
+/// System Config コントロールメッセージを名前付きの設定で構築するためのビルダーです。
+///
+/// 設定を省略した場合、`version` は `PROTOCOL_VERSION`、`session_id` はクライアントからの送信で必要とされる Zero、
+/// `utc_time` は `build()` を呼び出した時点のシステム時刻、`ping_interval` と `session_timeout` はそれぞれ
+/// `DEFAULT_PING_INTERVAL` と `DEFAULT_SESSION_TIMEOUT` となります。
+pub struct SystemConfigBuilder {
+  version: u16,
+  node_id: Uuid,
+  session_id: Uuid,
+  utc_time: Option<SystemTime>,
+  ping_interval: u32,
+  session_timeout: u32,
 }
 
-/// System Config コントロールメッセージの識別子。
-const ID_CTRL_SYSCONFIG: u8 = 'Q' as u8;
+impl SystemConfigBuilder {
+  /// 指定されたノード ID の System Config を構築するビルダーを作成します。
+  pub fn new(node_id: Uuid) -> SystemConfigBuilder {
+    SystemConfigBuilder {
+      version: PROTOCOL_VERSION,
+      node_id,
+      session_id: Uuid::nil(),
+      utc_time: None,
+      ping_interval: DEFAULT_PING_INTERVAL,
+      session_timeout: DEFAULT_SESSION_TIMEOUT,
+    }
+  }
+
+  /// プロトコルのバージョンを設定します。
+  pub fn version(mut self, version: u16) -> Self {
+    self.version = version;
+    self
+  }
+
+  /// サーバ応答で通知するセッション ID を設定します。
+  pub fn session_id(mut self, session_id: Uuid) -> Self {
+    self.session_id = session_id;
+    self
+  }
 
-/// Ping コントロールメッセージの識別子。
-const ID_CTRL_PING: u8 = 'P' as u8;
+  /// 現在のシステム時刻の代わりに使用する時刻を設定します。
+  pub fn utc_time(mut self, utc_time: SystemTime) -> Self {
+    self.utc_time = Some(utc_time);
+    self
+  }
+
+  /// ping 間隔 (秒) を設定します。
+  pub fn ping_interval(mut self, ping_interval: u32) -> Self {
+    self.ping_interval = ping_interval;
+    self
+  }
+
+  /// セッションタイムアウト (秒) を設定します。
+  pub fn session_timeout(mut self, session_timeout: u32) -> Self {
+    self.session_timeout = session_timeout;
+    self
+  }
+
+  pub fn build(self) -> Result<Control> {
+    let utc_time = self.utc_time.unwrap_or_else(SystemTime::now);
+    Control::new_system_config(
+      self.version,
+      self.node_id,
+      self.session_id,
+      utc_time,
+      self.ping_interval,
+      self.session_timeout,
+    )
+  }
+}
+
+/// Control メッセージの種類を表す識別子です。バイナリ表現ではこの値が 1 バイトで出力されます。識別子の値は
+/// この列挙型で一元的に管理され、新しい種類に既存の値と衝突する識別子を割り当てるとコンパイルエラーとなります。
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControlType {
+  /// System Config コントロールメッセージ。
+  SystemConfig = b'Q',
+  /// Ping コントロールメッセージ。
+  Ping = b'P',
+  /// Close コントロールメッセージ。
+  Close = b'C',
+}
+
+impl ControlType {
+  /// 定義されているすべての種類。
+  pub const ALL: [ControlType; 3] =
+    [ControlType::SystemConfig, ControlType::Ping, ControlType::Close];
+}
+
+impl From<ControlType> for u8 {
+  fn from(control_type: ControlType) -> u8 {
+    control_type as u8
+  }
+}
+
+impl TryFrom<u8> for ControlType {
+  type Error = Error;
+
+  /// 識別子から種類を復元します。未定義の値は `Error::IllegalControlType` となります。
+  fn try_from(value: u8) -> Result<ControlType> {
+    let found = ControlType::ALL.iter().find(|control_type| **control_type as u8 == value);
+    found.copied().ok_or(Error::IllegalControlType { value })
+  }
+}
 
 impl Control {
-  /// System Config コントロールメッセージを構築します。
+  /// System Config コントロールメッセージを構築します。`utc_time` は UTC ミリ秒に変換して格納されます。
   pub fn new_system_config(
     version: u16,
     node_id: Uuid,
     session_id: Uuid,
-    utc_time: u64,
+    utc_time: SystemTime,
     ping_interval: u32,
     session_timeout: u32,
   ) -> Result<Control> {
@@ -184,18 +748,57 @@ impl Control {
       version,
       node_id,
       session_id,
-      utc_time,
+      utc_time: to_utc_millis(utc_time),
       ping_interval,
       session_timeout,
     })
   }
 
-  /// Ping コントロールメッセージを構築します。
-  pub fn new_ping(utc_time: u64) -> Result<Control> {
-    Ok(Control::Ping { utc_time })
+  /// Ping コントロールメッセージを構築します。`utc_time` は UTC ミリ秒に変換して格納されます。
+  pub fn new_ping(utc_time: SystemTime) -> Result<Control> {
+    Ok(Control::Ping { utc_time: to_utc_millis(utc_time) })
   }
 
-  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<()> {
+  /// System Config または Ping が示す時刻を参照します。時刻を持たない Close の場合は `None` を返します。
+  pub fn timestamp(&self) -> Option<SystemTime> {
+    match self {
+      Control::SystemConfig { utc_time, .. } | Control::Ping { utc_time } => {
+        Some(from_utc_millis(*utc_time))
+      }
+      Control::Close { .. } => None,
+    }
+  }
+
+  /// Close コントロールメッセージを構築します。`reason` が 2 バイトの長さで表現できない場合はエラーとなります。
+  pub fn new_close(reason_code: u16, reason: Vec<u8>) -> Result<Control> {
+    if reason.len() > u16::MAX as usize {
+      Err(Error::PayloadTooLarge { length: reason.len(), maximum: u16::MAX as usize })
+    } else {
+      Ok(Control::Close { reason_code, reason })
+    }
+  }
+
+  /// このメッセージの種類を参照します。
+  pub fn control_type(&self) -> ControlType {
+    match self {
+      Control::SystemConfig { .. } => ControlType::SystemConfig,
+      Control::Ping { .. } => ControlType::Ping,
+      Control::Close { .. } => ControlType::Close,
+    }
+  }
+
+  /// このメッセージをシリアライズしたときのバイナリ長を参照します。
+  pub fn serialized_len(&self) -> usize {
+    match self {
+      Control::SystemConfig { .. } => 1 + 2 + 16 + 16 + 8 + 4 + 4,
+      Control::Ping { .. } => 1 + 8,
+      Control::Close { reason, .. } => 1 + 2 + bin_len(reason),
+    }
+  }
+
+  /// このメッセージをシリアライズして出力し、出力したバイト数を返します。
+  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<usize> {
+    write_u8(buf, self.control_type().into())?;
     match self {
       Control::SystemConfig {
         version,
@@ -205,7 +808,6 @@ impl Control {
         ping_interval,
         session_timeout,
       } => {
-        write_u8(buf, ID_CTRL_SYSCONFIG)?;
         write_u16(buf, *version)?;
         write_u128(buf, node_id.as_u128())?;
         write_u128(buf, session_id.as_u128())?;
@@ -214,16 +816,19 @@ impl Control {
         write_u32(buf, *session_timeout)?;
       }
       Control::Ping { utc_time } => {
-        write_u8(buf, ID_CTRL_PING)?;
         write_u64(buf, *utc_time)?;
       }
+      Control::Close { reason_code, reason } => {
+        write_u16(buf, *reason_code)?;
+        write_bin(buf, reason)?;
+      }
     }
-    Ok(())
+    Ok(self.serialized_len())
   }
 
   pub fn read_from<R: Read>(buf: &mut R) -> Result<Control> {
-    match read_u8(buf)? {
-      ID_CTRL_SYSCONFIG => Ok(Control::SystemConfig {
+    match ControlType::try_from(read_u8(buf)?)? {
+      ControlType::SystemConfig => Ok(Control::SystemConfig {
         version: read_u16(buf)?,
         node_id: Uuid::from_u128(read_u128(buf)?),
         session_id: Uuid::from_u128(read_u128(buf)?),
@@ -231,12 +836,35 @@ impl Control {
         ping_interval: read_u32(buf)?,
         session_timeout: read_u32(buf)?,
       }),
-      ID_CTRL_PING => Ok(Control::Ping { utc_time: read_u64(buf)? }),
-      unexpected => Err(Error::IllegalControlType { value: unexpected }),
+      ControlType::Ping => Ok(Control::Ping { utc_time: read_u64(buf)? }),
+      ControlType::Close => {
+        Ok(Control::Close { reason_code: read_u16(buf)?, reason: read_bin(buf)? })
+      }
     }
   }
+
+  /// 指定されたバイト列の先頭から `Control` を復元し、復元した値と消費したバイト数を返します。`Cursor` を使用せずに
+  /// スライスから直接読み込む点を除いて `read_from()` と同じ動作で、バイト列が不足している場合は
+  /// `Error::NeedMoreBytes` を返します。
+  pub fn from_bytes(bytes: &[u8]) -> Result<(Control, usize)> {
+    read_from_bytes(bytes, Control::read_from)
+  }
 }
 
+/// Open メッセージの識別子。
+const ID_OPEN: u8 = b'O';
+
+/// Close メッセージの識別子。
+const ID_CLOSE: u8 = b'C';
+
+/// Block メッセージの識別子。
+const ID_BLOCK: u8 = b'B';
+
+/// Control メッセージの識別子。
+const ID_CONTROL: u8 = b'X';
+
+/// 先頭の 1 バイトでメッセージの種類を識別するメッセージ。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Message {
   Open(Open),
   Close(Close),
@@ -244,14 +872,327 @@ pub enum Message {
   Control(Control),
 }
 
+/// `Message::dispatch()` によってメッセージの種類ごとに呼び出されるハンドラーです。すべてのメソッドはデフォルトで
+/// 何もしないため、実装は関心のある種類のメソッドだけをオーバーライドします。
+pub trait MessageVisitor {
+  fn on_open(&mut self, _open: Open) {}
+  fn on_close(&mut self, _close: Close) {}
+  fn on_block(&mut self, _block: Block) {}
+  fn on_control(&mut self, _control: Control) {}
+}
+
+impl Message {
+  /// このメッセージをその種類に対応する `visitor` のメソッドに渡します。
+  pub fn dispatch<V: MessageVisitor>(self, visitor: &mut V) {
+    match self {
+      Message::Open(open) => visitor.on_open(open),
+      Message::Close(close) => visitor.on_close(close),
+      Message::Block(block) => visitor.on_block(block),
+      Message::Control(control) => visitor.on_control(control),
+    }
+  }
+
+  /// ログやエラーメッセージで使用するこのメッセージの種類の名前を参照します。Control メッセージの場合はその種類の
+  /// 名前となります。
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Message::Open(_) => "Open",
+      Message::Close(_) => "Close",
+      Message::Block(_) => "Block",
+      Message::Control(Control::SystemConfig { .. }) => "SystemConfig",
+      Message::Control(Control::Ping { .. }) => "Ping",
+      Message::Control(Control::Close { .. }) => "Control::Close",
+    }
+  }
+
+  /// このメッセージが属するパイプの ID を参照します。パイプに属さない Control メッセージの場合は `None` を返します。
+  pub fn pipe_id(&self) -> Option<u16> {
+    match self {
+      Message::Open(open) => Some(open.pipe_id()),
+      Message::Close(close) => Some(close.pipe_id()),
+      Message::Block(block) => Some(block.pipe_id()),
+      Message::Control(_) => None,
+    }
+  }
+
+  /// このメッセージの宛先となるパイプ ID を参照します。`pipe_id()` と異なり、Control メッセージは
+  /// `CONTROL_PIPE_ID` を宛先とします。受信したメッセージをパイプ ID によって振り分ける場合に使用します。
+  pub fn target_pipe_id(&self) -> u16 {
+    self.pipe_id().unwrap_or(CONTROL_PIPE_ID)
+  }
+
+  /// このメッセージをシリアライズしたときの識別子を含むバイナリ長を参照します。
+  pub fn serialized_len(&self) -> usize {
+    1 + match self {
+      Message::Open(open) => open.serialized_len(),
+      Message::Close(close) => close.serialized_len(),
+      Message::Block(block) => block.serialized_len(),
+      Message::Control(control) => control.serialized_len(),
+    }
+  }
+
+  /// このメッセージをシリアライズして出力します。シリアライズしたバイナリ長が `MAX_MESSAGE_SIZE` を超える場合は
+  /// 何も出力せずに `Error::MessageTooLarge` を返します。出力したバイト数を返します。
+  pub fn write_to<W: Write>(&self, buf: &mut W) -> Result<usize> {
+    let length = self.serialized_len();
+    if length > MAX_MESSAGE_SIZE {
+      return Err(Error::MessageTooLarge { length, maximum: MAX_MESSAGE_SIZE });
+    }
+    match self {
+      Message::Open(open) => {
+        write_u8(buf, ID_OPEN)?;
+        open.write_to(buf)?;
+      }
+      Message::Close(close) => {
+        write_u8(buf, ID_CLOSE)?;
+        close.write_to(buf)?;
+      }
+      Message::Block(block) => {
+        write_u8(buf, ID_BLOCK)?;
+        block.write_to(buf)?;
+      }
+      Message::Control(control) => {
+        write_u8(buf, ID_CONTROL)?;
+        control.write_to(buf)?;
+      }
+    }
+    Ok(length)
+  }
+
+  /// このメッセージをシリアライズしたバイト列を返します。同じメッセージを複数の Wire に送信する場合、この結果を
+  /// `Wire::send_raw()` に渡すことでシリアライズを一度だけで済ませることができます。
+  pub fn to_bytes(&self) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(self.serialized_len());
+    self.write_to(&mut buf)?;
+    Ok(buf)
+  }
+
+  /// 指定されたバイト列の先頭からメッセージを復元し、復元したメッセージと消費したバイト数を返します。
+  ///
+  /// このメソッドはどのような入力に対してもパニックせず、不正なバイト列に対しては `Error` を返します。ネットワーク
+  /// から受信した信頼できないバイト列を復元する場合はこのメソッドを使用してください。
+  pub fn try_decode(bytes: &[u8]) -> Result<(Message, usize)> {
+    Message::from_bytes(bytes)
+  }
+
+  /// 指定されたバイト列の先頭からメッセージを復元し、復元したメッセージと消費したバイト数を返します。`try_decode()`
+  /// と同じ動作です。
+  pub fn from_bytes(bytes: &[u8]) -> Result<(Message, usize)> {
+    read_from_bytes(bytes, Message::read_from)
+  }
+
+  pub fn read_from<R: Read>(buf: &mut R) -> Result<Message> {
+    match read_u8(buf)? {
+      ID_OPEN => Ok(Message::Open(Open::read_from(buf)?)),
+      ID_CLOSE => Ok(Message::Close(Close::read_from(buf)?)),
+      ID_BLOCK => Ok(Message::Block(Block::read_from(buf)?)),
+      ID_CONTROL => Ok(Message::Control(Control::read_from(buf)?)),
+      unexpected => Err(Error::IllegalMessageType { value: unexpected }),
+    }
+  }
+
+  /// 非同期ストリームから 1 つのメッセージを読み込みます。
+  ///
+  /// 不足しているバイト数だけを読み込むため、ストリーム上で後続するメッセージのバイトを消費することはありません。
+  /// メッセージの途中でストリームが終端に達した場合は `Error::BufferUnsatisfied` を返します。
+  #[cfg(feature = "async-io")]
+  pub async fn read_from_async<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Message> {
+    let mut buffer = Vec::with_capacity(64);
+    loop {
+      match Message::try_decode(&buffer) {
+        Ok((msg, _)) => return Ok(msg),
+        Err(Error::NeedMoreBytes { needed }) => {
+          let offset = buffer.len();
+          buffer.resize(offset + needed, 0u8);
+          reader.read_exact(&mut buffer[offset..]).await?;
+        }
+        Err(err) => return Err(err),
+      }
+    }
+  }
+
+  /// このメッセージを非同期ストリームに書き込みます。
+  #[cfg(feature = "async-io")]
+  pub async fn write_to_async<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<()> {
+    let mut buffer = Vec::with_capacity(self.serialized_len());
+    self.write_to(&mut buffer)?;
+    writer.write_all(&buffer).await?;
+    Ok(())
+  }
+}
+
+/// 複数のメッセージが連結されたバイト列から先頭のメッセージを順に復元するイテレータです。
+///
+/// バッファの末尾に不完全なメッセージが残っている場合は `Error::NeedMoreBytes` を返して終了します。
+pub struct Messages<'a> {
+  cursor: Cursor<&'a [u8]>,
+  failed: bool,
+}
+
+impl<'a> Messages<'a> {
+  pub fn new(buf: &'a [u8]) -> Messages<'a> {
+    Messages { cursor: Cursor::new(buf), failed: false }
+  }
+}
+
+impl<'a> Iterator for Messages<'a> {
+  type Item = Result<Message>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed || self.cursor.position() as usize >= self.cursor.get_ref().len() {
+      return None;
+    }
+    let result = Message::read_from(&mut self.cursor);
+    self.failed = result.is_err();
+    Some(result)
+  }
+}
+
+/// 指定されたプロトコルのバージョンがこのライブラリと互換性を持つかを判定します。メジャーバージョンが一致する場合
+/// に互換性があるとみなします。
+pub fn is_compatible_version(version: u16) -> bool {
+  version >> 8 == PROTOCOL_VERSION >> 8
+}
+
+/// 指定された時刻を Control メッセージの `utc_time` の単位である UTC ミリ秒に変換します。ミリ秒未満は切り捨て
+/// られ、UNIX エポックより前の時刻は 0 となります。
+pub fn to_utc_millis(time: SystemTime) -> u64 {
+  time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// UTC ミリ秒で表現された `utc_time` を時刻に変換します。
+pub fn from_utc_millis(utc_time: u64) -> SystemTime {
+  UNIX_EPOCH + Duration::from_millis(utc_time)
+}
+
+/// 複数のメッセージが連結されたバイト列からすべてのメッセージを復元します。
+pub fn decode_all(buf: &[u8]) -> Result<Vec<Message>> {
+  Messages::new(buf).collect()
+}
+
+/// ストリームから断片的に到着するバイト列を蓄積し、完全に受信したメッセージから順に復元するデコーダーです。
+pub struct StreamDecoder {
+  buffer: Vec<u8>,
+}
+
+impl StreamDecoder {
+  pub fn new() -> StreamDecoder {
+    StreamDecoder { buffer: Vec::new() }
+  }
+
+  /// ストリームから受信したバイト列を追加します。
+  pub fn push(&mut self, bytes: &[u8]) {
+    self.buffer.extend_from_slice(bytes);
+  }
+
+  /// まだメッセージとして復元されていないバイト数を参照します。
+  pub fn buffered_len(&self) -> usize {
+    self.buffer.len()
+  }
+
+  /// 蓄積されたバイト列の先頭からメッセージを復元します。メッセージを復元するためのバイト数が不足している場合は
+  /// `None` を返します。
+  pub fn next_message(&mut self) -> Result<Option<Message>> {
+    match Message::try_decode(&self.buffer) {
+      Ok((msg, length)) => {
+        self.buffer.drain(..length);
+        Ok(Some(msg))
+      }
+      Err(Error::NeedMoreBytes { .. }) => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
+}
+
+impl Default for StreamDecoder {
+  fn default() -> Self {
+    StreamDecoder::new()
+  }
+}
+
+/// `MessageWriter` がバッファを書き出すデフォルトの閾値 (バイト) です。
+pub const DEFAULT_WRITE_THRESHOLD: usize = 16 * 1024;
+
+/// シリアライズしたメッセージをバッファに蓄積し、まとめて出力先に書き込むライターです。
+///
+/// 小さなメッセージを連続して送信するときに、メッセージごとに `write` を呼び出すことを避けます。バッファのサイズが
+/// 閾値に達するか `flush()` が呼び出されたときに、蓄積したメッセージを 1 回の書き込みで出力します。各メッセージは
+/// `Message::write_to()` と同じ形式でシリアライズされるため、`MAX_MESSAGE_SIZE` を超えるメッセージはバッファに
+/// 追加されずにエラーとなります。バッファに残っているメッセージは破棄時に書き出されないため、必要に応じて `flush()`
+/// を呼び出してください。
+pub struct MessageWriter<W: Write> {
+  writer: W,
+  buffer: Vec<u8>,
+  threshold: usize,
+}
+
+impl<W: Write> MessageWriter<W> {
+  /// デフォルトの閾値でバッファリングを行うライターを構築します。
+  pub fn new(writer: W) -> MessageWriter<W> {
+    MessageWriter::with_threshold(writer, DEFAULT_WRITE_THRESHOLD)
+  }
+
+  /// 指定された閾値でバッファリングを行うライターを構築します。
+  pub fn with_threshold(writer: W, threshold: usize) -> MessageWriter<W> {
+    MessageWriter { writer, buffer: Vec::with_capacity(threshold), threshold }
+  }
+
+  /// 指定されたメッセージをバッファに追加します。バッファのサイズが閾値に達した場合は出力先に書き込みます。
+  pub fn write(&mut self, msg: &Message) -> Result<()> {
+    msg.write_to(&mut self.buffer)?;
+    if self.buffer.len() >= self.threshold {
+      self.flush()?;
+    }
+    Ok(())
+  }
+
+  /// バッファに蓄積したメッセージを出力先に書き込みます。
+  pub fn flush(&mut self) -> Result<()> {
+    if !self.buffer.is_empty() {
+      self.writer.write_all(&self.buffer)?;
+      self.buffer.clear();
+    }
+    self.writer.flush()?;
+    Ok(())
+  }
+
+  /// まだ出力先に書き込まれていないバイト数を参照します。
+  pub fn buffered_len(&self) -> usize {
+    self.buffer.len()
+  }
+
+  /// 出力先を参照します。
+  pub fn get_ref(&self) -> &W {
+    &self.writer
+  }
+}
+
+/// `read` を使用してバイト列の先頭から値を読み込み、読み込んだ値と消費したバイト数を返します。
+fn read_from_bytes<'a, T, F>(bytes: &'a [u8], read: F) -> Result<(T, usize)>
+where
+  F: FnOnce(&mut Cursor<&'a [u8]>) -> Result<T>,
+{
+  let mut cursor = Cursor::new(bytes);
+  let value = read(&mut cursor)?;
+  Ok((value, cursor.position() as usize))
+}
+
 fn verify_pipe_id(pipe_id: u16) -> Result<()> {
-  if pipe_id == 0 {
+  if pipe_id == CONTROL_PIPE_ID {
     Err(Error::ZeroPipeId)
   } else {
     Ok(())
   }
 }
 
+/// パイプ ID を読み込みます。Control メッセージのために予約されている 0 の場合は `Error::ZeroPipeId` を返します。
+#[inline]
+fn read_pipe_id<R: Read>(buf: &mut R) -> Result<u16> {
+  let pipe_id = read_u16(buf)?;
+  verify_pipe_id(pipe_id)?;
+  Ok(pipe_id)
+}
+
 #[inline]
 fn write_u8<W: Write>(buf: &mut W, value: u8) -> Result<()> {
   buf.write_u8(value).map_err(Error::from)
@@ -259,7 +1200,9 @@ fn write_u8<W: Write>(buf: &mut W, value: u8) -> Result<()> {
 
 #[inline]
 fn read_u8<R: Read>(buf: &mut R) -> Result<u8> {
-  buf.read_u8().map_err(Error::from)
+  let mut bytes = [0u8; 1];
+  read_fully(buf, &mut bytes)?;
+  Ok(bytes[0])
 }
 
 #[inline]
@@ -269,7 +1212,9 @@ fn write_u16<W: Write>(buf: &mut W, value: u16) -> Result<()> {
 
 #[inline]
 fn read_u16<R: Read>(buf: &mut R) -> Result<u16> {
-  buf.read_u16::<LittleEndian>().map_err(Error::from)
+  let mut bytes = [0u8; 2];
+  read_fully(buf, &mut bytes)?;
+  Ok(LittleEndian::read_u16(&bytes))
 }
 
 #[inline]
@@ -279,7 +1224,9 @@ fn write_u32<W: Write>(buf: &mut W, value: u32) -> Result<()> {
 
 #[inline]
 fn read_u32<R: Read>(buf: &mut R) -> Result<u32> {
-  buf.read_u32::<LittleEndian>().map_err(Error::from)
+  let mut bytes = [0u8; 4];
+  read_fully(buf, &mut bytes)?;
+  Ok(LittleEndian::read_u32(&bytes))
 }
 
 #[inline]
@@ -289,7 +1236,9 @@ fn write_u64<W: Write>(buf: &mut W, value: u64) -> Result<()> {
 
 #[inline]
 fn read_u64<R: Read>(buf: &mut R) -> Result<u64> {
-  buf.read_u64::<LittleEndian>().map_err(Error::from)
+  let mut bytes = [0u8; 8];
+  read_fully(buf, &mut bytes)?;
+  Ok(LittleEndian::read_u64(&bytes))
 }
 
 #[inline]
@@ -299,7 +1248,14 @@ fn write_u128<W: Write>(buf: &mut W, value: u128) -> Result<()> {
 
 #[inline]
 fn read_u128<R: Read>(buf: &mut R) -> Result<u128> {
-  buf.read_u128::<LittleEndian>().map_err(Error::from)
+  let mut bytes = [0u8; 16];
+  read_fully(buf, &mut bytes)?;
+  Ok(LittleEndian::read_u128(&bytes))
+}
+
+#[inline]
+fn bin_len(value: &[u8]) -> usize {
+  2 + value.len()
 }
 
 #[inline]
@@ -311,10 +1267,22 @@ fn write_bin<W: Write>(buf: &mut W, value: &[u8]) -> Result<()> {
 #[inline]
 fn read_bin<R: Read>(buf: &mut R) -> Result<Vec<u8>> {
   let expected = read_u16(buf)? as usize;
-  let mut buffer = Vec::<u8>::with_capacity(expected);
-  unsafe {
-    buffer.set_len(expected);
-  }
-  buf.read_exact(&mut buffer)?;
+  let mut buffer = vec![0u8; expected];
+  read_fully(buf, &mut buffer)?;
   Ok(buffer)
 }
+
+/// 指定されたバッファを満たすまで読み込みます。バッファを満たす前に入力が終了した場合、不足しているバイト数を
+/// 持つ `Error::NeedMoreBytes` を返します。
+fn read_fully<R: Read>(buf: &mut R, bytes: &mut [u8]) -> Result<()> {
+  let mut position = 0;
+  while position < bytes.len() {
+    match buf.read(&mut bytes[position..]) {
+      Ok(0) => return Err(Error::NeedMoreBytes { needed: bytes.len() - position }),
+      Ok(len) => position += len,
+      Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+      Err(err) => return Err(Error::from(err)),
+    }
+  }
+  Ok(())
+}
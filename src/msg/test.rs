@@ -1,12 +1,30 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::io::Cursor;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use uuid::Uuid;
 
 use crate::error::Error;
-use crate::msg::{Block, Close, Control, Open, MAX_LOSS_RATE, MAX_PAYLOAD_SIZE};
+use crate::msg::{
+  decode_all, from_utc_millis, is_compatible_version, to_utc_millis, Block, BlockReassembler,
+  Close, Control, ControlType, LossShaper, Message, MessageVisitor, MessageWriter, Messages, Open,
+  PayloadReassembler, StreamDecoder, SystemConfigBuilder, CONTROL_PIPE_ID, DEFAULT_PING_INTERVAL,
+  DEFAULT_SESSION_TIMEOUT, MAX_LOSS_RATE, MAX_MESSAGE_SIZE, MAX_PAYLOAD_SIZE, PROTOCOL_VERSION,
+};
 use crate::test::SampleValues;
 
+/// 未完成のバッファから復元しようとしたときのエラーであることを検証します。
+fn assert_need_more_bytes(err: Error) {
+  match err {
+    Error::NeedMoreBytes { needed } => assert!(needed > 0),
+    unexpected => panic!("unexpected error: {:?}", unexpected),
+  }
+}
+
 #[test]
 fn test_open_new() {
   let mut sample = SampleValues::new(49087450211597u64);
@@ -35,7 +53,7 @@ fn test_open_read_write() {
   // バイナリ表現が想定と一致しているか
   let mut buf = Vec::new();
   let open = Open::new(1u16, 2u16, 3u8, Vec::from([4u8, 5u8])).unwrap();
-  open.write_to(&mut buf).unwrap();
+  assert_eq!(open.write_to(&mut buf).unwrap(), buf.len());
   assert_eq!(&[0x01u8, 0x00, 0x02, 0x00, 0x03, 0x02, 0x00, 0x04, 0x05][..], buf);
 
   // 復元したメッセージが元の値と一致しているか
@@ -44,10 +62,7 @@ fn test_open_read_write() {
 
   // 未完成のバッファを検出できるか
   for i in 0..(buf.len() - 1) {
-    assert_eq!(
-      Error::BufferUnsatisfied,
-      Open::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err()
-    );
+    assert_need_more_bytes(Open::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err());
   }
 }
 
@@ -69,12 +84,44 @@ fn test_close_new() {
   assert!(Close::new(0xFFFFu16, failure, result.clone()).is_ok());
 }
 
+#[test]
+fn test_close_success_failure() {
+  let mut sample = SampleValues::new(830175620943u64);
+  let pipe_id = sample.next_u16() | 1;
+  let result = sample.next_bytes(256);
+
+  // success() は failure フラグを false に設定する
+  let success = Close::success(pipe_id, result.clone()).unwrap();
+  assert!(!success.failure);
+  assert!(success.is_success());
+  assert_eq!(Close::new(pipe_id, false, result.clone()).unwrap(), success);
+
+  // failure() は failure フラグを true に設定する
+  let failure = Close::failure(pipe_id, result.clone()).unwrap();
+  assert!(failure.failure);
+  assert!(!failure.is_success());
+  assert_eq!(Close::new(pipe_id, true, result.clone()).unwrap(), failure);
+
+  // 復元したメッセージでもフラグが保持されている
+  for close in [success, failure].iter() {
+    let mut buf = Vec::new();
+    close.write_to(&mut buf).unwrap();
+    let restored = Close::read_from(&mut Cursor::new(&buf[..])).unwrap();
+    assert_eq!(close, &restored);
+    assert_eq!(close.is_success(), restored.is_success());
+  }
+
+  // pipe_id に境界値を設定
+  assert_eq!(Close::success(0u16, result.clone()).unwrap_err(), Error::ZeroPipeId);
+  assert_eq!(Close::failure(0u16, result).unwrap_err(), Error::ZeroPipeId);
+}
+
 #[test]
 fn test_close_read_write() {
   // バイナリ表現が想定と一致しているか
   let mut buf = Vec::new();
   let close = Close::new(1u16, true, Vec::from([2u8, 3])).unwrap();
-  close.write_to(&mut buf).unwrap();
+  assert_eq!(close.write_to(&mut buf).unwrap(), buf.len());
   assert_eq!(&[0x01u8, 0x00, 0x01, 0x02, 0x00, 0x02, 0x03][..], buf);
 
   // 復元したメッセージが元の値と一致しているか
@@ -83,10 +130,7 @@ fn test_close_read_write() {
 
   // 未完成のバッファを検出できるか
   for i in 0..(buf.len() - 1) {
-    assert_eq!(
-      Error::BufferUnsatisfied,
-      Close::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err()
-    );
+    assert_need_more_bytes(Close::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err());
   }
 }
 
@@ -102,8 +146,11 @@ fn test_block_new() {
   let block = Block::new(pipe_id, eof, loss, payload.clone()).unwrap();
   assert_eq!(pipe_id, block.pipe_id);
   assert_eq!(loss, block.loss);
-  assert_eq!(payload, block.payload);
+  assert_eq!(&payload[..], block.payload());
   assert_eq!(eof, block.eof);
+  assert_eq!((eof, loss), (block.is_eof(), block.loss()));
+  let final_block = Block::new(pipe_id, true, 0, vec![]).unwrap();
+  assert_eq!((true, 0), (final_block.is_eof(), final_block.loss()));
 
   // pipe_id に境界値を設定
   assert_eq!(Block::new(0u16, eof, loss, payload.clone()).unwrap_err(), Error::ZeroPipeId);
@@ -118,10 +165,136 @@ fn test_block_new() {
   // payload に上限以上の長さを設定
   assert_eq!(
     Block::new(pipe_id, eof, loss, sample.next_bytes(MAX_PAYLOAD_SIZE + 1)).unwrap_err(),
-    Error::PayloadTooLarge {
-      length: (MAX_PAYLOAD_SIZE + 1) as usize,
-      maximum: MAX_PAYLOAD_SIZE as usize,
+    Error::PayloadTooLarge { length: MAX_PAYLOAD_SIZE + 1, maximum: MAX_PAYLOAD_SIZE }
+  );
+}
+
+#[test]
+fn test_block_set_loss() {
+  let mut block = Block::new(1u16, false, 0u8, vec![2u8, 3u8]).unwrap();
+
+  // 上限までの値に更新できる
+  for loss in 0..=MAX_LOSS_RATE {
+    block.set_loss(loss).unwrap();
+    assert_eq!(loss, block.loss());
+  }
+
+  // 上限を超える値には更新できず、元の値が維持される
+  assert_eq!(
+    Error::LossRateTooBig { loss: (MAX_LOSS_RATE + 1) as usize, maximum: MAX_LOSS_RATE as usize },
+    block.set_loss(MAX_LOSS_RATE + 1).unwrap_err()
+  );
+  assert_eq!(MAX_LOSS_RATE, block.loss());
+
+  // EOF を示すブロックには 0 しか設定できない
+  let mut eof = Block::new(1u16, true, 0u8, vec![]).unwrap();
+  assert_eq!(Error::LossRateTooBig { loss: 1, maximum: 0 }, eof.set_loss(1).unwrap_err());
+  eof.set_loss(0).unwrap();
+  assert_eq!(0, eof.loss());
+}
+
+#[test]
+fn test_block_checksum() {
+  let payload = b"123456789".to_vec();
+  let plain = Block::new(1u16, false, 2u8, payload.clone()).unwrap().with_sequence(3);
+  let block = plain.clone().with_checksum();
+  assert!(!plain.has_checksum());
+  assert!(block.has_checksum());
+  assert_eq!(plain.serialized_len() + 4, block.serialized_len());
+
+  // チェックサムを付加した Block を復元できる
+  let mut buffer = Vec::new();
+  assert_eq!(block.write_to(&mut buffer).unwrap(), buffer.len());
+  assert_eq!(block.serialized_len(), buffer.len());
+  assert_eq!(block, Block::read_from(&mut Cursor::new(&buffer)).unwrap());
+
+  // 1 バイトでも破損していれば検出される
+  let mut corrupted = buffer.clone();
+  let offset = buffer.len() - 4 - 1;
+  corrupted[offset] ^= 0x01;
+  match Block::read_from(&mut Cursor::new(&corrupted)).unwrap_err() {
+    Error::ChecksumMismatch { expected, actual } => assert_ne!(expected, actual),
+    unexpected => panic!("unexpected error: {:?}", unexpected),
+  }
+
+  // チェックサムのない Block のバイナリ表現は変わらない
+  let mut buffer = Vec::new();
+  assert_eq!(plain.write_to(&mut buffer).unwrap(), buffer.len());
+  assert_eq!(2 + 2 + 4 + 2 + payload.len(), buffer.len());
+  assert_eq!(plain, Block::read_from(&mut Cursor::new(&buffer)).unwrap());
+}
+
+#[test]
+fn test_block_should_drop() {
+  let mut rng = StdRng::seed_from_u64(0);
+
+  // 消失確率 0 のブロックは消失せず、最大値のブロックは必ず消失する
+  let mut block = Block::new(1u16, false, 0u8, vec![]).unwrap();
+  assert!(!block.should_drop(&mut rng));
+  let mut block = Block::new(1u16, false, MAX_LOSS_RATE, vec![]).unwrap();
+  assert!(block.should_drop(&mut rng));
+  assert_eq!(MAX_LOSS_RATE, block.loss());
+
+  // 判定を通過したブロックの消失確率は 0 にリセットされ、以降は消失しない
+  let mut passed = 0;
+  for _ in 0..100 {
+    let mut block = Block::new(1u16, false, 1u8, vec![]).unwrap();
+    if !block.should_drop(&mut rng) {
+      assert_eq!(0, block.loss());
+      assert!(!block.should_drop(&mut rng));
+      passed += 1;
     }
+  }
+  assert!(passed > 0);
+}
+
+#[test]
+fn test_loss_shaper_reproducible() {
+  // 同じシードの乱数生成器を持つ LossShaper は同じ消失判定の列を生成する
+  let decisions = |seed: u64| {
+    let mut shaper = LossShaper::new(StdRng::seed_from_u64(seed));
+    (0..1000u32)
+      .map(|i| {
+        let mut block = Block::new(1u16, false, (i % (MAX_LOSS_RATE as u32 + 1)) as u8, vec![]);
+        shaper.decide(block.as_mut().unwrap())
+      })
+      .collect::<Vec<_>>()
+  };
+  let expected = decisions(42);
+  assert_eq!(expected, decisions(42));
+  assert_ne!(expected, decisions(43));
+  assert!(expected.iter().any(|d| *d) && expected.iter().any(|d| !*d));
+}
+
+#[test]
+fn test_block_clear_loss() {
+  // 消失判定を通過したブロックの loss は 0 にリセットされる
+  let mut block = Block::new(1u16, false, MAX_LOSS_RATE, vec![2u8, 3u8]).unwrap();
+  block.clear_loss();
+  assert_eq!(0, block.loss());
+  let mut buf = Vec::new();
+  block.write_to(&mut buf).unwrap();
+  assert_eq!(block, Block::read_from(&mut Cursor::new(&buf[..])).unwrap());
+}
+
+#[test]
+fn test_block_shared_payload() {
+  let mut sample = SampleValues::new(6107258333u64);
+  let block = Block::new(1u16, false, 0u8, sample.next_bytes(1024)).unwrap();
+
+  // 中継した Block は元の Block とペイロードの領域を共有する
+  let forward1 = Block::with_shared_payload(2u16, false, 0u8, block.payload_arc()).unwrap();
+  let forward2 = Block::with_shared_payload(3u16, true, 0u8, forward1.payload_arc()).unwrap();
+  assert!(Arc::ptr_eq(&block.payload_arc(), &forward1.payload_arc()));
+  assert!(Arc::ptr_eq(&forward1.payload_arc(), &forward2.payload_arc()));
+  assert_eq!(block.payload().as_ptr(), forward2.payload().as_ptr());
+  assert_eq!(block.payload(), forward2.payload());
+
+  // 共有したペイロードも new() と同様に検証される
+  let payload: Arc<[u8]> = Arc::from(sample.next_bytes(MAX_PAYLOAD_SIZE + 1));
+  assert_eq!(
+    Error::PayloadTooLarge { length: MAX_PAYLOAD_SIZE + 1, maximum: MAX_PAYLOAD_SIZE },
+    Block::with_shared_payload(1u16, false, 0u8, payload).unwrap_err()
   );
 }
 
@@ -130,8 +303,9 @@ fn test_block_read_write() {
   // バイナリ表現が想定と一致しているか
   let mut buf = Vec::new();
   let block = Block::new(1u16, true, 2u8, Vec::from([3u8, 4])).unwrap();
-  block.write_to(&mut buf).unwrap();
-  assert_eq!(&[0x01u8, 0x00, (1 << 7) | 0x02, 0x02, 0x00, 0x03, 0x04][..], buf);
+  assert_eq!(block.write_to(&mut buf).unwrap(), buf.len());
+  assert_eq!(&[0x01u8, 0x00, 0x02, 1 << 7, 0x02, 0x00, 0x03, 0x04][..], buf);
+  assert_eq!(buf.len(), block.serialized_len());
 
   // 復元したメッセージが元の値と一致しているか
   let restored = Block::read_from(&mut Cursor::new(&buf[..])).unwrap();
@@ -139,13 +313,153 @@ fn test_block_read_write() {
 
   // 未完成のバッファを検出できるか
   for i in 0..(buf.len() - 1) {
+    assert_need_more_bytes(Block::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err());
+  }
+
+  // 予約ビットが設定されている場合はエラーとなる
+  for bit in 7..13 {
+    let mut buf = buf.clone();
+    buf[2 + bit / 8] |= 1 << (bit % 8);
     assert_eq!(
-      Error::BufferUnsatisfied,
-      Block::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err()
+      Error::ReservedBitsSet { bits: 1 << bit },
+      Block::read_from(&mut Cursor::new(&buf[..])).unwrap_err()
     );
   }
 }
 
+#[test]
+fn test_block_sequence_read_write() {
+  // シーケンス番号を持つ Block のバイナリ表現が想定と一致しているか
+  let mut buf = Vec::new();
+  let block = Block::new(1u16, false, 2u8, Vec::from([3u8, 4])).unwrap().with_sequence(5);
+  assert_eq!(Some(5), block.sequence());
+  assert_eq!(block.write_to(&mut buf).unwrap(), buf.len());
+  assert_eq!(
+    &[0x01u8, 0x00, 0x02, 1 << 6, 0x05, 0x00, 0x00, 0x00, 0x02, 0x00, 0x03, 0x04][..],
+    buf
+  );
+  assert_eq!(buf.len(), block.serialized_len());
+
+  // 復元したメッセージが元の値と一致しているか
+  let restored = Block::read_from(&mut Cursor::new(&buf[..])).unwrap();
+  assert_eq!(block, restored);
+
+  // 未完成のバッファを検出できるか
+  for i in 0..(buf.len() - 1) {
+    assert_need_more_bytes(Block::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err());
+  }
+}
+
+/// 指定されたシーケンス番号とペイロードを持つ Block を構築します。
+fn sequenced_block(sequence: u32) -> Block {
+  Block::new(1u16, false, 0u8, sequence.to_le_bytes().to_vec()).unwrap().with_sequence(sequence)
+}
+
+#[test]
+fn test_block_reassembler_in_order() {
+  // 順序通りに到着した Block はそのまま取り出せる
+  let mut reassembler = BlockReassembler::new(10);
+  for sequence in 10..20 {
+    assert!(reassembler.push(sequenced_block(sequence)).unwrap());
+    assert_eq!(Some(sequenced_block(sequence)), reassembler.pop());
+    assert_eq!(None, reassembler.gaps().next());
+  }
+  assert_eq!(None, reassembler.pop());
+  assert_eq!(20, reassembler.next_sequence());
+
+  // シーケンス番号を持たない Block は受け付けない
+  assert_eq!(
+    Error::BlockSequenceNotSpecified { pipe_id: 1 },
+    reassembler.push(Block::new(1u16, false, 0u8, vec![]).unwrap()).unwrap_err()
+  );
+}
+
+#[test]
+fn test_block_reassembler_out_of_order() {
+  // 順序が入れ替わって到着した Block をシーケンス番号の順に取り出せる
+  let mut reassembler = BlockReassembler::new(0);
+  for sequence in [3u32, 1, 4, 0, 2].iter() {
+    assert!(reassembler.push(sequenced_block(*sequence)).unwrap());
+  }
+  for sequence in 0..5 {
+    assert_eq!(Some(sequenced_block(sequence)), reassembler.pop());
+  }
+  assert_eq!(None, reassembler.pop());
+  assert_eq!(0, reassembler.pending_len());
+
+  // 重複して到着した Block は破棄される
+  assert!(!reassembler.push(sequenced_block(2)).unwrap());
+  assert!(reassembler.push(sequenced_block(6)).unwrap());
+  assert!(!reassembler.push(sequenced_block(6)).unwrap());
+  assert_eq!(1, reassembler.pending_len());
+}
+
+#[test]
+fn test_block_reassembler_gaps() {
+  // 後続の Block が到着していれば欠落しているシーケンス番号を検出できる
+  let mut reassembler = BlockReassembler::new(0);
+  assert_eq!(None, reassembler.gaps().next());
+  for sequence in [0u32, 2, 5].iter() {
+    reassembler.push(sequenced_block(*sequence)).unwrap();
+  }
+  assert_eq!(vec![1, 3, 4], reassembler.gaps().collect::<Vec<_>>());
+
+  // 欠落している Block の手前までしか取り出せない
+  assert_eq!(Some(sequenced_block(0)), reassembler.pop());
+  assert_eq!(None, reassembler.pop());
+  assert_eq!(1, reassembler.next_sequence());
+
+  // 欠落していた Block が到着すると後続の Block も取り出せる
+  reassembler.push(sequenced_block(1)).unwrap();
+  assert_eq!(vec![3, 4], reassembler.gaps().collect::<Vec<_>>());
+  assert_eq!(Some(sequenced_block(1)), reassembler.pop());
+  assert_eq!(Some(sequenced_block(2)), reassembler.pop());
+  assert_eq!(None, reassembler.pop());
+}
+
+#[test]
+fn test_block_reassembler_wrap_around() {
+  // u32::MAX の次のシーケンス番号は 0 として並べ直される
+  let mut reassembler = BlockReassembler::new(u32::MAX - 1);
+  for sequence in [1u32, u32::MAX, 0].iter() {
+    assert!(reassembler.push(sequenced_block(*sequence)).unwrap());
+  }
+  assert_eq!(vec![u32::MAX - 1], reassembler.gaps().collect::<Vec<_>>());
+  assert!(reassembler.push(sequenced_block(u32::MAX - 1)).unwrap());
+  for sequence in [u32::MAX - 1, u32::MAX, 0, 1].iter() {
+    assert_eq!(Some(sequenced_block(*sequence)), reassembler.pop());
+  }
+  assert_eq!(2, reassembler.next_sequence());
+
+  // 0 に戻る前のシーケンス番号はすでに取り出したものとして破棄される
+  assert!(!reassembler.push(sequenced_block(u32::MAX)).unwrap());
+  assert_eq!(0, reassembler.pending_len());
+}
+
+#[test]
+fn test_block_reassembler_window() {
+  let mut reassembler = BlockReassembler::with_window(0, 4);
+
+  // 並べ直しの範囲を超えて先の Block は保持せずにエラーとなる
+  assert!(reassembler.push(sequenced_block(3)).unwrap());
+  assert_eq!(
+    Error::SequenceOutOfWindow { pipe_id: 1, sequence: 4, next: 0, window: 4 },
+    reassembler.push(sequenced_block(4)).unwrap_err()
+  );
+  assert_eq!(
+    Error::SequenceOutOfWindow { pipe_id: 1, sequence: u32::MAX / 2, next: 0, window: 4 },
+    reassembler.push(sequenced_block(u32::MAX / 2)).unwrap_err()
+  );
+  assert_eq!(1, reassembler.pending_len());
+  assert_eq!(vec![0, 1, 2], reassembler.gaps().collect::<Vec<_>>());
+
+  // 取り出すと範囲が先に進む
+  assert!(reassembler.push(sequenced_block(0)).unwrap());
+  assert_eq!(Some(sequenced_block(0)), reassembler.pop());
+  assert!(reassembler.push(sequenced_block(4)).unwrap());
+  assert_eq!(vec![1, 2], reassembler.gaps().collect::<Vec<_>>());
+}
+
 #[test]
 fn test_control_new_system_config() {
   let mut sample = SampleValues::new(48907095721u64);
@@ -154,7 +468,7 @@ fn test_control_new_system_config() {
   let version = sample.next_u16();
   let node_id = sample.next_uuid();
   let session_id = sample.next_uuid();
-  let utc_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+  let utc_time = SystemTime::now();
   let ping_interval = sample.next_u32();
   let session_timeout = sample.next_u32();
   if let Control::SystemConfig {
@@ -177,11 +491,11 @@ fn test_control_new_system_config() {
     assert_eq!(version, p1);
     assert_eq!(node_id, p2);
     assert_eq!(session_id, p3);
-    assert_eq!(utc_time, p4);
+    assert_eq!(to_utc_millis(utc_time), p4);
     assert_eq!(ping_interval, p5);
     assert_eq!(session_timeout, p6);
   } else {
-    assert!(false);
+    unreachable!();
   }
 }
 
@@ -193,18 +507,18 @@ fn test_control_system_config_read_write() {
     1u16,
     Uuid::from_u128(2u128),
     Uuid::from_u128(3u128),
-    4u64,
+    from_utc_millis(4),
     5u32,
     6u32,
   )
   .unwrap();
-  sys_config.write_to(&mut buf).unwrap();
+  assert_eq!(sys_config.write_to(&mut buf).unwrap(), buf.len());
   assert_eq!(
     &[
-      'Q' as u8, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-      0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
-      0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00
+      b'Q', 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00,
+      0x00, 0x00, 0x06, 0x00, 0x00, 0x00
     ][..],
     buf
   );
@@ -215,31 +529,117 @@ fn test_control_system_config_read_write() {
 
   // 未完成のバッファを検出できるか
   for i in 0..(buf.len() - 1) {
-    assert_eq!(
-      Error::BufferUnsatisfied,
-      Control::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err()
-    );
+    assert_need_more_bytes(Control::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err());
+  }
+}
+
+#[test]
+fn test_is_compatible_version() {
+  assert!(is_compatible_version(PROTOCOL_VERSION));
+  assert!(is_compatible_version(0x0100));
+  assert!(is_compatible_version(0x01FF));
+  assert!(!is_compatible_version(0x0000));
+  assert!(!is_compatible_version(0x0200));
+}
+
+#[test]
+fn test_system_config_builder() {
+  let mut sample = SampleValues::new(7338104227u64);
+  let (version, node_id, session_id) = (sample.next_u16(), sample.next_uuid(), sample.next_uuid());
+  let (utc_time, ping_interval, session_timeout) =
+    (from_utc_millis(sample.next_u32() as u64), sample.next_u32(), sample.next_u32());
+
+  // 位置引数のコンストラクタと同じメッセージを構築する
+  let expected = Control::new_system_config(
+    version,
+    node_id,
+    session_id,
+    utc_time,
+    ping_interval,
+    session_timeout,
+  )
+  .unwrap();
+  let actual = SystemConfigBuilder::new(node_id)
+    .version(version)
+    .session_id(session_id)
+    .utc_time(utc_time)
+    .ping_interval(ping_interval)
+    .session_timeout(session_timeout)
+    .build()
+    .unwrap();
+  assert_eq!(expected, actual);
+
+  // 省略した設定にはデフォルト値が使用される
+  let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+  let actual = SystemConfigBuilder::new(node_id).build().unwrap();
+  let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+  if let Control::SystemConfig {
+    version,
+    node_id: n,
+    session_id,
+    utc_time,
+    ping_interval,
+    session_timeout,
+  } = actual
+  {
+    assert_eq!(PROTOCOL_VERSION, version);
+    assert_eq!(node_id, n);
+    assert_eq!(Uuid::nil(), session_id);
+    assert!(before <= utc_time && utc_time <= after);
+    assert_eq!(DEFAULT_PING_INTERVAL, ping_interval);
+    assert_eq!(DEFAULT_SESSION_TIMEOUT, session_timeout);
+  } else {
+    unreachable!();
   }
 }
 
 #[test]
 fn test_control_new_ping() {
-  // 設定した値と同じ値が参照できる
-  let utc_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+  // 設定した時刻が UTC ミリ秒で格納される
+  let utc_time = SystemTime::now();
   if let Control::Ping { utc_time: p1 } = Control::new_ping(utc_time).unwrap() {
-    assert_eq!(utc_time, p1);
+    assert_eq!(utc_time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64, p1);
   } else {
-    assert!(false);
+    unreachable!();
   }
 }
 
+#[test]
+fn test_control_timestamp() {
+  // ミリ秒の精度で元の時刻に戻る
+  let utc_time = SystemTime::now();
+  let node_id = Uuid::nil();
+  for control in [
+    Control::new_ping(utc_time).unwrap(),
+    Control::new_system_config(1, node_id, node_id, utc_time, 2, 3).unwrap(),
+  ] {
+    let timestamp = control.timestamp().unwrap();
+    assert!(timestamp <= utc_time);
+    assert!(utc_time.duration_since(timestamp).unwrap() < Duration::from_millis(1));
+  }
+
+  // ミリ秒単位の時刻は変化せず、UNIX エポックより前の時刻は 0 となる
+  let utc_time = from_utc_millis(1_234_567_890_123);
+  assert_eq!(Some(utc_time), Control::new_ping(utc_time).unwrap().timestamp());
+  let before_epoch = UNIX_EPOCH - Duration::from_secs(1);
+  assert_eq!(Some(UNIX_EPOCH), Control::new_ping(before_epoch).unwrap().timestamp());
+
+  // 時刻を持たない Close
+  assert_eq!(None, Control::new_close(0, vec![]).unwrap().timestamp());
+}
+
 #[test]
 fn test_control_ping_read_write() {
   // バイナリ表現が想定と一致しているか
   let mut buf = Vec::new();
-  let ping = Control::new_ping(1u64).unwrap();
-  ping.write_to(&mut buf).unwrap();
-  assert_eq!(&['P' as u8, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..], buf);
+  let ping = Control::new_ping(from_utc_millis(1)).unwrap();
+  assert_eq!(ping.write_to(&mut buf).unwrap(), buf.len());
+  assert_eq!(&[b'P', 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..], buf);
+
+  // 8 バイトの utc_time はリトルエンディアンで表現される
+  let mut buf2 = Vec::new();
+  Control::new_ping(from_utc_millis(0x0102_0304_0506_0708)).unwrap().write_to(&mut buf2).unwrap();
+  assert_eq!(&[b'P', 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01][..], buf2);
 
   // 復元したメッセージが元の値と一致しているか
   let restored = Control::read_from(&mut Cursor::new(&buf[..])).unwrap();
@@ -247,9 +647,676 @@ fn test_control_ping_read_write() {
 
   // 未完成のバッファを検出できるか
   for i in 0..(buf.len() - 1) {
+    assert_need_more_bytes(Control::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err());
+  }
+}
+
+#[test]
+fn test_control_new_close() {
+  // 設定した値と同じ値が参照できる
+  if let Control::Close { reason_code, reason } = Control::new_close(1, b"bye".to_vec()).unwrap() {
+    assert_eq!(1, reason_code);
+    assert_eq!(b"bye".to_vec(), reason);
+  } else {
+    unreachable!();
+  }
+
+  // 長さを 2 バイトで表現できない理由は構築できない
+  match Control::new_close(1, vec![0u8; u16::MAX as usize + 1]).unwrap_err() {
+    Error::PayloadTooLarge { length, maximum } => {
+      assert_eq!(u16::MAX as usize + 1, length);
+      assert_eq!(u16::MAX as usize, maximum);
+    }
+    unexpected => panic!("unexpected error: {:?}", unexpected),
+  }
+}
+
+#[test]
+fn test_control_close_read_write() {
+  // バイナリ表現が想定と一致しているか
+  let mut buf = Vec::new();
+  let close = Control::new_close(1u16, vec![2u8, 3u8]).unwrap();
+  assert_eq!(close.write_to(&mut buf).unwrap(), buf.len());
+  assert_eq!(&[b'C', 0x01, 0x00, 0x02, 0x00, 0x02, 0x03][..], buf);
+  assert_eq!(buf.len(), close.serialized_len());
+
+  // 復元したメッセージが元の値と一致しているか
+  let restored = Control::read_from(&mut Cursor::new(&buf[..])).unwrap();
+  assert_eq!(close, restored);
+
+  // 未完成のバッファを検出できるか
+  for i in 0..(buf.len() - 1) {
+    assert_need_more_bytes(Control::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err());
+  }
+}
+
+#[test]
+fn test_control_type() {
+  // すべての種類が識別子を介して相互に変換でき、識別子は重複しない
+  let mut values = HashSet::new();
+  for control_type in ControlType::ALL.iter().copied() {
+    let value = u8::from(control_type);
+    assert!(values.insert(value), "duplicate identifier: {}", value);
+    assert_eq!(control_type, ControlType::try_from(value).unwrap());
+  }
+
+  // 各メッセージのバイナリ表現の先頭は種類の識別子となる
+  let controls = [
+    SystemConfigBuilder::new(Uuid::from_u128(1)).build().unwrap(),
+    Control::new_ping(from_utc_millis(2)).unwrap(),
+    Control::new_close(3, vec![4]).unwrap(),
+  ];
+  for (control, control_type) in controls.iter().zip(ControlType::ALL.iter()) {
+    assert_eq!(*control_type, control.control_type());
+    let mut buf = Vec::new();
+    control.write_to(&mut buf).unwrap();
+    assert_eq!(u8::from(*control_type), buf[0]);
+  }
+
+  // 未定義の識別子は不正な種類となる
+  for value in (0..=u8::MAX).filter(|value| !values.contains(value)) {
+    assert_eq!(Error::IllegalControlType { value }, ControlType::try_from(value).unwrap_err());
     assert_eq!(
-      Error::BufferUnsatisfied,
-      Control::read_from(&mut Cursor::new(&buf[0..i])).unwrap_err()
+      Error::IllegalControlType { value },
+      Control::read_from(&mut Cursor::new(&[value][..])).unwrap_err()
     );
   }
 }
+
+#[test]
+fn test_message_read_write() {
+  // バイナリ表現が想定と一致しているか
+  let mut buf = Vec::new();
+  let ping = Message::Control(Control::new_ping(from_utc_millis(1)).unwrap());
+  assert_eq!(ping.write_to(&mut buf).unwrap(), buf.len());
+  assert_eq!(&[b'X', b'P', 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..], buf);
+
+  // 復元したメッセージが元の値と一致しているか
+  let restored = Message::read_from(&mut Cursor::new(&buf[..])).unwrap();
+  assert_eq!(ping, restored);
+
+  // 未知のメッセージ種別を検出できるか
+  assert_eq!(
+    Error::IllegalMessageType { value: b'?' },
+    Message::read_from(&mut Cursor::new(&[b'?'][..])).unwrap_err()
+  );
+}
+
+#[test]
+fn test_messages() {
+  let messages = vec![
+    Message::Open(Open::new(1u16, 2u16, 3u8, Vec::from([4u8, 5u8])).unwrap()),
+    Message::Block(Block::new(1u16, true, 0u8, Vec::from([6u8, 7, 8])).unwrap()),
+    Message::Close(Close::success(1u16, Vec::from([9u8])).unwrap()),
+  ];
+  let mut buf = Vec::new();
+  for msg in messages.iter() {
+    msg.write_to(&mut buf).unwrap();
+  }
+
+  // 連結したメッセージが順番に復元できる
+  let restored = Messages::new(&buf[..]).collect::<Result<Vec<Message>, Error>>().unwrap();
+  assert_eq!(messages, restored);
+  assert_eq!(messages, decode_all(&buf[..]).unwrap());
+  assert!(Messages::new(&[]).next().is_none());
+
+  // 末尾の不完全なメッセージを検出できる
+  let mut iter = Messages::new(&buf[..buf.len() - 1]);
+  assert!(iter.next().unwrap().is_ok());
+  assert!(iter.next().unwrap().is_ok());
+  assert_eq!(Error::NeedMoreBytes { needed: 1 }, iter.next().unwrap().unwrap_err());
+  assert!(iter.next().is_none());
+  assert_eq!(Error::NeedMoreBytes { needed: 1 }, decode_all(&buf[..buf.len() - 1]).unwrap_err());
+}
+
+#[test]
+fn test_message_pipe_id() {
+  let open = Open::new(1, 2, 3, vec![]).unwrap();
+  assert_eq!(Some(1), Message::Open(open).pipe_id());
+  let close = Close::new(4, false, vec![]).unwrap();
+  assert_eq!(Some(4), Message::Close(close).pipe_id());
+  let block = Block::new(0xFFFF, false, 0, vec![]).unwrap();
+  assert_eq!(Some(0xFFFF), Message::Block(block).pipe_id());
+  assert_eq!(None, Message::Control(Control::new_ping(from_utc_millis(0)).unwrap()).pipe_id());
+}
+
+#[test]
+fn test_control_pipe_id() {
+  // Control メッセージはパイプ 0 を宛先とし、検証に失敗せずに書き込みと復元ができる
+  let ping = Message::Control(Control::new_ping(from_utc_millis(1)).unwrap());
+  assert_eq!(CONTROL_PIPE_ID, ping.target_pipe_id());
+  let mut buf = Vec::new();
+  ping.write_to(&mut buf).unwrap();
+  let (restored, _) = Message::from_bytes(&buf).unwrap();
+  assert_eq!(ping, restored);
+  assert_eq!((None, CONTROL_PIPE_ID), (restored.pipe_id(), restored.target_pipe_id()));
+
+  // その他のメッセージは自身のパイプ ID を宛先とする
+  let open = Message::Open(Open::new(1, 2, 3, vec![]).unwrap());
+  assert_eq!(1, open.target_pipe_id());
+
+  // パイプ 0 を宛先とする Control 以外のメッセージは復元できない
+  for msg in [
+    Message::Open(Open::new(1, 2, 3, vec![4]).unwrap()),
+    Message::Close(Close::new(1, false, vec![4]).unwrap()),
+    Message::Block(Block::new(1, true, 0, vec![4]).unwrap()),
+  ] {
+    let mut buf = Vec::new();
+    msg.write_to(&mut buf).unwrap();
+    buf[1..3].copy_from_slice(&CONTROL_PIPE_ID.to_le_bytes());
+    assert_eq!(Error::ZeroPipeId, Message::from_bytes(&buf).unwrap_err());
+  }
+}
+
+#[test]
+fn test_message_serialized_len() {
+  let mut sample = SampleValues::new(3208957201u64);
+
+  // serialized_len() が実際にシリアライズしたバイナリ長と一致する
+  let messages = [
+    Message::Open(Open::new(1u16, 2u16, 3u8, sample.next_bytes(100)).unwrap()),
+    Message::Close(Close::failure(1u16, sample.next_bytes(200)).unwrap()),
+    Message::Block(Block::new(1u16, false, 4u8, sample.next_bytes(300)).unwrap()),
+    Message::Control(Control::new_ping(from_utc_millis(5)).unwrap()),
+    Message::Control(
+      Control::new_system_config(
+        1u16,
+        sample.next_uuid(),
+        sample.next_uuid(),
+        from_utc_millis(2),
+        3u32,
+        4u32,
+      )
+      .unwrap(),
+    ),
+  ];
+  for msg in messages.iter() {
+    let mut buf = Vec::new();
+    assert_eq!(msg.write_to(&mut buf).unwrap(), buf.len());
+    assert_eq!(buf.len(), msg.serialized_len());
+    assert_eq!(buf, msg.to_bytes().unwrap());
+  }
+
+  // MAX_MESSAGE_SIZE ちょうどのメッセージは出力できる
+  let overhead = Message::Open(Open::new(1u16, 2u16, 3u8, Vec::new()).unwrap()).serialized_len();
+  let params = sample.next_bytes(MAX_MESSAGE_SIZE - overhead);
+  let open = Message::Open(Open::new(1u16, 2u16, 3u8, params).unwrap());
+  let mut buf = Vec::new();
+  open.write_to(&mut buf).unwrap();
+  assert_eq!(MAX_MESSAGE_SIZE, buf.len());
+
+  // MAX_MESSAGE_SIZE を超えるメッセージは何も出力せずにエラーとなる
+  let params = sample.next_bytes(MAX_MESSAGE_SIZE - overhead + 1);
+  let open = Message::Open(Open::new(1u16, 2u16, 3u8, params).unwrap());
+  let mut buf = Vec::new();
+  assert_eq!(
+    Error::MessageTooLarge { length: MAX_MESSAGE_SIZE + 1, maximum: MAX_MESSAGE_SIZE },
+    open.write_to(&mut buf).unwrap_err()
+  );
+  assert!(buf.is_empty());
+  assert_eq!(
+    Error::MessageTooLarge { length: MAX_MESSAGE_SIZE + 1, maximum: MAX_MESSAGE_SIZE },
+    open.to_bytes().unwrap_err()
+  );
+}
+
+#[test]
+fn test_need_more_bytes() {
+  // [pipe_id:2][function_id:2][priority:1][length:2][params:2]
+  let mut buf = Vec::new();
+  Open::new(1u16, 2u16, 3u8, Vec::from([4u8, 5u8])).unwrap().write_to(&mut buf).unwrap();
+  let needed = |len: usize| match Open::read_from(&mut Cursor::new(&buf[..len])).unwrap_err() {
+    Error::NeedMoreBytes { needed } => needed,
+    unexpected => panic!("unexpected error: {:?}", unexpected),
+  };
+  assert_eq!(2, needed(0));
+  assert_eq!(1, needed(1));
+  assert_eq!(1, needed(4));
+  assert_eq!(2, needed(5));
+  assert_eq!(2, needed(7));
+  assert_eq!(1, needed(8));
+
+  // 長さ付きバイナリの場合は長さから残りのバイト数が算出される
+  let mut buf = Vec::new();
+  Block::new(1u16, false, 0u8, vec![0u8; 1000]).unwrap().write_to(&mut buf).unwrap();
+  assert_eq!(
+    Error::NeedMoreBytes { needed: 1000 - 10 },
+    Block::read_from(&mut Cursor::new(&buf[..6 + 10])).unwrap_err()
+  );
+}
+
+#[test]
+fn test_message_try_decode() {
+  let mut sample = SampleValues::new(7203495811u64);
+
+  // 完全なメッセージは復元でき、消費したバイト数が返される
+  let msg = sample.next_message();
+  let mut buf = Vec::new();
+  msg.write_to(&mut buf).unwrap();
+  let length = buf.len();
+  buf.extend_from_slice(&[0xFFu8; 8]);
+  assert_eq!((msg, length), Message::try_decode(&buf[..]).unwrap());
+
+  // 切り詰めたり破損させたバイト列に対してもパニックせずに結果を返す
+  for _ in 0..1000 {
+    let mut buf = Vec::new();
+    sample.next_message().write_to(&mut buf).unwrap();
+    let length = sample.next_u32() as usize % (buf.len() + 1);
+    buf.truncate(length);
+    for _ in 0..(sample.next_u8() % 4) {
+      if !buf.is_empty() {
+        let i = sample.next_u32() as usize % buf.len();
+        buf[i] = sample.next_u8();
+      }
+    }
+    if let Ok((_, consumed)) = Message::try_decode(&buf[..]) {
+      assert!(consumed <= buf.len());
+    }
+  }
+
+  // 完全にランダムなバイト列
+  for _ in 0..1000 {
+    let length = sample.next_u8() as usize;
+    let _ = Message::try_decode(&sample.next_bytes(length)[..]);
+  }
+}
+
+#[test]
+fn test_from_bytes() {
+  let mut sample = SampleValues::new(3908271145u64);
+
+  /// `from_bytes()` が `read_from()` と同じ値を復元し、後続するバイトを消費せず、未完成のバッファを検出することを
+  /// 検証します。
+  fn verify<T, R, F>(value: T, buf: Vec<u8>, read: R, from_bytes: F)
+  where
+    T: PartialEq + std::fmt::Debug,
+    R: Fn(&[u8]) -> Result<T, Error>,
+    F: Fn(&[u8]) -> Result<(T, usize), Error>,
+  {
+    let length = buf.len();
+    let mut buf = buf;
+    buf.extend_from_slice(&[0xFFu8; 4]);
+    assert_eq!(value, read(&buf[..]).unwrap());
+    assert_eq!((value, length), from_bytes(&buf[..]).unwrap());
+    for i in 0..length {
+      assert_need_more_bytes(read(&buf[0..i]).unwrap_err());
+      assert_need_more_bytes(from_bytes(&buf[0..i]).unwrap_err());
+    }
+  }
+
+  for _ in 0..100 {
+    let mut buf = Vec::new();
+    let open = sample.next_open();
+    assert_eq!(open.write_to(&mut buf).unwrap(), buf.len());
+    verify(open, buf, |b| Open::read_from(&mut Cursor::new(b)), Open::from_bytes);
+
+    let mut buf = Vec::new();
+    let close = sample.next_close();
+    assert_eq!(close.write_to(&mut buf).unwrap(), buf.len());
+    verify(close, buf, |b| Close::read_from(&mut Cursor::new(b)), Close::from_bytes);
+
+    let mut buf = Vec::new();
+    let block = sample.next_block();
+    assert_eq!(block.write_to(&mut buf).unwrap(), buf.len());
+    verify(block, buf, |b| Block::read_from(&mut Cursor::new(b)), Block::from_bytes);
+
+    let mut buf = Vec::new();
+    let control = sample.next_control();
+    assert_eq!(control.write_to(&mut buf).unwrap(), buf.len());
+    verify(control, buf, |b| Control::read_from(&mut Cursor::new(b)), Control::from_bytes);
+
+    let mut buf = Vec::new();
+    let msg = sample.next_message();
+    assert_eq!(msg.write_to(&mut buf).unwrap(), buf.len());
+    verify(msg, buf, |b| Message::read_from(&mut Cursor::new(b)), Message::from_bytes);
+  }
+
+  // 不正なメッセージタイプは read_from() と同じエラーとなる
+  assert_eq!(
+    Error::IllegalMessageType { value: 0xFF },
+    Message::from_bytes(&[0xFFu8]).unwrap_err()
+  );
+}
+
+#[test]
+fn test_message_clone_and_hash() {
+  let mut sample = SampleValues::new(57483);
+  for _ in 0..100 {
+    let open = sample.next_open();
+    assert_eq!(open, open.clone());
+    let close = sample.next_close();
+    assert_eq!(close, close.clone());
+    let block = sample.next_block();
+    assert_eq!(block, block.clone());
+    let control = sample.next_control();
+    assert_eq!(control, control.clone());
+    let msg = sample.next_message();
+    assert_eq!(msg, msg.clone());
+  }
+
+  // 等しいメッセージは同じキーとして扱われる
+  let block = Block::new(1u16, false, 0u8, vec![2u8, 3u8]).unwrap();
+  let mut set = HashSet::new();
+  assert!(set.insert(Message::Block(block.clone())));
+  assert!(!set.insert(Message::Block(block.clone())));
+  assert!(set.insert(Message::Block(block.with_sequence(1))));
+  assert_eq!(2, set.len());
+}
+
+/// 書き込みの呼び出し回数を記録するライターです。
+struct CountingWriter {
+  bytes: Vec<u8>,
+  writes: usize,
+}
+
+impl std::io::Write for CountingWriter {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.writes += 1;
+    self.bytes.extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+#[test]
+fn test_message_writer() {
+  let msgs = (1..=10u16)
+    .map(|i| Message::Block(Block::new(i, false, 0u8, vec![i as u8; 16]).unwrap()))
+    .collect::<Vec<_>>();
+
+  // 閾値に達するまでは書き込まれず、flush() でまとめて書き込まれる
+  let mut writer = MessageWriter::new(CountingWriter { bytes: Vec::new(), writes: 0 });
+  for msg in msgs.iter() {
+    writer.write(msg).unwrap();
+  }
+  assert_eq!(0, writer.get_ref().writes);
+  writer.flush().unwrap();
+  assert_eq!(0, writer.buffered_len());
+  assert_eq!(1, writer.get_ref().writes);
+  assert_eq!(msgs, decode_all(&writer.get_ref().bytes).unwrap());
+
+  // 閾値を超えたときに書き込まれる
+  let threshold = msgs[0].serialized_len() * 4;
+  let mut writer =
+    MessageWriter::with_threshold(CountingWriter { bytes: Vec::new(), writes: 0 }, threshold);
+  for msg in msgs.iter() {
+    writer.write(msg).unwrap();
+  }
+  writer.flush().unwrap();
+  assert_eq!(3, writer.get_ref().writes);
+  assert_eq!(msgs, decode_all(&writer.get_ref().bytes).unwrap());
+
+  // 上限を超えるメッセージはバッファに追加されない
+  let mut writer = MessageWriter::new(CountingWriter { bytes: Vec::new(), writes: 0 });
+  let large = Message::Open(Open::new(1, 2, 3, vec![0u8; MAX_MESSAGE_SIZE]).unwrap());
+  assert!(matches!(writer.write(&large).unwrap_err(), Error::MessageTooLarge { .. }));
+  assert_eq!(0, writer.buffered_len());
+}
+
+#[test]
+fn test_stream_decoder() {
+  let mut sample = SampleValues::new(5963);
+  let msgs = (0..100).map(|_| sample.next_message()).collect::<Vec<_>>();
+  let mut buffer = Vec::new();
+  for msg in msgs.iter() {
+    msg.write_to(&mut buffer).unwrap();
+  }
+
+  // 任意の位置で分割されて到着したバイト列からすべてのメッセージを復元できる
+  let mut decoder = StreamDecoder::new();
+  let mut restored = Vec::new();
+  for chunk in buffer.chunks(7) {
+    decoder.push(chunk);
+    while let Some(msg) = decoder.next_message().unwrap() {
+      restored.push(msg);
+    }
+  }
+  assert_eq!(msgs, restored);
+  assert_eq!(0, decoder.buffered_len());
+
+  // 不正なバイト列はエラーとなる
+  let mut decoder = StreamDecoder::new();
+  decoder.push(&[0xFF]);
+  assert_eq!(Error::IllegalMessageType { value: 0xFF }, decoder.next_message().unwrap_err());
+}
+
+/// 1 回の読み込みで 1 バイトだけを返し、その都度 `Poll::Pending` を挟む非同期ストリームです。
+#[cfg(feature = "async-io")]
+struct TrickleReader {
+  bytes: Vec<u8>,
+  position: usize,
+  pending: bool,
+}
+
+#[cfg(feature = "async-io")]
+impl futures::io::AsyncRead for TrickleReader {
+  fn poll_read(
+    mut self: std::pin::Pin<&mut Self>,
+    cx: &mut std::task::Context<'_>,
+    buf: &mut [u8],
+  ) -> std::task::Poll<std::io::Result<usize>> {
+    self.pending = !self.pending;
+    if self.pending {
+      cx.waker().wake_by_ref();
+      return std::task::Poll::Pending;
+    }
+    if buf.is_empty() || self.position >= self.bytes.len() {
+      return std::task::Poll::Ready(Ok(0));
+    }
+    buf[0] = self.bytes[self.position];
+    self.position += 1;
+    std::task::Poll::Ready(Ok(1))
+  }
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn test_message_read_write_async() {
+  use crate::test::block_on;
+  use futures::io::Cursor;
+
+  // 非同期に書き込んだメッセージを非同期に読み込むことができる
+  let mut sample = SampleValues::new(4092);
+  let msgs = (0..100).map(|_| sample.next_message()).collect::<Vec<_>>();
+  let mut writer = Cursor::new(Vec::new());
+  for msg in msgs.iter() {
+    block_on(Box::pin(msg.write_to_async(&mut writer))).unwrap();
+  }
+  let bytes = writer.into_inner();
+  let mut reader = Cursor::new(bytes.clone());
+  for msg in msgs.iter() {
+    assert_eq!(*msg, block_on(Box::pin(Message::read_from_async(&mut reader))).unwrap());
+  }
+
+  // 同期 API と同じバイト列になっている
+  let mut expected = Vec::new();
+  for msg in msgs.iter() {
+    msg.write_to(&mut expected).unwrap();
+  }
+  assert_eq!(expected, bytes);
+
+  // 1 バイトずつ到着するストリームからも後続のメッセージを消費せずに読み込める
+  let mut reader = TrickleReader { bytes, position: 0, pending: false };
+  for msg in msgs.iter() {
+    assert_eq!(*msg, block_on(Box::pin(Message::read_from_async(&mut reader))).unwrap());
+  }
+
+  // メッセージの途中でストリームが終端に達した
+  let mut buffer = Vec::new();
+  msgs[0].write_to(&mut buffer).unwrap();
+  buffer.pop();
+  let mut reader = Cursor::new(buffer);
+  assert_eq!(
+    Error::BufferUnsatisfied,
+    block_on(Box::pin(Message::read_from_async(&mut reader))).unwrap_err()
+  );
+}
+
+/// 種類ごとに受け取ったメッセージの数を数える `MessageVisitor` です。
+#[derive(Default)]
+struct CountingVisitor {
+  opens: usize,
+  closes: usize,
+  blocks: usize,
+  controls: usize,
+}
+
+impl MessageVisitor for CountingVisitor {
+  fn on_open(&mut self, _open: Open) {
+    self.opens += 1;
+  }
+  fn on_close(&mut self, _close: Close) {
+    self.closes += 1;
+  }
+  fn on_block(&mut self, _block: Block) {
+    self.blocks += 1;
+  }
+  fn on_control(&mut self, _control: Control) {
+    self.controls += 1;
+  }
+}
+
+/// Block だけを受け取る `MessageVisitor` です。
+#[derive(Default)]
+struct BlockVisitor {
+  payloads: Vec<Vec<u8>>,
+}
+
+impl MessageVisitor for BlockVisitor {
+  fn on_block(&mut self, block: Block) {
+    self.payloads.push(block.payload().to_vec());
+  }
+}
+
+#[test]
+fn test_message_dispatch() {
+  let messages = || {
+    vec![
+      Message::Open(Open::new(1, 2, 3, vec![]).unwrap()),
+      Message::Block(Block::new(1, false, 0, vec![1]).unwrap()),
+      Message::Block(Block::new(1, true, 0, vec![2]).unwrap()),
+      Message::Close(Close::success(1, vec![]).unwrap()),
+      Message::Control(Control::new_close(0, vec![]).unwrap()),
+    ]
+  };
+
+  // 各メッセージは種類に対応するメソッドに渡される
+  let mut visitor = CountingVisitor::default();
+  for msg in messages() {
+    msg.dispatch(&mut visitor);
+  }
+  assert_eq!((1, 1, 2, 1), (visitor.opens, visitor.closes, visitor.blocks, visitor.controls));
+  let names = messages().iter().map(Message::type_name).collect::<Vec<_>>();
+  assert_eq!(vec!["Open", "Block", "Block", "Close", "Control::Close"], names);
+
+  // オーバーライドしていない種類は無視される
+  let mut visitor = BlockVisitor::default();
+  for msg in messages() {
+    msg.dispatch(&mut visitor);
+  }
+  assert_eq!(vec![vec![1u8], vec![2u8]], visitor.payloads);
+}
+
+#[test]
+fn test_block_split() {
+  let data = SampleValues::new(58201u64).next_bytes(200 * 1024);
+  let blocks = Block::split(1, 10, &data).unwrap();
+
+  // MAX_PAYLOAD_SIZE 以下のペイロードに分割され、EOF は最後の Block にのみ設定される
+  assert_eq!(data.len().div_ceil(MAX_PAYLOAD_SIZE), blocks.len());
+  let (last, rest) = blocks.split_last().unwrap();
+  for block in rest {
+    assert_eq!(MAX_PAYLOAD_SIZE, block.payload().len());
+    assert_eq!((1, false, 10), (block.pipe_id(), block.is_eof(), block.loss()));
+  }
+  assert_eq!(data.len() % MAX_PAYLOAD_SIZE, last.payload().len());
+  assert_eq!((true, 0), (last.is_eof(), last.loss()));
+  assert_eq!(data, blocks.iter().flat_map(|block| block.payload().to_vec()).collect::<Vec<_>>());
+
+  // ちょうど割り切れるデータや空のデータ
+  let blocks = Block::split(1, 0, &vec![0u8; MAX_PAYLOAD_SIZE * 2]).unwrap();
+  assert_eq!(2, blocks.len());
+  assert_eq!((MAX_PAYLOAD_SIZE, true), (blocks[1].payload().len(), blocks[1].is_eof()));
+  assert_eq!(vec![Block::new(1, true, 0, vec![]).unwrap()], Block::split(1, 10, &[]).unwrap());
+
+  // 不正なパラメータ
+  assert_eq!(Error::ZeroPipeId, Block::split(0, 0, &data).unwrap_err());
+  assert!(matches!(Block::split(1, MAX_LOSS_RATE + 1, &data), Err(Error::LossRateTooBig { .. })));
+}
+
+#[test]
+fn test_payload_reassembler() {
+  let data = SampleValues::new(92610u64).next_bytes(200 * 1024);
+  let mut reassembler = PayloadReassembler::default();
+
+  // 複数のパイプの Block が交互に到着しても、それぞれ EOF の Block で元のデータに復元される
+  let first = Block::split(1, 0, &data).unwrap();
+  let second = Block::split(3, 0, &data[..1000]).unwrap();
+  assert_eq!(None, reassembler.push(first[0].clone()).unwrap());
+  assert_eq!(Some(data[..1000].to_vec()), reassembler.push(second[0].clone()).unwrap());
+  assert_eq!(MAX_PAYLOAD_SIZE, reassembler.buffered_len(1));
+  for block in &first[1..first.len() - 1] {
+    assert_eq!(None, reassembler.push(block.clone()).unwrap());
+  }
+  assert_eq!(Some(data.clone()), reassembler.push(first.last().unwrap().clone()).unwrap());
+  assert_eq!(0, reassembler.buffered_len(1));
+  assert_eq!(0, reassembler.total_buffered_len());
+
+  // 1 つの Block で完結するデータ
+  let block = Block::new(1, true, 0, vec![1, 2, 3]).unwrap();
+  assert_eq!(Some(vec![1, 2, 3]), reassembler.push(block).unwrap());
+  let block = Block::new(1, true, 0, vec![]).unwrap();
+  assert_eq!(Some(vec![]), reassembler.push(block).unwrap());
+
+  // 破棄したパイプのデータは連結されない
+  reassembler.push(Block::new(1, false, 0, vec![1]).unwrap()).unwrap();
+  assert_eq!(1, reassembler.total_buffered_len());
+  reassembler.discard(1);
+  assert_eq!(0, reassembler.total_buffered_len());
+  assert_eq!(Some(vec![2]), reassembler.push(Block::new(1, true, 0, vec![2]).unwrap()).unwrap());
+}
+
+#[test]
+fn test_payload_reassembler_size_limit() {
+  let mut reassembler = PayloadReassembler::new(100 * 1024, 1024 * 1024);
+  let blocks = Block::split(1, 0, &vec![0u8; 200 * 1024]).unwrap();
+  assert_eq!(None, reassembler.push(blocks[0].clone()).unwrap());
+
+  // 上限を超えたパイプのデータは破棄される
+  let length = 2 * MAX_PAYLOAD_SIZE;
+  assert_eq!(
+    Error::PayloadTooLarge { length, maximum: 100 * 1024 },
+    reassembler.push(blocks[1].clone()).unwrap_err()
+  );
+  assert_eq!(0, reassembler.buffered_len(1));
+  assert_eq!(0, reassembler.total_buffered_len());
+}
+
+#[test]
+fn test_payload_reassembler_total_size_limit() {
+  let mut reassembler = PayloadReassembler::new(MAX_PAYLOAD_SIZE * 2, MAX_PAYLOAD_SIZE * 3);
+  let block = |pipe_id: u16, eof: bool| Block::new(pipe_id, eof, 0, vec![0u8; MAX_PAYLOAD_SIZE]);
+
+  // パイプごとの上限内でも、すべてのパイプの合計が上限を超えるとその Block のパイプのデータは破棄される
+  assert_eq!(None, reassembler.push(block(1, false).unwrap()).unwrap());
+  assert_eq!(None, reassembler.push(block(3, false).unwrap()).unwrap());
+  assert_eq!(None, reassembler.push(block(5, false).unwrap()).unwrap());
+  assert_eq!(3 * MAX_PAYLOAD_SIZE, reassembler.total_buffered_len());
+  assert_eq!(
+    Error::ReassemblyBufferFull { length: 4 * MAX_PAYLOAD_SIZE, maximum: 3 * MAX_PAYLOAD_SIZE },
+    reassembler.push(block(3, false).unwrap()).unwrap_err()
+  );
+  assert_eq!(0, reassembler.buffered_len(3));
+  assert_eq!(2 * MAX_PAYLOAD_SIZE, reassembler.total_buffered_len());
+
+  // 復元したデータや破棄したデータの分だけ再び連結できるようになる
+  let restored = reassembler.push(block(1, true).unwrap()).unwrap().unwrap();
+  assert_eq!(2 * MAX_PAYLOAD_SIZE, restored.len());
+  assert_eq!(MAX_PAYLOAD_SIZE, reassembler.total_buffered_len());
+  assert_eq!(None, reassembler.push(block(3, false).unwrap()).unwrap());
+  assert_eq!(None, reassembler.push(block(3, false).unwrap()).unwrap());
+  assert_eq!(3 * MAX_PAYLOAD_SIZE, reassembler.total_buffered_len());
+}
@@ -0,0 +1,366 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::msg::{Block, Close, Open, CONTROL_PIPE_ID, MAX_PAYLOAD_SIZE};
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// パイプの状態を表す列挙型です。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeState {
+  /// Open を送受信したがまだ Block を受け付けていない状態。
+  Opening,
+  /// Block を受け付けている状態。
+  Open,
+  /// EOF を示す Block を受け付けたため、これ以上の Block を受け付けず Close を待っている状態。
+  HalfClosed,
+  /// Close を受け付けた状態。
+  Closed,
+}
+
+impl Display for PipeState {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let name = match self {
+      PipeState::Opening => "opening",
+      PipeState::Open => "open",
+      PipeState::HalfClosed => "half-closed",
+      PipeState::Closed => "closed",
+    };
+    f.write_str(name)
+  }
+}
+
+/// 同じ `pipe_id` を持つ Open、Block、Close の一連のメッセージを 1 つの論理的なパイプとして扱い、その状態遷移を
+/// 検証します。
+///
+/// ```text
+/// Opening --open()--> Open --accept_block(eof)--> HalfClosed
+///    |                  |                             |
+///    +------------------+--------close()--------------+--> Closed
+/// ```
+///
+/// Close はファンクション呼び出しの失敗を通知するためにどの状態からでも受け付けます。クローズ後のメッセージは
+/// `Error::PipeClosed` で拒否されます。
+///
+/// 期限を設定したパイプは、期限までに Close を受け付けなければ `expire()` によってタイムアウトを示す失敗の Close
+/// でクローズされます。
+#[derive(Debug)]
+pub struct Pipe {
+  pipe_id: u16,
+  priority: u8,
+  state: PipeState,
+  /// 相手からの応答を待つ期限。`None` の場合はタイムアウトしない。
+  deadline: Option<Instant>,
+}
+
+impl Pipe {
+  /// 指定された Open に対応する `Opening` 状態のパイプを構築します。
+  pub fn new(open: &Open) -> Pipe {
+    Pipe {
+      pipe_id: open.pipe_id(),
+      priority: open.priority(),
+      state: PipeState::Opening,
+      deadline: None,
+    }
+  }
+
+  pub fn pipe_id(&self) -> u16 {
+    self.pipe_id
+  }
+
+  pub fn priority(&self) -> u8 {
+    self.priority
+  }
+
+  pub fn state(&self) -> PipeState {
+    self.state
+  }
+
+  /// 相手からの応答を待つ期限を参照します。
+  pub fn deadline(&self) -> Option<Instant> {
+    self.deadline
+  }
+
+  /// 相手からの応答を待つ期限を設定します。`None` を指定するとタイムアウトしません。
+  pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+    self.deadline = deadline;
+  }
+
+  /// まだクローズしていないこのパイプが指定された時刻に期限を過ぎているかを判定します。
+  pub fn is_expired(&self, now: Instant) -> bool {
+    self.state != PipeState::Closed
+      && self.deadline.map(|deadline| deadline <= now).unwrap_or(false)
+  }
+
+  /// 期限を過ぎたこのパイプをクローズし、相手の代わりにタイムアウトを示す失敗の Close を生成します。Close の
+  /// `result` には `Error::PipeTimeout` のエラーコード (2 バイト) とメッセージが格納されます。
+  pub fn expire(&mut self, timeout: Duration) -> Result<Close> {
+    if self.state == PipeState::Closed {
+      return Err(Error::PipeClosed { pipe_id: self.pipe_id });
+    }
+    let err = Error::PipeTimeout { pipe_id: self.pipe_id, timeout };
+    let close = failure_close(self.pipe_id, &err)?;
+    self.state = PipeState::Closed;
+    self.deadline = None;
+    Ok(close)
+  }
+
+  /// パイプが開かれたことを記録し、Block を受け付ける状態にします。
+  pub fn open(&mut self) -> Result<()> {
+    match self.state {
+      PipeState::Opening => {
+        self.state = PipeState::Open;
+        Ok(())
+      }
+      _ => Err(self.illegal("open")),
+    }
+  }
+
+  /// このパイプに対する Block を受け付けます。EOF を示す Block を受け付けるとパイプは `HalfClosed` となります。
+  pub fn accept_block(&mut self, block: &Block) -> Result<()> {
+    self.verify_pipe_id(block.pipe_id())?;
+    match self.state {
+      PipeState::Open => {
+        if block.is_eof() {
+          self.state = PipeState::HalfClosed;
+        }
+        Ok(())
+      }
+      PipeState::Opening => Err(self.illegal("block")),
+      PipeState::HalfClosed | PipeState::Closed => Err(Error::PipeClosed { pipe_id: self.pipe_id }),
+    }
+  }
+
+  /// このパイプに対する Close を受け付けます。
+  pub fn close(&mut self, close: &Close) -> Result<()> {
+    self.verify_pipe_id(close.pipe_id())?;
+    match self.state {
+      PipeState::Closed => Err(Error::PipeClosed { pipe_id: self.pipe_id }),
+      _ => {
+        self.state = PipeState::Closed;
+        Ok(())
+      }
+    }
+  }
+
+  fn verify_pipe_id(&self, pipe_id: u16) -> Result<()> {
+    if pipe_id == self.pipe_id {
+      Ok(())
+    } else {
+      Err(Error::PipeIdMismatch { expected: self.pipe_id, actual: pipe_id })
+    }
+  }
+
+  fn illegal(&self, operation: &str) -> Error {
+    if self.state == PipeState::Closed {
+      Error::PipeClosed { pipe_id: self.pipe_id }
+    } else {
+      Error::IllegalPipeTransition {
+        pipe_id: self.pipe_id,
+        state: self.state.to_string(),
+        operation: operation.to_string(),
+      }
+    }
+  }
+}
+
+/// セッションの端点がパイプを開くときに使用する `pipe_id` を割り当てます。
+///
+/// 両方の端点が同時にパイプを開いても ID が衝突しないように、接続を受け付けた側 (`Wire::is_server()` が true)
+/// は偶数、接続した側は奇数の ID を使用します。0 は Control メッセージのために予約されているため割り当てません。
+/// 解放された ID は、遅れて到着したメッセージや相手がまだクローズしていないパイプと新しいパイプを混同しないように、
+/// 一度も割り当てていない ID をすべて使い切るまで再利用せず、その後は解放された順に再利用されます。
+#[derive(Debug)]
+pub struct PipeIdAllocator {
+  is_server: bool,
+  /// まだ一度も割り当てていない次の ID。
+  next: u32,
+  released: VecDeque<u16>,
+  in_use: HashSet<u16>,
+}
+
+impl PipeIdAllocator {
+  /// 端点の役割を指定して構築します。
+  pub fn new(is_server: bool) -> PipeIdAllocator {
+    let next = if is_server { 2 } else { 1 };
+    PipeIdAllocator { is_server, next, released: VecDeque::new(), in_use: HashSet::new() }
+  }
+
+  /// この端点が割り当てることのできる ID の総数を参照します。
+  pub fn capacity(&self) -> usize {
+    // 偶数は 2～65,534、奇数は 1～65,535
+    if self.is_server {
+      0x7FFF
+    } else {
+      0x8000
+    }
+  }
+
+  /// 指定された ID がこの端点の割り当てる範囲 (接続を受け付けた側は偶数、接続した側は奇数) に属する場合に true を
+  /// 返します。
+  pub fn is_own(&self, pipe_id: u16) -> bool {
+    pipe_id.is_multiple_of(2) == self.is_server
+  }
+
+  /// 使用中の ID の数を参照します。
+  pub fn in_use(&self) -> usize {
+    self.in_use.len()
+  }
+
+  /// 新しい ID を割り当てます。一度も割り当てていない ID を優先し、それを使い切った後は最も古く解放された ID を
+  /// 割り当てます。すべての ID が使用中の場合はエラーとなります。
+  pub fn allocate(&mut self) -> Result<u16> {
+    let pipe_id = if self.next <= u16::MAX as u32 {
+      let pipe_id = self.next as u16;
+      self.next += 2;
+      pipe_id
+    } else if let Some(pipe_id) = self.released.pop_front() {
+      pipe_id
+    } else {
+      return Err(Error::PipeIdExhausted { capacity: self.capacity() });
+    };
+    self.in_use.insert(pipe_id);
+    Ok(pipe_id)
+  }
+
+  /// パイプのクローズによって使用しなくなった ID を解放します。この端点が割り当てていない ID や、すでに解放した ID
+  /// を指定した場合は何もせずに false を返します。
+  pub fn release(&mut self, pipe_id: u16) -> bool {
+    if self.in_use.remove(&pipe_id) {
+      self.released.push_back(pipe_id);
+      true
+    } else {
+      false
+    }
+  }
+}
+
+/// 指定されたエラーを理由とする失敗の Close を構築します。`result` にはエラーコード (2 バイト) とメッセージが
+/// 格納されます。
+pub(crate) fn failure_close(pipe_id: u16, err: &Error) -> Result<Close> {
+  let mut result = err.code().to_be_bytes().to_vec();
+  result.extend_from_slice(err.to_string().as_bytes());
+  Close::failure(pipe_id, result)
+}
+
+/// パイプで受信した Block のペイロードを連結したバイトストリームとして読み出す `Read` の実装です。
+///
+/// Block はチャネルから順に取り出され、すべてのデータをバッファリングすることなく逐次読み出すことができます。EOF を
+/// 示す Block のペイロードを読み終えると 0 を返します。EOF の Block を受信する前にチャネルが切断された場合は
+/// `ErrorKind::UnexpectedEof` のエラーとなります。
+pub struct BlockReader {
+  receiver: Receiver<Block>,
+  /// 読み出し中のブロックのペイロード。
+  payload: Arc<[u8]>,
+  /// `payload` の中で次に読み出す位置。
+  position: usize,
+  /// EOF を示す Block を受信している場合に true。
+  eof: bool,
+}
+
+impl BlockReader {
+  /// 指定されたチャネルから Block を取り出す Reader を構築します。
+  pub fn new(receiver: Receiver<Block>) -> BlockReader {
+    BlockReader { receiver, payload: Arc::from(Vec::new()), position: 0, eof: false }
+  }
+}
+
+impl Read for BlockReader {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    while self.position == self.payload.len() {
+      if self.eof || buf.is_empty() {
+        return Ok(0);
+      }
+      let block = self.receiver.recv().map_err(|_| {
+        std::io::Error::new(ErrorKind::UnexpectedEof, "the pipe was disconnected before EOF")
+      })?;
+      self.payload = block.payload_arc();
+      self.position = 0;
+      self.eof = block.is_eof();
+    }
+    let length = std::cmp::min(buf.len(), self.payload.len() - self.position);
+    buf[..length].copy_from_slice(&self.payload[self.position..self.position + length]);
+    self.position += length;
+    Ok(length)
+  }
+}
+
+/// `Write` で書き込まれたバイト列を Block に分割してパイプに送信する `BlockReader` と対になる実装です。
+///
+/// 書き込まれたデータは `MAX_PAYLOAD_SIZE` までバッファリングされ、バッファが満たされると Block としてチャネルに
+/// 送信されます。`flush()` はバッファに残っているデータを EOF を示す Block として送信し、ストリームを終了します。
+/// それ以降の書き込みは `ErrorKind::BrokenPipe` のエラーとなります。`flush()` せずにドロップした場合も EOF の
+/// Block が送信されます。
+pub struct BlockWriter {
+  pipe_id: u16,
+  sender: Sender<Block>,
+  /// まだ送信していないペイロード。
+  buffer: Vec<u8>,
+  /// EOF を示す Block を送信している場合に true。
+  closed: bool,
+}
+
+impl BlockWriter {
+  /// 指定されたパイプ ID の Block をチャネルに送信する Writer を構築します。パイプ ID が 0 の場合はエラーとなり
+  /// ます。
+  pub fn new(pipe_id: u16, sender: Sender<Block>) -> Result<BlockWriter> {
+    if pipe_id == CONTROL_PIPE_ID {
+      return Err(Error::ZeroPipeId);
+    }
+    Ok(BlockWriter { pipe_id, sender, buffer: Vec::new(), closed: false })
+  }
+
+  fn emit(&mut self, eof: bool) -> std::io::Result<()> {
+    let payload = std::mem::take(&mut self.buffer);
+    let block = Block::new(self.pipe_id, eof, 0, payload)
+      .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err.to_string()))?;
+    self.sender.send(block).map_err(|_| broken_pipe(self.pipe_id))
+  }
+}
+
+impl Write for BlockWriter {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    if self.closed {
+      return Err(broken_pipe(self.pipe_id));
+    } else if buf.is_empty() {
+      return Ok(0);
+    }
+    // 最後の Block に EOF を設定できるように、満たされたバッファは次のデータが書き込まれたときに送信する
+    if self.buffer.len() == MAX_PAYLOAD_SIZE {
+      self.emit(false)?;
+    }
+    let length = std::cmp::min(buf.len(), MAX_PAYLOAD_SIZE - self.buffer.len());
+    self.buffer.extend_from_slice(&buf[..length]);
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    if !self.closed {
+      self.closed = true;
+      self.emit(true)?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for BlockWriter {
+  fn drop(&mut self) {
+    if let Err(err) = self.flush() {
+      log::debug!("failed to send EOF to the pipe {}: {}", self.pipe_id, err);
+    }
+  }
+}
+
+fn broken_pipe(pipe_id: u16) -> std::io::Error {
+  std::io::Error::new(
+    ErrorKind::BrokenPipe,
+    format!("the pipe {} has already been closed", pipe_id),
+  )
+}
@@ -0,0 +1,242 @@
+use std::io::{ErrorKind, Read, Write};
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::msg::{Block, Close, Open, MAX_PAYLOAD_SIZE};
+use crate::pipe::{BlockReader, BlockWriter, Pipe, PipeIdAllocator, PipeState};
+
+fn block(pipe_id: u16, eof: bool) -> Block {
+  Block::new(pipe_id, eof, 0, vec![1, 2, 3]).unwrap()
+}
+
+#[test]
+fn test_pipe_lifecycle() {
+  let mut pipe = Pipe::new(&Open::new(1, 2, 3, vec![]).unwrap());
+  assert_eq!((1, 3, PipeState::Opening), (pipe.pipe_id(), pipe.priority(), pipe.state()));
+
+  // Open → Block → EOF → Close の順に遷移する
+  pipe.open().unwrap();
+  assert_eq!(PipeState::Open, pipe.state());
+  for _ in 0..3 {
+    pipe.accept_block(&block(1, false)).unwrap();
+    assert_eq!(PipeState::Open, pipe.state());
+  }
+  pipe.accept_block(&block(1, true)).unwrap();
+  assert_eq!(PipeState::HalfClosed, pipe.state());
+  pipe.close(&Close::success(1, vec![]).unwrap()).unwrap();
+  assert_eq!(PipeState::Closed, pipe.state());
+
+  // 失敗を通知する Close はどの状態からでも受け付ける
+  let mut pipe = Pipe::new(&Open::new(1, 2, 3, vec![]).unwrap());
+  pipe.close(&Close::failure(1, vec![]).unwrap()).unwrap();
+  assert_eq!(PipeState::Closed, pipe.state());
+  let mut pipe = Pipe::new(&Open::new(1, 2, 3, vec![]).unwrap());
+  pipe.open().unwrap();
+  pipe.close(&Close::failure(1, vec![]).unwrap()).unwrap();
+  assert_eq!(PipeState::Closed, pipe.state());
+}
+
+#[test]
+fn test_pipe_illegal_transitions() {
+  let mut pipe = Pipe::new(&Open::new(1, 2, 3, vec![]).unwrap());
+
+  // 開かれる前の Block は受け付けない
+  match pipe.accept_block(&block(1, false)).unwrap_err() {
+    Error::IllegalPipeTransition { pipe_id, state, operation } => {
+      assert_eq!((1, "opening", "block"), (pipe_id, state.as_str(), operation.as_str()));
+    }
+    unexpected => panic!("unexpected error: {:?}", unexpected),
+  }
+  assert_eq!(PipeState::Opening, pipe.state());
+
+  // 二重に開くことはできない
+  pipe.open().unwrap();
+  assert!(matches!(pipe.open().unwrap_err(), Error::IllegalPipeTransition { .. }));
+
+  // 異なるパイプのメッセージは受け付けない
+  assert_eq!(
+    Error::PipeIdMismatch { expected: 1, actual: 2 },
+    pipe.accept_block(&block(2, false)).unwrap_err()
+  );
+  assert_eq!(
+    Error::PipeIdMismatch { expected: 1, actual: 2 },
+    pipe.close(&Close::success(2, vec![]).unwrap()).unwrap_err()
+  );
+  assert_eq!(PipeState::Open, pipe.state());
+
+  // EOF 以降の Block は受け付けない
+  pipe.accept_block(&block(1, true)).unwrap();
+  assert_eq!(Error::PipeClosed { pipe_id: 1 }, pipe.accept_block(&block(1, false)).unwrap_err());
+
+  // クローズ後のメッセージは受け付けない
+  pipe.close(&Close::success(1, vec![]).unwrap()).unwrap();
+  assert_eq!(Error::PipeClosed { pipe_id: 1 }, pipe.open().unwrap_err());
+  assert_eq!(Error::PipeClosed { pipe_id: 1 }, pipe.accept_block(&block(1, true)).unwrap_err());
+  assert_eq!(
+    Error::PipeClosed { pipe_id: 1 },
+    pipe.close(&Close::success(1, vec![]).unwrap()).unwrap_err()
+  );
+  assert_eq!(PipeState::Closed, pipe.state());
+}
+
+#[test]
+fn test_pipe_id_allocator_parity() {
+  // 接続を受け付けた側は偶数、接続した側は奇数の 0 以外の ID を割り当てる
+  for (is_server, parity) in [(true, 0u16), (false, 1u16)].iter() {
+    let mut allocator = PipeIdAllocator::new(*is_server);
+    let mut previous = 0u16;
+    for _ in 0..allocator.capacity() {
+      let pipe_id = allocator.allocate().unwrap();
+      assert_ne!(0, pipe_id);
+      assert_eq!(*parity, pipe_id % 2);
+      assert!(allocator.is_own(pipe_id));
+      assert!(!allocator.is_own(pipe_id ^ 1));
+      assert!(pipe_id > previous);
+      previous = pipe_id;
+    }
+    assert_eq!(allocator.capacity(), allocator.in_use());
+
+    // すべての ID を使い切るとエラーとなる
+    assert_eq!(
+      Error::PipeIdExhausted { capacity: allocator.capacity() },
+      allocator.allocate().unwrap_err()
+    );
+  }
+  assert_eq!(
+    u16::MAX as usize,
+    PipeIdAllocator::new(true).capacity() + PipeIdAllocator::new(false).capacity()
+  );
+}
+
+#[test]
+fn test_pipe_id_allocator_recycling() {
+  for is_server in [true, false].iter() {
+    let mut allocator = PipeIdAllocator::new(*is_server);
+    let ids = (0..4).map(|_| allocator.allocate().unwrap()).collect::<Vec<_>>();
+
+    // 割り当てていない ID や解放済みの ID は解放できない
+    assert!(!allocator.release(0));
+    assert!(!allocator.release(ids[3] + 2));
+    assert!(!allocator.release(ids[0] + 1));
+
+    // 解放した ID は未使用の ID が残っている間は再利用されない
+    assert!(allocator.release(ids[2]));
+    assert!(allocator.release(ids[0]));
+    assert!(!allocator.release(ids[0]));
+    assert_eq!(2, allocator.in_use());
+    let mut last = ids[3];
+    for _ in 4..allocator.capacity() {
+      let pipe_id = allocator.allocate().unwrap();
+      assert_ne!(ids[0], pipe_id);
+      assert_ne!(ids[2], pipe_id);
+      assert!(pipe_id > last);
+      last = pipe_id;
+    }
+    assert_eq!(allocator.capacity() - 2, allocator.in_use());
+
+    // 未使用の ID を使い切った後は解放した順に再利用される
+    assert_eq!(ids[2], allocator.allocate().unwrap());
+    assert_eq!(ids[0], allocator.allocate().unwrap());
+    assert_eq!(allocator.capacity(), allocator.in_use());
+    assert_eq!(
+      Error::PipeIdExhausted { capacity: allocator.capacity() },
+      allocator.allocate().unwrap_err()
+    );
+  }
+}
+
+#[test]
+fn test_pipe_expire() {
+  let mut pipe = Pipe::new(&Open::new(1, 2, 3, vec![]).unwrap());
+  let now = Instant::now();
+  assert!(!pipe.is_expired(now + Duration::from_secs(3600)));
+
+  // 期限を過ぎたパイプは失敗の Close でクローズされる
+  pipe.set_deadline(Some(now + Duration::from_secs(1)));
+  assert!(!pipe.is_expired(now));
+  assert!(pipe.is_expired(now + Duration::from_secs(1)));
+  let close = pipe.expire(Duration::from_secs(1)).unwrap();
+  assert_eq!((1, false), (close.pipe_id(), close.is_success()));
+  assert_eq!(&604u16.to_be_bytes(), &close.result()[..2]);
+  assert_eq!(PipeState::Closed, pipe.state());
+  assert!(!pipe.is_expired(now + Duration::from_secs(2)));
+  assert_eq!(Error::PipeClosed { pipe_id: 1 }, pipe.expire(Duration::from_secs(1)).unwrap_err());
+}
+
+#[test]
+fn test_block_reader() {
+  // 複数の Block に分割したデータを io::copy で元のバイト列として読み出せる
+  let data = (0..MAX_PAYLOAD_SIZE * 3 + 100).map(|i| i as u8).collect::<Vec<_>>();
+  let (sender, receiver) = channel();
+  let blocks = Block::split(1, 0, &data).unwrap();
+  assert_eq!(4, blocks.len());
+  let producer = std::thread::spawn(move || {
+    for block in blocks {
+      sender.send(block).unwrap();
+    }
+  });
+  let mut reader = BlockReader::new(receiver);
+  let mut copied = Vec::new();
+  assert_eq!(data.len() as u64, std::io::copy(&mut reader, &mut copied).unwrap());
+  assert_eq!(data, copied);
+  assert_eq!(0, reader.read(&mut [0u8; 16]).unwrap());
+  producer.join().unwrap();
+
+  // 空のペイロードを持つ Block は読み飛ばされる
+  let (sender, receiver) = channel();
+  sender.send(Block::new(1, false, 0, vec![]).unwrap()).unwrap();
+  sender.send(Block::new(1, false, 0, vec![1, 2]).unwrap()).unwrap();
+  sender.send(Block::new(1, true, 0, vec![3]).unwrap()).unwrap();
+  let mut bytes = Vec::new();
+  BlockReader::new(receiver).read_to_end(&mut bytes).unwrap();
+  assert_eq!(vec![1, 2, 3], bytes);
+
+  // EOF の前にチャネルが切断されるとエラーとなる
+  let (sender, receiver) = channel();
+  sender.send(Block::new(1, false, 0, vec![1, 2]).unwrap()).unwrap();
+  drop(sender);
+  let mut bytes = Vec::new();
+  let err = BlockReader::new(receiver).read_to_end(&mut bytes).unwrap_err();
+  assert_eq!(ErrorKind::UnexpectedEof, err.kind());
+  assert_eq!(vec![1, 2], bytes);
+}
+
+#[test]
+fn test_block_writer() {
+  // 300KB のデータを io::copy で書き込むと MAX_PAYLOAD_SIZE 以下の Block に分割され、最後の Block のみが EOF となる
+  let data = (0..300 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+  let (sender, receiver) = channel();
+  let mut writer = BlockWriter::new(1, sender).unwrap();
+  assert_eq!(data.len() as u64, std::io::copy(&mut data.as_slice(), &mut writer).unwrap());
+  writer.flush().unwrap();
+  let blocks = receiver.try_iter().collect::<Vec<_>>();
+  assert_eq!(data.len().div_ceil(MAX_PAYLOAD_SIZE), blocks.len());
+  for (i, block) in blocks.iter().enumerate() {
+    assert_eq!(1, block.pipe_id());
+    assert_eq!(i + 1 == blocks.len(), block.is_eof());
+    assert!(block.payload().len() <= MAX_PAYLOAD_SIZE);
+  }
+  let (sender, receiver) = channel();
+  blocks.into_iter().for_each(|block| sender.send(block).unwrap());
+  let mut reassembled = Vec::new();
+  BlockReader::new(receiver).read_to_end(&mut reassembled).unwrap();
+  assert_eq!(data, reassembled);
+
+  // EOF を送信した後の書き込みはエラーとなる
+  assert_eq!(ErrorKind::BrokenPipe, writer.write(&[1]).unwrap_err().kind());
+  writer.flush().unwrap();
+
+  // flush せずにドロップした場合も EOF の Block が送信される
+  let (sender, receiver) = channel();
+  let mut writer = BlockWriter::new(2, sender).unwrap();
+  write!(writer, "hello").unwrap();
+  drop(writer);
+  let blocks = receiver.try_iter().collect::<Vec<_>>();
+  assert_eq!(1, blocks.len());
+  assert!(blocks[0].is_eof());
+  assert_eq!(b"hello", blocks[0].payload());
+
+  // パイプ ID 0 は使用できない
+  assert!(matches!(BlockWriter::new(0, channel().0), Err(Error::ZeroPipeId)));
+}
@@ -0,0 +1,551 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use log;
+use uuid::Uuid;
+
+use crate::bridge::Wire;
+use crate::error::Error;
+use crate::msg::{
+  is_compatible_version, Close, Control, Message, Open, SystemConfigBuilder, DEFAULT_PING_INTERVAL,
+  DEFAULT_SESSION_TIMEOUT, PROTOCOL_VERSION,
+};
+use crate::pipe::{failure_close, Pipe, PipeIdAllocator};
+use crate::Result;
+
+#[cfg(test)]
+mod test;
+
+/// ハンドシェイクで相手の応答を待つデフォルトの最大時間です。
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ノードを識別する ID です。`Control::SystemConfig` で相手に通知され、セッションはノードが再起動しても同じ ID
+/// を持つことを前提としています。`load_or_create()` を使用することで ID をファイルに保存し、再起動後も同じ ID を
+/// 使用することができます。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(Uuid);
+
+impl NodeId {
+  /// ランダムな (バージョン 4 の) UUID による新しい ID を生成します。
+  pub fn generate() -> NodeId {
+    NodeId(Uuid::new_v4())
+  }
+
+  /// 指定されたファイルに保存されている ID を読み込みます。ファイルが存在しない場合は新しい ID を生成してファイルに
+  /// 保存します。ファイルの内容が UUID として解釈できない場合は `ErrorKind::InvalidData` のエラーとなります。
+  pub fn load_or_create<P: AsRef<Path>>(path: P) -> Result<NodeId> {
+    let path = path.as_ref();
+    match std::fs::read_to_string(path) {
+      Ok(text) => return NodeId::parse(path, &text),
+      Err(err) if err.kind() == ErrorKind::NotFound => (),
+      Err(err) => return Err(From::from(err)),
+    }
+    let node_id = NodeId::generate();
+    match std::fs::OpenOptions::new().write(true).create_new(true).open(path) {
+      Ok(mut file) => {
+        writeln!(file, "{}", node_id.0.to_hyphenated())?;
+        file.sync_all()?;
+        log::info!("new node-id {} was saved to {}", node_id, path.display());
+        Ok(node_id)
+      }
+      // 同時に起動した他のプロセスが先に保存した
+      Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+        NodeId::parse(path, &std::fs::read_to_string(path)?)
+      }
+      Err(err) => Err(From::from(err)),
+    }
+  }
+
+  fn parse(path: &Path, text: &str) -> Result<NodeId> {
+    match Uuid::parse_str(text.trim()) {
+      Ok(uuid) if !uuid.is_nil() => Ok(NodeId(uuid)),
+      _ => {
+        let message = format!("{} doesn't contain a valid node-id: {:?}", path.display(), text);
+        Err(From::from(std::io::Error::new(ErrorKind::InvalidData, message)))
+      }
+    }
+  }
+
+  /// この ID の UUID を参照します。
+  pub fn as_uuid(&self) -> Uuid {
+    self.0
+  }
+}
+
+impl From<Uuid> for NodeId {
+  fn from(uuid: Uuid) -> Self {
+    NodeId(uuid)
+  }
+}
+
+impl From<NodeId> for Uuid {
+  fn from(node_id: NodeId) -> Self {
+    node_id.0
+  }
+}
+
+impl Display for NodeId {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    self.0.fmt(f)
+  }
+}
+
+/// ハンドシェイクによって合意したセッションのパラメータです。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionConfig {
+  /// 相手のノード ID。
+  pub node_id: NodeId,
+  /// サーバが割り当てたセッション ID。
+  pub session_id: Uuid,
+  /// サーバが指定した ping 間隔 (秒)。
+  pub ping_interval: u32,
+  /// サーバが指定したセッションタイムアウト (秒)。
+  pub session_timeout: u32,
+}
+
+/// 確立したセッションを表し、Wire と合意したパラメータを保持します。メッセージングを行うアプリケーションはこの
+/// オブジェクトを通してパイプを開きます。
+///
+/// このセッションが開いたパイプは、相手からの応答がないままセッションタイムアウトを過ぎると `expire_pipes()` に
+/// よってタイムアウトを示す失敗の Close でクローズされ、その ID は解放されます。
+///
+/// 相手が開いたパイプは `accept_pipe()` で受け付けます。`set_max_open_pipes()` で上限を設定した場合、上限を超える
+/// Open はリソース不足を示す失敗の Close で拒否されます。
+pub struct Session<W: Wire> {
+  wire: W,
+  config: SessionConfig,
+  pipe_ids: PipeIdAllocator,
+  /// このセッションが開き、まだクローズしていないパイプ。
+  pipes: HashMap<u16, Pipe>,
+  /// パイプが相手からの応答を待つ最大時間。`None` の場合はタイムアウトしない。
+  pipe_timeout: Option<Duration>,
+  /// 相手が開き、このセッションが受け付けてまだクローズしていないパイプ。
+  accepted: HashSet<u16>,
+  /// 相手が同時に開くことのできるパイプの上限。`None` の場合は制限しない。
+  max_open_pipes: Option<usize>,
+}
+
+impl<W: Wire> Session<W> {
+  /// ハンドシェイクを完了した Wire と合意したパラメータからセッションを構築します。パイプのタイムアウトには
+  /// セッションタイムアウトが使用されます。
+  pub fn new(wire: W, config: SessionConfig) -> Session<W> {
+    let pipe_ids = PipeIdAllocator::new(wire.is_server());
+    let pipe_timeout = match config.session_timeout {
+      0 => None,
+      timeout => Some(Duration::from_secs(u64::from(timeout))),
+    };
+    Session {
+      wire,
+      config,
+      pipe_ids,
+      pipes: HashMap::new(),
+      pipe_timeout,
+      accepted: HashSet::new(),
+      max_open_pipes: None,
+    }
+  }
+
+  /// ハンドシェイクで合意したパラメータを参照します。
+  pub fn config(&self) -> &SessionConfig {
+    &self.config
+  }
+
+  /// 相手のノード ID を参照します。
+  pub fn node_id(&self) -> NodeId {
+    self.config.node_id
+  }
+
+  /// このセッションの ID を参照します。
+  pub fn session_id(&self) -> Uuid {
+    self.config.session_id
+  }
+
+  /// ping 間隔 (秒) を参照します。
+  pub fn ping_interval(&self) -> u32 {
+    self.config.ping_interval
+  }
+
+  /// セッションタイムアウト (秒) を参照します。
+  pub fn session_timeout(&self) -> u32 {
+    self.config.session_timeout
+  }
+
+  /// パイプが相手からの応答を待つ最大時間を参照します。
+  pub fn pipe_timeout(&self) -> Option<Duration> {
+    self.pipe_timeout
+  }
+
+  /// パイプが相手からの応答を待つ最大時間を設定します。`None` を指定するとタイムアウトしません。この設定はこれ以降
+  /// に開くパイプと、応答によって期限が延長されるパイプに適用されます。
+  pub fn set_pipe_timeout(&mut self, timeout: Option<Duration>) {
+    self.pipe_timeout = timeout;
+  }
+
+  /// このセッションが開き、まだクローズしていないパイプの数を参照します。
+  pub fn open_pipes(&self) -> usize {
+    self.pipes.len()
+  }
+
+  /// 相手が同時に開くことのできるパイプの上限を参照します。
+  pub fn max_open_pipes(&self) -> Option<usize> {
+    self.max_open_pipes
+  }
+
+  /// 相手が同時に開くことのできるパイプの上限を設定します。`None` を指定すると制限しません。すでに受け付けている
+  /// パイプには影響しません。
+  pub fn set_max_open_pipes(&mut self, max_open_pipes: Option<usize>) {
+    self.max_open_pipes = max_open_pipes;
+  }
+
+  /// 相手が開き、このセッションが受け付けてまだクローズしていないパイプの数を参照します。
+  pub fn accepted_pipes(&self) -> usize {
+    self.accepted.len()
+  }
+
+  /// このセッションが使用している Wire を参照します。
+  pub fn wire(&mut self) -> &mut W {
+    &mut self.wire
+  }
+
+  /// 新しいパイプ ID を割り当てて、指定されたファンクションを呼び出す Open を送信します。送信に失敗した場合、
+  /// 割り当てた ID は解放されます。
+  pub fn open_pipe(&mut self, function_id: u16, priority: u8, params: Vec<u8>) -> Result<u16> {
+    let pipe_id = self.pipe_ids.allocate()?;
+    let result = Open::new(pipe_id, function_id, priority, params).and_then(|open| {
+      let pipe = Pipe::new(&open);
+      self.wire.send(Message::Open(open)).map(|()| pipe)
+    });
+    match result {
+      Ok(mut pipe) => {
+        pipe.set_deadline(self.pipe_timeout.map(|timeout| Instant::now() + timeout));
+        self.pipes.insert(pipe_id, pipe);
+        Ok(pipe_id)
+      }
+      Err(err) => {
+        self.pipe_ids.release(pipe_id);
+        Err(err)
+      }
+    }
+  }
+
+  /// 指定されたパイプで相手からの応答を受信したことを記録し、そのパイプの期限を延長します。このセッションが開いて
+  /// いないパイプの場合は何もせずに false を返します。
+  pub fn touch_pipe(&mut self, pipe_id: u16) -> bool {
+    match self.pipes.get_mut(&pipe_id) {
+      Some(pipe) => {
+        pipe.set_deadline(self.pipe_timeout.map(|timeout| Instant::now() + timeout));
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// 相手から受信した Close でこのセッションが開いたパイプをクローズし、その ID を解放します。
+  pub fn close_pipe(&mut self, close: &Close) -> Result<()> {
+    let pipe_id = close.pipe_id();
+    let mut pipe = self.pipes.remove(&pipe_id).ok_or(Error::PipeClosed { pipe_id })?;
+    pipe.close(close)?;
+    self.pipe_ids.release(pipe_id);
+    Ok(())
+  }
+
+  /// 相手から受信した Open のパイプを受け付けます。パイプ ID がこのセッションの割り当てる範囲に属する場合は
+  /// `Error::ForeignPipeId`、受け付けているパイプの数が上限に達している場合は `Error::TooManyPipes` のエラーコードを
+  /// 理由とする失敗の Close を相手に送信し、false を返します。
+  pub fn accept_pipe(&mut self, open: &Open) -> Result<bool> {
+    let pipe_id = open.pipe_id();
+    if self.pipe_ids.is_own(pipe_id) {
+      log::warn!("pipe {} was refused: the id belongs to this end", pipe_id);
+      let close = failure_close(pipe_id, &Error::ForeignPipeId { pipe_id })?;
+      self.wire.send(Message::Close(close))?;
+      return Ok(false);
+    }
+    if self.accepted.contains(&pipe_id) {
+      return Err(Error::IllegalPipeTransition {
+        pipe_id,
+        state: "open".to_string(),
+        operation: "open".to_string(),
+      });
+    }
+    if let Some(maximum) = self.max_open_pipes {
+      if self.accepted.len() >= maximum {
+        log::warn!("pipe {} was refused: {} pipes are already open", pipe_id, maximum);
+        let close = failure_close(pipe_id, &Error::TooManyPipes { maximum })?;
+        self.wire.send(Message::Close(close))?;
+        return Ok(false);
+      }
+    }
+    self.accepted.insert(pipe_id);
+    Ok(true)
+  }
+
+  /// 受け付けたパイプに対する Close を相手に送信し、そのパイプをクローズします。
+  pub fn close_accepted_pipe(&mut self, close: Close) -> Result<()> {
+    let pipe_id = close.pipe_id();
+    if !self.accepted.contains(&pipe_id) {
+      return Err(Error::PipeClosed { pipe_id });
+    }
+    self.wire.send(Message::Close(close))?;
+    self.accepted.remove(&pipe_id);
+    Ok(())
+  }
+
+  /// 指定された時刻に期限を過ぎているパイプを破棄し、相手の代わりにタイムアウトを示す失敗の Close を生成して返し
+  /// ます。破棄したパイプの ID は解放されますが、相手が遅れてそのパイプにメッセージを送っても新しいパイプと混同しない
+  /// ように、未使用の ID が残っている間は再利用されません。
+  pub fn expire_pipes(&mut self, now: Instant) -> Result<Vec<Close>> {
+    let mut expired = self
+      .pipes
+      .values()
+      .filter(|pipe| pipe.is_expired(now))
+      .map(Pipe::pipe_id)
+      .collect::<Vec<_>>();
+    expired.sort_unstable();
+    let timeout = self.pipe_timeout.unwrap_or_default();
+    let mut closes = Vec::with_capacity(expired.len());
+    for pipe_id in expired {
+      if let Some(mut pipe) = self.pipes.remove(&pipe_id) {
+        closes.push(pipe.expire(timeout)?);
+        self.pipe_ids.release(pipe_id);
+        log::debug!("pipe {} timed out after {:?}", pipe_id, timeout);
+      }
+    }
+    Ok(closes)
+  }
+}
+
+/// `Control::SystemConfig` を交換してセッションを確立するハンドシェイクです。
+///
+/// クライアントは Zero のセッション ID を持つ System Config を送信し、サーバは新しいセッション ID と自身の ping 間隔
+/// およびセッションタイムアウトを格納した System Config で応答します。セッションのパラメータはサーバの応答で決定
+/// します。互換性のないバージョンを受信したサーバは、そのエラーコードを理由とする `Control::Close` を送信して
+/// ハンドシェイクを中止します。
+pub struct Handshake {
+  version: u16,
+  node_id: NodeId,
+  ping_interval: u32,
+  session_timeout: u32,
+  timeout: Duration,
+}
+
+impl Handshake {
+  /// 指定されたノード ID で System Config を送信するハンドシェイクを作成します。
+  pub fn new(node_id: NodeId) -> Handshake {
+    Handshake {
+      version: PROTOCOL_VERSION,
+      node_id,
+      ping_interval: DEFAULT_PING_INTERVAL,
+      session_timeout: DEFAULT_SESSION_TIMEOUT,
+      timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+    }
+  }
+
+  /// 送信するプロトコルのバージョンを設定します。
+  pub fn version(mut self, version: u16) -> Self {
+    self.version = version;
+    self
+  }
+
+  /// サーバとして指定する ping 間隔 (秒) を設定します。
+  pub fn ping_interval(mut self, ping_interval: u32) -> Self {
+    self.ping_interval = ping_interval;
+    self
+  }
+
+  /// サーバとして指定するセッションタイムアウト (秒) を設定します。
+  pub fn session_timeout(mut self, session_timeout: u32) -> Self {
+    self.session_timeout = session_timeout;
+    self
+  }
+
+  /// 相手の System Config を待つ最大時間を設定します。
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// クライアントとしてハンドシェイクを行います。System Config を送信し、サーバの応答を受信するまで呼び出し元の
+  /// スレッドはブロックします。
+  pub fn begin_client<W: Wire>(&self, wire: &mut W) -> Result<SessionConfig> {
+    wire.send(Message::Control(self.system_config(wire, Uuid::nil())?))?;
+    let (version, node_id, session_id, ping_interval, session_timeout) = self.receive(wire)?;
+    if !is_compatible_version(version) {
+      return Err(Error::IncompatibleVersion { version });
+    }
+    if session_id.is_nil() {
+      let message = "the server didn't assign a session-id".to_string();
+      return Err(Error::IllegalHandshake { message });
+    }
+    Ok(SessionConfig { node_id, session_id, ping_interval, session_timeout })
+  }
+
+  /// サーバとしてハンドシェイクを行います。クライアントの System Config を受信するまで呼び出し元のスレッドは
+  /// ブロックします。クライアントが 0 でないセッション ID を送信した場合は `Error::NonZeroClientSessionId` で
+  /// ハンドシェイクを拒否します。
+  pub fn begin_server<W: Wire>(&self, wire: &mut W) -> Result<SessionConfig> {
+    let (version, node_id, client_session_id, _, _) = self.receive(wire)?;
+    let rejection = if !is_compatible_version(version) {
+      Some(Error::IncompatibleVersion { version })
+    } else if !client_session_id.is_nil() {
+      Some(Error::NonZeroClientSessionId { session_id: client_session_id })
+    } else {
+      None
+    };
+    if let Some(err) = rejection {
+      let close = Control::new_close(err.code(), err.to_string().into_bytes())?;
+      if let Err(err) = wire.send(Message::Control(close)) {
+        log::warn!("failed to reject the handshake: {}", err);
+      }
+      return Err(err);
+    }
+    let session_id = Uuid::new_v4();
+    wire.send(Message::Control(self.system_config(wire, session_id)?))?;
+    let (ping_interval, session_timeout) = (self.ping_interval, self.session_timeout);
+    Ok(SessionConfig { node_id, session_id, ping_interval, session_timeout })
+  }
+
+  /// `wire` で送信する System Config を構築します。クライアント側の Wire では 0 以外のセッション ID を指定することは
+  /// できません。
+  fn system_config<W: Wire>(&self, wire: &W, session_id: Uuid) -> Result<Control> {
+    if !wire.is_server() && !session_id.is_nil() {
+      return Err(Error::NonZeroClientSessionId { session_id });
+    }
+    SystemConfigBuilder::new(self.node_id.as_uuid())
+      .version(self.version)
+      .session_id(session_id)
+      .ping_interval(self.ping_interval)
+      .session_timeout(self.session_timeout)
+      .build()
+  }
+
+  /// 相手の System Config を受信し、その version, node_id, session_id, ping_interval, session_timeout を返します。
+  fn receive<W: Wire>(&self, wire: &mut W) -> Result<(u16, NodeId, Uuid, u32, u32)> {
+    let deadline = Instant::now() + self.timeout;
+    let msg = loop {
+      if let Some(msg) = wire.try_recv()? {
+        break msg;
+      }
+      if Instant::now() >= deadline {
+        return Err(From::from(std::io::Error::from(std::io::ErrorKind::TimedOut)));
+      }
+      sleep(Duration::from_millis(1));
+    };
+    match msg {
+      Message::Control(Control::SystemConfig {
+        version,
+        node_id,
+        session_id,
+        ping_interval,
+        session_timeout,
+        ..
+      }) => Ok((version, NodeId::from(node_id), session_id, ping_interval, session_timeout)),
+      Message::Control(Control::Close { reason_code, .. }) => {
+        let message = format!("the peer rejected the handshake with code {}", reason_code);
+        Err(Error::IllegalHandshake { message })
+      }
+      msg => Err(Error::UnexpectedMessage { expected: "SystemConfig", got: msg.type_name() }),
+    }
+  }
+}
+
+/// 多数のクライアントを扱うサーバで、確立したセッションを相手のアドレスとノード ID の両方から参照するための
+/// テーブルです。内部でロックを行うため、複数のハンドラースレッドから共有して使用することができます。
+///
+/// 1 つのアドレスと 1 つのノード ID はそれぞれ高々 1 つのセッションに対応します。同じノードが別のアドレスから
+/// 再接続した場合などは、後から登録したセッションが以前のものを置き換えます。
+pub struct SessionRegistry<T: Clone> {
+  entries: Mutex<RegistryEntries<T>>,
+}
+
+struct RegistryEntries<T> {
+  by_addr: HashMap<SocketAddr, (NodeId, T)>,
+  by_node: HashMap<NodeId, SocketAddr>,
+}
+
+impl<T: Clone> SessionRegistry<T> {
+  /// 空のテーブルを構築します。
+  pub fn new() -> SessionRegistry<T> {
+    let entries = RegistryEntries { by_addr: HashMap::new(), by_node: HashMap::new() };
+    SessionRegistry { entries: Mutex::new(entries) }
+  }
+
+  /// 相手のアドレスとノード ID に対応付けてセッションを登録します。同じアドレスまたは同じノード ID で登録されて
+  /// いたセッションは削除され、その値が返されます。
+  pub fn insert(&self, address: SocketAddr, node_id: NodeId, session: T) -> Result<Vec<T>> {
+    let mut entries = self.entries.lock()?;
+    let mut replaced = Vec::new();
+    if let Some((_, session)) = entries.remove(&address) {
+      replaced.push(session);
+    }
+    if let Some(address) = entries.by_node.get(&node_id).copied() {
+      if let Some((_, session)) = entries.remove(&address) {
+        replaced.push(session);
+      }
+    }
+    entries.by_addr.insert(address, (node_id, session));
+    entries.by_node.insert(node_id, address);
+    Ok(replaced)
+  }
+
+  /// 指定された相手のアドレスで登録されているセッションを参照します。
+  pub fn get_by_addr(&self, address: &SocketAddr) -> Result<Option<T>> {
+    let entries = self.entries.lock()?;
+    Ok(entries.by_addr.get(address).map(|(_, session)| session.clone()))
+  }
+
+  /// 指定された相手のノード ID で登録されているセッションを参照します。
+  pub fn get_by_node(&self, node_id: &NodeId) -> Result<Option<T>> {
+    let entries = self.entries.lock()?;
+    let session = entries.by_node.get(node_id).and_then(|address| entries.by_addr.get(address));
+    Ok(session.map(|(_, session)| session.clone()))
+  }
+
+  /// 指定された相手のアドレスで登録されているセッションを両方のキーから削除し、その値を返します。
+  pub fn remove(&self, address: &SocketAddr) -> Result<Option<T>> {
+    Ok(self.entries.lock()?.remove(address).map(|(_, session)| session))
+  }
+
+  /// 登録されているセッションの数を参照します。
+  pub fn len(&self) -> Result<usize> {
+    Ok(self.entries.lock()?.by_addr.len())
+  }
+
+  /// 登録されているセッションが存在しない場合に true を返します。
+  pub fn is_empty(&self) -> Result<bool> {
+    Ok(self.entries.lock()?.by_addr.is_empty())
+  }
+}
+
+impl<T: Clone> Default for SessionRegistry<T> {
+  fn default() -> Self {
+    SessionRegistry::new()
+  }
+}
+
+impl<W: Wire> SessionRegistry<Arc<Mutex<Session<W>>>> {
+  /// ハンドシェイクを完了したセッションを、Wire のリモート側アドレスと合意した相手のノード ID で登録します。
+  /// 登録したセッションは他のスレッドと共有するために `Arc<Mutex<_>>` でラップして返されます。
+  pub fn register(&self, session: Session<W>) -> Result<Arc<Mutex<Session<W>>>> {
+    let address = session.wire.remote_address()?;
+    let node_id = session.node_id();
+    let session = Arc::new(Mutex::new(session));
+    self.insert(address, node_id, session.clone())?;
+    Ok(session)
+  }
+}
+
+impl<T> RegistryEntries<T> {
+  fn remove(&mut self, address: &SocketAddr) -> Option<(NodeId, T)> {
+    let (node_id, session) = self.by_addr.remove(address)?;
+    if self.by_node.get(&node_id) == Some(address) {
+      self.by_node.remove(&node_id);
+    }
+    Some((node_id, session))
+  }
+}
@@ -0,0 +1,368 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::bridge::io::dispatcher::Dispatcher;
+use crate::bridge::tcp::TcpWire;
+use crate::bridge::Wire;
+use crate::error::Error;
+use crate::msg::{Close, Control, Message, Open, SystemConfigBuilder};
+use crate::session::{Handshake, NodeId, Session, SessionConfig, SessionRegistry};
+
+/// ローカルで接続したクライアントとサーバの Wire を作成します。
+fn wire_pair() -> (TcpWire, TcpWire) {
+  let dispatcher = Arc::new(Dispatcher::new(1024, 1024).unwrap());
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let client = TcpWire::connect(dispatcher.clone(), listener.local_addr().unwrap()).unwrap();
+  let (server, _) = listener.accept().unwrap();
+  (client, TcpWire::register(dispatcher, server, true).unwrap())
+}
+
+/// テストで使用するノード ID を構築します。
+fn node_id(value: u128) -> NodeId {
+  NodeId::from(Uuid::from_u128(value))
+}
+
+#[test]
+fn test_handshake() {
+  let (client_id, server_id) = (node_id(1), node_id(2));
+  let (mut client, mut server) = wire_pair();
+  let handle = spawn(move || {
+    let handshake = Handshake::new(server_id).ping_interval(5).session_timeout(30);
+    handshake.begin_server(&mut server).unwrap()
+  });
+
+  // クライアントの提示した値ではなくサーバの値で合意する
+  let handshake = Handshake::new(client_id).ping_interval(100).session_timeout(200);
+  let client_session = handshake.begin_client(&mut client).unwrap();
+  let server_session = handle.join().unwrap();
+  assert_eq!(server_id, client_session.node_id);
+  assert_eq!(client_id, server_session.node_id);
+  assert!(!client_session.session_id.is_nil());
+  assert_eq!(server_session.session_id, client_session.session_id);
+  assert_eq!((5, 30), (client_session.ping_interval, client_session.session_timeout));
+  assert_eq!((5, 30), (server_session.ping_interval, server_session.session_timeout));
+}
+
+#[test]
+fn test_handshake_incompatible_version() {
+  let (mut client, mut server) = wire_pair();
+  let handle = spawn(move || Handshake::new(node_id(0)).begin_server(&mut server));
+
+  // サーバは互換性のないバージョンを拒否し、クライアントは拒否されたことを検出する
+  let handshake = Handshake::new(node_id(0)).version(0x0200);
+  let err = handshake.begin_client(&mut client).unwrap_err();
+  assert_eq!(Error::IncompatibleVersion { version: 0x0200 }, handle.join().unwrap().unwrap_err());
+  assert!(matches!(err, Error::IllegalHandshake { .. }), "{:?}", err);
+}
+
+#[test]
+fn test_handshake_client_session_id() {
+  // クライアントは 0 のセッション ID を送信する
+  let (mut client, mut server) = wire_pair();
+  let handle = spawn(move || Handshake::new(node_id(1)).begin_client(&mut client));
+  let received = loop {
+    if let Some(msg) = server.try_recv().unwrap() {
+      break msg;
+    }
+    std::thread::yield_now();
+  };
+  match received {
+    Message::Control(Control::SystemConfig { session_id, .. }) => assert!(session_id.is_nil()),
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+  drop(server);
+  assert!(handle.join().unwrap().is_err());
+
+  // クライアント側の Wire では 0 以外のセッション ID を指定した System Config を構築できない
+  let (client, server) = wire_pair();
+  let session_id = Uuid::from_u128(2);
+  let handshake = Handshake::new(node_id(0));
+  let err = handshake.system_config(&client, session_id).unwrap_err();
+  assert_eq!(Error::NonZeroClientSessionId { session_id }, err);
+  assert!(handshake.system_config(&server, session_id).is_ok());
+
+  // サーバは 0 以外のセッション ID を送信したクライアントを拒否する
+  let (mut client, mut server) = wire_pair();
+  let config = SystemConfigBuilder::new(Uuid::nil()).session_id(session_id).build().unwrap();
+  client.send(Message::Control(config)).unwrap();
+  let err = Handshake::new(node_id(0)).begin_server(&mut server).unwrap_err();
+  assert_eq!(Error::NonZeroClientSessionId { session_id }, err);
+  let rejected = loop {
+    if let Some(msg) = client.try_recv().unwrap() {
+      break msg;
+    }
+    std::thread::yield_now();
+  };
+  match rejected {
+    Message::Control(Control::Close { reason_code, .. }) => assert_eq!(err.code(), reason_code),
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+}
+
+#[test]
+fn test_handshake_unexpected_message() {
+  let (mut client, mut server) = wire_pair();
+  client.send(Message::Open(Open::new(1, 2, 3, vec![]).unwrap())).unwrap();
+  let err = Handshake::new(node_id(0)).begin_server(&mut server).unwrap_err();
+  assert_eq!(Error::UnexpectedMessage { expected: "SystemConfig", got: "Open" }, err);
+
+  // 応答がなければタイムアウトする
+  let handshake = Handshake::new(node_id(0)).timeout(Duration::from_millis(50));
+  let err = handshake.begin_client(&mut client).unwrap_err();
+  assert!(matches!(err, Error::Io { kind: std::io::ErrorKind::TimedOut, .. }), "{:?}", err);
+}
+
+#[test]
+fn test_session_open_pipe() {
+  let (client, mut server) = wire_pair();
+  let config = SessionConfig {
+    node_id: node_id(1),
+    session_id: Uuid::from_u128(2),
+    ping_interval: 3,
+    session_timeout: 4,
+  };
+  let mut session = Session::new(client, config.clone());
+  assert_eq!(&config, session.config());
+  assert_eq!(node_id(1), session.node_id());
+  assert_eq!(Uuid::from_u128(2), session.session_id());
+  assert_eq!((3, 4), (session.ping_interval(), session.session_timeout()));
+
+  // クライアント側のセッションは奇数のパイプ ID で Open を送信する
+  let first = session.open_pipe(10, 20, vec![1, 2, 3]).unwrap();
+  let second = session.open_pipe(11, 21, vec![]).unwrap();
+  assert_eq!((1, 1), (first % 2, second % 2));
+  assert_ne!(first, second);
+
+  // 相手に正しい内容の Open が届く
+  let mut received = Vec::new();
+  while received.len() < 2 {
+    match server.try_recv().unwrap() {
+      Some(msg) => received.push(msg),
+      None => std::thread::yield_now(),
+    }
+  }
+  assert_eq!(Message::Open(Open::new(first, 10, 20, vec![1, 2, 3]).unwrap()), received[0]);
+  assert_eq!(Message::Open(Open::new(second, 11, 21, vec![]).unwrap()), received[1]);
+
+  // 不正なパラメータで失敗した場合はパイプを開かない (ID は解放されるがすぐには再利用されない)
+  assert!(session.open_pipe(12, 0, vec![0u8; 0x10000]).is_err());
+  assert_eq!(2, session.open_pipes());
+  assert_eq!(second + 4, session.open_pipe(12, 0, vec![]).unwrap());
+}
+
+#[test]
+fn test_session_pipe_timeout() {
+  let (client, _server) = wire_pair();
+  let config = SessionConfig {
+    node_id: node_id(1),
+    session_id: Uuid::from_u128(2),
+    ping_interval: 3,
+    session_timeout: 4,
+  };
+  let mut session = Session::new(client, config);
+  assert_eq!(Some(Duration::from_secs(4)), session.pipe_timeout());
+  session.set_pipe_timeout(Some(Duration::from_millis(100)));
+
+  // 期限前はタイムアウトしない
+  let pipe_id = session.open_pipe(10, 0, vec![]).unwrap();
+  let opened = Instant::now();
+  assert!(session.expire_pipes(opened).unwrap().is_empty());
+  assert_eq!(1, session.open_pipes());
+
+  // 相手が応答しないまま期限を過ぎるとタイムアウトを示す失敗の Close が生成されパイプは破棄される
+  let closes = session.expire_pipes(opened + Duration::from_millis(200)).unwrap();
+  assert_eq!(1, closes.len());
+  assert_eq!(pipe_id, closes[0].pipe_id());
+  assert!(!closes[0].is_success());
+  let timeout = Error::PipeTimeout { pipe_id, timeout: Duration::from_millis(100) };
+  assert_eq!(&timeout.code().to_be_bytes(), &closes[0].result()[..2]);
+  assert_eq!(timeout.to_string().as_bytes(), &closes[0].result()[2..]);
+  assert_eq!(0, session.open_pipes());
+  assert!(session.expire_pipes(opened + Duration::from_secs(1)).unwrap().is_empty());
+
+  // 相手に通知せずに破棄したパイプの ID はすぐには再利用されず、相手の Close でクローズしたパイプはタイムアウトしない
+  let expired = pipe_id;
+  let pipe_id = session.open_pipe(10, 0, vec![]).unwrap();
+  assert_ne!(expired, pipe_id);
+  assert!(session.touch_pipe(pipe_id));
+  session.close_pipe(&Close::success(pipe_id, vec![]).unwrap()).unwrap();
+  assert!(!session.touch_pipe(pipe_id));
+  assert!(session.expire_pipes(Instant::now() + Duration::from_secs(1)).unwrap().is_empty());
+  assert_eq!(
+    Error::PipeClosed { pipe_id },
+    session.close_pipe(&Close::success(pipe_id, vec![]).unwrap()).unwrap_err()
+  );
+}
+
+#[test]
+fn test_session_max_open_pipes() {
+  let (mut client, server) = wire_pair();
+  let config = SessionConfig {
+    node_id: node_id(1),
+    session_id: Uuid::from_u128(2),
+    ping_interval: 3,
+    session_timeout: 4,
+  };
+  let mut session = Session::new(server, config);
+  assert_eq!(None, session.max_open_pipes());
+  session.set_max_open_pipes(Some(2));
+  assert_eq!(Some(2), session.max_open_pipes());
+
+  // 上限までは相手の Open を受け付ける
+  assert!(session.accept_pipe(&Open::new(1, 10, 0, vec![]).unwrap()).unwrap());
+  assert!(session.accept_pipe(&Open::new(3, 10, 0, vec![]).unwrap()).unwrap());
+  assert_eq!(2, session.accepted_pipes());
+  assert!(matches!(
+    session.accept_pipe(&Open::new(3, 10, 0, vec![]).unwrap()).unwrap_err(),
+    Error::IllegalPipeTransition { pipe_id: 3, .. }
+  ));
+
+  // 上限を超える Open はリソース不足を示す失敗の Close で拒否される
+  assert!(!session.accept_pipe(&Open::new(5, 10, 0, vec![]).unwrap()).unwrap());
+  assert_eq!(2, session.accepted_pipes());
+  let close = loop {
+    match client.try_recv().unwrap() {
+      Some(Message::Close(close)) => break close,
+      Some(msg) => panic!("unexpected message: {:?}", msg),
+      None => std::thread::yield_now(),
+    }
+  };
+  let err = Error::TooManyPipes { maximum: 2 };
+  assert_eq!(5, close.pipe_id());
+  assert!(!close.is_success());
+  assert_eq!(&605u16.to_be_bytes(), &close.result()[..2]);
+  assert_eq!(err.to_string().as_bytes(), &close.result()[2..]);
+
+  // 受け付けたパイプをクローズすると再び受け付けられる
+  session.close_accepted_pipe(Close::success(1, vec![]).unwrap()).unwrap();
+  assert_eq!(
+    Error::PipeClosed { pipe_id: 1 },
+    session.close_accepted_pipe(Close::success(1, vec![]).unwrap()).unwrap_err()
+  );
+  assert!(session.accept_pipe(&Open::new(5, 10, 0, vec![]).unwrap()).unwrap());
+  assert_eq!(2, session.accepted_pipes());
+}
+
+#[test]
+fn test_session_accept_pipe_parity() {
+  let (mut client, server) = wire_pair();
+  let config = SessionConfig {
+    node_id: node_id(1),
+    session_id: Uuid::from_u128(2),
+    ping_interval: 3,
+    session_timeout: 4,
+  };
+  let mut session = Session::new(server, config);
+
+  // 接続を受け付けた側のセッションは相手が開く奇数の ID のみを受け付ける
+  assert!(session.accept_pipe(&Open::new(1, 10, 0, vec![]).unwrap()).unwrap());
+
+  // 自身が割り当てる偶数の ID の Open は失敗の Close で拒否される
+  assert!(!session.accept_pipe(&Open::new(2, 10, 0, vec![]).unwrap()).unwrap());
+  assert_eq!(1, session.accepted_pipes());
+  let close = loop {
+    match client.try_recv().unwrap() {
+      Some(Message::Close(close)) => break close,
+      Some(msg) => panic!("unexpected message: {:?}", msg),
+      None => std::thread::yield_now(),
+    }
+  };
+  let err = Error::ForeignPipeId { pipe_id: 2 };
+  assert_eq!(2, close.pipe_id());
+  assert!(!close.is_success());
+  assert_eq!(&606u16.to_be_bytes(), &close.result()[..2]);
+  assert_eq!(err.to_string().as_bytes(), &close.result()[2..]);
+
+  // 拒否した ID はこのセッションが自身のパイプに使用できる
+  assert_eq!(2, session.open_pipe(10, 0, vec![]).unwrap());
+}
+
+#[test]
+fn test_session_registry() {
+  let server_id = node_id(100);
+  let registry = Arc::new(SessionRegistry::new());
+
+  // 2 つのクライアントとのハンドシェイクを完了したセッションを登録する
+  let mut clients = Vec::new();
+  for client_id in [node_id(1), node_id(2)].iter().copied() {
+    let (mut client, mut server) = wire_pair();
+    let address = client.local_address().unwrap();
+    let handle =
+      spawn(move || Handshake::new(server_id).begin_server(&mut server).map(|c| (server, c)));
+    Handshake::new(client_id).begin_client(&mut client).unwrap();
+    let (server, config) = handle.join().unwrap().unwrap();
+    let registered = registry.register(Session::new(server, config)).unwrap();
+    clients.push((client, client_id, address, registered));
+  }
+  assert_eq!(2, registry.len().unwrap());
+
+  // 別のスレッドからアドレスとノード ID のどちらでも同じセッションを参照できる
+  for (_, client_id, address, registered) in clients.iter() {
+    let (registry, client_id, address) = (registry.clone(), *client_id, *address);
+    let (by_addr, by_node) = spawn(move || {
+      (registry.get_by_addr(&address).unwrap(), registry.get_by_node(&client_id).unwrap())
+    })
+    .join()
+    .unwrap();
+    assert!(Arc::ptr_eq(registered, &by_addr.unwrap()));
+    assert!(Arc::ptr_eq(registered, &by_node.unwrap()));
+    assert_eq!(client_id, registered.lock().unwrap().node_id());
+  }
+  assert!(registry.get_by_node(&node_id(3)).unwrap().is_none());
+
+  // 削除したセッションはどちらのキーからも参照できない
+  let (_, client_id, address, registered) = &clients[0];
+  assert!(Arc::ptr_eq(registered, &registry.remove(address).unwrap().unwrap()));
+  assert!(registry.get_by_addr(address).unwrap().is_none());
+  assert!(registry.get_by_node(client_id).unwrap().is_none());
+  assert!(registry.remove(address).unwrap().is_none());
+  assert_eq!(1, registry.len().unwrap());
+}
+
+#[test]
+fn test_session_registry_replace() {
+  let registry = SessionRegistry::new();
+  let address = |port: u16| -> SocketAddr { ([127, 0, 0, 1], port).into() };
+  let (node1, node2) = (node_id(1), node_id(2));
+  assert!(registry.insert(address(1000), node1, "a").unwrap().is_empty());
+  assert!(registry.insert(address(2000), node2, "b").unwrap().is_empty());
+
+  // 同じノードが別のアドレスから再接続すると以前のセッションは置き換えられる
+  assert_eq!(vec!["a"], registry.insert(address(3000), node1, "c").unwrap());
+  assert_eq!(None, registry.get_by_addr(&address(1000)).unwrap());
+  assert_eq!(Some("c"), registry.get_by_node(&node1).unwrap());
+
+  // 同じアドレスを別のノードで登録すると以前のセッションは置き換えられる
+  assert_eq!(vec!["b"], registry.insert(address(2000), node_id(3), "d").unwrap());
+  assert_eq!(None, registry.get_by_node(&node2).unwrap());
+  assert_eq!(Some("d"), registry.get_by_addr(&address(2000)).unwrap());
+  assert_eq!(2, registry.len().unwrap());
+}
+
+#[test]
+fn test_node_id_load_or_create() {
+  let path = std::env::temp_dir().join(format!("bumblebees-{}-node-id", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+
+  // 最初の呼び出しで生成した ID がファイルに保存され、以降は同じ ID が読み込まれる
+  let node_id = NodeId::load_or_create(&path).unwrap();
+  assert!(path.exists());
+  assert_eq!(node_id, NodeId::load_or_create(&path).unwrap());
+  assert_eq!(node_id.to_string(), std::fs::read_to_string(&path).unwrap().trim());
+  assert_ne!(node_id, NodeId::generate());
+
+  // UUID として解釈できない内容は上書きせずにエラーとする
+  std::fs::write(&path, "not a uuid").unwrap();
+  let err = NodeId::load_or_create(&path).unwrap_err();
+  assert!(matches!(err, Error::Io { kind: std::io::ErrorKind::InvalidData, .. }), "{:?}", err);
+  assert_eq!("not a uuid", std::fs::read_to_string(&path).unwrap());
+  std::fs::remove_file(&path).unwrap();
+
+  let uuid = Uuid::from_u128(1);
+  assert_eq!(uuid, Uuid::from(NodeId::from(uuid)));
+  assert_eq!(uuid, NodeId::from(uuid).as_uuid());
+}
@@ -1,8 +1,21 @@
-use rand;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake};
+use std::thread::{current, park, Thread};
+use std::time::SystemTime;
+
 use rand::prelude::StdRng;
 use rand::{RngCore, SeedableRng};
 use uuid::Uuid;
 
+use crate::bridge::io::executor::{ConnectionHandler, Executor};
+use crate::msg::{
+  from_utc_millis, Block, Close, Control, Message, Open, MAX_LOSS_RATE, MAX_MESSAGE_SIZE,
+  MAX_PAYLOAD_SIZE,
+};
+
 /// 一様にランダムなテスト用の値を採集するための構造体。シードを指定することでランダムだが決定論的な値を生成する。
 pub struct SampleValues {
   rng: Box<StdRng>,
@@ -12,8 +25,8 @@ impl SampleValues {
   /// シードを指定してサンプル値ジェネレータを初期化します。
   pub fn new(seed: u64) -> SampleValues {
     let mut s = [0u8; 32];
-    for i in 0..8 {
-      s[i] = ((seed >> (i * 8)) & 0xFF) as u8
+    for (i, b) in s.iter_mut().enumerate().take(8) {
+      *b = ((seed >> (i * 8)) & 0xFF) as u8
     }
     SampleValues { rng: Box::new(rand::rngs::StdRng::from_seed(s)) }
   }
@@ -35,10 +48,7 @@ impl SampleValues {
   }
 
   pub fn next_bytes(&mut self, length: usize) -> Vec<u8> {
-    let mut bytes = Vec::with_capacity(length);
-    for _ in 0..length {
-      bytes.push(0u8)
-    }
+    let mut bytes = vec![0u8; length];
     self.rng.fill_bytes(&mut bytes);
     bytes
   }
@@ -48,6 +58,143 @@ impl SampleValues {
     self.rng.fill_bytes(&mut bytes[..]);
     Uuid::from_bytes(bytes)
   }
+
+  /// 0 を除くパイプ ID を生成します。
+  pub fn next_pipe_id(&mut self) -> u16 {
+    (self.next_u32() % 0xFFFF) as u16 + 1
+  }
+
+  /// UTC ミリ秒の範囲でランダムな時刻を生成します。
+  pub fn next_utc_time(&mut self) -> SystemTime {
+    from_utc_millis((self.next_u32() as u64) << 32 | self.next_u32() as u64)
+  }
+
+  /// 0 から `max` までの長さのランダムなバイト配列を生成します。
+  pub fn next_bytes_upto(&mut self, max: usize) -> Vec<u8> {
+    let length = self.next_u32() as usize % (max + 1);
+    self.next_bytes(length)
+  }
+
+  /// `Message` としてシリアライズ可能な Open メッセージを生成します。
+  pub fn next_open(&mut self) -> Open {
+    let (pipe_id, function_id, priority) = (self.next_pipe_id(), self.next_u16(), self.next_u8());
+    let max =
+      MAX_MESSAGE_SIZE - Message::Open(Open::new(1, 0, 0, vec![]).unwrap()).serialized_len();
+    Open::new(pipe_id, function_id, priority, self.next_bytes_upto(max)).unwrap()
+  }
+
+  /// `Message` としてシリアライズ可能な Close メッセージを生成します。
+  pub fn next_close(&mut self) -> Close {
+    let (pipe_id, failure) = (self.next_pipe_id(), self.next_bool());
+    let max =
+      MAX_MESSAGE_SIZE - Message::Close(Close::new(1, false, vec![]).unwrap()).serialized_len();
+    Close::new(pipe_id, failure, self.next_bytes_upto(max)).unwrap()
+  }
+
+  /// Block メッセージを生成します。EOF を示す Block の損失許容確率は 0 となります。
+  pub fn next_block(&mut self) -> Block {
+    let (pipe_id, eof) = (self.next_pipe_id(), self.next_bool());
+    let loss = if eof { 0 } else { self.next_u8() % (MAX_LOSS_RATE + 1) };
+    let block = Block::new(pipe_id, eof, loss, self.next_bytes_upto(MAX_PAYLOAD_SIZE)).unwrap();
+    if self.next_bool() {
+      block.with_sequence(self.next_u32())
+    } else {
+      block
+    }
+  }
+
+  /// System Config、Ping または Close の Control メッセージを生成します。
+  pub fn next_control(&mut self) -> Control {
+    match self.next_u8() % 3 {
+      0 => {
+        let (version, node_id, session_id) = (self.next_u16(), self.next_uuid(), self.next_uuid());
+        let utc_time = self.next_utc_time();
+        let (ping_interval, session_timeout) = (self.next_u32(), self.next_u32());
+        Control::new_system_config(
+          version,
+          node_id,
+          session_id,
+          utc_time,
+          ping_interval,
+          session_timeout,
+        )
+        .unwrap()
+      }
+      1 => Control::new_ping(self.next_utc_time()).unwrap(),
+      _ => Control::new_close(self.next_u16(), self.next_bytes_upto(1024)).unwrap(),
+    }
+  }
+
+  /// いずれかの種類のメッセージを生成します。
+  pub fn next_message(&mut self) -> Message {
+    match self.next_u8() % 4 {
+      0 => Message::Open(self.next_open()),
+      1 => Message::Close(self.next_close()),
+      2 => Message::Block(self.next_block()),
+      _ => Message::Control(self.next_control()),
+    }
+  }
+}
+
+/// 指定された Future が完了するまで現在のスレッドをブロックしてその結果を返します。
+pub fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+  struct ThreadWaker(Thread);
+  impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.unpark();
+    }
+  }
+
+  let waker = Arc::new(ThreadWaker(current())).into();
+  let mut cx = Context::from_waker(&waker);
+  loop {
+    match Pin::new(&mut future).poll(&mut cx) {
+      Poll::Ready(result) => return result,
+      Poll::Pending => park(),
+    }
+  }
+}
+
+/// 投入された処理を 1 つのワーカースレッドで順に実行する Executor です。
+pub struct WorkerExecutor {
+  sender: Mutex<Sender<Box<dyn FnOnce() + Send + 'static>>>,
+}
+
+impl WorkerExecutor {
+  pub fn new(name: &str) -> WorkerExecutor {
+    let (sender, receiver) = channel::<Box<dyn FnOnce() + Send + 'static>>();
+    std::thread::Builder::new()
+      .name(name.to_string())
+      .spawn(move || {
+        for task in receiver {
+          task();
+        }
+      })
+      .unwrap();
+    WorkerExecutor { sender: Mutex::new(sender) }
+  }
+}
+
+impl Executor for WorkerExecutor {
+  fn execute(&self, task: Box<dyn FnOnce() + Send + 'static>) {
+    self.sender.lock().unwrap().send(task).unwrap();
+  }
+}
+
+/// 受信したデータと EOF を、処理したスレッドの名前とともに送信する ConnectionHandler です。
+pub struct RecordingHandler {
+  pub sender: Sender<(Option<String>, Option<Vec<u8>>)>,
+}
+
+impl ConnectionHandler for RecordingHandler {
+  fn on_data(&mut self, data: Vec<u8>) {
+    let name = current().name().map(|name| name.to_string());
+    self.sender.send((name, Some(data))).unwrap();
+  }
+  fn on_eof(&mut self) {
+    let name = current().name().map(|name| name.to_string());
+    self.sender.send((name, None)).unwrap();
+  }
 }
 
 #[test]
@@ -66,3 +213,17 @@ fn test_sample_values() {
   let mut sample = SampleValues::new(783629830u64);
   assert_eq!(sample.next_bytes(1024).len(), 1024);
 }
+
+#[test]
+fn test_sample_messages() {
+  use std::io::Cursor;
+
+  // 生成したメッセージはすべてシリアライズして復元できる
+  let mut sample = SampleValues::new(90817236450u64);
+  for _ in 0..1000 {
+    let msg = sample.next_message();
+    let mut buf = Vec::new();
+    msg.write_to(&mut buf).unwrap();
+    assert_eq!(msg, Message::read_from(&mut Cursor::new(&buf[..])).unwrap());
+  }
+}